@@ -0,0 +1,239 @@
+//! On-disk cache of the parsed application list
+//!
+//! Scanning and parsing every .desktop file on each launch is the slowest
+//! part of apps mode. The parsed items are kept in the XDG state dir and
+//! reused until one of the application directories changes.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::Item;
+
+/// What the cached items were built from
+///
+/// Directory mtimes change whenever an entry is added, removed or
+/// replaced (package managers and editors write via rename), so every
+/// directory the scan descends into is recorded, not just the top-level
+/// ones. The desktop list and terminal are included because visibility
+/// and `Terminal=true` commands depend on them.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheKey {
+    /// Each scanned directory with its mtime (seconds, nanoseconds), None
+    /// if it doesn't exist
+    dirs: Vec<(PathBuf, Option<(u64, u32)>)>,
+    desktops: Vec<String>,
+    terminal: String,
+}
+
+impl CacheKey {
+    pub fn new(dirs: &[PathBuf], desktops: &[String], terminal: &str) -> Self {
+        let mut scanned = Vec::new();
+        for dir in dirs {
+            record_tree(dir, &mut scanned);
+        }
+        Self {
+            dirs: scanned,
+            desktops: desktops.to_vec(),
+            terminal: terminal.to_string(),
+        }
+    }
+}
+
+/// Record `dir` and the directories below it, without following symlinks
+/// to directories, like the application scan
+fn record_tree(dir: &Path, scanned: &mut Vec<(PathBuf, Option<(u64, u32)>)>) {
+    scanned.push((dir.to_path_buf(), mtime(dir)));
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut subdirs: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|e| e.path())
+        .collect();
+    subdirs.sort();
+    for subdir in subdirs {
+        record_tree(&subdir, scanned);
+    }
+}
+
+fn mtime(path: &Path) -> Option<(u64, u32)> {
+    let modified = fs::metadata(path).and_then(|m| m.modified()).ok()?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+    Some((since_epoch.as_secs(), since_epoch.subsec_nanos()))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AppCache {
+    key: CacheKey,
+    items: Vec<Item>,
+}
+
+/// Cached items at `path` if they were built for `key`
+pub fn load(path: &Path, key: &CacheKey) -> Option<Vec<Item>> {
+    let content = fs::read_to_string(path).ok()?;
+    let cache: AppCache = serde_json::from_str(&content).ok()?;
+    (cache.key == *key).then_some(cache.items)
+}
+
+/// Replace the cache at `path`
+pub fn store(path: &Path, key: &CacheKey, items: &[Item]) -> Result<()> {
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let cache = AppCache { key: key.clone(), items: items.to_vec() };
+
+    // Write to a uniquely named file, then rename, so a concurrent launch
+    // never reads half a cache and two launches never share a temp file
+    let mut tmp = tempfile::NamedTempFile::new_in(dir)?;
+    serde_json::to_writer(&mut tmp, &cache)?;
+    tmp.persist(path)?;
+    Ok(())
+}
+
+/// Default cache location
+pub fn cache_path() -> Option<PathBuf> {
+    dirs::state_dir().map(|d| d.join("cvh-fuzzy/apps-cache.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    fn scratch(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("cvh-fuzzy-cache-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("applications")).unwrap();
+        root
+    }
+
+    fn set_mtime(dir: &Path, secs: u64) {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        File::open(dir).unwrap().set_modified(time).unwrap();
+    }
+
+    fn items() -> Vec<Item> {
+        vec![Item {
+            display: "Firefox".to_string(),
+            value: "firefox".to_string(),
+            icon: Some("firefox".to_string()),
+            id: Some("firefox.desktop".to_string()),
+        }]
+    }
+
+    #[test]
+    fn test_unchanged_dirs_hit() {
+        let root = scratch("hit");
+        let dirs = vec![root.join("applications"), root.join("missing")];
+        set_mtime(&dirs[0], 1_700_000_000);
+        let cache = root.join("state/apps-cache.json");
+
+        let key = CacheKey::new(&dirs, &[], "foot");
+        store(&cache, &key, &items()).unwrap();
+
+        let again = CacheKey::new(&dirs, &[], "foot");
+        assert_eq!(load(&cache, &again), Some(items()));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_changed_mtime_misses() {
+        let root = scratch("mtime");
+        let dirs = vec![root.join("applications")];
+        set_mtime(&dirs[0], 1_700_000_000);
+        let cache = root.join("apps-cache.json");
+        store(&cache, &CacheKey::new(&dirs, &[], "foot"), &items()).unwrap();
+
+        set_mtime(&dirs[0], 1_700_000_100);
+        assert_eq!(load(&cache, &CacheKey::new(&dirs, &[], "foot")), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_changed_subdir_misses() {
+        let root = scratch("subdir");
+        let dirs = vec![root.join("applications")];
+        let kde = dirs[0].join("kde");
+        fs::create_dir(&kde).unwrap();
+        std::os::unix::fs::symlink(&dirs[0], kde.join("loop")).unwrap();
+        set_mtime(&dirs[0], 1_700_000_000);
+        set_mtime(&kde, 1_700_000_000);
+        let cache = root.join("apps-cache.json");
+        store(&cache, &CacheKey::new(&dirs, &[], "foot"), &items()).unwrap();
+        assert_eq!(load(&cache, &CacheKey::new(&dirs, &[], "foot")), Some(items()));
+
+        // A package dropping a file into a subdirectory leaves the top-level
+        // mtime alone
+        set_mtime(&kde, 1_700_000_100);
+        assert_eq!(load(&cache, &CacheKey::new(&dirs, &[], "foot")), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_new_dir_misses() {
+        let root = scratch("created");
+        let dirs = vec![root.join("applications"), root.join("later")];
+        let cache = root.join("apps-cache.json");
+        store(&cache, &CacheKey::new(&dirs, &[], "foot"), &items()).unwrap();
+
+        fs::create_dir(root.join("later")).unwrap();
+        assert_eq!(load(&cache, &CacheKey::new(&dirs, &[], "foot")), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_environment_changes_miss() {
+        let root = scratch("env");
+        let dirs = vec![root.join("applications")];
+        let cache = root.join("apps-cache.json");
+        store(&cache, &CacheKey::new(&dirs, &["GNOME".to_string()], "foot"), &items()).unwrap();
+
+        assert_eq!(load(&cache, &CacheKey::new(&dirs, &["KDE".to_string()], "foot")), None);
+        assert_eq!(load(&cache, &CacheKey::new(&dirs, &["GNOME".to_string()], "kitty")), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_stores_leave_a_whole_cache() {
+        let root = scratch("concurrent");
+        let dirs = vec![root.join("applications")];
+        let key = CacheKey::new(&dirs, &[], "foot");
+        let cache = root.join("apps-cache.json");
+
+        std::thread::scope(|scope| {
+            for _ in 0..8 {
+                scope.spawn(|| store(&cache, &key, &items()).unwrap());
+            }
+        });
+        assert_eq!(load(&cache, &key), Some(items()));
+        // No temp files are left behind
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 2);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_missing_or_corrupt_cache_misses() {
+        let root = scratch("corrupt");
+        let key = CacheKey::new(&[root.join("applications")], &[], "foot");
+        let cache = root.join("apps-cache.json");
+        assert_eq!(load(&cache, &key), None);
+
+        fs::write(&cache, "{not json").unwrap();
+        assert_eq!(load(&cache, &key), None);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
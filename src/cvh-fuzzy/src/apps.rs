@@ -4,145 +4,544 @@
 
 use anyhow::Result;
 use std::{
+    collections::HashSet,
     env,
+    ffi::OsString,
     fs,
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::OnceLock,
 };
 
-use crate::Item;
+use crate::{app_cache::{self, CacheKey}, usage::{self, UsageStore}, Item};
 
 /// Standard XDG application directories
 fn get_application_dirs() -> Vec<PathBuf> {
+    application_dirs(
+        env::var_os("XDG_DATA_HOME"),
+        env::var_os("XDG_DATA_DIRS"),
+        dirs::home_dir(),
+    )
+}
+
+/// The `applications` directories under `$XDG_DATA_HOME` and each
+/// `$XDG_DATA_DIRS` entry, most important first
+///
+/// Unset or empty variables fall back to the spec defaults, relative
+/// entries are ignored as the spec requires, and repeated directories are
+/// only listed once.
+fn application_dirs(
+    data_home: Option<OsString>,
+    data_dirs: Option<OsString>,
+    home: Option<PathBuf>,
+) -> Vec<PathBuf> {
     let mut dirs = Vec::new();
 
     // User applications
-    if let Some(data_home) = env::var_os("XDG_DATA_HOME") {
-        dirs.push(PathBuf::from(data_home).join("applications"));
-    } else if let Some(home) = dirs::home_dir() {
-        dirs.push(home.join(".local/share/applications"));
+    match data_home.filter(|d| !d.is_empty()) {
+        Some(data_home) => dirs.push(PathBuf::from(data_home)),
+        None => dirs.extend(home.map(|h| h.join(".local/share"))),
     }
 
     // System applications
-    if let Some(data_dirs) = env::var_os("XDG_DATA_DIRS") {
-        for dir in env::split_paths(&data_dirs) {
-            dirs.push(dir.join("applications"));
+    match data_dirs.filter(|d| !d.is_empty()) {
+        Some(data_dirs) => dirs.extend(env::split_paths(&data_dirs)),
+        None => dirs.extend(["/usr/local/share", "/usr/share"].map(PathBuf::from)),
+    }
+
+    let mut seen = HashSet::new();
+    dirs.into_iter()
+        .filter(|dir| dir.is_absolute())
+        .map(|dir| dir.join("applications"))
+        .filter(|dir| seen.insert(dir.clone()))
+        .collect()
+}
+
+/// Fields of a desktop entry relevant to launching
+#[derive(Debug, Default)]
+struct DesktopEntry {
+    name: Option<String>,
+    exec: Option<String>,
+    icon: Option<String>,
+    no_display: bool,
+    hidden: bool,
+    terminal: bool,
+    only_show_in: Vec<String>,
+    not_show_in: Vec<String>,
+}
+
+impl DesktopEntry {
+    /// Parse the `[Desktop Entry]` group of a .desktop file
+    fn parse(content: &str) -> Self {
+        let mut entry = Self::default();
+        let mut in_desktop_entry = false;
+
+        for line in content.lines() {
+            let line = line.trim();
+
+            if line.starts_with('[') {
+                in_desktop_entry = line == "[Desktop Entry]";
+                continue;
+            }
+
+            if !in_desktop_entry {
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim();
+                match key.trim() {
+                    "Name" if entry.name.is_none() => entry.name = Some(unescape_value(value)),
+                    "Exec" => entry.exec = Some(unescape_value(value)),
+                    "Icon" => entry.icon = Some(unescape_value(value)),
+                    "NoDisplay" => entry.no_display = value.eq_ignore_ascii_case("true"),
+                    "Hidden" => entry.hidden = value.eq_ignore_ascii_case("true"),
+                    "Terminal" => entry.terminal = value.eq_ignore_ascii_case("true"),
+                    "OnlyShowIn" => entry.only_show_in = split_list(value),
+                    "NotShowIn" => entry.not_show_in = split_list(value),
+                    _ => {}
+                }
+            }
+        }
+
+        entry
+    }
+
+    /// Whether the entry should appear in a launcher on `desktops`
+    /// (the `$XDG_CURRENT_DESKTOP` list)
+    fn is_visible(&self, desktops: &[String]) -> bool {
+        if self.no_display || self.hidden {
+            return false;
+        }
+
+        let on_current = |list: &[String]| list.iter().any(|d| desktops.contains(d));
+        if !self.only_show_in.is_empty() && !on_current(&self.only_show_in) {
+            return false;
         }
-    } else {
-        dirs.push(PathBuf::from("/usr/local/share/applications"));
-        dirs.push(PathBuf::from("/usr/share/applications"));
+
+        !on_current(&self.not_show_in)
+    }
+
+    /// Build the launcher item, requiring both Name and Exec
+    fn into_item(self, id: String, path: &Path) -> Option<Item> {
+        let name = self.name?;
+        let exec = self.exec?;
+
+        let exec_clean = expand_exec(&exec, &name, self.icon.as_deref(), path);
+
+        // Wrap in terminal if needed
+        let final_exec = if self.terminal {
+            format!("{} -e {}", terminal_emulator(), exec_clean)
+        } else {
+            exec_clean
+        };
+
+        Some(Item {
+            display: name,
+            value: final_exec,
+            icon: self.icon,
+            id: Some(id),
+        })
     }
+}
 
-    dirs
+/// Split a `;`-separated desktop entry list
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
 }
 
-/// Parse a .desktop file and extract relevant info
-fn parse_desktop_file(path: &PathBuf) -> Option<Item> {
-    let content = fs::read_to_string(path).ok()?;
+/// Desktops named in `$XDG_CURRENT_DESKTOP`
+fn current_desktops() -> Vec<String> {
+    env::var("XDG_CURRENT_DESKTOP")
+        .map(|v| v.split(':').filter(|s| !s.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
 
-    let mut name = None;
-    let mut exec = None;
-    let mut icon = None;
-    let mut no_display = false;
-    let mut hidden = false;
-    let mut terminal = false;
-    let mut in_desktop_entry = false;
+/// Turn `(desktop-id, path, entry)` triples, in directory precedence order,
+/// into visible items. The first entry for a desktop-id wins even when it
+/// is hidden, so a user override can also hide a system entry.
+fn collect_items(
+    entries: impl IntoIterator<Item = (String, PathBuf, DesktopEntry)>,
+    desktops: &[String],
+) -> Vec<Item> {
+    let mut seen_ids = HashSet::new();
 
-    for line in content.lines() {
-        let line = line.trim();
+    entries
+        .into_iter()
+        .filter(|(id, _, _)| seen_ids.insert(id.clone()))
+        .filter(|(_, _, entry)| entry.is_visible(desktops))
+        .filter_map(|(id, path, entry)| entry.into_item(id, &path))
+        .collect()
+}
 
-        if line.starts_with('[') {
-            in_desktop_entry = line == "[Desktop Entry]";
+/// Undo the desktop entry escapes for string values (`\s`, `\n`, `\t`,
+/// `\r`, `\\`)
+fn unescape_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
             continue;
         }
+        match chars.next() {
+            Some('s') => out.push(' '),
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+/// Expand the field codes of an Exec value for launching without files
+///
+/// File and URL codes (`%f %F %u %U`) and deprecated ones (`%d %D %n %N %v
+/// %m`) expand to nothing, `%i` becomes `--icon <icon>`, `%c` the app name,
+/// `%k` the desktop file path and `%%` a literal percent sign.
+fn expand_exec(exec: &str, name: &str, icon: Option<&str>, path: &Path) -> String {
+    let mut out = String::with_capacity(exec.len());
+    let mut chars = exec.chars();
 
-        if !in_desktop_entry {
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
             continue;
         }
-
-        if let Some((key, value)) = line.split_once('=') {
-            match key.trim() {
-                "Name" if name.is_none() => name = Some(value.trim().to_string()),
-                "Exec" => exec = Some(value.trim().to_string()),
-                "Icon" => icon = Some(value.trim().to_string()),
-                "NoDisplay" => no_display = value.trim().eq_ignore_ascii_case("true"),
-                "Hidden" => hidden = value.trim().eq_ignore_ascii_case("true"),
-                "Terminal" => terminal = value.trim().eq_ignore_ascii_case("true"),
-                _ => {}
+        match chars.next() {
+            Some('%') => out.push('%'),
+            Some('i') => {
+                if let Some(icon) = icon.filter(|i| !i.is_empty()) {
+                    out.push_str("--icon ");
+                    out.push_str(&shell_quote(icon));
+                }
             }
+            Some('c') => out.push_str(&shell_quote(name)),
+            Some('k') => out.push_str(&shell_quote(&path.to_string_lossy())),
+            // Everything else, including unknown codes, is dropped
+            _ => {}
         }
     }
 
-    // Skip hidden or no-display entries
-    if no_display || hidden {
-        return None;
-    }
-
-    let name = name?;
-    let exec = exec?;
-
-    // Clean up exec (remove field codes like %f, %u, etc.)
-    let exec_clean = exec
-        .replace("%f", "")
-        .replace("%F", "")
-        .replace("%u", "")
-        .replace("%U", "")
-        .replace("%d", "")
-        .replace("%D", "")
-        .replace("%n", "")
-        .replace("%N", "")
-        .replace("%i", "")
-        .replace("%c", "")
-        .replace("%k", "")
-        .replace("%v", "")
-        .replace("%m", "")
-        .trim()
-        .to_string();
-
-    // Wrap in terminal if needed
-    let final_exec = if terminal {
-        format!("foot -e {}", exec_clean)
-    } else {
-        exec_clean
-    };
+    out.trim().to_string()
+}
+
+/// Quote a value so `sh -c` passes it through as a single argument
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Terminal emulator used for `Terminal=true` entries: `$TERMINAL`, or the
+/// first known emulator found in `$PATH`
+fn terminal_emulator() -> &'static str {
+    static TERMINAL: OnceLock<String> = OnceLock::new();
 
-    Some(Item {
-        display: name,
-        value: final_exec,
-        icon,
+    TERMINAL.get_or_init(|| {
+        if let Some(term) = env::var("TERMINAL").ok().filter(|t| !t.is_empty()) {
+            return term;
+        }
+
+        let paths: Vec<PathBuf> = env::var_os("PATH")
+            .map(|p| env::split_paths(&p).collect())
+            .unwrap_or_default();
+        ["kitty", "foot", "alacritty", "wezterm", "xterm"]
+            .iter()
+            .find(|name| paths.iter().any(|dir| dir.join(name).is_file()))
+            .unwrap_or(&"foot")
+            .to_string()
     })
 }
 
 /// Load all applications from XDG directories
+///
+/// The parsed list is cached and reused while the application directories
+/// are unchanged; launch ranking is always applied fresh.
 pub fn load_applications() -> Result<Vec<Item>> {
-    let mut items = Vec::new();
-    let mut seen_names = std::collections::HashSet::new();
+    let dirs = get_application_dirs();
+    let desktops = current_desktops();
+    let key = CacheKey::new(&dirs, &desktops, terminal_emulator());
+    let cache = app_cache::cache_path();
 
-    for dir in get_application_dirs() {
-        if !dir.exists() {
-            continue;
+    let mut items = match cache.as_deref().and_then(|path| app_cache::load(path, &key)) {
+        Some(items) => items,
+        None => {
+            let items = scan_applications(&dirs, &desktops);
+            if let Some(path) = &cache {
+                let _ = app_cache::store(path, &key, &items);
+            }
+            items
         }
+    };
 
-        let entries = match fs::read_dir(&dir) {
-            Ok(e) => e,
-            Err(_) => continue,
-        };
+    // Float frequently launched apps to the top. Nucleo breaks score ties
+    // by insertion order, so this ordering is what an empty query shows.
+    rank_by_usage(&mut items, &UsageStore::load(), usage::now());
 
-        for entry in entries.filter_map(|e| e.ok()) {
-            let path = entry.path();
+    Ok(items)
+}
 
-            if path.extension().map_or(false, |ext| ext == "desktop") {
-                if let Some(item) = parse_desktop_file(&path) {
-                    // Deduplicate by name
-                    if seen_names.insert(item.display.clone()) {
-                        items.push(item);
-                    }
-                }
+/// Parse the entries in `dirs` into items sorted alphabetically
+fn scan_applications(dirs: &[PathBuf], desktops: &[String]) -> Vec<Item> {
+    let mut entries = Vec::new();
+
+    for dir in dirs {
+        collect_desktop_files(dir, "", &mut entries);
+    }
+
+    let mut items = collect_items(entries, desktops);
+    items.sort_by(|a, b| a.display.to_lowercase().cmp(&b.display.to_lowercase()));
+    items
+}
+
+/// Gather the .desktop files under `dir`, descending into subdirectories
+///
+/// The desktop-id of `applications/kde/okular.desktop` is
+/// `kde-okular.desktop`; `prefix` carries the subdirectory part. Symlinks
+/// to directories aren't followed, so a link loop can't recurse forever;
+/// `CacheKey` walks the same directories. Missing or unreadable
+/// directories and files are skipped.
+fn collect_desktop_files(dir: &Path, prefix: &str, entries: &mut Vec<(String, PathBuf, DesktopEntry)>) {
+    let Ok(dir_entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    let mut paths: Vec<(PathBuf, bool)> = dir_entries
+        .filter_map(|e| e.ok())
+        .map(|e| (e.path(), e.file_type().is_ok_and(|t| t.is_dir())))
+        .collect();
+    paths.sort();
+
+    for (path, is_dir) in paths {
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+            continue;
+        };
+
+        if is_dir {
+            collect_desktop_files(&path, &format!("{}{}-", prefix, name), entries);
+        } else if path.extension().is_some_and(|ext| ext == "desktop") {
+            if let Ok(content) = fs::read_to_string(&path) {
+                entries.push((format!("{}{}", prefix, name), path, DesktopEntry::parse(&content)));
             }
         }
     }
+}
 
-    // Sort alphabetically
-    items.sort_by(|a, b| a.display.to_lowercase().cmp(&b.display.to_lowercase()));
+/// Stable-sort items by launch frecency, most used first
+fn rank_by_usage(items: &mut [Item], store: &UsageStore, now: u64) {
+    items.sort_by_cached_key(|item| {
+        let score = item.id.as_deref().map_or(0.0, |id| store.score(id, now));
+        // Scores are non-negative, so their bit patterns order like the values
+        std::cmp::Reverse(score.to_bits())
+    });
+}
 
-    Ok(items)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(exec: &str) -> String {
+        expand_exec(exec, "My App", Some("my-app"), Path::new("/usr/share/applications/my.desktop"))
+    }
+
+    #[test]
+    fn test_file_codes_removed() {
+        assert_eq!(expand("firefox %u"), "firefox");
+        assert_eq!(expand("code --new-window %F"), "code --new-window");
+        assert_eq!(expand("vlc --started-from-file %U"), "vlc --started-from-file");
+    }
+
+    #[test]
+    fn test_icon_name_and_path_substituted() {
+        assert_eq!(expand("app %i"), "app --icon 'my-app'");
+        assert_eq!(expand("app --class %c"), "app --class 'My App'");
+        assert_eq!(expand("app %k"), "app '/usr/share/applications/my.desktop'");
+        assert_eq!(
+            expand_exec("app %i %f", "App", None, Path::new("/x.desktop")),
+            "app"
+        );
+    }
+
+    #[test]
+    fn test_percent_escape() {
+        assert_eq!(expand("printf 100%% %f"), "printf 100%");
+    }
+
+    #[test]
+    fn test_shell_quote_escapes_single_quotes() {
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_unescape_value() {
+        assert_eq!(unescape_value("a\\sb\\\\c"), "a b\\c");
+    }
+
+    fn entry(content: &str) -> DesktopEntry {
+        DesktopEntry::parse(&format!("[Desktop Entry]\n{}", content))
+    }
+
+    fn names(items: Vec<Item>) -> Vec<String> {
+        items.into_iter().map(|i| i.display).collect()
+    }
+
+    #[test]
+    fn test_hidden_entries_dropped() {
+        let entries = vec![
+            ("a.desktop".to_string(), PathBuf::from("/a.desktop"), entry("Name=A\nExec=a")),
+            ("b.desktop".to_string(), PathBuf::from("/b.desktop"), entry("Name=B\nExec=b\nNoDisplay=true")),
+            ("c.desktop".to_string(), PathBuf::from("/c.desktop"), entry("Name=C\nExec=c\nHidden=true")),
+        ];
+        assert_eq!(names(collect_items(entries, &[])), vec!["A"]);
+    }
+
+    #[test]
+    fn test_show_in_respects_current_desktop() {
+        let desktops = vec!["niri".to_string()];
+        let only_gnome = entry("Name=G\nExec=g\nOnlyShowIn=GNOME;");
+        let only_niri = entry("Name=N\nExec=n\nOnlyShowIn=GNOME;niri;");
+        let not_niri = entry("Name=X\nExec=x\nNotShowIn=niri;");
+        let not_kde = entry("Name=K\nExec=k\nNotShowIn=KDE;");
+
+        assert!(!only_gnome.is_visible(&desktops));
+        assert!(only_niri.is_visible(&desktops));
+        assert!(!not_niri.is_visible(&desktops));
+        assert!(not_kde.is_visible(&desktops));
+        assert!(!only_niri.is_visible(&[]));
+    }
+
+    #[test]
+    fn test_user_override_wins() {
+        let entries = vec![
+            (
+                "firefox.desktop".to_string(),
+                PathBuf::from("/home/u/.local/share/applications/firefox.desktop"),
+                entry("Name=Firefox (custom)\nExec=firefox --private-window"),
+            ),
+            (
+                "firefox.desktop".to_string(),
+                PathBuf::from("/usr/share/applications/firefox.desktop"),
+                entry("Name=Firefox\nExec=firefox %u"),
+            ),
+        ];
+        let items = collect_items(entries, &[]);
+        assert_eq!(names(items.clone()), vec!["Firefox (custom)"]);
+        assert_eq!(items[0].value, "firefox --private-window");
+    }
+
+    #[test]
+    fn test_rank_by_usage() {
+        let now = 1_700_000_000;
+        let mut store = UsageStore::default();
+        store.record("kitty.desktop", now);
+        store.record("kitty.desktop", now);
+        store.record("zed.desktop", now);
+
+        let mut items: Vec<Item> = ["alacritty", "kitty", "zed"]
+            .iter()
+            .map(|n| Item {
+                display: n.to_string(),
+                value: n.to_string(),
+                icon: None,
+                id: Some(format!("{}.desktop", n)),
+            })
+            .collect();
+        rank_by_usage(&mut items, &store, now);
+        assert_eq!(names(items), vec!["kitty", "zed", "alacritty"]);
+    }
+
+    #[test]
+    fn test_hidden_override_hides_system_entry() {
+        let entries = vec![
+            ("htop.desktop".to_string(), PathBuf::from("/home/u/htop.desktop"), entry("Hidden=true")),
+            ("htop.desktop".to_string(), PathBuf::from("/usr/htop.desktop"), entry("Name=Htop\nExec=htop")),
+        ];
+        assert!(collect_items(entries, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_application_dirs_precedence() {
+        let home = Some(PathBuf::from("/home/u"));
+        assert_eq!(
+            application_dirs(
+                Some("/data/home".into()),
+                Some("/var/lib/flatpak/exports/share:relative:/nix/profile/share:/data/home".into()),
+                home.clone(),
+            ),
+            [
+                "/data/home/applications",
+                "/var/lib/flatpak/exports/share/applications",
+                "/nix/profile/share/applications",
+            ]
+            .map(PathBuf::from)
+        );
+        assert_eq!(
+            application_dirs(Some("".into()), Some("".into()), home),
+            [
+                "/home/u/.local/share/applications",
+                "/usr/local/share/applications",
+                "/usr/share/applications",
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn test_scan_applications_follows_data_dirs_in_order() {
+        let root = std::env::temp_dir().join(format!("cvh-fuzzy-apps-{}", std::process::id()));
+        let write = |dir: &str, file: &str, content: &str| {
+            let path = root.join(dir).join("applications").join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("[Desktop Entry]\n{}\n", content)).unwrap();
+        };
+        write("home", "editor.desktop", "Name=Editor (mine)\nExec=editor --mine");
+        write("flatpak", "editor.desktop", "Name=Editor (flatpak)\nExec=flatpak run editor");
+        write("flatpak", "Calc.desktop", "Name=Calc\nExec=calc");
+        write("nix", "kde/viewer.desktop", "Name=Viewer\nExec=viewer");
+        write("nix", "Calc.desktop", "Name=Calc (nix)\nExec=nix-calc");
+
+        let data_dirs = env::join_paths([root.join("flatpak"), root.join("missing"), root.join("nix")]).unwrap();
+        let dirs = application_dirs(Some(root.join("home").into()), Some(data_dirs), None);
+        let items = scan_applications(&dirs, &[]);
+
+        let found: Vec<(&str, &str)> =
+            items.iter().map(|i| (i.id.as_deref().unwrap(), i.value.as_str())).collect();
+        assert_eq!(
+            found,
+            [
+                ("Calc.desktop", "calc"),
+                ("editor.desktop", "editor --mine"),
+                ("kde-viewer.desktop", "viewer"),
+            ]
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_scan_applications_skips_directory_symlinks() {
+        let root = std::env::temp_dir().join(format!("cvh-fuzzy-apps-loop-{}", std::process::id()));
+        let apps = root.join("applications");
+        fs::create_dir_all(apps.join("kde")).unwrap();
+        fs::write(apps.join("kde/viewer.desktop"), "[Desktop Entry]\nName=Viewer\nExec=viewer\n").unwrap();
+        std::os::unix::fs::symlink(&apps, apps.join("kde/loop")).unwrap();
+        std::os::unix::fs::symlink(apps.join("kde/viewer.desktop"), apps.join("linked.desktop")).unwrap();
+
+        let items = scan_applications(&[apps], &[]);
+        let ids: Vec<&str> = items.iter().map(|i| i.id.as_deref().unwrap()).collect();
+        assert_eq!(ids, ["kde-viewer.desktop", "linked.desktop"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,80 @@
+//! Clipboard output
+//!
+//! Copies the selection by piping it into an external clipboard helper
+
+use anyhow::{bail, Context, Result};
+use std::{
+    io::{self, Write},
+    process::{Command, Stdio},
+};
+
+/// Clipboard helpers in order of preference: Wayland first, then X11
+const HELPERS: &[(&str, &[&str])] = &[
+    ("wl-copy", &[]),
+    ("xclip", &["-selection", "clipboard"]),
+];
+
+/// Copy `text` to the system clipboard
+pub fn copy(text: &str) -> Result<()> {
+    copy_with(HELPERS, text)
+}
+
+/// Copy `text` using the first helper in `helpers` that is installed
+fn copy_with(helpers: &[(&str, &[&str])], text: &str) -> Result<()> {
+    for (program, args) in helpers {
+        let child = Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn();
+
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("failed to run {}", program)),
+        };
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(text.as_bytes())
+                .with_context(|| format!("failed to write to {}", program))?;
+        }
+
+        let status = child.wait()?;
+        if !status.success() {
+            bail!("{} exited with {}", program, status);
+        }
+        return Ok(());
+    }
+
+    let names: Vec<&str> = helpers.iter().map(|(program, _)| *program).collect();
+    bail!(
+        "no clipboard helper found (tried {}); install wl-clipboard or xclip",
+        names.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_falls_back_to_next_helper() {
+        let helpers: &[(&str, &[&str])] = &[("cvh-fuzzy-missing-helper", &[]), ("cat", &[])];
+        assert!(copy_with(helpers, "hello").is_ok());
+    }
+
+    #[test]
+    fn test_error_when_no_helper_found() {
+        let helpers: &[(&str, &[&str])] = &[("cvh-fuzzy-missing-a", &[]), ("cvh-fuzzy-missing-b", &[])];
+        let err = copy_with(helpers, "hello").unwrap_err().to_string();
+        assert!(err.contains("no clipboard helper found"), "{}", err);
+        assert!(err.contains("cvh-fuzzy-missing-a, cvh-fuzzy-missing-b"), "{}", err);
+    }
+
+    #[test]
+    fn test_failing_helper_is_reported() {
+        let helpers: &[(&str, &[&str])] = &[("false", &[])];
+        assert!(copy_with(helpers, "hello").is_err());
+    }
+}
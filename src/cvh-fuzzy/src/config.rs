@@ -1,8 +1,12 @@
 //! Configuration module for cvh-fuzzy
 
+use anyhow::{anyhow, Context, Result};
+use ratatui::style::Color;
 use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
+use crate::keys::{Action, Keymap};
+
 /// Main configuration structure
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -10,7 +14,7 @@ pub struct Config {
     #[serde(default = "default_mode")]
     pub default_mode: String,
 
-    /// Window height in lines
+    /// Maximum height in lines, used when `--height` isn't given
     #[serde(default = "default_height")]
     pub height: u16,
 
@@ -29,21 +33,103 @@ pub struct Config {
     /// Colors
     #[serde(default)]
     pub colors: Colors,
+
+    /// Key bindings
+    #[serde(default)]
+    pub keys: KeyBindings,
 }
 
+/// UI colors, each a name ("cyan", "darkgray"), "#rrggbb" or a 0-255
+/// terminal palette index
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Colors {
+    /// Query and preview text
     #[serde(default = "default_fg")]
     pub fg: String,
 
+    /// Background of the whole finder ("reset" keeps the terminal's)
     #[serde(default = "default_bg")]
     pub bg: String,
 
+    /// Matched characters in results
     #[serde(default = "default_highlight")]
     pub highlight: String,
 
+    /// Text of the selected result
+    #[serde(default = "default_highlight_fg")]
+    pub highlight_fg: String,
+
+    /// Background of the selected result
+    #[serde(default = "default_highlight_bg")]
+    pub highlight_bg: String,
+
+    /// Results and preview borders
     #[serde(default = "default_border_color")]
     pub border: String,
+
+    /// Prompt border and title
+    #[serde(default = "default_prompt_color")]
+    pub prompt: String,
+}
+
+/// Colors parsed into their `ratatui` form
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub fg: Color,
+    pub bg: Color,
+    pub highlight: Color,
+    pub highlight_fg: Color,
+    pub highlight_bg: Color,
+    pub border: Color,
+    pub prompt: Color,
+}
+
+/// Key bindings, each action mapped to a list of key specs like "ctrl-j"
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeyBindings {
+    #[serde(default = "default_accept_keys")]
+    pub accept: Vec<String>,
+
+    #[serde(default = "default_up_keys")]
+    pub up: Vec<String>,
+
+    #[serde(default = "default_down_keys")]
+    pub down: Vec<String>,
+
+    #[serde(default = "default_clear_keys")]
+    pub clear: Vec<String>,
+
+    #[serde(default = "default_abort_keys")]
+    pub abort: Vec<String>,
+
+    #[serde(default = "default_left_keys")]
+    pub left: Vec<String>,
+
+    #[serde(default = "default_right_keys")]
+    pub right: Vec<String>,
+
+    #[serde(default = "default_home_keys")]
+    pub home: Vec<String>,
+
+    #[serde(default = "default_end_keys")]
+    pub end: Vec<String>,
+
+    #[serde(default = "default_delete_word_keys")]
+    pub delete_word: Vec<String>,
+
+    #[serde(default = "default_kill_line_keys")]
+    pub kill_line: Vec<String>,
+
+    #[serde(default = "default_history_prev_keys")]
+    pub history_prev: Vec<String>,
+
+    #[serde(default = "default_history_next_keys")]
+    pub history_next: Vec<String>,
+
+    #[serde(default = "default_accept_query_keys")]
+    pub accept_query: Vec<String>,
 }
 
 impl Default for Config {
@@ -60,6 +146,7 @@ impl Default for Config {
                 "__pycache__".to_string(),
             ],
             colors: Colors::default(),
+            keys: KeyBindings::default(),
         }
     }
 }
@@ -70,11 +157,94 @@ impl Default for Colors {
             fg: default_fg(),
             bg: default_bg(),
             highlight: default_highlight(),
+            highlight_fg: default_highlight_fg(),
+            highlight_bg: default_highlight_bg(),
             border: default_border_color(),
+            prompt: default_prompt_color(),
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Colors::default().theme().expect("default colors are valid")
+    }
+}
+
+impl Colors {
+    /// Parse every color, failing on the first invalid one
+    pub fn theme(&self) -> Result<Theme> {
+        let parse = |name: &str, spec: &str| {
+            spec.parse::<Color>()
+                .map_err(|_| anyhow!("invalid color '{}' for '{}'", spec, name))
+        };
+
+        Ok(Theme {
+            fg: parse("fg", &self.fg)?,
+            bg: parse("bg", &self.bg)?,
+            highlight: parse("highlight", &self.highlight)?,
+            highlight_fg: parse("highlight_fg", &self.highlight_fg)?,
+            highlight_bg: parse("highlight_bg", &self.highlight_bg)?,
+            border: parse("border", &self.border)?,
+            prompt: parse("prompt", &self.prompt)?,
+        })
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            accept: default_accept_keys(),
+            up: default_up_keys(),
+            down: default_down_keys(),
+            clear: default_clear_keys(),
+            abort: default_abort_keys(),
+            left: default_left_keys(),
+            right: default_right_keys(),
+            home: default_home_keys(),
+            end: default_end_keys(),
+            delete_word: default_delete_word_keys(),
+            kill_line: default_kill_line_keys(),
+            history_prev: default_history_prev_keys(),
+            history_next: default_history_next_keys(),
+            accept_query: default_accept_query_keys(),
         }
     }
 }
 
+impl KeyBindings {
+    /// Build the key lookup table, failing on the first invalid key spec
+    pub fn keymap(&self) -> Result<Keymap> {
+        let mut keymap = Keymap::default();
+        let actions = [
+            ("accept", &self.accept, Action::Accept),
+            ("up", &self.up, Action::Up),
+            ("down", &self.down, Action::Down),
+            ("clear", &self.clear, Action::Clear),
+            ("abort", &self.abort, Action::Abort),
+            ("left", &self.left, Action::Left),
+            ("right", &self.right, Action::Right),
+            ("home", &self.home, Action::Home),
+            ("end", &self.end, Action::End),
+            ("delete_word", &self.delete_word, Action::DeleteWord),
+            ("kill_line", &self.kill_line, Action::KillLine),
+            ("history_prev", &self.history_prev, Action::HistoryPrev),
+            ("history_next", &self.history_next, Action::HistoryNext),
+            ("accept_query", &self.accept_query, Action::AcceptQuery),
+        ];
+
+        for (name, specs, action) in actions {
+            for spec in specs {
+                keymap
+                    .bind(spec, action)
+                    .with_context(|| format!("in key binding for '{}'", name))?;
+            }
+        }
+
+        Ok(keymap)
+    }
+}
+
 fn default_mode() -> String {
     "apps".to_string()
 }
@@ -88,37 +258,200 @@ fn default_border() -> bool {
 }
 
 fn default_fg() -> String {
-    "#eceff4".to_string()
+    "white".to_string()
 }
 
 fn default_bg() -> String {
-    "#2e3440".to_string()
+    "reset".to_string()
 }
 
 fn default_highlight() -> String {
-    "#88c0d0".to_string()
+    "cyan".to_string()
+}
+
+fn default_highlight_fg() -> String {
+    "white".to_string()
+}
+
+fn default_highlight_bg() -> String {
+    "darkgray".to_string()
 }
 
 fn default_border_color() -> String {
-    "#4c566a".to_string()
+    "darkgray".to_string()
+}
+
+fn default_prompt_color() -> String {
+    "cyan".to_string()
+}
+
+fn default_accept_keys() -> Vec<String> {
+    vec!["enter".to_string()]
+}
+
+fn default_up_keys() -> Vec<String> {
+    vec!["up".to_string()]
+}
+
+fn default_down_keys() -> Vec<String> {
+    vec!["down".to_string()]
+}
+
+fn default_clear_keys() -> Vec<String> {
+    vec!["ctrl-u".to_string()]
+}
+
+fn default_abort_keys() -> Vec<String> {
+    vec!["esc".to_string(), "ctrl-c".to_string()]
+}
+
+fn default_left_keys() -> Vec<String> {
+    vec!["left".to_string()]
+}
+
+fn default_right_keys() -> Vec<String> {
+    vec!["right".to_string()]
+}
+
+fn default_home_keys() -> Vec<String> {
+    vec!["home".to_string(), "ctrl-a".to_string()]
+}
+
+fn default_end_keys() -> Vec<String> {
+    vec!["end".to_string(), "ctrl-e".to_string()]
+}
+
+fn default_delete_word_keys() -> Vec<String> {
+    vec!["ctrl-w".to_string()]
+}
+
+fn default_kill_line_keys() -> Vec<String> {
+    vec!["ctrl-k".to_string()]
+}
+
+fn default_history_prev_keys() -> Vec<String> {
+    vec!["ctrl-p".to_string()]
+}
+
+fn default_history_next_keys() -> Vec<String> {
+    vec!["ctrl-n".to_string()]
+}
+
+/// Shift-enter only reaches us from terminals that report modifiers on
+/// enter, so alt-enter is bound too
+fn default_accept_query_keys() -> Vec<String> {
+    vec!["shift-enter".to_string(), "alt-enter".to_string()]
 }
 
 impl Config {
     /// Load configuration from file
-    pub fn load() -> Self {
+    ///
+    /// A missing file yields the defaults; a malformed one is an error so
+    /// that typos in action names or key specs don't go unnoticed.
+    pub fn load() -> Result<Self> {
         let config_path = dirs::config_dir()
             .map(|d| d.join("cvh-fuzzy/config.toml"));
 
         if let Some(path) = config_path {
             if path.exists() {
-                if let Ok(content) = std::fs::read_to_string(&path) {
-                    if let Ok(config) = toml::from_str(&content) {
-                        return config;
-                    }
-                }
+                let content = std::fs::read_to_string(&path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                let config: Self = toml::from_str(&content)
+                    .with_context(|| format!("failed to parse {}", path.display()))?;
+                config.keys.keymap()
+                    .with_context(|| format!("invalid key bindings in {}", path.display()))?;
+                config.colors.theme()
+                    .with_context(|| format!("invalid colors in {}", path.display()))?;
+                return Ok(config);
             }
         }
 
-        Self::default()
+        Ok(Self::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyModifiers};
+
+    #[test]
+    fn test_default_keymap_matches_builtin_bindings() {
+        let keymap = KeyBindings::default().keymap().unwrap();
+        assert_eq!(keymap.lookup(KeyCode::Enter, KeyModifiers::NONE), Some(Action::Accept));
+        assert_eq!(keymap.lookup(KeyCode::Enter, KeyModifiers::SHIFT), Some(Action::AcceptQuery));
+        assert_eq!(keymap.lookup(KeyCode::Down, KeyModifiers::NONE), Some(Action::Down));
+        assert_eq!(keymap.lookup(KeyCode::Char('n'), KeyModifiers::CONTROL), Some(Action::HistoryNext));
+        assert_eq!(keymap.lookup(KeyCode::Char('p'), KeyModifiers::CONTROL), Some(Action::HistoryPrev));
+        assert_eq!(keymap.lookup(KeyCode::Char('u'), KeyModifiers::CONTROL), Some(Action::Clear));
+        assert_eq!(keymap.lookup(KeyCode::Esc, KeyModifiers::NONE), Some(Action::Abort));
+    }
+
+    #[test]
+    fn test_partial_override_keeps_other_defaults() {
+        let config: Config = toml::from_str("[keys]\ndown = [\"ctrl-j\"]\n").unwrap();
+        let keymap = config.keys.keymap().unwrap();
+        assert_eq!(keymap.lookup(KeyCode::Char('j'), KeyModifiers::CONTROL), Some(Action::Down));
+        assert_eq!(keymap.lookup(KeyCode::Down, KeyModifiers::NONE), None);
+        assert_eq!(keymap.lookup(KeyCode::Enter, KeyModifiers::NONE), Some(Action::Accept));
+    }
+
+    #[test]
+    fn test_unknown_action_rejected() {
+        let result: Result<Config, _> = toml::from_str("[keys]\nacept = [\"enter\"]\n");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_invalid_key_spec_rejected() {
+        let config: Config = toml::from_str("[keys]\naccept = [\"ctrl-\"]\n").unwrap();
+        assert!(config.keys.keymap().is_err());
+    }
+
+    #[test]
+    fn test_default_theme_matches_builtin_colors() {
+        let theme = Theme::default();
+        assert_eq!(theme.fg, Color::White);
+        assert_eq!(theme.bg, Color::Reset);
+        assert_eq!(theme.highlight, Color::Cyan);
+        assert_eq!(theme.highlight_fg, Color::White);
+        assert_eq!(theme.highlight_bg, Color::DarkGray);
+        assert_eq!(theme.border, Color::DarkGray);
+        assert_eq!(theme.prompt, Color::Cyan);
+    }
+
+    #[test]
+    fn test_theme_parses_hex_names_and_indexes() {
+        let config: Config = toml::from_str(
+            "[colors]\nhighlight = \"#88c0d0\"\nhighlight_bg = \"#2E3440\"\nborder = \"lightblue\"\nprompt = \"208\"\n",
+        )
+        .unwrap();
+        let theme = config.colors.theme().unwrap();
+        assert_eq!(theme.highlight, Color::Rgb(0x88, 0xc0, 0xd0));
+        assert_eq!(theme.highlight_bg, Color::Rgb(0x2e, 0x34, 0x40));
+        assert_eq!(theme.border, Color::LightBlue);
+        assert_eq!(theme.prompt, Color::Indexed(208));
+        assert_eq!(theme.fg, Color::White, "unset colors keep their default");
+    }
+
+    #[test]
+    fn test_invalid_color_rejected() {
+        let config: Config = toml::from_str("[colors]\nborder = \"#12345\"\n").unwrap();
+        let err = config.colors.theme().unwrap_err();
+        assert!(err.to_string().contains("border"));
+
+        let result: Result<Config, _> = toml::from_str("[colors]\nselection = \"red\"\n");
+        assert!(result.is_err(), "unknown color keys are rejected");
+    }
+
+    #[test]
+    fn test_default_mode_and_height_from_config() {
+        let config: Config = toml::from_str("default_mode = \"files\"\nheight = 20\n").unwrap();
+        assert_eq!(config.default_mode, "files");
+        assert_eq!(config.height, 20);
+
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.default_mode, "apps");
+        assert_eq!(config.height, 40);
     }
 }
@@ -0,0 +1,146 @@
+//! Field selection for delimited input lines
+//!
+//! `--with-nth` and `--accept-nth` pick which fields of a stdin line are
+//! shown and which are returned, fzf style: `2`, `-1`, `2..`, `..3`,
+//! `1..3`, or several of them separated by commas.
+
+use std::str::FromStr;
+
+/// One field index or inclusive range; indices are 1-based and negative
+/// ones count from the end of the line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FieldRange {
+    start: Option<i64>,
+    end: Option<i64>,
+}
+
+/// Fields picked from a line, in the order given
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSpec {
+    ranges: Vec<FieldRange>,
+}
+
+impl FromStr for FieldSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ranges = s.split(',').map(parse_range).collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { ranges })
+    }
+}
+
+fn parse_range(s: &str) -> Result<FieldRange, String> {
+    let index = |part: &str| -> Result<Option<i64>, String> {
+        if part.is_empty() {
+            return Ok(None);
+        }
+        match part.parse::<i64>() {
+            Ok(0) => Err("field indices start at 1".to_string()),
+            Ok(n) => Ok(Some(n)),
+            Err(_) => Err(format!("invalid field index '{}'", part)),
+        }
+    };
+
+    let s = s.trim();
+    match s.split_once("..") {
+        Some((start, end)) => Ok(FieldRange { start: index(start)?, end: index(end)? }),
+        None if s.is_empty() => Err("empty field".to_string()),
+        None => {
+            let n = index(s)?;
+            Ok(FieldRange { start: n, end: n })
+        }
+    }
+}
+
+/// Zero-based position of a 1-based/negative index among `len` fields
+fn position(index: i64, len: usize) -> Option<usize> {
+    if index > 0 {
+        Some(index as usize - 1)
+    } else {
+        len.checked_sub(index.unsigned_abs() as usize)
+    }
+}
+
+impl FieldSpec {
+    /// The selected fields of `line`, rejoined with `delimiter`
+    ///
+    /// Fields the line doesn't have are left out.
+    pub fn select(&self, line: &str, delimiter: &str) -> String {
+        let fields: Vec<&str> = line.split(delimiter).collect();
+        let len = fields.len();
+
+        let mut picked: Vec<&str> = Vec::new();
+        for range in &self.ranges {
+            let start = match range.start {
+                Some(index) => position(index, len).unwrap_or(0),
+                None => 0,
+            };
+            let end = match range.end {
+                Some(index) => match position(index, len) {
+                    Some(end) => end,
+                    None => continue,
+                },
+                None => len.saturating_sub(1),
+            };
+            if start < len && start <= end {
+                picked.extend(&fields[start..=end.min(len - 1)]);
+            }
+        }
+
+        picked.join(delimiter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn select(spec: &str, line: &str) -> String {
+        spec.parse::<FieldSpec>().unwrap().select(line, "\t")
+    }
+
+    #[test]
+    fn test_single_fields() {
+        assert_eq!(select("1", "firefox\tFirefox Web Browser"), "firefox");
+        assert_eq!(select("2", "firefox\tFirefox Web Browser"), "Firefox Web Browser");
+        assert_eq!(select("-1", "a\tb\tc"), "c");
+        assert_eq!(select("-3", "a\tb\tc"), "a");
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(select("2..", "a\tb\tc"), "b\tc");
+        assert_eq!(select("..2", "a\tb\tc"), "a\tb");
+        assert_eq!(select("1..2", "a\tb\tc"), "a\tb");
+        assert_eq!(select("..", "a\tb\tc"), "a\tb\tc");
+        assert_eq!(select("-2..", "a\tb\tc"), "b\tc");
+    }
+
+    #[test]
+    fn test_lists_keep_order() {
+        assert_eq!(select("3,1", "a\tb\tc"), "c\ta");
+    }
+
+    #[test]
+    fn test_missing_fields_are_skipped() {
+        assert_eq!(select("4", "a\tb\tc"), "");
+        assert_eq!(select("-4", "a\tb\tc"), "");
+        assert_eq!(select("2..5", "a\tb\tc"), "b\tc");
+        assert_eq!(select("2", "only"), "");
+    }
+
+    #[test]
+    fn test_other_delimiters() {
+        let spec: FieldSpec = "2".parse().unwrap();
+        assert_eq!(spec.select("id: pretty name", ": "), "pretty name");
+        assert_eq!(spec.select("1,2,3", ","), "2");
+    }
+
+    #[test]
+    fn test_invalid_specs() {
+        assert!("0".parse::<FieldSpec>().is_err());
+        assert!("x".parse::<FieldSpec>().is_err());
+        assert!("1,".parse::<FieldSpec>().is_err());
+        assert!("1..b".parse::<FieldSpec>().is_err());
+    }
+}
@@ -0,0 +1,245 @@
+//! File system traversal
+//!
+//! Walks a search root for the Files/Dirs modes, honoring ignore files
+
+use ignore::WalkBuilder;
+use std::{
+    collections::HashSet,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+/// Options controlling a file system walk
+#[derive(Debug, Clone, Copy)]
+pub struct WalkOptions {
+    /// Collect directories instead of files
+    pub dirs: bool,
+    /// Honor .gitignore/.ignore files and skip hidden entries
+    pub respect_ignore: bool,
+    /// Maximum number of entries collected (applied after filtering)
+    pub limit: usize,
+    /// How deep to descend below the root, 1 being its direct children
+    /// (unlimited if None; 0 counts as 1)
+    pub max_depth: Option<usize>,
+}
+
+/// Walk `base`, passing each matching path (excluding `base` itself) to
+/// `visit` until it returns false or `opts.limit` paths were visited
+pub fn walk_each(base: &Path, opts: WalkOptions, mut visit: impl FnMut(PathBuf) -> bool) {
+    // Directories already descended into, keyed by the (device, inode) of
+    // their target, so symlink cycles and aliased directories are walked once
+    let visited = Arc::new(Mutex::new(HashSet::new()));
+    if let Some(key) = dir_key(base) {
+        visited.lock().unwrap().insert(key);
+    }
+
+    // The root is never listed, so depth 0 would find nothing at all
+    let max_depth = opts.max_depth.map(|max| max.max(1));
+    let entries = WalkBuilder::new(base)
+        .standard_filters(opts.respect_ignore)
+        // Honor .gitignore even when the root isn't inside a git checkout
+        .require_git(false)
+        .follow_links(true)
+        .max_depth(max_depth)
+        .filter_entry(move |e| {
+            if e.depth() == 0 || !e.file_type().is_some_and(|t| t.is_dir()) {
+                return true;
+            }
+            // Not descended into, so an alias found higher up still is
+            if max_depth.is_some_and(|max| e.depth() >= max) {
+                return true;
+            }
+            dir_key(e.path()).is_none_or(|key| visited.lock().unwrap().insert(key))
+        })
+        .build()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.depth() > 0)
+        .filter(|e| {
+            e.file_type().is_some_and(|t| if opts.dirs { t.is_dir() } else { t.is_file() })
+        })
+        .take(opts.limit);
+
+    for entry in entries {
+        if !visit(entry.into_path()) {
+            break;
+        }
+    }
+}
+
+/// Walk each of `roots` in turn, sharing `opts.limit` between them
+///
+/// `visit` gets the index of the root a path was found under.
+pub fn walk_roots(roots: &[PathBuf], opts: WalkOptions, mut visit: impl FnMut(usize, PathBuf) -> bool) {
+    let mut remaining = opts.limit;
+    for (index, root) in roots.iter().enumerate() {
+        if remaining == 0 {
+            return;
+        }
+        let mut stopped = false;
+        walk_each(root, WalkOptions { limit: remaining, ..opts }, |path| {
+            remaining -= 1;
+            stopped = !visit(index, path);
+            !stopped
+        });
+        if stopped {
+            return;
+        }
+    }
+}
+
+/// Identity of the directory a path resolves to
+fn dir_key(path: &Path) -> Option<(u64, u64)> {
+    fs::metadata(path).ok().map(|m| (m.dev(), m.ino()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{os::unix::fs::symlink, time::{Duration, Instant}};
+
+    fn walk(base: &Path, opts: WalkOptions) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        walk_each(base, opts, |path| {
+            paths.push(path);
+            true
+        });
+        paths
+    }
+
+    /// Create a small tree with a .gitignore and hidden entries
+    fn fixture(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("cvh-fuzzy-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("target/debug")).unwrap();
+        fs::create_dir_all(root.join(".hidden")).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n*.log\n").unwrap();
+        fs::write(root.join("src/main.rs"), "").unwrap();
+        fs::write(root.join("notes.txt"), "").unwrap();
+        fs::write(root.join("debug.log"), "").unwrap();
+        fs::write(root.join("target/debug/app"), "").unwrap();
+        fs::write(root.join(".hidden/secret"), "").unwrap();
+
+        root
+    }
+
+    fn relative(root: &Path, paths: Vec<PathBuf>) -> Vec<String> {
+        let mut out: Vec<String> = paths
+            .iter()
+            .map(|p| p.strip_prefix(root).unwrap().display().to_string())
+            .collect();
+        out.sort();
+        out
+    }
+
+    #[test]
+    fn test_respects_gitignore() {
+        let root = fixture("ignore");
+        let opts = WalkOptions { dirs: false, respect_ignore: true, limit: 100, max_depth: None };
+        assert_eq!(relative(&root, walk(&root, opts)), vec!["notes.txt", "src/main.rs"]);
+
+        let opts = WalkOptions { dirs: true, ..opts };
+        assert_eq!(relative(&root, walk(&root, opts)), vec!["src"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_no_ignore_includes_everything() {
+        let root = fixture("no-ignore");
+        let opts = WalkOptions { dirs: false, respect_ignore: false, limit: 100, max_depth: None };
+        let found = relative(&root, walk(&root, opts));
+        assert!(found.contains(&"debug.log".to_string()));
+        assert!(found.contains(&"target/debug/app".to_string()));
+        assert!(found.contains(&".hidden/secret".to_string()));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_limit_applies_after_filtering() {
+        let root = fixture("limit");
+        let opts = WalkOptions { dirs: false, respect_ignore: true, limit: 1, max_depth: None };
+        assert_eq!(walk(&root, opts).len(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_max_depth_limits_descent() {
+        let root = fixture("depth");
+        fs::create_dir_all(root.join("src/bin/tools")).unwrap();
+        fs::write(root.join("src/bin/cli.rs"), "").unwrap();
+        fs::write(root.join("src/bin/tools/gen.rs"), "").unwrap();
+        let opts = WalkOptions { dirs: false, respect_ignore: true, limit: 100, max_depth: Some(1) };
+
+        assert_eq!(relative(&root, walk(&root, opts)), vec!["notes.txt"]);
+        assert_eq!(relative(&root, walk(&root, WalkOptions { dirs: true, ..opts })), vec!["src"]);
+        assert_eq!(
+            relative(&root, walk(&root, WalkOptions { max_depth: Some(3), ..opts })),
+            vec!["notes.txt", "src/bin/cli.rs", "src/main.rs"]
+        );
+        assert_eq!(relative(&root, walk(&root, WalkOptions { max_depth: Some(0), ..opts })), vec!["notes.txt"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_walk_roots_shares_limit() {
+        let first = fixture("roots-a");
+        let second = fixture("roots-b");
+        let roots = vec![first.clone(), second.clone()];
+        let opts = WalkOptions { dirs: false, respect_ignore: true, limit: 100, max_depth: None };
+
+        let mut found = Vec::new();
+        walk_roots(&roots, opts, |index, path| {
+            found.push((index, relative(&roots[index], vec![path]).remove(0)));
+            true
+        });
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                (0, "notes.txt".to_string()),
+                (0, "src/main.rs".to_string()),
+                (1, "notes.txt".to_string()),
+                (1, "src/main.rs".to_string()),
+            ]
+        );
+
+        let mut count = 0;
+        walk_roots(&roots, WalkOptions { limit: 3, ..opts }, |_, _| {
+            count += 1;
+            true
+        });
+        assert_eq!(count, 3);
+
+        fs::remove_dir_all(&first).unwrap();
+        fs::remove_dir_all(&second).unwrap();
+    }
+
+    #[test]
+    fn test_symlink_cycle_terminates_without_duplicates() {
+        let root = std::env::temp_dir().join(format!("cvh-fuzzy-cycle-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("top.txt"), "").unwrap();
+        fs::write(root.join("a/file.txt"), "").unwrap();
+        symlink("..", root.join("a/up")).unwrap();
+        symlink("a", root.join("alias")).unwrap();
+
+        let start = Instant::now();
+        let opts = WalkOptions { dirs: false, respect_ignore: false, limit: 10_000, max_depth: None };
+        let found = relative(&root, walk(&root, opts));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        assert_eq!(found.len(), 2, "unexpected entries: {:?}", found);
+        assert!(found.contains(&"top.txt".to_string()));
+        assert_eq!(found.iter().filter(|p| p.ends_with("file.txt")).count(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//! File type glyphs
+//!
+//! Sorts walked paths into the categories cvh-icons uses for desktop icons
+//! (see `IconType::determine_type` there) and gives each a Nerd Font glyph
+
+use std::path::Path;
+
+/// Kind of a file system entry, as far as the list shows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Folder,
+    Symlink,
+    Executable,
+    Image,
+    Document,
+    Archive,
+    Video,
+    Audio,
+}
+
+impl FileKind {
+    /// Classify `path`, looking at the file system for links, directories
+    /// and extensionless executables
+    pub fn of(path: &Path) -> Self {
+        if path.is_symlink() {
+            return Self::Symlink;
+        }
+
+        if path.is_dir() {
+            return Self::Folder;
+        }
+
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => Self::from_extension(ext),
+            None if is_executable(path) => Self::Executable,
+            None => Self::File,
+        }
+    }
+
+    /// Classify a file by its extension alone
+    ///
+    /// The table is a copy of the one in cvh-icons'
+    /// `IconType::determine_type` (src/cvh-icons/src/icons/mod.rs); the
+    /// crates share no code, so change both together.
+    pub fn from_extension(ext: &str) -> Self {
+        match ext.to_lowercase().as_str() {
+            "sh" | "bash" | "zsh" | "fish" | "py" | "rb" | "pl" => Self::Executable,
+            "png" | "jpg" | "jpeg" | "gif" | "bmp" | "svg" | "webp" | "ico" => Self::Image,
+            "pdf" | "doc" | "docx" | "odt" | "txt" | "md" | "rst" => Self::Document,
+            "zip" | "tar" | "gz" | "bz2" | "xz" | "7z" | "rar" | "zst" => Self::Archive,
+            "mp4" | "mkv" | "avi" | "mov" | "webm" | "flv" => Self::Video,
+            "mp3" | "flac" | "wav" | "ogg" | "m4a" | "opus" => Self::Audio,
+            _ => Self::File,
+        }
+    }
+
+    /// Nerd Font glyph shown before the path
+    pub fn glyph(self) -> &'static str {
+        match self {
+            Self::File => "\u{f15b}",
+            Self::Folder => "\u{f07b}",
+            Self::Symlink => "\u{f481}",
+            Self::Executable => "\u{f489}",
+            Self::Image => "\u{f1c5}",
+            Self::Document => "\u{f15c}",
+            Self::Archive => "\u{f1c6}",
+            Self::Video => "\u{f1c8}",
+            Self::Audio => "\u{f1c7}",
+        }
+    }
+}
+
+/// Whether any execute bit is set on `path`
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{collections::HashSet, fs};
+
+    #[test]
+    fn test_extensions_map_to_categories() {
+        let cases = [
+            ("png", FileKind::Image),
+            ("JPG", FileKind::Image),
+            ("svg", FileKind::Image),
+            ("mp3", FileKind::Audio),
+            ("opus", FileKind::Audio),
+            ("mkv", FileKind::Video),
+            ("tar", FileKind::Archive),
+            ("zst", FileKind::Archive),
+            ("pdf", FileKind::Document),
+            ("md", FileKind::Document),
+            ("sh", FileKind::Executable),
+            ("rs", FileKind::File),
+            ("", FileKind::File),
+        ];
+        for (ext, kind) in cases {
+            assert_eq!(FileKind::from_extension(ext), kind, "extension {:?}", ext);
+        }
+    }
+
+    #[test]
+    fn test_glyphs_are_distinct() {
+        let kinds = [
+            FileKind::File,
+            FileKind::Folder,
+            FileKind::Symlink,
+            FileKind::Executable,
+            FileKind::Image,
+            FileKind::Document,
+            FileKind::Archive,
+            FileKind::Video,
+            FileKind::Audio,
+        ];
+        let glyphs: HashSet<&str> = kinds.iter().map(|kind| kind.glyph()).collect();
+        assert_eq!(glyphs.len(), kinds.len());
+    }
+
+    #[test]
+    fn test_kind_of_paths_on_disk() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let root = std::env::temp_dir().join(format!("cvh-fuzzy-filetype-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("photos")).unwrap();
+        fs::write(root.join("cover.webp"), "").unwrap();
+        fs::write(root.join("README"), "").unwrap();
+        fs::write(root.join("run"), "").unwrap();
+        fs::set_permissions(root.join("run"), fs::Permissions::from_mode(0o755)).unwrap();
+        symlink("cover.webp", root.join("link.webp")).unwrap();
+
+        assert_eq!(FileKind::of(&root.join("photos")), FileKind::Folder);
+        assert_eq!(FileKind::of(&root.join("cover.webp")), FileKind::Image);
+        assert_eq!(FileKind::of(&root.join("README")), FileKind::File);
+        assert_eq!(FileKind::of(&root.join("run")), FileKind::Executable);
+        assert_eq!(FileKind::of(&root.join("link.webp")), FileKind::Symlink);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
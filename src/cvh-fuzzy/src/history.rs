@@ -0,0 +1,272 @@
+//! Shell history loading
+//!
+//! Reads zsh, bash and fish history files into searchable items
+
+use clap::ValueEnum;
+use std::{collections::HashMap, env, fs, path::PathBuf};
+
+use crate::Item;
+
+/// Maximum number of history entries loaded
+const MAX_HISTORY: usize = 1000;
+
+/// Shells whose history can be searched
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Zsh,
+    Bash,
+    Fish,
+}
+
+impl Shell {
+    /// Detect the user's shell from `$SHELL`, defaulting to zsh
+    pub fn detect() -> Self {
+        env::var("SHELL")
+            .ok()
+            .and_then(|path| Self::from_path(&path))
+            .unwrap_or(Shell::Zsh)
+    }
+
+    fn from_path(path: &str) -> Option<Self> {
+        match path.rsplit('/').next()? {
+            "zsh" => Some(Shell::Zsh),
+            "bash" => Some(Shell::Bash),
+            "fish" => Some(Shell::Fish),
+            _ => None,
+        }
+    }
+
+    /// Location of this shell's history file
+    fn history_file(self) -> Option<PathBuf> {
+        match self {
+            Shell::Zsh => dirs::home_dir().map(|h| h.join(".zsh_history")),
+            Shell::Bash => dirs::home_dir().map(|h| h.join(".bash_history")),
+            Shell::Fish => dirs::data_dir().map(|d| d.join("fish/fish_history")),
+        }
+    }
+
+    /// Extract commands from history file content, oldest first
+    fn parse(self, content: &str) -> Vec<String> {
+        match self {
+            Shell::Zsh => parse_zsh(content),
+            Shell::Bash => parse_bash(content),
+            Shell::Fish => parse_fish(content),
+        }
+    }
+}
+
+/// Ordering of history entries
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum HistorySort {
+    /// Newest first, collapsing consecutive duplicates
+    #[default]
+    Recency,
+    /// Most frequently run first, with recency as a tiebreak
+    Frequency,
+}
+
+/// Load the history of `shell` as items in the requested order
+pub fn load_history(shell: Shell, sort: HistorySort) -> Vec<Item> {
+    let Some(path) = shell.history_file() else {
+        return Vec::new();
+    };
+
+    // History files aren't guaranteed to be valid UTF-8 (zsh metafies bytes)
+    let content = match fs::read(&path) {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(_) => return Vec::new(),
+    };
+
+    let commands = shell.parse(&content);
+    match sort {
+        HistorySort::Recency => to_items(commands),
+        HistorySort::Frequency => to_ranked_items(commands),
+    }
+}
+
+/// Turn oldest-first commands into newest-first items, collapsing
+/// consecutive duplicates
+fn to_items(commands: Vec<String>) -> Vec<Item> {
+    let mut items: Vec<Item> = Vec::new();
+
+    for cmd in commands.into_iter().rev() {
+        if cmd.is_empty() || items.last().is_some_and(|last| last.value == cmd) {
+            continue;
+        }
+        items.push(Item {
+            display: cmd.clone(),
+            value: cmd,
+            icon: None,
+            id: None,
+        });
+        if items.len() >= MAX_HISTORY {
+            break;
+        }
+    }
+
+    items
+}
+
+/// Collapse identical commands and order them by run count, most recently
+/// run first among equals. The count is shown in the icon column.
+fn to_ranked_items(commands: Vec<String>) -> Vec<Item> {
+    // command -> (count, position of last occurrence)
+    let mut stats: HashMap<String, (usize, usize)> = HashMap::new();
+    for (pos, cmd) in commands.into_iter().enumerate() {
+        if cmd.is_empty() {
+            continue;
+        }
+        let entry = stats.entry(cmd).or_insert((0, pos));
+        entry.0 += 1;
+        entry.1 = pos;
+    }
+
+    let mut ranked: Vec<(String, (usize, usize))> = stats.into_iter().collect();
+    ranked.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+    ranked
+        .into_iter()
+        .take(MAX_HISTORY)
+        .map(|(cmd, (count, _))| Item {
+            display: cmd.clone(),
+            value: cmd,
+            icon: Some(format!("{:>4}", count)),
+            id: None,
+        })
+        .collect()
+}
+
+/// Zsh history: plain lines or extended `: timestamp:duration;command`
+fn parse_zsh(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .map(|line| {
+            if line.starts_with(':') {
+                line.split_once(';').map_or(line, |(_, cmd)| cmd)
+            } else {
+                line
+            }
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Bash history: plain lines, with `#timestamp` lines when HISTTIMEFORMAT is set
+fn parse_bash(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter(|line| {
+            !line
+                .strip_prefix('#')
+                .is_some_and(|ts| !ts.is_empty() && ts.bytes().all(|b| b.is_ascii_digit()))
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Fish history: YAML-ish `- cmd: ...` entries followed by `when:`/`paths:`
+fn parse_fish(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| line.strip_prefix("- cmd: "))
+        .map(unescape_fish)
+        .collect()
+}
+
+/// Undo fish's escaping of backslashes and newlines in stored commands
+fn unescape_fish(cmd: &str) -> String {
+    let mut out = String::with_capacity(cmd.len());
+    let mut chars = cmd.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values(items: Vec<Item>) -> Vec<String> {
+        items.into_iter().map(|i| i.value).collect()
+    }
+
+    #[test]
+    fn test_parse_zsh() {
+        let content = ": 1700000000:0;ls -la\n: 1700000001:0;echo a; echo b\ngit status\n";
+        assert_eq!(parse_zsh(content), vec!["ls -la", "echo a; echo b", "git status"]);
+    }
+
+    #[test]
+    fn test_parse_bash() {
+        let content = "#1700000000\nls -la\ncargo build\n# a comment\n";
+        assert_eq!(parse_bash(content), vec!["ls -la", "cargo build", "# a comment"]);
+    }
+
+    #[test]
+    fn test_parse_fish() {
+        let content = "- cmd: ls -la\n  when: 1700000000\n- cmd: echo a\\\\nb\n  when: 1700000001\n  paths:\n    - foo\n- cmd: printf 'x\\ny'\n  when: 1700000002\n";
+        assert_eq!(
+            parse_fish(content),
+            vec!["ls -la", "echo a\\nb", "printf 'x\ny'"]
+        );
+    }
+
+    #[test]
+    fn test_to_items_newest_first_and_deduped() {
+        let commands = ["ls", "make", "make", "ls", "", "git"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(values(to_items(commands)), vec!["git", "ls", "make", "ls"]);
+    }
+
+    #[test]
+    fn test_to_items_capped() {
+        let commands = (0..MAX_HISTORY + 50).map(|i| format!("cmd {}", i)).collect();
+        let items = to_items(commands);
+        assert_eq!(items.len(), MAX_HISTORY);
+        assert_eq!(items[0].value, format!("cmd {}", MAX_HISTORY + 49));
+    }
+
+    #[test]
+    fn test_frequency_ranks_common_commands_first() {
+        let commands = ["make", "ls", "make", "make", "git", "make", "make"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let items = to_ranked_items(commands);
+        assert_eq!(values(items.clone()), vec!["make", "git", "ls"]);
+        assert_eq!(items[0].icon.as_deref(), Some("   5"));
+    }
+
+    #[test]
+    fn test_frequency_ties_broken_by_recency() {
+        let commands = ["a", "b", "c", "b", "a"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(values(to_ranked_items(commands)), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_shell_from_path() {
+        assert_eq!(Shell::from_path("/usr/bin/fish"), Some(Shell::Fish));
+        assert_eq!(Shell::from_path("/bin/bash"), Some(Shell::Bash));
+        assert_eq!(Shell::from_path("/bin/tcsh"), None);
+    }
+}
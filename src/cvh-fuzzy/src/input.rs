@@ -0,0 +1,174 @@
+//! Query input buffer
+//!
+//! Editable text with a cursor, independent of the terminal
+
+/// Query text and cursor position
+///
+/// The cursor is a byte offset into `text` and always sits on a char
+/// boundary.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Input {
+    text: String,
+    cursor: usize,
+}
+
+impl Input {
+    /// Current text
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Byte offset of the cursor
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Replace the text, moving the cursor to the end
+    pub fn set(&mut self, text: impl Into<String>) {
+        self.text = text.into();
+        self.cursor = self.text.len();
+    }
+
+    /// Remove all text
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    /// Insert a char at the cursor
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    /// Delete the char before the cursor
+    pub fn backspace(&mut self) {
+        if let Some(start) = self.prev_boundary() {
+            self.text.drain(start..self.cursor);
+            self.cursor = start;
+        }
+    }
+
+    /// Move the cursor one char left
+    pub fn move_left(&mut self) {
+        if let Some(start) = self.prev_boundary() {
+            self.cursor = start;
+        }
+    }
+
+    /// Move the cursor one char right
+    pub fn move_right(&mut self) {
+        if let Some(c) = self.text[self.cursor..].chars().next() {
+            self.cursor += c.len_utf8();
+        }
+    }
+
+    /// Move the cursor to the start
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    /// Move the cursor to the end
+    pub fn end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Delete the word before the cursor, along with any whitespace
+    /// between it and the cursor
+    pub fn delete_word(&mut self) {
+        let before = &self.text[..self.cursor];
+        let trimmed = before.trim_end();
+        let start = trimmed
+            .rfind(char::is_whitespace)
+            .map_or(0, |i| i + trimmed[i..].chars().next().map_or(1, char::len_utf8));
+
+        self.text.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    /// Delete everything from the cursor to the end
+    pub fn kill_to_end(&mut self) {
+        self.text.truncate(self.cursor);
+    }
+
+    /// Byte offset of the char boundary before the cursor
+    fn prev_boundary(&self) -> Option<usize> {
+        self.text[..self.cursor]
+            .char_indices()
+            .next_back()
+            .map(|(i, _)| i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input(text: &str, cursor: usize) -> Input {
+        Input {
+            text: text.to_string(),
+            cursor,
+        }
+    }
+
+    #[test]
+    fn test_insert_in_middle() {
+        let mut buf = input("fire", 2);
+        buf.insert('X');
+        assert_eq!(buf, input("fiXre", 3));
+    }
+
+    #[test]
+    fn test_backspace_and_movement() {
+        let mut buf = input("firefox", 7);
+        buf.move_left();
+        buf.move_left();
+        buf.backspace();
+        assert_eq!(buf, input("fireox", 4));
+
+        buf.home();
+        buf.backspace();
+        assert_eq!(buf.cursor(), 0);
+        buf.move_left();
+        assert_eq!(buf.cursor(), 0);
+
+        buf.end();
+        buf.move_right();
+        assert_eq!(buf.cursor(), 6);
+    }
+
+    #[test]
+    fn test_multibyte_cursor() {
+        let mut buf = Input::default();
+        buf.set("héllo");
+        buf.move_left();
+        buf.move_left();
+        buf.move_left();
+        buf.move_left();
+        assert_eq!(buf.cursor(), 1);
+        buf.move_right();
+        assert_eq!(buf.cursor(), 3);
+        buf.backspace();
+        assert_eq!(buf, input("hllo", 1));
+    }
+
+    #[test]
+    fn test_delete_word() {
+        let mut buf = input("src main  ", 10);
+        buf.delete_word();
+        assert_eq!(buf, input("src ", 4));
+        buf.delete_word();
+        assert_eq!(buf, input("", 0));
+
+        let mut buf = input("one two three", 7);
+        buf.delete_word();
+        assert_eq!(buf, input("one  three", 4));
+    }
+
+    #[test]
+    fn test_kill_to_end() {
+        let mut buf = input("firefox", 4);
+        buf.kill_to_end();
+        assert_eq!(buf, input("fire", 4));
+    }
+}
@@ -0,0 +1,177 @@
+//! Key binding support
+//!
+//! Parses key specs like "ctrl-j" and maps key presses to actions
+
+use anyhow::{bail, Result};
+use crossterm::event::{KeyCode, KeyModifiers};
+use std::collections::HashMap;
+
+/// An action that can be bound to a key
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    /// Confirm the highlighted item
+    Accept,
+    /// Move the selection up
+    Up,
+    /// Move the selection down
+    Down,
+    /// Clear the query
+    Clear,
+    /// Quit without a selection
+    Abort,
+    /// Move the query cursor left
+    Left,
+    /// Move the query cursor right
+    Right,
+    /// Move the query cursor to the start
+    Home,
+    /// Move the query cursor to the end
+    End,
+    /// Delete the word before the query cursor
+    DeleteWord,
+    /// Delete from the query cursor to the end
+    KillLine,
+    /// Recall the previous query at an empty prompt, otherwise `Up`
+    HistoryPrev,
+    /// Recall the next query at an empty prompt, otherwise `Down`
+    HistoryNext,
+    /// Finish with the typed query instead of the highlighted item
+    AcceptQuery,
+}
+
+/// Lookup table from key presses to actions
+#[derive(Debug, Clone, Default)]
+pub struct Keymap {
+    bindings: HashMap<(KeyCode, KeyModifiers), Action>,
+}
+
+impl Keymap {
+    /// Bind a key spec to an action
+    pub fn bind(&mut self, spec: &str, action: Action) -> Result<()> {
+        let key = parse_key_spec(spec)?;
+        self.bindings.insert(key, action);
+        Ok(())
+    }
+
+    /// Find the action bound to a key press
+    ///
+    /// Non-character keys fall back to their unmodified binding, so e.g.
+    /// shift-enter still accepts when only "enter" is bound.
+    pub fn lookup(&self, code: KeyCode, modifiers: KeyModifiers) -> Option<Action> {
+        if let Some(&action) = self.bindings.get(&(code, modifiers)) {
+            return Some(action);
+        }
+
+        match code {
+            KeyCode::Char(_) => None,
+            _ => self.bindings.get(&(code, KeyModifiers::NONE)).copied(),
+        }
+    }
+}
+
+/// Parse a key spec such as "ctrl-j", "alt-k", "shift-tab" or "enter"
+pub fn parse_key_spec(spec: &str) -> Result<(KeyCode, KeyModifiers)> {
+    let mut modifiers = KeyModifiers::NONE;
+    let mut rest = spec.trim();
+
+    loop {
+        let lower = rest.to_ascii_lowercase();
+        let (modifier, len) = if lower.starts_with("ctrl-") {
+            (KeyModifiers::CONTROL, 5)
+        } else if lower.starts_with("alt-") {
+            (KeyModifiers::ALT, 4)
+        } else if lower.starts_with("shift-") {
+            (KeyModifiers::SHIFT, 6)
+        } else {
+            break;
+        };
+        modifiers |= modifier;
+        rest = &rest[len..];
+    }
+
+    let code = match rest.to_ascii_lowercase().as_str() {
+        "enter" | "return" => KeyCode::Enter,
+        "esc" | "escape" => KeyCode::Esc,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "backspace" | "bs" => KeyCode::Backspace,
+        "delete" | "del" => KeyCode::Delete,
+        "insert" => KeyCode::Insert,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" | "pgup" => KeyCode::PageUp,
+        "pagedown" | "pgdn" => KeyCode::PageDown,
+        "space" => KeyCode::Char(' '),
+        name => {
+            let mut chars = rest.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => {
+                    // Terminals report uppercase letters with shift held
+                    if c.is_ascii_uppercase() {
+                        modifiers |= KeyModifiers::SHIFT;
+                    }
+                    KeyCode::Char(c)
+                }
+                _ => match name.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => bail!("invalid key spec '{}'", spec),
+                },
+            }
+        }
+    };
+
+    Ok((code, modifiers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_modifiers() {
+        assert_eq!(
+            parse_key_spec("ctrl-j").unwrap(),
+            (KeyCode::Char('j'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            parse_key_spec("ctrl-alt-k").unwrap(),
+            (KeyCode::Char('k'), KeyModifiers::CONTROL | KeyModifiers::ALT)
+        );
+        assert_eq!(
+            parse_key_spec("ctrl--").unwrap(),
+            (KeyCode::Char('-'), KeyModifiers::CONTROL)
+        );
+    }
+
+    #[test]
+    fn test_parse_named_keys() {
+        assert_eq!(parse_key_spec("enter").unwrap(), (KeyCode::Enter, KeyModifiers::NONE));
+        assert_eq!(parse_key_spec("f5").unwrap(), (KeyCode::F(5), KeyModifiers::NONE));
+        assert_eq!(
+            parse_key_spec("shift-tab").unwrap(),
+            (KeyCode::Tab, KeyModifiers::SHIFT)
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(parse_key_spec("ctrl-").is_err());
+        assert!(parse_key_spec("hyper-x").is_err());
+        assert!(parse_key_spec("f13").is_err());
+    }
+
+    #[test]
+    fn test_lookup_fallback() {
+        let mut keymap = Keymap::default();
+        keymap.bind("esc", Action::Abort).unwrap();
+        keymap.bind("ctrl-c", Action::Abort).unwrap();
+
+        assert_eq!(keymap.lookup(KeyCode::Esc, KeyModifiers::SHIFT), Some(Action::Abort));
+        assert_eq!(keymap.lookup(KeyCode::Char('c'), KeyModifiers::CONTROL), Some(Action::Abort));
+        assert_eq!(keymap.lookup(KeyCode::Char('c'), KeyModifiers::NONE), None);
+    }
+}
@@ -0,0 +1,136 @@
+//! Background item loading
+//!
+//! Runs slow item sources on a worker thread and streams results to the UI
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::Item;
+
+/// Maximum number of items taken from the channel per drain, so a fast
+/// producer can't stall the event loop
+const MAX_DRAIN: usize = 4096;
+
+/// Handle given to the worker for sending items
+pub struct Sink {
+    tx: Sender<Item>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl Sink {
+    /// Send an item, returning false once the loader was cancelled or dropped
+    pub fn send(&self, item: Item) -> bool {
+        !self.cancel.load(Ordering::Relaxed) && self.tx.send(item).is_ok()
+    }
+}
+
+/// A running (or finished) background load
+pub struct Loader {
+    rx: Receiver<Item>,
+    cancel: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+    finished: bool,
+}
+
+impl Loader {
+    /// Start `job` on a worker thread
+    pub fn spawn(job: impl FnOnce(Sink) + Send + 'static) -> Self {
+        let (tx, rx) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let sink = Sink {
+            tx,
+            cancel: Arc::clone(&cancel),
+        };
+        let handle = thread::spawn(move || job(sink));
+
+        Self {
+            rx,
+            cancel,
+            handle: Some(handle),
+            finished: false,
+        }
+    }
+
+    /// Take the items received so far
+    pub fn drain(&mut self) -> Vec<Item> {
+        let mut items = Vec::new();
+        while items.len() < MAX_DRAIN {
+            match self.rx.try_recv() {
+                Ok(item) => items.push(item),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.finished = true;
+                    break;
+                }
+            }
+        }
+        items
+    }
+
+    /// Whether the worker may still produce items
+    pub fn is_loading(&self) -> bool {
+        !self.finished
+    }
+}
+
+impl Drop for Loader {
+    /// Stop the worker and wait for it, so quitting mid-load is clean
+    fn drop(&mut self) {
+        self.cancel.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn item(n: usize) -> Item {
+        Item {
+            display: n.to_string(),
+            value: n.to_string(),
+            icon: None,
+            id: None,
+        }
+    }
+
+    #[test]
+    fn test_streams_all_items() {
+        let mut loader = Loader::spawn(|sink| {
+            for n in 0..10_000 {
+                sink.send(item(n));
+            }
+        });
+
+        let mut received = Vec::new();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while loader.is_loading() && Instant::now() < deadline {
+            received.extend(loader.drain());
+        }
+
+        assert!(!loader.is_loading());
+        assert_eq!(received.len(), 10_000);
+        assert_eq!(received[9_999].display, "9999");
+    }
+
+    #[test]
+    fn test_drop_stops_worker() {
+        let loader = Loader::spawn(|sink| {
+            // Would run forever if cancellation didn't stop it
+            let mut n = 0;
+            while sink.send(item(n)) {
+                n += 1;
+            }
+        });
+        drop(loader);
+    }
+}
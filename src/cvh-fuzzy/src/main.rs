@@ -8,33 +8,61 @@
 
 use anyhow::Result;
 use clap::{Parser, ValueEnum};
-use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers},
-    execute,
-    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+use crossterm::event::{
+    self, Event, KeyCode, KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+};
+use nucleo::{
+    pattern::{CaseMatching, Normalization},
+    Config, Nucleo,
 };
-use nucleo::{Config, Nucleo};
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Modifier, Style},
-    text::Line,
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    layout::{Constraint, Direction, Layout, Margin, Rect},
+    style::{Modifier, Style},
+    text::{Line, Span},
+    widgets::{
+        Block, Borders, List, ListDirection, ListItem, ListState, Paragraph, Scrollbar,
+        ScrollbarOrientation, ScrollbarState,
+    },
     Frame, Terminal,
 };
+use serde::{Deserialize, Serialize};
 use std::{
     env,
-    fs,
     io::{self, BufRead},
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
-use walkdir::WalkDir;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
 
+mod app_cache;
 mod apps;
+mod clipboard;
 mod config;
+mod fields;
+mod files;
+mod filetype;
+mod history;
+mod input;
+mod keys;
+mod loader;
 mod matcher;
+mod preview;
+mod queries;
+mod terminal;
+mod usage;
+
+use config::Theme;
+use fields::FieldSpec;
+use filetype::FileKind;
+use history::{HistorySort, Shell};
+use input::Input;
+use keys::{Action, Keymap};
+use loader::Loader;
+use matcher::FuzzyMatcher;
+use preview::Preview;
+use queries::QueryRing;
 
 /// CVH Fuzzy - Universal fuzzy finder
 #[derive(Parser, Debug)]
@@ -43,17 +71,17 @@ mod matcher;
 #[command(version = "0.1.0")]
 #[command(about = "Universal fuzzy finder for files, apps, and commands")]
 struct Args {
-    /// Search mode
-    #[arg(short, long, value_enum, default_value = "apps")]
-    mode: Mode,
+    /// Search mode (defaults to `default_mode` from the config)
+    #[arg(short, long, value_enum)]
+    mode: Option<Mode>,
 
-    /// Initial query
+    /// Initial query (supports 'exact, ^prefix, suffix$ and !negated terms)
     #[arg(short, long, default_value = "")]
     query: String,
 
-    /// Maximum height (lines)
-    #[arg(long, default_value = "40")]
-    height: u16,
+    /// Maximum height in lines (defaults to `height` from the config)
+    #[arg(long)]
+    height: Option<u16>,
 
     /// Show border
     #[arg(long, default_value = "true")]
@@ -63,13 +91,176 @@ struct Args {
     #[arg(long)]
     print0: bool,
 
-    /// Directory to search (for files/dirs mode)
-    #[arg(short = 'p', long)]
-    path: Option<PathBuf>,
+    /// Print the final query as the first line of output, even when
+    /// aborted
+    #[arg(long)]
+    print_query: bool,
+
+    /// Select the only match without showing the finder
+    #[arg(short = '1', long)]
+    select_1: bool,
+
+    /// Exit immediately (status 1) when nothing matches the initial query
+    #[arg(short = '0', long)]
+    exit_0: bool,
+
+    /// Copy the selection to the clipboard instead of printing it
+    #[arg(long)]
+    clipboard: bool,
+
+    /// Directory to search (for files/dirs mode); repeat or separate with
+    /// commas to search several
+    #[arg(short = 'p', long, value_delimiter = ',')]
+    path: Vec<PathBuf>,
 
     /// Read items from stdin
     #[arg(long)]
     stdin: bool,
+
+    /// Field delimiter for `--with-nth`/`--accept-nth`
+    #[arg(short = 'd', long, default_value = "\t", value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    delimiter: String,
+
+    /// Fields of each stdin line to show, e.g. `2`, `2..` or `1,3`
+    #[arg(long, value_name = "FIELDS", allow_negative_numbers = true)]
+    with_nth: Option<FieldSpec>,
+
+    /// Fields of the selected stdin line to print, e.g. `1`
+    #[arg(long, value_name = "FIELDS", allow_negative_numbers = true)]
+    accept_nth: Option<FieldSpec>,
+
+    /// Order stdin lines by this field before matching (input order by
+    /// default)
+    #[arg(long, value_name = "FIELD", allow_negative_numbers = true)]
+    sort_field: Option<FieldSpec>,
+
+    /// Compare `--sort-field` values as numbers instead of text
+    #[arg(long, requires = "sort_field")]
+    numeric: bool,
+
+    /// Reverse the order of stdin lines or history entries, after any
+    /// sorting
+    #[arg(long)]
+    tac: bool,
+
+    /// Shell whose history to search (defaults to $SHELL)
+    #[arg(long, value_enum)]
+    shell: Option<Shell>,
+
+    /// Order of history entries
+    #[arg(long, value_enum, default_value = "recency")]
+    history_sort: HistorySort,
+
+    /// Don't respect .gitignore/.ignore files or skip hidden entries
+    #[arg(long)]
+    no_ignore: bool,
+
+    /// How many levels below each search root to walk; 1 (or 0) lists
+    /// only direct children (unlimited by default)
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Screen layout
+    #[arg(long, value_enum, default_value = "default")]
+    layout: ListLayout,
+
+    /// Ordering among equal-score matches
+    #[arg(long, value_enum, default_value = "length")]
+    tiebreak: Tiebreak,
+
+    /// Rows kept visible above/below the selection while scrolling
+    #[arg(long, default_value = "3")]
+    scroll_off: usize,
+
+    /// Maximum number of matches kept (speeds up rendering on huge inputs)
+    #[arg(long)]
+    max_results: Option<usize>,
+
+    /// Disable mouse support (keeps the terminal's own selection/paste)
+    #[arg(long)]
+    no_mouse: bool,
+
+    /// Don't draw a scrollbar when the results overflow the list
+    #[arg(long)]
+    no_scrollbar: bool,
+
+    /// Don't show file type glyphs (they need a Nerd Font)
+    #[arg(long)]
+    no_icons: bool,
+
+    /// Show a preview pane of the highlighted file (files/dirs mode)
+    #[arg(long)]
+    preview: bool,
+
+    /// Match case exactly instead of only when the query has uppercase
+    #[arg(long, conflicts_with = "case_insensitive")]
+    case_sensitive: bool,
+
+    /// Ignore case even when the query has uppercase
+    #[arg(long)]
+    case_insensitive: bool,
+
+    /// Match the whole query as a plain substring (no fuzzy matching or
+    /// special characters)
+    #[arg(long)]
+    literal: bool,
+
+    /// Make accents matter: "cafe" no longer matches "café"
+    #[arg(long)]
+    exact_diacritics: bool,
+}
+
+/// Case matching selected by `--case-sensitive`/`--case-insensitive`
+fn case_matching(sensitive: bool, insensitive: bool) -> CaseMatching {
+    if sensitive {
+        CaseMatching::Respect
+    } else if insensitive {
+        CaseMatching::Ignore
+    } else {
+        CaseMatching::Smart
+    }
+}
+
+/// Normalization selected by `--exact-diacritics`
+fn normalization(exact_diacritics: bool) -> Normalization {
+    if exact_diacritics {
+        Normalization::Never
+    } else {
+        Normalization::Smart
+    }
+}
+
+/// Compose `text` into NFC so accented letters are single chars
+///
+/// Nucleo folds diacritics per char, so a decomposed "e" + U+0301 would
+/// otherwise match "e" even with `--exact-diacritics`, and "é" in the
+/// query would never match it.
+fn compose(text: String) -> String {
+    if is_nfc(&text) {
+        text
+    } else {
+        text.nfc().collect()
+    }
+}
+
+/// Escape `query` into a single substring term of the pattern syntax
+///
+/// The leading `'` makes the term a substring match, escaping spaces keeps
+/// it one term and escaping a trailing `$` stops it anchoring to the end.
+fn literal_pattern(query: &str) -> String {
+    let mut pattern = String::with_capacity(query.len() + 2);
+    pattern.push('\'');
+    let body = query.strip_suffix('$');
+    for c in body.unwrap_or(query).chars() {
+        if c == ' ' {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    if body.is_some() {
+        pattern.push_str("\\$");
+    }
+    pattern
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
@@ -86,8 +277,74 @@ enum Mode {
     Stdin,
 }
 
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum ListLayout {
+    /// Prompt at the top, results below it
+    #[default]
+    Default,
+    /// Prompt at the bottom, results above it with the best match nearest
+    Reverse,
+}
+
+/// Exit status when nothing matched the initial query (`--exit-0`)
+const EXIT_NO_MATCH: i32 = 1;
+/// Exit status when the user aborted without selecting
+const EXIT_ABORTED: i32 = 130;
+/// Exit status when the user accepted the typed query instead of an item
+const EXIT_NEW_QUERY: i32 = 3;
+
+/// What the finder should finish with once accepted
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum AcceptMode {
+    /// The highlighted item
+    #[default]
+    Selection,
+    /// The raw query, e.g. for a wrapper to create a new item
+    Query,
+}
+
+/// How the finder was left
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+    Selected(Item),
+    NewQuery(String),
+    Aborted,
+}
+
+/// What to do after matching the initial query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Startup {
+    /// Accept the only match without showing the finder
+    Select,
+    /// Exit without a selection
+    NoMatch,
+    /// Run the finder
+    Interactive,
+}
+
+/// Decide whether `--select-1`/`--exit-0` skip the finder for `matched` results
+fn startup_action(matched: usize, select_1: bool, exit_0: bool) -> Startup {
+    match matched {
+        0 if exit_0 => Startup::NoMatch,
+        1 if select_1 => Startup::Select,
+        _ => Startup::Interactive,
+    }
+}
+
+/// Ordering among matches with equal scores
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+enum Tiebreak {
+    /// Shorter display text first
+    #[default]
+    Length,
+    /// Earlier first matched character first
+    Begin,
+    /// Original input order
+    Index,
+}
+
 /// An item that can be searched
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 struct Item {
     /// Display text
     display: String,
@@ -95,12 +352,41 @@ struct Item {
     value: String,
     /// Optional icon or type indicator
     icon: Option<String>,
+    /// Stable identifier (the desktop-id for applications)
+    id: Option<String>,
 }
 
+/// Maximum delay between two clicks on the same row to count as a double-click
+const DOUBLE_CLICK: Duration = Duration::from_millis(400);
+
+/// First visible row that keeps `selected` at least `scroll_off` rows away
+/// from either edge of a `height`-row viewport over `len` rows, moving the
+/// current `offset` as little as possible
+fn scroll_offset(offset: usize, selected: usize, height: usize, len: usize, scroll_off: usize) -> usize {
+    if len <= height {
+        return 0;
+    }
+
+    // A margin larger than half the viewport would make the offset jitter
+    let margin = scroll_off.min(height.saturating_sub(1) / 2);
+    let mut offset = offset;
+    if selected < offset + margin {
+        offset = selected.saturating_sub(margin);
+    }
+    if selected + margin >= offset + height {
+        offset = selected + margin + 1 - height;
+    }
+
+    offset.min(len - height)
+}
+
+/// Frames of the loading spinner shown in the list title
+const SPINNER: &[char] = &['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+
 /// Application state
 struct App {
-    /// Current query string
-    query: String,
+    /// Current query and cursor
+    query: Input,
     /// All items
     items: Vec<Item>,
     /// Filtered/matched items (indices into items, already sorted by score)
@@ -111,49 +397,144 @@ struct App {
     list_state: ListState,
     /// Nucleo matcher
     matcher: Nucleo<String>,
+    /// Matcher used to compute highlight positions for visible rows
+    highlighter: FuzzyMatcher,
     /// Should quit
     should_quit: bool,
     /// Selected item (if any)
-    selected_item: Option<String>,
+    selected_item: Option<Item>,
+    /// Whether accepting finishes with the selection or the query
+    accept_mode: AcceptMode,
+    /// Preview pane state (if enabled)
+    preview: Option<Preview>,
+    /// Key bindings
+    keymap: Keymap,
+    /// Draw the prompt below the results, best match nearest to it
+    reverse: bool,
+    /// Rows kept visible above/below the selection while scrolling
+    scroll_off: usize,
+    /// Maximum number of matches kept (all if None)
+    max_results: Option<usize>,
+    /// Ordering among equal-score matches
+    tiebreak: Tiebreak,
+    /// Inner area of the results list from the last draw, for mouse hits
+    list_area: Rect,
+    /// Time and row of the last left click, for double-click detection
+    last_click: Option<(Instant, usize)>,
+    /// Background loader still streaming items (if any)
+    loader: Option<Loader>,
+    /// Number of loader polls, used to animate the spinner
+    spinner_tick: usize,
+    /// Colors used when drawing
+    theme: Theme,
+    /// Maximum number of terminal rows to draw in
+    max_height: u16,
+    /// How the query's case is matched
+    case_matching: CaseMatching,
+    /// Match the query as one plain substring
+    literal: bool,
+    /// Whether accented letters also match their base letter
+    normalization: Normalization,
+    /// Draw a scrollbar when the results overflow the list
+    scrollbar: bool,
+    /// Earlier queries of this mode, recalled at an empty prompt
+    query_history: QueryRing,
 }
 
 impl App {
-    fn new(items: Vec<Item>) -> Self {
+    fn new(items: Vec<Item>, keymap: Keymap) -> Self {
         let config = Config::DEFAULT;
         let matcher = Nucleo::new(config, Arc::new(|| {}), None, 1);
 
-        // Inject items into matcher with their index as data
-        let injector = matcher.injector();
-        for (idx, item) in items.iter().enumerate() {
-            let _ = injector.push(idx.to_string(), |_, cols| {
-                cols[0] = item.display.clone().into();
-            });
-        }
-
         let mut app = App {
-            query: String::new(),
-            items,
+            query: Input::default(),
+            items: Vec::new(),
             filtered: Vec::new(),
             selected: 0,
             list_state: ListState::default(),
             matcher,
+            highlighter: FuzzyMatcher::new(),
             should_quit: false,
             selected_item: None,
+            accept_mode: AcceptMode::Selection,
+            preview: None,
+            keymap,
+            reverse: false,
+            scroll_off: 3,
+            max_results: None,
+            tiebreak: Tiebreak::Length,
+            list_area: Rect::default(),
+            last_click: None,
+            loader: None,
+            spinner_tick: 0,
+            theme: Theme::default(),
+            max_height: u16::MAX,
+            case_matching: CaseMatching::Smart,
+            literal: false,
+            normalization: Normalization::Smart,
+            scrollbar: true,
+            query_history: QueryRing::default(),
         };
 
+        app.push_items(items);
         app.update_filter();
         app
     }
 
+    /// Add items, injecting them into the matcher with their index as data
+    ///
+    /// Displays are composed to NFC first, which looks the same but keeps
+    /// matching and highlighting consistent.
+    fn push_items(&mut self, items: Vec<Item>) {
+        let injector = self.matcher.injector();
+        for mut item in items {
+            item.display = compose(item.display);
+            let idx = self.items.len();
+            let _ = injector.push(idx.to_string(), |_, cols| {
+                cols[0] = item.display.clone().into();
+            });
+            self.items.push(item);
+        }
+    }
+
+    /// Pull newly streamed items from the background loader
+    fn poll_loader(&mut self) {
+        let Some(loader) = self.loader.as_mut() else {
+            return;
+        };
+
+        let items = loader.drain();
+        let finished = !loader.is_loading();
+        self.push_items(items);
+        self.spinner_tick = self.spinner_tick.wrapping_add(1);
+
+        if finished {
+            self.loader = None;
+        }
+    }
+
+    /// Wait until every item is loaded and matched against the query
+    fn finish_matching(&mut self) {
+        while self.loader.is_some() {
+            self.poll_loader();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        while self.matcher.tick(10).running {}
+        self.update_filter();
+    }
+
+    /// Re-run matching for the current query
+    ///
+    /// The query is split on whitespace into terms that must all match.
+    /// Each term follows fzf syntax: `'foo` is an exact substring, `^foo`
+    /// and `foo$` anchor to the start/end, `!foo` excludes matches, and a
+    /// term without special characters is matched fuzzily. With `literal`
+    /// set the whole query is instead one plain substring.
     fn update_filter(&mut self) {
         // Update pattern in matcher
-        self.matcher.pattern.reparse(
-            0,
-            &self.query,
-            nucleo::pattern::CaseMatching::Smart,
-            nucleo::pattern::Normalization::Smart,
-            false,
-        );
+        let query = compose(self.query.as_str().to_string());
+        let pattern = if self.literal { literal_pattern(&query) } else { query };
+        self.matcher.pattern.reparse(0, &pattern, self.case_matching, self.normalization, false);
 
         // Tick the matcher
         let _status = self.matcher.tick(10);
@@ -174,6 +555,11 @@ impl App {
             }
         }
 
+        self.apply_tiebreak();
+        if let Some(max) = self.max_results {
+            self.filtered.truncate(max);
+        }
+
         // Reset selection if out of bounds
         if self.selected >= self.filtered.len() {
             self.selected = 0;
@@ -181,12 +567,46 @@ impl App {
 
         // Update list state
         self.list_state.select(Some(self.selected));
+        self.update_scroll();
+    }
+
+    /// Reorder equal-score runs of `filtered` by the configured tiebreak
+    ///
+    /// Nucleo already breaks ties by length and then insertion order, so
+    /// only the other orderings need the matches rescored here.
+    fn apply_tiebreak(&mut self) {
+        if self.tiebreak == Tiebreak::Length {
+            return;
+        }
+
+        let pattern = self.matcher.pattern.column_pattern(0);
+        let mut keyed: Vec<(u32, usize, usize)> = self
+            .filtered
+            .iter()
+            .map(|&idx| {
+                let display = &self.items[idx].display;
+                let score = self.highlighter.score_pattern(pattern, display).unwrap_or(0);
+                let key = match self.tiebreak {
+                    Tiebreak::Begin => self
+                        .highlighter
+                        .indices(pattern, display)
+                        .first()
+                        .map_or(0, |&i| i as usize),
+                    _ => idx,
+                };
+                (score, key, idx)
+            })
+            .collect();
+
+        keyed.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        self.filtered = keyed.into_iter().map(|(_, _, idx)| idx).collect();
     }
 
     fn select_next(&mut self) {
         if !self.filtered.is_empty() {
             self.selected = (self.selected + 1) % self.filtered.len();
             self.list_state.select(Some(self.selected));
+            self.update_scroll();
         }
     }
 
@@ -194,282 +614,1317 @@ impl App {
         if !self.filtered.is_empty() {
             self.selected = self.selected.checked_sub(1).unwrap_or(self.filtered.len() - 1);
             self.list_state.select(Some(self.selected));
+            self.update_scroll();
+        }
+    }
+
+    /// Scroll the list so `scroll_off` rows stay visible around the selection
+    fn update_scroll(&mut self) {
+        let height = self.list_area.height as usize;
+        if height == 0 {
+            // Not drawn yet; the list widget keeps the selection visible
+            return;
+        }
+
+        *self.list_state.offset_mut() = scroll_offset(
+            self.list_state.offset(),
+            self.selected,
+            height,
+            self.filtered.len(),
+            self.scroll_off,
+        );
+    }
+
+    /// Move the selection one row up on screen, towards worse matches when
+    /// the layout is reversed
+    fn move_up(&mut self) {
+        if self.reverse {
+            self.select_next();
+        } else {
+            self.select_prev();
+        }
+    }
+
+    /// Move the selection one row down on screen
+    fn move_down(&mut self) {
+        if self.reverse {
+            self.select_prev();
+        } else {
+            self.select_next();
         }
     }
 
     fn confirm_selection(&mut self) {
         if let Some(&idx) = self.filtered.get(self.selected) {
             if let Some(item) = self.items.get(idx) {
-                self.selected_item = Some(item.value.clone());
+                self.selected_item = Some(item.clone());
             }
         }
         self.should_quit = true;
     }
 
+    /// Finish with the query, whatever is highlighted
+    fn accept_query(&mut self) {
+        self.accept_mode = AcceptMode::Query;
+        self.should_quit = true;
+    }
+
+    /// How the finder was left, taking the selection
+    fn outcome(&mut self) -> Outcome {
+        match self.accept_mode {
+            AcceptMode::Query => Outcome::NewQuery(self.query.as_str().to_string()),
+            AcceptMode::Selection => self.selected_item.take().map_or(Outcome::Aborted, Outcome::Selected),
+        }
+    }
+
+    /// Value of the currently highlighted item
+    fn current_value(&self) -> Option<&str> {
+        self.filtered
+            .get(self.selected)
+            .and_then(|&idx| self.items.get(idx))
+            .map(|item| item.value.as_str())
+    }
+
+    /// Re-read the preview if the highlighted item changed
+    fn refresh_preview(&mut self) {
+        let path = self.current_value().map(str::to_string);
+        if let Some(preview) = self.preview.as_mut() {
+            preview.update(path.as_deref());
+        }
+    }
+
     fn handle_key(&mut self, key: KeyCode, modifiers: KeyModifiers) {
-        match (key, modifiers) {
-            // Quit without selection
-            (KeyCode::Esc, _) | (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
-                self.should_quit = true;
-            }
-            // Navigation
-            (KeyCode::Down, _) | (KeyCode::Char('n'), KeyModifiers::CONTROL) => {
-                self.select_next();
-            }
-            (KeyCode::Up, _) | (KeyCode::Char('p'), KeyModifiers::CONTROL) => {
-                self.select_prev();
-            }
-            // Confirm selection
-            (KeyCode::Enter, _) => {
-                self.confirm_selection();
+        if let Some(action) = self.keymap.lookup(key, modifiers) {
+            match action {
+                Action::Abort => self.should_quit = true,
+                Action::Down => self.move_down(),
+                Action::Up => self.move_up(),
+                Action::Accept => self.confirm_selection(),
+                Action::AcceptQuery => self.accept_query(),
+                Action::Left => self.query.move_left(),
+                Action::Right => self.query.move_right(),
+                Action::Home => self.query.home(),
+                Action::End => self.query.end(),
+                Action::Clear => {
+                    self.query.clear();
+                    self.update_filter();
+                }
+                Action::DeleteWord => {
+                    self.query.delete_word();
+                    self.update_filter();
+                }
+                Action::KillLine => {
+                    self.query.kill_to_end();
+                    self.update_filter();
+                }
+                Action::HistoryPrev if self.browsing_history() => {
+                    if let Some(query) = self.query_history.older() {
+                        self.query.set(query);
+                        self.update_filter();
+                    }
+                }
+                Action::HistoryNext if self.browsing_history() => {
+                    match self.query_history.newer() {
+                        Some(query) => self.query.set(query),
+                        None => self.query.clear(),
+                    }
+                    self.update_filter();
+                }
+                Action::HistoryPrev => self.move_up(),
+                Action::HistoryNext => self.move_down(),
             }
+            return;
+        }
+
+        match (key, modifiers) {
             // Backspace
             (KeyCode::Backspace, _) => {
-                self.query.pop();
-                self.update_filter();
-            }
-            // Clear query
-            (KeyCode::Char('u'), KeyModifiers::CONTROL) => {
-                self.query.clear();
+                self.query.backspace();
                 self.update_filter();
             }
             // Type character
             (KeyCode::Char(c), KeyModifiers::NONE | KeyModifiers::SHIFT) => {
-                self.query.push(c);
+                self.query.insert(c);
                 self.update_filter();
             }
             _ => {}
         }
     }
-}
 
-fn load_items(mode: Mode, path: Option<PathBuf>) -> Result<Vec<Item>> {
-    match mode {
-        Mode::Apps => apps::load_applications(),
-        Mode::Files => {
-            let base = path.unwrap_or_else(|| env::current_dir().unwrap_or_default());
-            let mut items = Vec::new();
-            for entry in WalkDir::new(&base)
-                .follow_links(true)
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| e.file_type().is_file())
-                .take(10000)
-            {
-                let path = entry.path();
-                let display = path.strip_prefix(&base)
-                    .unwrap_or(path)
-                    .display()
-                    .to_string();
-                items.push(Item {
-                    display: display.clone(),
-                    value: path.display().to_string(),
-                    icon: Some("".to_string()),
-                });
-            }
-            Ok(items)
-        }
-        Mode::Dirs => {
-            let base = path.unwrap_or_else(|| env::current_dir().unwrap_or_default());
-            let mut items = Vec::new();
-            for entry in WalkDir::new(&base)
-                .follow_links(true)
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| e.file_type().is_dir())
-                .take(5000)
-            {
-                let path = entry.path();
-                let display = path.strip_prefix(&base)
-                    .unwrap_or(path)
-                    .display()
-                    .to_string();
-                if !display.is_empty() {
-                    items.push(Item {
-                        display: display.clone(),
-                        value: path.display().to_string(),
-                        icon: Some("".to_string()),
-                    });
+    /// Whether the history keys recall queries: at an empty prompt, or
+    /// while the prompt still shows the last recalled query
+    fn browsing_history(&mut self) -> bool {
+        let query = self.query.as_str();
+        if self.query_history.current() == Some(query) {
+            return true;
+        }
+        self.query_history.reset();
+        query.is_empty()
+    }
+
+    fn handle_mouse(&mut self, mouse: MouseEvent) {
+        match mouse.kind {
+            MouseEventKind::ScrollUp => self.move_up(),
+            MouseEventKind::ScrollDown => self.move_down(),
+            MouseEventKind::Down(MouseButton::Left) => {
+                let Some(row) = self.row_at(mouse.column, mouse.row) else {
+                    return;
+                };
+                self.selected = row;
+                self.list_state.select(Some(row));
+
+                let now = Instant::now();
+                let double = self
+                    .last_click
+                    .is_some_and(|(at, last)| last == row && now.duration_since(at) <= DOUBLE_CLICK);
+                if double {
+                    self.last_click = None;
+                    self.confirm_selection();
+                } else {
+                    self.last_click = Some((now, row));
                 }
             }
-            Ok(items)
+            _ => {}
+        }
+    }
+
+    /// Index into `filtered` of the list row under a terminal cell
+    fn row_at(&self, column: u16, row: u16) -> Option<usize> {
+        let area = self.list_area;
+        let inside = column >= area.x
+            && column < area.x + area.width
+            && row >= area.y
+            && row < area.y + area.height;
+        if !inside {
+            return None;
         }
+
+        // Reversed lists draw the first item on the bottom row
+        let from_start = if self.reverse {
+            area.y + area.height - 1 - row
+        } else {
+            row - area.y
+        };
+        let idx = self.list_state.offset() + from_start as usize;
+        (idx < self.filtered.len()).then_some(idx)
+    }
+}
+
+/// Load items for `mode`. File walks can be slow on large trees, so they
+/// stream from a background loader instead of being returned directly.
+fn load_items(mode: Mode, args: &Args) -> Result<(Vec<Item>, Option<Loader>)> {
+    match mode {
+        Mode::Apps => Ok((apps::load_applications()?, None)),
+        Mode::Files | Mode::Dirs => Ok((Vec::new(), Some(spawn_walk(mode, args)))),
         Mode::History => {
-            let mut items = Vec::new();
-            // Try to read zsh history
-            if let Some(home) = dirs::home_dir() {
-                let hist_file = home.join(".zsh_history");
-                if let Ok(content) = fs::read_to_string(&hist_file) {
-                    for line in content.lines().rev().take(1000) {
-                        // Zsh history format: : timestamp:0;command
-                        let cmd = if line.starts_with(':') {
-                            line.split(';').nth(1).unwrap_or(line)
-                        } else {
-                            line
-                        };
-                        if !cmd.is_empty() {
-                            items.push(Item {
-                                display: cmd.to_string(),
-                                value: cmd.to_string(),
-                                icon: None,
-                            });
-                        }
-                    }
-                }
+            let shell = args.shell.unwrap_or_else(Shell::detect);
+            let mut items = history::load_history(shell, args.history_sort);
+            if args.tac {
+                items.reverse();
             }
-            Ok(items)
+            Ok((items, None))
         }
         Mode::Stdin => {
-            let mut items = Vec::new();
-            let stdin = io::stdin();
-            for line in stdin.lock().lines().take(10000) {
-                if let Ok(line) = line {
-                    items.push(Item {
-                        display: line.clone(),
-                        value: line,
-                        icon: None,
-                    });
-                }
-            }
-            Ok(items)
+            Ok((stdin_items(read_lines(io::stdin().lock()), args), None))
+        }
+    }
+}
+
+/// Read up to 10000 lines, skipping any that aren't valid UTF-8
+fn read_lines(reader: impl BufRead) -> Vec<String> {
+    reader.lines().take(10000).filter_map(Result::ok).collect()
+}
+
+/// Turn stdin `lines` into items, applying `--sort-field` and then `--tac`
+fn stdin_items(mut lines: Vec<String>, args: &Args) -> Vec<Item> {
+    if let Some(field) = &args.sort_field {
+        sort_lines(&mut lines, field, &args.delimiter, args.numeric);
+    }
+    if args.tac {
+        lines.reverse();
+    }
+    lines.into_iter().map(|line| stdin_item(line, args)).collect()
+}
+
+/// Stable sort of `lines` by `field`
+///
+/// Numeric sorting puts lines whose field isn't a number last, in input
+/// order.
+fn sort_lines(lines: &mut [String], field: &FieldSpec, delimiter: &str, numeric: bool) {
+    if numeric {
+        lines.sort_by_cached_key(|line| {
+            let value = field.select(line, delimiter).trim().parse::<f64>().ok();
+            (value.is_none(), value.map(OrderedKey))
+        });
+    } else {
+        lines.sort_by_cached_key(|line| field.select(line, delimiter));
+    }
+}
+
+/// Total order over f64 sort keys
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct OrderedKey(f64);
+
+impl Eq for OrderedKey {}
+
+impl PartialOrd for OrderedKey {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedKey {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Item for one stdin line, its display and value picked by
+/// `--with-nth`/`--accept-nth` (the whole line by default)
+fn stdin_item(line: String, args: &Args) -> Item {
+    let pick = |spec: &Option<FieldSpec>| match spec {
+        Some(spec) => spec.select(&line, &args.delimiter),
+        None => line.clone(),
+    };
+    Item {
+        display: pick(&args.with_nth),
+        value: pick(&args.accept_nth),
+        icon: None,
+        id: None,
+    }
+}
+
+/// Build a result line with the matched character positions emphasized
+fn highlight_line<'a>(item: &'a Item, indices: &[u32], selected: bool, theme: &Theme) -> Line<'a> {
+    let plain = if selected {
+        Style::default().fg(theme.highlight_fg)
+    } else {
+        Style::default()
+    };
+    let matched = Style::default()
+        .fg(theme.highlight)
+        .add_modifier(Modifier::BOLD);
+
+    let mut spans = Vec::new();
+    if let Some(ref icon) = item.icon {
+        spans.push(Span::styled(format!("{} ", icon), plain));
+    }
+
+    if indices.is_empty() {
+        spans.push(Span::styled(item.display.as_str(), plain));
+        return Line::from(spans);
+    }
+
+    // Group consecutive chars sharing the same matched state into one span
+    let mut run = String::new();
+    let mut run_matched = false;
+    let mut next = indices.iter().peekable();
+    for (pos, c) in item.display.chars().enumerate() {
+        let is_match = next.peek().is_some_and(|&&i| i as usize == pos);
+        if is_match {
+            next.next();
+        }
+        if is_match != run_matched && !run.is_empty() {
+            let style = if run_matched { matched } else { plain };
+            spans.push(Span::styled(std::mem::take(&mut run), style));
         }
+        run_matched = is_match;
+        run.push(c);
+    }
+    if !run.is_empty() {
+        let style = if run_matched { matched } else { plain };
+        spans.push(Span::styled(run, style));
+    }
+
+    Line::from(spans)
+}
+
+/// Walk the search root for files or directories on a background thread
+fn spawn_walk(mode: Mode, args: &Args) -> Loader {
+    let mut roots = args.path.clone();
+    if roots.is_empty() {
+        roots.push(env::current_dir().unwrap_or_default());
+    }
+    let dirs = mode == Mode::Dirs;
+    let opts = files::WalkOptions {
+        dirs,
+        respect_ignore: !args.no_ignore,
+        limit: if dirs { 5000 } else { 10000 },
+        max_depth: args.max_depth,
+    };
+    let icons = !args.no_icons;
+
+    Loader::spawn(move |sink| {
+        let labels = root_labels(&roots);
+        files::walk_roots(&roots, opts, |index, path| {
+            sink.send(path_item(&roots[index], labels[index].as_deref(), &path, icons))
+        });
+    })
+}
+
+/// Prefix shown before paths found under each search root
+///
+/// A single root shows paths relative to it. With several, paths start
+/// with their root's directory name, or are shown in full for roots that
+/// share a name, so equal relative paths can't be confused.
+fn root_labels(roots: &[PathBuf]) -> Vec<Option<PathBuf>> {
+    if roots.len() == 1 {
+        return vec![Some(PathBuf::new())];
+    }
+
+    let names: Vec<Option<&std::ffi::OsStr>> = roots.iter().map(|root| root.file_name()).collect();
+    names
+        .iter()
+        .map(|name| {
+            name.filter(|name| names.iter().filter(|other| **other == Some(*name)).count() == 1)
+                .map(PathBuf::from)
+        })
+        .collect()
+}
+
+/// Item for a walked path, displayed under its root's label (see
+/// `root_labels`) or in full without one, with its type glyph if `icons`
+fn path_item(base: &Path, label: Option<&Path>, path: &Path, icons: bool) -> Item {
+    let display = match (label, path.strip_prefix(base)) {
+        (Some(label), Ok(relative)) => label.join(relative),
+        _ => path.to_path_buf(),
+    }
+    .display()
+    .to_string();
+    Item {
+        display,
+        value: path.display().to_string(),
+        icon: icons.then(|| FileKind::of(path).glyph().to_string()),
+        id: None,
     }
 }
 
 fn ui(frame: &mut Frame, app: &mut App, show_border: bool) {
+    let theme = app.theme;
+    let area = limit_height(frame.area(), app.max_height, app.reverse);
+    frame.render_widget(Block::default().style(Style::default().bg(theme.bg)), area);
+
     let chunks = Layout::default()
         .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(3),  // Input
-            Constraint::Min(1),     // List
-        ])
-        .split(frame.area());
+        .constraints(if app.reverse {
+            [Constraint::Min(1), Constraint::Length(3)]
+        } else {
+            [Constraint::Length(3), Constraint::Min(1)]
+        })
+        .split(area);
+    let (input_area, results_area) = if app.reverse {
+        (chunks[1], chunks[0])
+    } else {
+        (chunks[0], chunks[1])
+    };
 
     // Input box
     let input_block = if show_border {
         Block::default()
             .borders(Borders::ALL)
             .title(" CVH Fuzzy ")
-            .border_style(Style::default().fg(Color::Cyan))
+            .border_style(Style::default().fg(theme.prompt))
     } else {
         Block::default()
     };
 
-    let input = Paragraph::new(format!("> {}", app.query))
-        .style(Style::default().fg(Color::White))
+    // Split the prompt at the cursor and draw the char under it reversed
+    let query = app.query.as_str();
+    let (before, rest) = query.split_at(app.query.cursor());
+    let mut rest_chars = rest.chars();
+    let under_cursor = rest_chars.next().map_or(" ".to_string(), String::from);
+    let prompt = Line::from(vec![
+        Span::raw("> "),
+        Span::raw(before),
+        Span::styled(under_cursor, Style::default().add_modifier(Modifier::REVERSED)),
+        Span::raw(rest_chars.as_str()),
+    ]);
+
+    let input = Paragraph::new(prompt)
+        .style(Style::default().fg(theme.fg))
         .block(input_block);
-    frame.render_widget(input, chunks[0]);
+    frame.render_widget(input, input_area);
+
+    // Split the results area when the preview pane is enabled
+    let (list_area, preview_area) = if app.preview.is_some() {
+        let halves = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(results_area);
+        (halves[0], Some(halves[1]))
+    } else {
+        (results_area, None)
+    };
+
+    // Results list. Match positions are only computed for rows around the
+    // viewport so large result sets don't pay for highlighting every item.
+    let height = list_area.height as usize;
+    let offset = app.list_state.offset();
+    let visible = offset.min(app.selected.saturating_sub(height))
+        ..(offset + height).max(app.selected + height);
+    let pattern = app.matcher.pattern.column_pattern(0);
 
-    // Results list
     let items: Vec<ListItem> = app
         .filtered
         .iter()
-        .map(|&idx| {
+        .enumerate()
+        .map(|(row, &idx)| {
             let item = &app.items[idx];
-            let content = if let Some(ref icon) = item.icon {
-                format!("{} {}", icon, item.display)
+            let indices = if visible.contains(&row) {
+                app.highlighter.indices(pattern, &item.display)
             } else {
-                item.display.clone()
+                Vec::new()
             };
-            ListItem::new(Line::from(content))
+            let line = highlight_line(item, &indices, row == app.selected, &theme);
+            ListItem::new(line)
         })
         .collect();
 
     let list_block = if show_border {
         Block::default()
             .borders(Borders::ALL)
-            .title(format!(" {}/{} ", app.filtered.len(), app.items.len()))
-            .border_style(Style::default().fg(Color::DarkGray))
+            .title(match app.loader {
+                Some(_) => format!(
+                    " {} {}/{} ",
+                    SPINNER[app.spinner_tick % SPINNER.len()],
+                    app.filtered.len(),
+                    app.items.len()
+                ),
+                None => format!(" {}/{} ", app.filtered.len(), app.items.len()),
+            })
+            .border_style(Style::default().fg(theme.border))
     } else {
         Block::default()
     };
 
+    app.list_area = list_block.inner(list_area);
+
+    let direction = if app.reverse {
+        ListDirection::BottomToTop
+    } else {
+        ListDirection::TopToBottom
+    };
+
     let list = List::new(items)
+        .direction(direction)
         .block(list_block)
         .highlight_style(
             Style::default()
-                .bg(Color::DarkGray)
-                .fg(Color::White)
+                .bg(theme.highlight_bg)
                 .add_modifier(Modifier::BOLD),
         )
         .highlight_symbol("  ");
 
-    frame.render_stateful_widget(list, chunks[1], &mut app.list_state);
+    frame.render_stateful_widget(list, list_area, &mut app.list_state);
+
+    // Scrollbar over the right edge, using the offset the list settled on
+    let rows = app.list_area.height as usize;
+    if let Some(mut state) = app
+        .scrollbar
+        .then(|| scrollbar_state(app.filtered.len(), app.list_state.offset(), rows, app.reverse))
+        .flatten()
+    {
+        let bar_area = if show_border {
+            list_area.inner(Margin { vertical: 1, horizontal: 0 })
+        } else {
+            list_area
+        };
+        let bar = scrollbar().style(Style::default().fg(theme.border));
+        frame.render_stateful_widget(bar, bar_area, &mut state);
+    }
+
+    // Preview pane
+    if let (Some(area), Some(preview)) = (preview_area, app.preview.as_ref()) {
+        let preview_block = if show_border {
+            Block::default()
+                .borders(Borders::ALL)
+                .title(" Preview ")
+                .border_style(Style::default().fg(theme.border))
+        } else {
+            Block::default()
+        };
+
+        let paragraph = Paragraph::new(preview.content())
+            .style(Style::default().fg(theme.fg))
+            .block(preview_block);
+        frame.render_widget(paragraph, area);
+    }
+}
+
+/// Vertical scrollbar on the right without arrow heads
+fn scrollbar() -> Scrollbar<'static> {
+    Scrollbar::new(ScrollbarOrientation::VerticalRight)
+        .begin_symbol(None)
+        .end_symbol(None)
+}
+
+/// Scrollbar position for `len` rows shown `height` at a time, the first
+/// visible one being `offset`; None when they all fit
+///
+/// The reverse layout draws row 0 at the bottom, so the thumb starts there.
+fn scrollbar_state(len: usize, offset: usize, height: usize, reverse: bool) -> Option<ScrollbarState> {
+    if len <= height {
+        return None;
+    }
+    let last_offset = len - height;
+    let offset = offset.min(last_offset);
+    let position = if reverse { last_offset - offset } else { offset };
+    Some(
+        ScrollbarState::new(last_offset + 1)
+            .position(position)
+            .viewport_content_length(height),
+    )
+}
+
+/// Shrink `area` to at most `max_height` rows, keeping the prompt's edge:
+/// the top normally, the bottom in the reverse layout
+fn limit_height(area: Rect, max_height: u16, reverse: bool) -> Rect {
+    let height = area.height.min(max_height);
+    let y = if reverse { area.y + area.height - height } else { area.y };
+    Rect { y, height, ..area }
 }
 
-fn run_tui(mut app: App, show_border: bool) -> Result<Option<String>> {
-    // Setup terminal
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stdout);
+fn run_tui(app: &mut App, show_border: bool, mouse: bool) -> Result<Outcome> {
+    // Setup terminal, restored by the guard on any exit path
+    let guard = terminal::setup(mouse)?;
+    let backend = CrosstermBackend::new(io::stdout());
     let mut terminal = Terminal::new(backend)?;
 
     // Main loop
     loop {
-        terminal.draw(|f| ui(f, &mut app, show_border))?;
+        terminal.draw(|f| ui(f, app, show_border))?;
 
         // Poll for events
         if event::poll(Duration::from_millis(50))? {
-            if let Event::Key(key) = event::read()? {
-                app.handle_key(key.code, key.modifiers);
+            match event::read()? {
+                Event::Key(key) => app.handle_key(key.code, key.modifiers),
+                Event::Mouse(mouse_event) => app.handle_mouse(mouse_event),
+                _ => {}
             }
         }
 
-        // Tick matcher for async results
+        // Pull streamed items and tick matcher for async results
+        app.poll_loader();
         app.matcher.tick(10);
         app.update_filter();
+        app.refresh_preview();
 
         if app.should_quit {
             break;
         }
     }
 
-    // Restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+    guard.restore();
 
-    Ok(app.selected_item)
+    Ok(app.outcome())
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
+    let config = config::Config::load()?;
+    let keymap = config.keys.keymap()?;
+    let theme = config.colors.theme()?;
 
     // Load items based on mode
-    let mode = if args.stdin { Mode::Stdin } else { args.mode };
-    let items = load_items(mode, args.path)?;
+    let mode = match args.mode {
+        _ if args.stdin => Mode::Stdin,
+        Some(mode) => mode,
+        None => Mode::from_str(&config.default_mode, true)
+            .map_err(|_| anyhow::anyhow!("invalid default_mode '{}' in config", config.default_mode))?,
+    };
+    let (items, loader) = load_items(mode, &args)?;
 
     // Create app
-    let mut app = App::new(items);
-    app.query = args.query;
+    let mut app = App::new(items, keymap);
+    app.loader = loader;
+    app.reverse = args.layout == ListLayout::Reverse;
+    app.scroll_off = args.scroll_off;
+    app.max_results = args.max_results;
+    app.tiebreak = args.tiebreak;
+    app.theme = theme;
+    app.max_height = args.height.unwrap_or(config.height);
+    app.case_matching = case_matching(args.case_sensitive, args.case_insensitive);
+    app.literal = args.literal;
+    app.normalization = normalization(args.exact_diacritics);
+    app.scrollbar = !args.no_scrollbar;
+    let mode_name = mode.to_possible_value().map(|v| v.get_name().to_string()).unwrap_or_default();
+    if let Some(path) = queries::ring_path(&mode_name) {
+        app.query_history = QueryRing::load(&path, QueryRing::DEFAULT_CAPACITY);
+    }
+    app.query.set(args.query.as_str());
     app.update_filter();
+    if args.preview && matches!(mode, Mode::Files | Mode::Dirs) {
+        app.preview = Some(Preview::new());
+        app.refresh_preview();
+    }
 
-    // Run TUI
-    if let Some(selected) = run_tui(app, args.border)? {
-        // Handle selection based on mode
-        match mode {
-            Mode::Apps => {
-                // Launch the application
-                std::process::Command::new("sh")
-                    .arg("-c")
-                    .arg(&selected)
-                    .spawn()?;
-            }
-            _ => {
-                // Print the selection
-                if args.print0 {
-                    print!("{}\0", selected);
-                } else {
-                    println!("{}", selected);
-                }
+    if args.select_1 || args.exit_0 {
+        app.finish_matching();
+    }
+    let outcome = match startup_action(app.filtered.len(), args.select_1, args.exit_0) {
+        Startup::Select => match app.filtered.first() {
+            Some(&idx) => Outcome::Selected(app.items[idx].clone()),
+            None => Outcome::Aborted,
+        },
+        Startup::NoMatch => {
+            print!("{}", compose_output(query_line(&app, &args), None, args.print0));
+            std::process::exit(EXIT_NO_MATCH);
+        }
+        Startup::Interactive => {
+            let outcome = run_tui(&mut app, args.border, !args.no_mouse)?;
+            // Kept whether or not the search was aborted
+            queries::record(&mode_name, app.query.as_str());
+            outcome
+        }
+    };
+
+    let selected = match outcome {
+        Outcome::Selected(item) => item,
+        Outcome::NewQuery(query) => {
+            print!("{}", compose_output(Some(&query), None, args.print0));
+            std::process::exit(EXIT_NEW_QUERY);
+        }
+        Outcome::Aborted => {
+            print!("{}", compose_output(query_line(&app, &args), None, args.print0));
+            std::process::exit(EXIT_ABORTED);
+        }
+    };
+
+    // Handle selection based on mode; only plain output carries it
+    let mut printed = None;
+    match mode {
+        Mode::Apps => {
+            // Launch the application
+            std::process::Command::new("sh")
+                .arg("-c")
+                .arg(&selected.value)
+                .spawn()?;
+            if let Some(id) = &selected.id {
+                usage::record_launch(id);
             }
         }
+        _ if args.clipboard => {
+            clipboard::copy(&selected.value)?;
+        }
+        _ => {
+            printed = Some(selected.value.as_str());
+        }
     }
+    print!("{}", compose_output(query_line(&app, &args), printed, args.print0));
 
     Ok(())
 }
+
+/// The query to print first, with `--print-query`
+fn query_line<'a>(app: &'a App, args: &Args) -> Option<&'a str> {
+    args.print_query.then(|| app.query.as_str())
+}
+
+/// Output records, each ended by a newline or, with `--print0`, a NUL:
+/// the query if asked for, then the selection
+fn compose_output(query: Option<&str>, selection: Option<&str>, print0: bool) -> String {
+    let terminator = if print0 { '\0' } else { '\n' };
+    let mut out = String::new();
+    for record in query.into_iter().chain(selection) {
+        out.push_str(record);
+        out.push(terminator);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app_with(displays: &[&str]) -> App {
+        let items = displays
+            .iter()
+            .map(|d| Item {
+                display: d.to_string(),
+                value: d.to_string(),
+                icon: None,
+                id: None,
+            })
+            .collect();
+        App::new(items, Keymap::default())
+    }
+
+    fn matches(app: &mut App, query: &str) -> Vec<String> {
+        app.query.set(query);
+        app.update_filter();
+        let mut found: Vec<String> = app
+            .filtered
+            .iter()
+            .map(|&idx| app.items[idx].display.clone())
+            .collect();
+        found.sort();
+        found
+    }
+
+    const FILES: &[&str] = &[
+        "src/main.rs",
+        "src/foo_test.rs",
+        "tests/foo.txt",
+        "docs/fxoxo.md",
+    ];
+
+    #[test]
+    fn test_plain_query_is_fuzzy() {
+        let mut app = app_with(FILES);
+        assert_eq!(matches(&mut app, "foo"), vec!["docs/fxoxo.md", "src/foo_test.rs", "tests/foo.txt"]);
+    }
+
+    #[test]
+    fn test_exact_term() {
+        let mut app = app_with(FILES);
+        assert_eq!(matches(&mut app, "'foo"), vec!["src/foo_test.rs", "tests/foo.txt"]);
+    }
+
+    #[test]
+    fn test_prefix_term() {
+        let mut app = app_with(FILES);
+        assert_eq!(matches(&mut app, "^src"), vec!["src/foo_test.rs", "src/main.rs"]);
+    }
+
+    #[test]
+    fn test_suffix_term() {
+        let mut app = app_with(FILES);
+        assert_eq!(matches(&mut app, ".rs$"), vec!["src/foo_test.rs", "src/main.rs"]);
+    }
+
+    #[test]
+    fn test_negated_term() {
+        let mut app = app_with(FILES);
+        assert_eq!(matches(&mut app, "!test"), vec!["docs/fxoxo.md", "src/main.rs"]);
+    }
+
+    #[test]
+    fn test_highlight_line_groups_matched_runs() {
+        let item = Item {
+            display: "firefox".to_string(),
+            value: String::new(),
+            icon: None,
+            id: None,
+        };
+        let line = highlight_line(&item, &[0, 1, 4], false, &Theme::default());
+        let parts: Vec<&str> = line.spans.iter().map(|s| s.content.as_ref()).collect();
+        assert_eq!(parts, vec!["fi", "re", "f", "ox"]);
+        assert!(line.spans[0].style.add_modifier.contains(Modifier::BOLD));
+        assert!(!line.spans[1].style.add_modifier.contains(Modifier::BOLD));
+    }
+
+    #[test]
+    fn test_limit_height_keeps_prompt_edge() {
+        let area = Rect::new(0, 0, 80, 50);
+        assert_eq!(limit_height(area, 20, false), Rect::new(0, 0, 80, 20));
+        assert_eq!(limit_height(area, 20, true), Rect::new(0, 30, 80, 20));
+        assert_eq!(limit_height(area, 100, false), area);
+    }
+
+    fn click(column: u16, row: u16) -> MouseEvent {
+        MouseEvent {
+            kind: MouseEventKind::Down(MouseButton::Left),
+            column,
+            row,
+            modifiers: KeyModifiers::NONE,
+        }
+    }
+
+    #[test]
+    fn test_click_selects_row_and_double_click_confirms() {
+        let mut app = app_with(FILES);
+        matches(&mut app, "");
+        app.list_area = Rect::new(1, 4, 40, 10);
+
+        app.handle_mouse(click(5, 6));
+        assert_eq!(app.selected, 2);
+        assert!(!app.should_quit);
+
+        // Clicks outside the list or below the last item are ignored
+        app.handle_mouse(click(5, 2));
+        app.handle_mouse(click(5, 12));
+        assert_eq!(app.selected, 2);
+
+        app.handle_mouse(click(5, 7));
+        app.handle_mouse(click(5, 7));
+        assert_eq!(app.selected, 3);
+        assert!(app.should_quit);
+        assert!(app.selected_item.is_some());
+    }
+
+    #[test]
+    fn test_outcomes_of_accept_accept_query_and_abort() {
+        let key = |code, modifiers| {
+            let mut app = app_with(FILES);
+            app.keymap = config::KeyBindings::default().keymap().unwrap();
+            matches(&mut app, "main");
+            app.handle_key(code, modifiers);
+            assert!(app.should_quit);
+            app.outcome()
+        };
+
+        let Outcome::Selected(item) = key(KeyCode::Enter, KeyModifiers::NONE) else {
+            panic!("enter should select the highlighted item");
+        };
+        assert!(item.value.contains("main"));
+        assert_eq!(key(KeyCode::Enter, KeyModifiers::SHIFT), Outcome::NewQuery("main".to_string()));
+        assert_eq!(key(KeyCode::Enter, KeyModifiers::ALT), Outcome::NewQuery("main".to_string()));
+        assert_eq!(key(KeyCode::Esc, KeyModifiers::NONE), Outcome::Aborted);
+    }
+
+    /// Rows of a `height`-row track covered by the scrollbar thumb
+    fn thumb_rows(state: Option<ScrollbarState>, height: u16) -> Option<std::ops::Range<u16>> {
+        use ratatui::{buffer::Buffer, widgets::StatefulWidget};
+
+        let mut state = state?;
+        let area = Rect::new(0, 0, 1, height);
+        let mut buffer = Buffer::empty(area);
+        scrollbar().render(area, &mut buffer, &mut state);
+        let thumb: Vec<u16> = (0..height).filter(|&y| buffer[(0, y)].symbol() == "█").collect();
+        Some(thumb[0]..thumb[thumb.len() - 1] + 1)
+    }
+
+    #[test]
+    fn test_scrollbar_tracks_offset() {
+        assert_eq!(thumb_rows(scrollbar_state(10, 0, 10, false), 10), None, "no overflow");
+
+        assert_eq!(thumb_rows(scrollbar_state(40, 0, 10, false), 10), Some(0..3));
+        assert_eq!(thumb_rows(scrollbar_state(40, 15, 10, false), 10), Some(4..6));
+        assert_eq!(thumb_rows(scrollbar_state(40, 30, 10, false), 10), Some(8..10));
+        assert_eq!(thumb_rows(scrollbar_state(40, 99, 10, false), 10), Some(8..10));
+
+        // Bottom-to-top lists start at the bottom
+        assert_eq!(thumb_rows(scrollbar_state(40, 0, 10, true), 10), Some(8..10));
+        assert_eq!(thumb_rows(scrollbar_state(40, 30, 10, true), 10), Some(0..3));
+    }
+
+    #[test]
+    fn test_wheel_moves_selection() {
+        let mut app = app_with(FILES);
+        matches(&mut app, "");
+        let wheel = |kind| MouseEvent { kind, column: 0, row: 0, modifiers: KeyModifiers::NONE };
+
+        app.handle_mouse(wheel(MouseEventKind::ScrollDown));
+        assert_eq!(app.selected, 1);
+        app.handle_mouse(wheel(MouseEventKind::ScrollUp));
+        app.handle_mouse(wheel(MouseEventKind::ScrollUp));
+        assert_eq!(app.selected, FILES.len() - 1);
+    }
+
+    #[test]
+    fn test_history_keys_recall_queries_at_empty_prompt() {
+        let mut app = app_with(FILES);
+        app.keymap = config::KeyBindings::default().keymap().unwrap();
+        app.query_history.push("main");
+        app.query_history.push("foo");
+        let press = |app: &mut App, c| {
+            app.handle_key(KeyCode::Char(c), KeyModifiers::CONTROL);
+            app.query.as_str().to_string()
+        };
+
+        assert_eq!(press(&mut app, 'p'), "foo");
+        assert_eq!(app.filtered.len(), 3, "recalled query is matched");
+        assert_eq!(press(&mut app, 'p'), "main");
+        assert_eq!(press(&mut app, 'n'), "foo");
+        assert_eq!(press(&mut app, 'n'), "");
+
+        // Once the query is edited the keys move through the results
+        matches(&mut app, "foo");
+        assert_eq!(press(&mut app, 'n'), "foo");
+        assert_eq!(app.selected, 1);
+        assert_eq!(press(&mut app, 'p'), "foo");
+        assert_eq!(app.selected, 0);
+    }
+
+    #[test]
+    fn test_streamed_items_are_matched() {
+        let mut app = app_with(&[]);
+        app.loader = Some(Loader::spawn(|sink| {
+            for d in FILES {
+                sink.send(Item {
+                    display: d.to_string(),
+                    value: d.to_string(),
+                    icon: None,
+                    id: None,
+                });
+            }
+        }));
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while app.loader.is_some() && Instant::now() < deadline {
+            app.poll_loader();
+        }
+
+        assert!(app.loader.is_none());
+        assert_eq!(app.items.len(), FILES.len());
+        assert_eq!(matches(&mut app, "^src"), vec!["src/foo_test.rs", "src/main.rs"]);
+    }
+
+    #[test]
+    fn test_reverse_layout_scrolls_to_selection() {
+        use ratatui::backend::TestBackend;
+
+        let names: Vec<String> = (0..30).map(|i| format!("item-{:02}", i)).collect();
+        let refs: Vec<&str> = names.iter().map(String::as_str).collect();
+        let mut app = app_with(&refs);
+        app.reverse = true;
+        matches(&mut app, "");
+
+        // "Up" on screen walks towards worse matches in the reversed list
+        for _ in 0..20 {
+            app.move_up();
+        }
+        assert_eq!(app.selected, 20);
+
+        let mut terminal = Terminal::new(TestBackend::new(30, 12)).unwrap();
+
+        let render = |terminal: &mut Terminal<TestBackend>, app: &mut App| -> Vec<String> {
+            terminal.draw(|f| ui(f, app, true)).unwrap();
+            let buffer = terminal.backend().buffer();
+            (0..buffer.area.height)
+                .map(|y| (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect())
+                .collect()
+        };
+        let display = |app: &App, row: usize| app.items[app.filtered[row]].display.clone();
+
+        let rows = render(&mut terminal, &mut app);
+        assert!(app.list_state.offset() > 0);
+        assert!(rows.iter().any(|r| r.contains(&display(&app, 20))), "{:#?}", rows);
+        assert!(rows[rows.len() - 2].contains('>'), "prompt should be at the bottom");
+
+        // Step back towards the prompt; the row above now holds the next
+        // worse match and clicking it selects it
+        app.move_down();
+        let rows = render(&mut terminal, &mut app);
+        let sel_row = rows.iter().position(|r| r.contains(&display(&app, 19))).unwrap();
+        assert!(rows[sel_row - 1].contains(&display(&app, 20)), "{:#?}", rows);
+
+        app.handle_mouse(click(5, sel_row as u16 - 1));
+        assert_eq!(app.selected, 20);
+    }
+
+    #[test]
+    fn test_scroll_offset_keeps_margin() {
+        // Moving down: scrolls once the selection gets within 3 rows of the bottom
+        assert_eq!(scroll_offset(0, 6, 10, 100, 3), 0);
+        assert_eq!(scroll_offset(0, 7, 10, 100, 3), 1);
+        assert_eq!(scroll_offset(1, 8, 10, 100, 3), 2);
+
+        // Moving up: scrolls once the selection gets within 3 rows of the top
+        assert_eq!(scroll_offset(20, 23, 10, 100, 3), 20);
+        assert_eq!(scroll_offset(20, 22, 10, 100, 3), 19);
+
+        // Wrapping to either end
+        assert_eq!(scroll_offset(90, 0, 10, 100, 3), 0);
+        assert_eq!(scroll_offset(0, 99, 10, 100, 3), 90);
+    }
+
+    #[test]
+    fn test_scroll_offset_edge_cases() {
+        // Everything fits
+        assert_eq!(scroll_offset(5, 3, 10, 8, 3), 0);
+        // Margin is capped at half the viewport
+        assert_eq!(scroll_offset(0, 2, 4, 100, 10), 0);
+        assert_eq!(scroll_offset(0, 3, 4, 100, 10), 1);
+        // No margin behaves like a plain "keep visible"
+        assert_eq!(scroll_offset(0, 9, 10, 100, 0), 0);
+        assert_eq!(scroll_offset(0, 10, 10, 100, 0), 1);
+    }
+
+    #[test]
+    fn test_max_results_caps_filtered() {
+        let mut app = app_with(FILES);
+        app.max_results = Some(2);
+        app.update_filter();
+        assert_eq!(app.filtered.len(), 2);
+        assert_eq!(app.items.len(), FILES.len());
+    }
+
+    fn ordered(app: &mut App, query: &str) -> Vec<String> {
+        app.query.set(query);
+        app.update_filter();
+        app.filtered.iter().map(|&idx| app.items[idx].display.clone()).collect()
+    }
+
+    #[test]
+    fn test_tiebreak_orders_equal_scores() {
+        // Every item matches "ab" as a plain substring with no word-boundary
+        // bonus, so all of them score the same
+        const TIED: &[&str] = &["xxabxxxx", "xabx", "xxxxxab", "xab"];
+        let mut matcher = FuzzyMatcher::new();
+        let pattern = nucleo::pattern::Pattern::parse(
+            "ab",
+            nucleo::pattern::CaseMatching::Smart,
+            nucleo::pattern::Normalization::Smart,
+        );
+        let scores: Vec<_> = TIED.iter().map(|d| matcher.score_pattern(&pattern, d)).collect();
+        assert!(scores.iter().all(|s| *s == scores[0]), "{:?}", scores);
+
+        let mut app = app_with(TIED);
+        assert_eq!(ordered(&mut app, "ab"), vec!["xab", "xabx", "xxxxxab", "xxabxxxx"]);
+
+        app.tiebreak = Tiebreak::Index;
+        assert_eq!(ordered(&mut app, "ab"), TIED.to_vec());
+
+        app.tiebreak = Tiebreak::Begin;
+        assert_eq!(ordered(&mut app, "ab"), vec!["xab", "xabx", "xxabxxxx", "xxxxxab"]);
+    }
+
+    #[test]
+    fn test_tiebreak_keeps_better_scores_first() {
+        let mut app = app_with(&["xxmain", "main"]);
+        app.tiebreak = Tiebreak::Index;
+        assert_eq!(ordered(&mut app, "main"), vec!["main", "xxmain"]);
+    }
+
+    #[test]
+    fn test_startup_action() {
+        assert_eq!(startup_action(1, true, false), Startup::Select);
+        assert_eq!(startup_action(2, true, true), Startup::Interactive);
+        assert_eq!(startup_action(0, true, false), Startup::Interactive);
+        assert_eq!(startup_action(0, false, true), Startup::NoMatch);
+        assert_eq!(startup_action(0, true, true), Startup::NoMatch);
+        assert_eq!(startup_action(1, false, true), Startup::Interactive);
+        assert_eq!(startup_action(0, false, false), Startup::Interactive);
+    }
+
+    #[test]
+    fn test_finish_matching_waits_for_loader() {
+        let mut app = app_with(&[]);
+        app.loader = Some(Loader::spawn(|sink| {
+            for n in 0..5000 {
+                sink.send(Item {
+                    display: format!("file{}", n),
+                    value: format!("file{}", n),
+                    icon: None,
+                    id: None,
+                });
+            }
+        }));
+        app.query.set("'file4999");
+        app.finish_matching();
+        assert!(app.loader.is_none());
+        assert_eq!(app.filtered.len(), 1);
+        assert_eq!(app.items[app.filtered[0]].display, "file4999");
+    }
+
+    #[test]
+    fn test_terms_and_together() {
+        let mut app = app_with(FILES);
+        assert_eq!(matches(&mut app, "^src !test .rs$"), vec!["src/main.rs"]);
+    }
+
+    #[test]
+    fn test_read_lines_skips_invalid_utf8() {
+        let input: &[u8] = b"first\n\xff\xfe broken\nafter\n";
+        assert_eq!(read_lines(input), ["first", "after"]);
+    }
+
+    #[test]
+    fn test_stdin_item_splits_fields() {
+        let args = Args::parse_from(["cvh-fuzzy", "--stdin", "--with-nth", "2", "--accept-nth", "1"]);
+        let item = stdin_item("org.mozilla.firefox\tFirefox Web Browser".to_string(), &args);
+        assert_eq!(item.display, "Firefox Web Browser");
+        assert_eq!(item.value, "org.mozilla.firefox");
+    }
+
+    #[test]
+    fn test_stdin_item_accepts_negative_fields() {
+        let args = Args::try_parse_from(["cvh-fuzzy", "--stdin", "--with-nth", "-1", "--accept-nth", "-2"]).unwrap();
+        let item = stdin_item("org.mozilla.firefox\tFirefox\tWeb Browser".to_string(), &args);
+        assert_eq!(item.display, "Web Browser");
+        assert_eq!(item.value, "Firefox");
+    }
+
+    #[test]
+    fn test_empty_delimiter_rejected() {
+        assert!(Args::try_parse_from(["cvh-fuzzy", "--stdin", "-d", ""]).is_err());
+    }
+
+    #[test]
+    fn test_stdin_item_defaults_to_whole_line() {
+        let args = Args::parse_from(["cvh-fuzzy", "--stdin"]);
+        let item = stdin_item("a\tb".to_string(), &args);
+        assert_eq!(item.display, "a\tb");
+        assert_eq!(item.value, "a\tb");
+
+        let args = Args::parse_from(["cvh-fuzzy", "--stdin", "-d", ":", "--with-nth", "2.."]);
+        let item = stdin_item("id:pretty:name".to_string(), &args);
+        assert_eq!(item.display, "pretty:name");
+        assert_eq!(item.value, "id:pretty:name");
+    }
+
+    fn sorted(lines: &[&str], field: &str, numeric: bool) -> Vec<String> {
+        let mut lines: Vec<String> = lines.iter().map(|l| l.to_string()).collect();
+        sort_lines(&mut lines, &field.parse().unwrap(), "\t", numeric);
+        lines
+    }
+
+    #[test]
+    fn test_sort_field_numeric() {
+        let lines = ["ten\t10", "two\t2", "one\t1"];
+        assert_eq!(sorted(&lines, "2", true), vec!["one\t1", "two\t2", "ten\t10"]);
+    }
+
+    #[test]
+    fn test_sort_field_lexical() {
+        let lines = ["ten\t10", "two\t2", "one\t1"];
+        assert_eq!(sorted(&lines, "2", false), vec!["one\t1", "ten\t10", "two\t2"]);
+        assert_eq!(sorted(&lines, "1", false), vec!["one\t1", "ten\t10", "two\t2"]);
+    }
+
+    #[test]
+    fn test_sort_field_non_numbers_last_and_stable() {
+        let lines = ["b\tn/a", "a\t3", "c\t", "d\t-1.5"];
+        assert_eq!(sorted(&lines, "-1", true), vec!["d\t-1.5", "a\t3", "b\tn/a", "c\t"]);
+    }
+
+    #[test]
+    fn test_numeric_requires_sort_field() {
+        assert!(Args::try_parse_from(["cvh-fuzzy", "--stdin", "--numeric"]).is_err());
+        let args = Args::try_parse_from(["cvh-fuzzy", "--stdin", "--sort-field", "-1", "--numeric"]).unwrap();
+        assert!(args.numeric);
+    }
+
+    #[test]
+    fn test_tac_reverses_input_after_sorting() {
+        let lines = || ["ten\t10", "two\t2", "one\t1"].map(String::from).to_vec();
+        let values = |argv: &[&str]| -> Vec<String> {
+            let args = Args::parse_from(argv);
+            stdin_items(lines(), &args).into_iter().map(|item| item.value).collect()
+        };
+
+        assert_eq!(values(&["cvh-fuzzy", "--stdin", "--tac"]), ["one\t1", "two\t2", "ten\t10"]);
+        assert_eq!(
+            values(&["cvh-fuzzy", "--stdin", "--tac", "--sort-field", "2", "--numeric"]),
+            ["ten\t10", "two\t2", "one\t1"]
+        );
+        assert_eq!(values(&["cvh-fuzzy", "--stdin"]), ["ten\t10", "two\t2", "one\t1"]);
+    }
+
+    #[test]
+    fn test_compose_output() {
+        assert_eq!(compose_output(None, Some("kitty"), false), "kitty\n");
+        assert_eq!(compose_output(None, Some("kitty"), true), "kitty\0");
+        assert_eq!(compose_output(Some("kit"), Some("kitty"), false), "kit\nkitty\n");
+        assert_eq!(compose_output(Some("kit"), Some("kitty"), true), "kit\0kitty\0");
+        // Aborted, or the selection went elsewhere
+        assert_eq!(compose_output(Some("new thing"), None, false), "new thing\n");
+        assert_eq!(compose_output(Some(""), None, true), "\0");
+        assert_eq!(compose_output(None, None, false), "");
+    }
+
+    #[test]
+    fn test_print_query_flag() {
+        let mut app = app_with(&["kitty"]);
+        app.query.set("kit");
+        let args = Args::parse_from(["cvh-fuzzy", "--stdin"]);
+        assert_eq!(query_line(&app, &args), None);
+        let args = Args::parse_from(["cvh-fuzzy", "--stdin", "--print-query"]);
+        assert_eq!(query_line(&app, &args), Some("kit"));
+    }
+
+    #[test]
+    fn test_path_accepts_several_roots() {
+        let args = Args::parse_from(["cvh-fuzzy", "-p", "/home/u/src", "-p", "/home/u/work,/tmp"]);
+        assert_eq!(
+            args.path,
+            vec![PathBuf::from("/home/u/src"), PathBuf::from("/home/u/work"), PathBuf::from("/tmp")]
+        );
+    }
+
+    fn displays(roots: &[&str], found: &[(usize, &str)]) -> Vec<String> {
+        let roots: Vec<PathBuf> = roots.iter().map(PathBuf::from).collect();
+        let labels = root_labels(&roots);
+        found
+            .iter()
+            .map(|&(index, path)| path_item(&roots[index], labels[index].as_deref(), Path::new(path), false).display)
+            .collect()
+    }
+
+    #[test]
+    fn test_path_item_icon() {
+        let item = path_item(Path::new("/x"), Some(Path::new("")), Path::new("/x/song.flac"), true);
+        assert_eq!(item.icon.as_deref(), Some(FileKind::Audio.glyph()));
+        assert_eq!(item.display, "song.flac");
+        assert_eq!(path_item(Path::new("/x"), None, Path::new("/x/song.flac"), false).icon, None);
+    }
+
+    #[test]
+    fn test_single_root_paths_are_relative() {
+        assert_eq!(displays(&["/home/u/src"], &[(0, "/home/u/src/a/main.rs")]), vec!["a/main.rs"]);
+    }
+
+    #[test]
+    fn test_several_roots_are_unambiguous() {
+        let found = [(0, "/home/u/src/main.rs"), (1, "/home/u/work/main.rs")];
+        assert_eq!(displays(&["/home/u/src", "/home/u/work"], &found), vec!["src/main.rs", "work/main.rs"]);
+
+        // Roots with the same name fall back to full paths
+        let found = [(0, "/a/src/main.rs"), (1, "/b/src/main.rs"), (2, "/c/docs/x.md")];
+        assert_eq!(
+            displays(&["/a/src", "/b/src", "/c/docs"], &found),
+            vec!["/a/src/main.rs", "/b/src/main.rs", "docs/x.md"]
+        );
+    }
+
+    #[test]
+    fn test_case_sensitive_flag() {
+        let mut app = app_with(&["foo", "Foo"]);
+        assert_eq!(matches(&mut app, "Foo"), vec!["Foo"]);
+
+        app.case_matching = case_matching(true, false);
+        assert_eq!(matches(&mut app, "foo"), vec!["foo"]);
+        assert_eq!(matches(&mut app, "Foo"), vec!["Foo"]);
+    }
+
+    #[test]
+    fn test_default_case_matching_is_smart() {
+        let mut app = app_with(&["foo"]);
+        assert_eq!(app.case_matching, CaseMatching::Smart);
+        assert_eq!(matches(&mut app, "foo"), vec!["foo"]);
+        assert!(matches(&mut app, "Foo").is_empty());
+    }
+
+    #[test]
+    fn test_case_insensitive_flag() {
+        let mut app = app_with(&["foo", "FOO"]);
+        app.case_matching = case_matching(false, true);
+        assert_eq!(matches(&mut app, "Foo"), vec!["FOO", "foo"]);
+    }
+
+    #[test]
+    fn test_literal_matches_substring_only() {
+        let mut app = app_with(&["src/main.rs", "s_r_c/m.rs", "docs/my notes.md", "!todo$"]);
+        app.literal = true;
+        assert_eq!(matches(&mut app, "src/m"), vec!["src/main.rs"]);
+        assert_eq!(matches(&mut app, "my notes"), vec!["docs/my notes.md"]);
+        assert_eq!(matches(&mut app, "!todo$"), vec!["!todo$"]);
+        assert_eq!(matches(&mut app, "^src"), Vec::<String>::new());
+        assert_eq!(matches(&mut app, "").len(), 4);
+    }
+
+    #[test]
+    fn test_diacritics_ignored_by_default() {
+        let mut app = app_with(&["café", "cafe\u{301} noir", "coffee"]);
+        assert_eq!(app.normalization, Normalization::Smart);
+        assert_eq!(matches(&mut app, "cafe"), vec!["café", "café noir"]);
+        assert_eq!(matches(&mut app, "café"), vec!["café", "café noir"]);
+    }
+
+    #[test]
+    fn test_exact_diacritics_flag() {
+        let mut app = app_with(&["café", "cafe\u{301} noir", "cafeteria"]);
+        app.normalization = normalization(true);
+        assert_eq!(matches(&mut app, "cafe"), vec!["cafeteria"]);
+        assert_eq!(matches(&mut app, "café"), vec!["café", "café noir"]);
+        assert_eq!(matches(&mut app, "cafe\u{301}"), vec!["café", "café noir"]);
+    }
+
+    #[test]
+    fn test_literal_pattern_escapes() {
+        assert_eq!(literal_pattern("a b"), "'a\\ b");
+        assert_eq!(literal_pattern("cost$"), "'cost\\$");
+        assert_eq!(literal_pattern("^x"), "'^x");
+    }
+}
@@ -41,6 +41,30 @@ impl FuzzyMatcher {
     pub fn matches(&mut self, pattern: &str, haystack: &str) -> bool {
         self.score(pattern, haystack).is_some()
     }
+
+    /// Score `haystack` against an already-parsed pattern
+    pub fn score_pattern(&mut self, pattern: &Pattern, haystack: &str) -> Option<u32> {
+        let mut buf = vec![];
+        let haystack = Utf32Str::new(haystack, &mut buf);
+        pattern.score(haystack, &mut self.matcher)
+    }
+
+    /// Sorted, deduplicated char positions of `haystack` matched by `pattern`
+    ///
+    /// Returns an empty list when the pattern is empty or doesn't match.
+    pub fn indices(&mut self, pattern: &Pattern, haystack: &str) -> Vec<u32> {
+        let mut buf = vec![];
+        let mut indices = vec![];
+        let haystack = Utf32Str::new(haystack, &mut buf);
+
+        if pattern.indices(haystack, &mut self.matcher, &mut indices).is_none() {
+            return Vec::new();
+        }
+
+        indices.sort_unstable();
+        indices.dedup();
+        indices
+    }
 }
 
 impl Default for FuzzyMatcher {
@@ -77,4 +101,15 @@ mod tests {
         let mut matcher = FuzzyMatcher::new();
         assert!(!matcher.matches("xyz", "Firefox"));
     }
+
+    #[test]
+    fn test_indices() {
+        let mut matcher = FuzzyMatcher::new();
+        let pattern = Pattern::parse("ffx", CaseMatching::Smart, Normalization::Smart);
+        assert_eq!(matcher.indices(&pattern, "Firefox"), vec![0, 4, 6]);
+
+        let empty = Pattern::parse("", CaseMatching::Smart, Normalization::Smart);
+        assert!(matcher.indices(&empty, "Firefox").is_empty());
+        assert!(matcher.indices(&pattern, "Chromium").is_empty());
+    }
 }
@@ -0,0 +1,133 @@
+//! Preview pane support
+//!
+//! Reads a capped, text-only excerpt of the highlighted file for display
+
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+/// Maximum number of bytes read from a previewed file
+const MAX_PREVIEW_BYTES: u64 = 64 * 1024;
+
+/// Number of leading bytes inspected for NUL when detecting binary files
+const BINARY_SNIFF_BYTES: usize = 1024;
+
+/// Maximum number of lines shown in the preview pane
+const MAX_PREVIEW_LINES: usize = 200;
+
+/// Cached preview of the most recently highlighted path
+#[derive(Debug, Default)]
+pub struct Preview {
+    /// Path the cached content belongs to
+    path: Option<String>,
+    /// Rendered preview text
+    content: String,
+}
+
+impl Preview {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Refresh the preview for `path`, re-reading only when the path changed
+    pub fn update(&mut self, path: Option<&str>) {
+        if self.path.as_deref() == path {
+            return;
+        }
+
+        self.content = match path {
+            Some(p) => render(Path::new(p)),
+            None => String::new(),
+        };
+        self.path = path.map(str::to_string);
+    }
+
+    /// Current preview text
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+}
+
+/// Build the preview text for a path, turning IO errors into pane text
+fn render(path: &Path) -> String {
+    let result = if path.is_dir() {
+        render_dir(path)
+    } else {
+        render_file(path)
+    };
+
+    result.unwrap_or_else(|e| e.to_string())
+}
+
+fn render_file(path: &Path) -> std::io::Result<String> {
+    let mut buf = Vec::new();
+    File::open(path)?
+        .take(MAX_PREVIEW_BYTES)
+        .read_to_end(&mut buf)?;
+
+    if is_binary(&buf) {
+        return Ok("<binary file>".to_string());
+    }
+
+    let text = String::from_utf8_lossy(&buf);
+    Ok(text
+        .lines()
+        .take(MAX_PREVIEW_LINES)
+        .collect::<Vec<_>>()
+        .join("\n"))
+}
+
+fn render_dir(path: &Path) -> std::io::Result<String> {
+    let mut names: Vec<String> = fs::read_dir(path)?
+        .filter_map(|e| e.ok())
+        .map(|e| {
+            let mut name = e.file_name().to_string_lossy().into_owned();
+            if e.file_type().is_ok_and(|t| t.is_dir()) {
+                name.push('/');
+            }
+            name
+        })
+        .collect();
+    names.sort();
+    names.truncate(MAX_PREVIEW_LINES);
+
+    Ok(names.join("\n"))
+}
+
+/// A file looks binary if its first KiB contains a NUL byte
+fn is_binary(buf: &[u8]) -> bool {
+    buf.iter().take(BINARY_SNIFF_BYTES).any(|&b| b == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_detection() {
+        assert!(is_binary(b"ELF\0\x01"));
+        assert!(!is_binary(b"fn main() {}\n"));
+    }
+
+    #[test]
+    fn test_missing_file_shows_error() {
+        let mut preview = Preview::new();
+        preview.update(Some("/nonexistent/cvh-fuzzy/preview"));
+        assert!(!preview.content().is_empty());
+    }
+
+    #[test]
+    fn test_line_cap() {
+        let path = std::env::temp_dir().join(format!("cvh-fuzzy-preview-{}", std::process::id()));
+        let body: String = (0..500).map(|i| format!("line {}\n", i)).collect();
+        fs::write(&path, body).unwrap();
+
+        let mut preview = Preview::new();
+        preview.update(path.to_str());
+        assert_eq!(preview.content().lines().count(), MAX_PREVIEW_LINES);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
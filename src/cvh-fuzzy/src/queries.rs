@@ -0,0 +1,189 @@
+//! Recent queries
+//!
+//! Queries are kept per mode in a small ring on disk and recalled at an
+//! empty prompt, like shell history at a fresh prompt
+
+use anyhow::Result;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Most recent queries, oldest first, with a recall cursor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryRing {
+    entries: Vec<String>,
+    capacity: usize,
+    /// Entry last recalled, None when not browsing
+    cursor: Option<usize>,
+}
+
+impl QueryRing {
+    /// Queries kept per mode
+    pub const DEFAULT_CAPACITY: usize = 100;
+
+    pub fn new(capacity: usize) -> Self {
+        Self { entries: Vec::new(), capacity, cursor: None }
+    }
+
+    /// Load the ring stored at `path`, treating a missing or unreadable
+    /// file as empty
+    pub fn load(path: &Path, capacity: usize) -> Self {
+        let mut ring = Self::new(capacity);
+        if let Ok(content) = fs::read_to_string(path) {
+            for line in content.lines() {
+                ring.push(line);
+            }
+        }
+        ring
+    }
+
+    /// Persist the ring, one query per line
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut content = self.entries.join("\n");
+        content.push('\n');
+        fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Add `query` as the newest entry
+    ///
+    /// Blank queries are ignored and an earlier copy of the same query is
+    /// dropped; the oldest entries go once the ring is full. Stops browsing.
+    pub fn push(&mut self, query: &str) {
+        self.cursor = None;
+        let query = query.trim();
+        if query.is_empty() || query.contains('\n') {
+            return;
+        }
+
+        self.entries.retain(|entry| entry != query);
+        self.entries.push(query.to_string());
+        let excess = self.entries.len().saturating_sub(self.capacity);
+        self.entries.drain(..excess);
+    }
+
+    /// Step to the next older query, staying on the oldest
+    pub fn older(&mut self) -> Option<&str> {
+        let index = match self.cursor {
+            Some(index) => index.saturating_sub(1),
+            None => self.entries.len().checked_sub(1)?,
+        };
+        self.cursor = Some(index);
+        self.current()
+    }
+
+    /// Step to the next newer query; past the newest, browsing stops and
+    /// None is returned
+    pub fn newer(&mut self) -> Option<&str> {
+        let index = self.cursor? + 1;
+        if index >= self.entries.len() {
+            self.cursor = None;
+            return None;
+        }
+        self.cursor = Some(index);
+        self.current()
+    }
+
+    /// The query last recalled, if browsing
+    pub fn current(&self) -> Option<&str> {
+        self.cursor.map(|index| self.entries[index].as_str())
+    }
+
+    /// Stop browsing
+    pub fn reset(&mut self) {
+        self.cursor = None;
+    }
+}
+
+impl Default for QueryRing {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+/// Where the queries of `mode` are kept
+pub fn ring_path(mode: &str) -> Option<PathBuf> {
+    dirs::state_dir().map(|d| d.join("cvh-fuzzy/queries").join(mode))
+}
+
+/// Add `query` to the stored ring of `mode`, ignoring any failure
+pub fn record(mode: &str, query: &str) {
+    let Some(path) = ring_path(mode) else {
+        return;
+    };
+    let mut ring = QueryRing::load(&path, QueryRing::DEFAULT_CAPACITY);
+    ring.push(query);
+    let _ = ring.save(&path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring(queries: &[&str]) -> QueryRing {
+        let mut ring = QueryRing::new(5);
+        for query in queries {
+            ring.push(query);
+        }
+        ring
+    }
+
+    #[test]
+    fn test_push_dedupes_and_ignores_blank() {
+        let ring = ring(&["fire", "term", "  ", "", "fire", "code "]);
+        assert_eq!(ring.entries, ["term", "fire", "code"]);
+    }
+
+    #[test]
+    fn test_push_drops_oldest_when_full() {
+        let ring = ring(&["a", "b", "c", "d", "e", "f", "g"]);
+        assert_eq!(ring.entries, ["c", "d", "e", "f", "g"]);
+    }
+
+    #[test]
+    fn test_cycle_older_and_newer() {
+        let mut ring = ring(&["a", "b", "c"]);
+        assert_eq!(ring.current(), None);
+        assert_eq!(ring.newer(), None);
+
+        assert_eq!(ring.older(), Some("c"));
+        assert_eq!(ring.older(), Some("b"));
+        assert_eq!(ring.older(), Some("a"));
+        assert_eq!(ring.older(), Some("a"), "stays on the oldest");
+
+        assert_eq!(ring.newer(), Some("b"));
+        assert_eq!(ring.newer(), Some("c"));
+        assert_eq!(ring.newer(), None, "past the newest is the empty prompt");
+        assert_eq!(ring.current(), None);
+        assert_eq!(ring.older(), Some("c"), "starts over from the newest");
+    }
+
+    #[test]
+    fn test_push_and_reset_stop_browsing() {
+        let mut ring = ring(&["a", "b"]);
+        ring.older();
+        ring.push("c");
+        assert_eq!(ring.older(), Some("c"));
+        ring.reset();
+        assert_eq!(ring.current(), None);
+        assert_eq!(QueryRing::new(3).older(), None);
+    }
+
+    #[test]
+    fn test_save_and_load_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("cvh-fuzzy-queries-{}", std::process::id()));
+        let path = dir.join("queries/apps");
+
+        assert_eq!(QueryRing::load(&path, 5), QueryRing::new(5));
+        let saved = ring(&["fire", "term", "code"]);
+        saved.save(&path).unwrap();
+        assert_eq!(QueryRing::load(&path, 5), saved);
+        assert_eq!(QueryRing::load(&path, 2).entries, ["term", "code"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,147 @@
+//! Terminal setup and teardown for the TUI
+//!
+//! Raw mode, the alternate screen and mouse capture must be undone however
+//! the finder exits: normally, through an early `?` return, or by panic.
+
+use crossterm::{
+    cursor::Show,
+    event::{DisableMouseCapture, EnableMouseCapture},
+    execute,
+    terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use std::{
+    io,
+    panic,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// Whether the terminal is currently set up for the TUI
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+/// Whether mouse capture was enabled with it
+static MOUSE: AtomicBool = AtomicBool::new(false);
+
+/// Runs a teardown exactly once, when restored or dropped
+pub struct RestoreGuard<F: FnOnce()> {
+    teardown: Option<F>,
+}
+
+impl<F: FnOnce()> RestoreGuard<F> {
+    pub fn new(teardown: F) -> Self {
+        Self { teardown: Some(teardown) }
+    }
+
+    /// Run the teardown now instead of on drop
+    pub fn restore(mut self) {
+        self.run();
+    }
+
+    fn run(&mut self) {
+        if let Some(teardown) = self.teardown.take() {
+            teardown();
+        }
+    }
+}
+
+impl<F: FnOnce()> Drop for RestoreGuard<F> {
+    fn drop(&mut self) {
+        self.run();
+    }
+}
+
+/// Enter raw mode and the alternate screen (and capture the mouse)
+///
+/// The returned guard restores the terminal when dropped. A panic hook
+/// restores it first too, so the panic message lands on the normal screen.
+pub fn setup(mouse: bool) -> io::Result<RestoreGuard<fn()>> {
+    install_panic_hook();
+
+    ACTIVE.store(true, Ordering::SeqCst);
+    MOUSE.store(mouse, Ordering::SeqCst);
+    let guard = RestoreGuard::new(restore as fn());
+
+    enable_raw_mode()?;
+    let mut stdout = io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    if mouse {
+        execute!(stdout, EnableMouseCapture)?;
+    }
+    Ok(guard)
+}
+
+/// Undo `setup`; does nothing if the terminal was already restored
+pub fn restore() {
+    if !ACTIVE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    // Keep going on errors, each step is independent
+    let _ = disable_raw_mode();
+    let mut stdout = io::stdout();
+    if MOUSE.load(Ordering::SeqCst) {
+        let _ = execute!(stdout, DisableMouseCapture);
+    }
+    let _ = execute!(stdout, LeaveAlternateScreen, Show);
+}
+
+fn install_panic_hook() {
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+    if INSTALLED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        default_hook(info);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn counting(count: &Cell<u32>) -> RestoreGuard<impl FnOnce() + '_> {
+        RestoreGuard::new(move || count.set(count.get() + 1))
+    }
+
+    fn fails_after_setup(count: &Cell<u32>) -> anyhow::Result<()> {
+        let _guard = counting(count);
+        Err(anyhow::anyhow!("draw failed"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_guard_restores_on_early_return() {
+        let count = Cell::new(0);
+        assert!(fails_after_setup(&count).is_err());
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn test_guard_restores_once_when_restored_explicitly() {
+        let count = Cell::new(0);
+        let guard = counting(&count);
+        assert_eq!(count.get(), 0);
+        guard.restore();
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn test_guard_restores_on_panic() {
+        let count = Cell::new(0);
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _guard = counting(&count);
+            panic!("render bug");
+        }));
+        assert!(result.is_err());
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn test_restore_without_setup_is_noop() {
+        assert!(!ACTIVE.load(Ordering::SeqCst));
+        restore();
+        assert!(!ACTIVE.load(Ordering::SeqCst));
+    }
+}
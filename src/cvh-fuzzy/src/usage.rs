@@ -0,0 +1,137 @@
+//! Application launch history
+//!
+//! Tracks how often and how recently each application was launched so
+//! frequently used apps can be ranked first
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const HOUR: u64 = 60 * 60;
+const DAY: u64 = 24 * HOUR;
+
+/// Launch statistics for one application
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Usage {
+    /// Number of launches
+    pub count: u32,
+    /// Unix timestamp (seconds) of the last launch
+    pub last_used: u64,
+}
+
+/// Launch statistics keyed by desktop-id
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct UsageStore {
+    apps: HashMap<String, Usage>,
+}
+
+impl UsageStore {
+    /// Load the store, treating a missing or unreadable file as empty
+    pub fn load() -> Self {
+        store_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Persist the store to disk
+    pub fn save(&self) -> Result<()> {
+        let Some(path) = store_path() else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Record a launch of `id` at `now`
+    pub fn record(&mut self, id: &str, now: u64) {
+        let usage = self.apps.entry(id.to_string()).or_default();
+        usage.count = usage.count.saturating_add(1);
+        usage.last_used = now;
+    }
+
+    /// Frecency score of `id`, zero if it was never launched
+    pub fn score(&self, id: &str, now: u64) -> f64 {
+        self.apps.get(id).map_or(0.0, |usage| frecency(usage, now))
+    }
+}
+
+/// Score launches by count, weighted by how recently the app was last used
+pub fn frecency(usage: &Usage, now: u64) -> f64 {
+    let age = now.saturating_sub(usage.last_used);
+    let weight = if age < HOUR {
+        4.0
+    } else if age < DAY {
+        2.0
+    } else if age < 7 * DAY {
+        1.0
+    } else if age < 30 * DAY {
+        0.5
+    } else {
+        0.25
+    };
+
+    f64::from(usage.count) * weight
+}
+
+/// Record a launch of `id` on disk, ignoring any failure
+pub fn record_launch(id: &str) {
+    let mut store = UsageStore::load();
+    store.record(id, now());
+    let _ = store.save();
+}
+
+/// Current Unix time in seconds
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn store_path() -> Option<PathBuf> {
+    dirs::state_dir().map(|d| d.join("cvh-fuzzy/apps.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn test_frecency_prefers_more_launches() {
+        let often = Usage { count: 10, last_used: NOW - 2 * DAY };
+        let rarely = Usage { count: 2, last_used: NOW - 2 * DAY };
+        assert!(frecency(&often, NOW) > frecency(&rarely, NOW));
+    }
+
+    #[test]
+    fn test_frecency_decays_with_age() {
+        let recent = Usage { count: 5, last_used: NOW - 60 };
+        let stale = Usage { count: 5, last_used: NOW - 60 * DAY };
+        assert!(frecency(&recent, NOW) > frecency(&stale, NOW));
+        assert_eq!(frecency(&recent, NOW), 20.0);
+        assert_eq!(frecency(&stale, NOW), 1.25);
+    }
+
+    #[test]
+    fn test_record_and_score() {
+        let mut store = UsageStore::default();
+        assert_eq!(store.score("firefox.desktop", NOW), 0.0);
+
+        store.record("firefox.desktop", NOW);
+        store.record("firefox.desktop", NOW);
+        assert_eq!(store.apps["firefox.desktop"], Usage { count: 2, last_used: NOW });
+        assert!(store.score("firefox.desktop", NOW) > store.score("kitty.desktop", NOW));
+    }
+}
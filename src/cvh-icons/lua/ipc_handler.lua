@@ -251,9 +251,294 @@ function json.decode(str)
     return result
 end
 
+-- Raw DEFLATE (RFC 1951) decoder for compressed frames, after zlib's
+-- puff.c. Plain arithmetic instead of bit operators keeps it working on
+-- Lua 5.1 and LuaJIT.
+local Inflate = {}
+
+local unpack = table.unpack or unpack
+
+local LENGTH_BASE = {
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258
+}
+local LENGTH_EXTRA = {
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0
+}
+local DIST_BASE = {
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577
+}
+local DIST_EXTRA = {
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13
+}
+-- Order code length code lengths are stored in
+local CLEN_ORDER = { 16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15 }
+
+local POW2 = {}
+do
+    local p = 1
+    for i = 0, 24 do
+        POW2[i] = p
+        p = p * 2
+    end
+end
+
+-- Bit reader over a string, least significant bit first
+local function bit_reader(data)
+    local pos, bitbuf, bitcnt = 1, 0, 0
+    local reader = {}
+
+    function reader.bits(n)
+        while bitcnt < n do
+            local byte = string.byte(data, pos)
+            if not byte then
+                error("truncated deflate stream")
+            end
+            pos = pos + 1
+            bitbuf = bitbuf + byte * POW2[bitcnt]
+            bitcnt = bitcnt + 8
+        end
+        local value = bitbuf % POW2[n]
+        bitbuf = math.floor(bitbuf / POW2[n])
+        bitcnt = bitcnt - n
+        return value
+    end
+
+    -- Skip to the next byte boundary; bits() never buffers a whole byte
+    -- more than it needs, so nothing is left buffered after this
+    function reader.align()
+        reader.bits(bitcnt % 8)
+    end
+
+    -- The next n bytes as a string, once aligned
+    function reader.take(n)
+        if pos + n - 1 > #data then
+            error("truncated deflate stream")
+        end
+        local chunk = string.sub(data, pos, pos + n - 1)
+        pos = pos + n
+        return chunk
+    end
+
+    return reader
+end
+
+-- Canonical Huffman code from code lengths lengths[0..n-1]
+local function huffman(lengths, n)
+    local count, symbol, offs = {}, {}, {}
+    for len = 0, 15 do
+        count[len] = 0
+    end
+    for sym = 0, n - 1 do
+        count[lengths[sym]] = count[lengths[sym]] + 1
+    end
+    offs[1] = 0
+    for len = 1, 14 do
+        offs[len + 1] = offs[len] + count[len]
+    end
+    for sym = 0, n - 1 do
+        local len = lengths[sym]
+        if len ~= 0 then
+            symbol[offs[len]] = sym
+            offs[len] = offs[len] + 1
+        end
+    end
+    return { count = count, symbol = symbol }
+end
+
+local function decode_symbol(reader, code_table)
+    local code, first, index = 0, 0, 0
+    for len = 1, 15 do
+        code = code + reader.bits(1)
+        local count = code_table.count[len]
+        if code - count < first then
+            return code_table.symbol[index + (code - first)]
+        end
+        index = index + count
+        first = (first + count) * 2
+        code = code * 2
+    end
+    error("invalid deflate code")
+end
+
+local fixed_codes
+local function fixed_tables()
+    if not fixed_codes then
+        local lengths = {}
+        for sym = 0, 287 do
+            lengths[sym] = (sym < 144 and 8) or (sym < 256 and 9) or (sym < 280 and 7) or 8
+        end
+        local dists = {}
+        for sym = 0, 29 do
+            dists[sym] = 5
+        end
+        fixed_codes = { huffman(lengths, 288), huffman(dists, 30) }
+    end
+    return fixed_codes[1], fixed_codes[2]
+end
+
+local function dynamic_tables(reader)
+    local nlen = reader.bits(5) + 257
+    local ndist = reader.bits(5) + 1
+    local ncode = reader.bits(4) + 4
+    if nlen > 286 or ndist > 30 then
+        error("invalid deflate table sizes")
+    end
+
+    local clens = {}
+    for i = 0, 18 do
+        clens[i] = 0
+    end
+    for i = 1, ncode do
+        clens[CLEN_ORDER[i]] = reader.bits(3)
+    end
+    local clen_code = huffman(clens, 19)
+
+    local lengths, index = {}, 0
+    while index < nlen + ndist do
+        local sym = decode_symbol(reader, clen_code)
+        if sym < 16 then
+            lengths[index] = sym
+            index = index + 1
+        else
+            local len, rep = 0, 0
+            if sym == 16 then
+                if index == 0 then
+                    error("deflate length repeat with no previous length")
+                end
+                len, rep = lengths[index - 1], 3 + reader.bits(2)
+            elseif sym == 17 then
+                rep = 3 + reader.bits(3)
+            else
+                rep = 11 + reader.bits(7)
+            end
+            if index + rep > nlen + ndist then
+                error("too many deflate code lengths")
+            end
+            for _ = 1, rep do
+                lengths[index] = len
+                index = index + 1
+            end
+        end
+    end
+
+    local dists = {}
+    for sym = 0, ndist - 1 do
+        dists[sym] = lengths[nlen + sym]
+    end
+    return huffman(lengths, nlen), huffman(dists, ndist)
+end
+
+-- Decode one Huffman-coded block into out, a byte array
+local function inflate_codes(reader, out, lencode, distcode, max_size)
+    local n = out.n
+    while true do
+        local sym = decode_symbol(reader, lencode)
+        if sym < 256 then
+            n = n + 1
+            out[n] = sym
+        elseif sym == 256 then
+            out.n = n
+            return
+        else
+            sym = sym - 256
+            if sym > 29 then
+                error("invalid deflate length code")
+            end
+            local len = LENGTH_BASE[sym] + reader.bits(LENGTH_EXTRA[sym])
+            local dsym = decode_symbol(reader, distcode)
+            if dsym > 29 then
+                error("invalid deflate distance code")
+            end
+            local dist = DIST_BASE[dsym + 1] + reader.bits(DIST_EXTRA[dsym + 1])
+            if dist > n then
+                error("deflate distance too far back")
+            end
+            for _ = 1, len do
+                n = n + 1
+                out[n] = out[n - dist]
+            end
+        end
+        if n > max_size then
+            error("inflated message exceeds " .. max_size .. " bytes")
+        end
+    end
+end
+
+-- Decompress a raw DEFLATE stream, failing once the output would pass
+-- max_size bytes
+function Inflate.inflate(data, max_size)
+    local reader = bit_reader(data)
+    local out = { n = 0 }
+    local final
+    repeat
+        final = reader.bits(1)
+        local kind = reader.bits(2)
+        if kind == 0 then
+            reader.align()
+            local len = reader.bits(16)
+            if len + reader.bits(16) ~= 65535 then
+                error("corrupt stored deflate block")
+            end
+            if out.n + len > max_size then
+                error("inflated message exceeds " .. max_size .. " bytes")
+            end
+            local chunk = reader.take(len)
+            for i = 1, len do
+                out[out.n + i] = string.byte(chunk, i)
+            end
+            out.n = out.n + len
+        elseif kind == 1 then
+            local lencode, distcode = fixed_tables()
+            inflate_codes(reader, out, lencode, distcode, max_size)
+        elseif kind == 2 then
+            local lencode, distcode = dynamic_tables(reader)
+            inflate_codes(reader, out, lencode, distcode, max_size)
+        else
+            error("invalid deflate block type")
+        end
+    until final == 1
+
+    local parts = {}
+    for i = 1, out.n, 4096 do
+        parts[#parts + 1] = string.char(unpack(out, i, math.min(i + 4095, out.n)))
+    end
+    return table.concat(parts)
+end
+
 -- IPC Communication
 local IPC = {}
 
+-- Largest message either side sends, before compression
+local MAX_MESSAGE_SIZE = 1048576
+
+-- Frame flags once "deflate" is agreed on in the handshake: every message
+-- then starts with one of these. The daemon deflates large requests; our
+-- responses always go out plain.
+local FRAME_PLAIN = 0
+local FRAME_DEFLATE = 1
+IPC.framed = false
+
+-- Unwrap a frame into the message it carries
+function IPC.decode_frame(frame)
+    local flag = string.byte(frame, 1)
+    if flag == FRAME_PLAIN then
+        return string.sub(frame, 2)
+    elseif flag == FRAME_DEFLATE then
+        local ok, result = pcall(Inflate.inflate, string.sub(frame, 2), MAX_MESSAGE_SIZE)
+        if not ok then
+            return nil, "Failed to inflate message: " .. tostring(result)
+        end
+        return result
+    elseif flag then
+        return nil, "Unknown frame flag " .. flag
+    end
+    return nil, "Empty frame"
+end
+
 -- Read a length-prefixed message from stdin
 function IPC.receive()
     -- Read 4-byte length prefix (little-endian)
@@ -265,7 +550,8 @@ function IPC.receive()
     local b1, b2, b3, b4 = string.byte(len_bytes, 1, 4)
     local length = b1 + b2 * 256 + b3 * 65536 + b4 * 16777216
 
-    if length > 1048576 then -- 1 MB limit
+    -- A frame is one byte longer than the largest message it may carry
+    if length > MAX_MESSAGE_SIZE + (IPC.framed and 1 or 0) then
         return nil, "Message too large"
     end
 
@@ -275,6 +561,14 @@ function IPC.receive()
         return nil, "Incomplete message"
     end
 
+    if IPC.framed then
+        local message, err = IPC.decode_frame(data)
+        if not message then
+            return nil, err
+        end
+        data = message
+    end
+
     local ok, result = pcall(json.decode, data)
     if not ok then
         return nil, "JSON decode error: " .. tostring(result)
@@ -286,6 +580,9 @@ end
 -- Send a length-prefixed message to stdout
 function IPC.send(msg)
     local data = json.encode(msg)
+    if IPC.framed then
+        data = string.char(FRAME_PLAIN) .. data
+    end
     local length = #data
 
     -- Write 4-byte length prefix (little-endian)
@@ -322,11 +619,12 @@ function Canvas:fill_rect(x, y, w, h, color)
     self.commands[#self.commands + 1] = cmd
 end
 
-function Canvas:stroke_rect(x, y, w, h, color, stroke_width)
+function Canvas:stroke_rect(x, y, w, h, color, stroke_width, dash)
     self.commands[#self.commands + 1] = {
         type = "StrokeRect",
         x = x, y = y, w = w, h = h,
-        color = color, width = stroke_width or 1
+        color = color, width = stroke_width or 1,
+        dash = dash
     }
 end
 
@@ -346,11 +644,21 @@ function Canvas:stroke_circle(cx, cy, r, color, stroke_width)
     }
 end
 
-function Canvas:line(x1, y1, x2, y2, color, stroke_width)
+function Canvas:arc(cx, cy, r, start_deg, end_deg, color, stroke_width)
+    self.commands[#self.commands + 1] = {
+        type = "Arc",
+        cx = cx, cy = cy, r = r,
+        start_deg = start_deg, end_deg = end_deg,
+        color = color, width = stroke_width or 1
+    }
+end
+
+function Canvas:line(x1, y1, x2, y2, color, stroke_width, dash)
     self.commands[#self.commands + 1] = {
         type = "Line",
         x1 = x1, y1 = y1, x2 = x2, y2 = y2,
-        color = color, width = stroke_width or 1
+        color = color, width = stroke_width or 1,
+        dash = dash
     }
 end
 
@@ -363,6 +671,15 @@ function Canvas:text(text, x, y, size, color, align)
     }
 end
 
+function Canvas:text_box(text, x, y, w, size, color, align)
+    self.commands[#self.commands + 1] = {
+        type = "TextBox",
+        text = text, x = x, y = y, w = w,
+        size = size, color = color,
+        align = align or "left"
+    }
+end
+
 function Canvas:image(path, x, y, w, h)
     self.commands[#self.commands + 1] = {
         type = "Image",
@@ -370,6 +687,61 @@ function Canvas:image(path, x, y, w, h)
     }
 end
 
+function Canvas:round_rect(x, y, w, h, r, color)
+    self.commands[#self.commands + 1] = {
+        type = "RoundRect",
+        x = x, y = y, w = w, h = h, r = r,
+        color = color
+    }
+end
+
+function Canvas:linear_gradient(x, y, w, h, stops)
+    local list = {}
+    for i, stop in ipairs(stops) do
+        list[i] = {
+            offset = stop.offset or stop[1],
+            color = stop.color or stop[2]
+        }
+    end
+    self.commands[#self.commands + 1] = {
+        type = "LinearGradient",
+        x = x, y = y, w = w, h = h,
+        stops = list
+    }
+end
+
+function Canvas:clip(x, y, w, h)
+    self.commands[#self.commands + 1] = {
+        type = "Clip",
+        x = x, y = y, w = w, h = h
+    }
+end
+
+function Canvas:reset_clip()
+    self.commands[#self.commands + 1] = { type = "ResetClip" }
+end
+
+function Canvas:set_alpha(alpha)
+    self.commands[#self.commands + 1] = { type = "SetAlpha", alpha = alpha }
+end
+
+function Canvas:reset_alpha()
+    self.commands[#self.commands + 1] = { type = "ResetAlpha" }
+end
+
+function Canvas:push_transform(rotate_deg, tx, ty, scale)
+    self.commands[#self.commands + 1] = {
+        type = "PushTransform",
+        rotate_deg = rotate_deg or 0,
+        tx = tx or 0, ty = ty or 0,
+        scale = scale or 1
+    }
+end
+
+function Canvas:pop_transform()
+    self.commands[#self.commands + 1] = { type = "PopTransform" }
+end
+
 function Canvas:clear(color)
     self.commands[#self.commands + 1] = {
         type = "Clear",
@@ -385,7 +757,210 @@ function Canvas:get_height()
     return self.height
 end
 
+-- The daemon's action policy, passed in at startup; without it the
+-- daemon's defaults apply
+local ActionPolicy = {}
+ActionPolicy.OPENER = "xdg-open"
+
+local function word_set(list, default)
+    local set = {}
+    for word in (list or default):gmatch("%S+") do
+        set[word] = true
+    end
+    return set
+end
+
+ActionPolicy.allowed_commands = word_set(os.getenv("CVH_ALLOWED_COMMANDS"), "xdg-open")
+ActionPolicy.allowed_uri_schemes = word_set(os.getenv("CVH_ALLOWED_URI_SCHEMES"), "file http https mailto")
+
+-- Absolute paths without ".." and URIs with an allowed scheme
+function ActionPolicy.can_open(target)
+    if target:sub(1, 1) == "/" then
+        for part in target:gmatch("[^/]+") do
+            if part == ".." then
+                return false
+            end
+        end
+        return true
+    end
+    local scheme = target:match("^(%a[%w+%-.]*):")
+    return scheme ~= nil and ActionPolicy.allowed_uri_schemes[scheme:lower()] == true
+end
+
+-- Whether the daemon would carry out an open or spawn of payload
+function ActionPolicy.allows(action, payload)
+    if action == "open" then
+        return ActionPolicy.can_open(payload)
+    end
+    local words = {}
+    for word in payload:gmatch("%S+") do
+        words[#words + 1] = word
+    end
+    if not words[1] or not ActionPolicy.allowed_commands[words[1]] then
+        return false
+    end
+    -- Spawning the opener is an open in disguise
+    if words[1] == ActionPolicy.OPENER then
+        return #words == 2 and ActionPolicy.can_open(words[2])
+    end
+    return true
+end
+
 -- Icon script manager
+-- cvh.open/cvh.spawn only queue a request: the first one made while an
+-- event is handled becomes that event's action, which the daemon checks
+-- against its action policy again before running it outside the sandbox.
+-- They return false for requests the policy rejects and for any that
+-- would be dropped: made outside an event, or after the event already has
+-- its action. True is provisional, as launching may still fail.
+local queued_actions = nil
+
+cvh = cvh or {}
+
+local function queue_action(action, payload)
+    if not ActionPolicy.allows(action, payload) then
+        io.stderr:write("cvh." .. action .. " rejected by the action policy: " .. payload .. "\n")
+        return false
+    end
+    if not queued_actions or queued_actions[1] then
+        return false
+    end
+    queued_actions[1] = { action = action, payload = payload }
+    return true
+end
+
+function cvh.open(target)
+    return queue_action("open", tostring(target))
+end
+
+function cvh.spawn(command)
+    return queue_action("spawn", tostring(command))
+end
+
+-- cvh.state: per-icon state that survives restarts. The daemon binds a
+-- read-write directory for this icon into the sandbox and names it in
+-- CVH_STATE_DIR; each script's state is one JSON object in there, in the
+-- same format as the daemon's StateStore.
+local State = {}
+State.MAX_BYTES = 16384
+State.MAX_DEPTH = 32
+State.dir = os.getenv("CVH_STATE_DIR")
+State.path = nil
+-- Each key's value, JSON-encoded
+State.values = {}
+
+-- Raise the daemon's error for values JSON can't represent
+local function check_json(value, depth)
+    if depth > State.MAX_DEPTH then
+        error("json nesting too deep", 0)
+    end
+    local t = type(value)
+    if t == "number" then
+        if value ~= value or value == math.huge or value == -math.huge then
+            error("cannot encode NaN or infinity as json", 0)
+        end
+    elseif t == "table" then
+        for k, v in pairs(value) do
+            check_json(k, depth + 1)
+            check_json(v, depth + 1)
+        end
+    elseif t ~= "nil" and t ~= "boolean" and t ~= "string" then
+        error("cannot encode " .. t .. " as json", 0)
+    end
+end
+
+local function encode_state(values)
+    local parts = {}
+    for key, encoded in pairs(values) do
+        parts[#parts + 1] = json.encode(key) .. ":" .. encoded
+    end
+    return "{" .. table.concat(parts, ",") .. "}"
+end
+
+-- Load what an earlier run of script_path saved; a missing or unreadable
+-- state file starts out empty, and without a directory nothing is kept
+function State.open(dir, script_path)
+    State.values = {}
+    State.path = nil
+    if not dir or dir == "" then
+        return
+    end
+    State.path = dir .. "/" .. script_path:match("[^/]*$") .. ".state.json"
+
+    local file = io.open(State.path, "rb")
+    if not file then
+        return
+    end
+    local data = file:read("*a")
+    file:close()
+    local ok, values = pcall(json.decode, data)
+    if ok and type(values) == "table" then
+        for key, value in pairs(values) do
+            State.values[tostring(key)] = json.encode(value)
+        end
+    end
+end
+
+-- Set key (nil removes it) and save; state over the size cap is refused
+-- and the old state kept
+function State.set(key, value)
+    local values = {}
+    for k, v in pairs(State.values) do
+        values[k] = v
+    end
+    values[key] = value ~= nil and json.encode(value) or nil
+
+    local encoded = encode_state(values)
+    if #encoded > State.MAX_BYTES then
+        error(string.format("state too large (%d bytes, max %d)", #encoded, State.MAX_BYTES), 0)
+    end
+
+    -- Write then rename so a crash never leaves half a state file
+    local tmp = State.path .. ".tmp"
+    local file, err = io.open(tmp, "wb")
+    if not file then
+        error(tostring(err), 0)
+    end
+    local written, write_err = file:write(encoded)
+    file:close()
+    if not written then
+        error(tostring(write_err), 0)
+    end
+    local renamed, rename_err = os.rename(tmp, State.path)
+    if not renamed then
+        error(tostring(rename_err), 0)
+    end
+    State.values = values
+end
+
+cvh.state = {}
+
+function cvh.state.get(key)
+    local encoded = State.values[tostring(key)]
+    if encoded == nil then
+        return nil
+    end
+    -- A fresh copy, as a restarted script would see it
+    return json.decode(encoded)
+end
+
+function cvh.state.set(key, value)
+    if type(key) ~= "string" and type(key) ~= "number" then
+        error("bad argument #1 to 'set' (string expected, got " .. type(key) .. ")", 2)
+    end
+    if not State.path then
+        error("no state store for this script", 2)
+    end
+    local ok, err = pcall(check_json, value, 1)
+    if not ok then
+        error(err, 2)
+    end
+    ok, err = pcall(State.set, tostring(key), value)
+    if not ok then
+        error("state set failed: " .. tostring(err), 2)
+    end
+end
+
 local IconManager = {}
 IconManager.loaded_script = nil
 IconManager.icon = nil
@@ -394,6 +969,7 @@ function IconManager.load_script(script_path)
     -- Reset any previously loaded script
     IconManager.icon = nil
     Icon = nil
+    State.open(State.dir, script_path)
 
     -- Load and execute the script
     local chunk, err = loadfile(script_path)
@@ -450,21 +1026,27 @@ function IconManager.call_init()
     return true
 end
 
+-- Returns the draw commands, nil (no error) and the delay in milliseconds
+-- before the next frame if render() returned one to animate
 function IconManager.call_render(canvas_width, canvas_height)
     if not IconManager.icon then
         return nil, "No icon loaded"
     end
 
     local canvas = Canvas.new(canvas_width, canvas_height)
+    local next_frame_ms = nil
 
     if type(IconManager.icon.render) == "function" then
-        local ok, err = pcall(IconManager.icon.render, IconManager.icon, canvas)
+        local ok, result = pcall(IconManager.icon.render, IconManager.icon, canvas)
         if not ok then
-            return nil, "render() failed: " .. tostring(err)
+            return nil, "render() failed: " .. tostring(result)
+        end
+        if type(result) == "number" and result >= 0 and result < 2^32 then
+            next_frame_ms = math.floor(result)
         end
     end
 
-    return canvas.commands
+    return canvas.commands, nil, next_frame_ms
 end
 
 function IconManager.call_event(event)
@@ -475,6 +1057,7 @@ function IconManager.call_event(event)
     local icon = IconManager.icon
     local handled = false
     local action = nil
+    queued_actions = {}
 
     if event.type == "Click" then
         if type(icon.on_click) == "function" then
@@ -516,6 +1099,12 @@ function IconManager.call_event(event)
         handled = true
     end
 
+    if queued_actions[1] then
+        handled = true
+        action = queued_actions[1]
+    end
+    queued_actions = nil
+
     return { handled = handled, action = action }
 end
 
@@ -554,13 +1143,30 @@ end
 -- Request handlers
 local Handlers = {}
 
+-- Optional features handled here; the daemon only uses those both sides list
+local FEATURES = { "render_batch", "ping", "deflate" }
+
 function Handlers.Handshake(request)
     local remote_version = request.version or 0
     local success = (remote_version == PROTOCOL_VERSION)
+
+    -- Messages after this acknowledgement are framed if both sides
+    -- support deflate
+    IPC.framed = false
+    for _, name in ipairs(request.features or {}) do
+        if name == "deflate" then
+            IPC.pending_framed = success
+        end
+    end
+
+    -- Only JSON is implemented here; the daemon stays on JSON unless
+    -- bincode is advertised
     return {
         type = "HandshakeAck",
         version = PROTOCOL_VERSION,
-        success = success
+        success = success,
+        encodings = { "json" },
+        features = FEATURES
     }
 end
 
@@ -596,7 +1202,7 @@ function Handlers.Render(request)
     end
 
     -- Call render
-    local commands, err = IconManager.call_render(
+    local commands, err, next_frame_ms = IconManager.call_render(
         context.canvas_width or metadata.width or 64,
         context.canvas_height or metadata.height or 80
     )
@@ -606,12 +1212,57 @@ function Handlers.Render(request)
 
     return {
         type = "Render",
-        commands = commands
+        commands = commands,
+        next_frame_ms = next_frame_ms
+    }
+end
+
+-- Render several icons with the same script; items that fail are left
+-- out and the daemon renders them individually instead
+function Handlers.RenderBatch(request)
+    local results = {}
+    for _, item in ipairs(request.items or {}) do
+        local response = Handlers.Render({
+            metadata = item.metadata,
+            context = item.context
+        })
+        if response.type == "Render" then
+            table.insert(results, {
+                id = item.id,
+                commands = response.commands,
+                next_frame_ms = response.next_frame_ms
+            })
+        else
+            io.stderr:write("Batch render of item " .. tostring(item.id) .. " failed: "
+                .. tostring(response.message) .. "\n")
+        end
+    end
+
+    return {
+        type = "RenderBatch",
+        results = results
     }
 end
 
+-- serde sends IconEvent externally tagged: "HoverEnter" for unit events,
+-- {Click = {button = 1, ...}} otherwise. Flatten it to {type = "Click", ...}
+local function normalize_event(event)
+    if type(event) == "string" then
+        return { type = event }
+    end
+    if type(event) == "table" and event.type == nil then
+        local kind, fields = next(event)
+        if type(kind) == "string" then
+            local normalized = type(fields) == "table" and fields or {}
+            normalized.type = kind
+            return normalized
+        end
+    end
+    return event
+end
+
 function Handlers.Event(request)
-    local event = request.event
+    local event = normalize_event(request.event)
 
     local result, err = IconManager.call_event(event)
     if not result then
@@ -639,6 +1290,10 @@ function Handlers.Position(request)
     }
 end
 
+function Handlers.Ping(request)
+    return { type = "Pong" }
+end
+
 function Handlers.Shutdown(request)
     return { type = "ShutdownAck" }
 end
@@ -695,6 +1350,12 @@ local function main()
             break
         end
 
+        -- Framing starts once the acknowledgement is out
+        if IPC.pending_framed then
+            IPC.framed = true
+            IPC.pending_framed = nil
+        end
+
         -- Check for shutdown
         if request_type == "Shutdown" then
             running = false
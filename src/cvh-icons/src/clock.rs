@@ -0,0 +1,155 @@
+//! Local calendar time
+//!
+//! Dates shown to the user, like a trashed file's deletion date or
+//! `cvh.time.date`, follow the local time zone. chrono reads the zone
+//! from `TZ` or `/etc/localtime`.
+
+use chrono::{DateTime, Datelike, FixedOffset, Local, Offset, TimeZone, Timelike, Utc};
+
+const WEEKDAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+/// A Unix time broken down into calendar fields in some time zone,
+/// numbered like Lua's `os.date("*t")`: `wday` 1 is Sunday and `yday` 1 is
+/// January 1st
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalTime {
+    pub secs: i64,
+    pub year: i32,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub min: u32,
+    pub sec: u32,
+    pub wday: u32,
+    pub yday: u32,
+}
+
+impl LocalTime {
+    /// The current time in the local time zone
+    pub fn now() -> Self {
+        Self::from_unix(Utc::now().timestamp())
+    }
+
+    /// `secs` since the Unix epoch in the local time zone
+    pub fn from_unix(secs: i64) -> Self {
+        let offset = Local
+            .timestamp_opt(secs, 0)
+            .single()
+            .map_or(Utc.fix(), |time| time.offset().fix());
+        Self::with_offset(secs, offset)
+    }
+
+    /// `secs` since the Unix epoch at a fixed UTC offset
+    ///
+    /// Times chrono can't represent (hundreds of thousands of years away)
+    /// become the epoch.
+    pub fn with_offset(secs: i64, offset: FixedOffset) -> Self {
+        let time = DateTime::from_timestamp(secs, 0).unwrap_or_default().with_timezone(&offset);
+        Self {
+            secs,
+            year: time.year(),
+            month: time.month(),
+            day: time.day(),
+            hour: time.hour(),
+            min: time.minute(),
+            sec: time.second(),
+            wday: time.weekday().num_days_from_sunday() + 1,
+            yday: time.ordinal(),
+        }
+    }
+
+    /// Expand strftime-style conversions in `fmt`
+    ///
+    /// Supports `%Y %y %m %d %e %H %M %S %j %A %a %B %b %p %s %F %T %%`;
+    /// anything else is copied as is.
+    pub fn format(&self, fmt: &str) -> String {
+        let weekday = WEEKDAYS[self.wday as usize - 1];
+        let month = MONTHS[self.month as usize - 1];
+        let mut out = String::with_capacity(fmt.len());
+        let mut chars = fmt.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => out.push_str(&self.year.to_string()),
+                Some('y') => out.push_str(&format!("{:02}", self.year.rem_euclid(100))),
+                Some('m') => out.push_str(&format!("{:02}", self.month)),
+                Some('d') => out.push_str(&format!("{:02}", self.day)),
+                Some('e') => out.push_str(&format!("{:2}", self.day)),
+                Some('H') => out.push_str(&format!("{:02}", self.hour)),
+                Some('M') => out.push_str(&format!("{:02}", self.min)),
+                Some('S') => out.push_str(&format!("{:02}", self.sec)),
+                Some('j') => out.push_str(&format!("{:03}", self.yday)),
+                Some('A') => out.push_str(weekday),
+                Some('a') => out.push_str(&weekday[..3]),
+                Some('B') => out.push_str(month),
+                Some('b') => out.push_str(&month[..3]),
+                Some('p') => out.push_str(if self.hour < 12 { "AM" } else { "PM" }),
+                Some('s') => out.push_str(&self.secs.to_string()),
+                Some('F') => out.push_str(&self.format("%Y-%m-%d")),
+                Some('T') => out.push_str(&self.format("%H:%M:%S")),
+                Some('%') => out.push('%'),
+                Some(other) => {
+                    out.push('%');
+                    out.push(other);
+                }
+                None => out.push('%'),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields_at_offsets() {
+        let utc = FixedOffset::east_opt(0).unwrap();
+        let t = LocalTime::with_offset(1_700_000_000, utc);
+        assert_eq!((t.year, t.month, t.day), (2023, 11, 14));
+        assert_eq!((t.hour, t.min, t.sec), (22, 13, 20));
+        // A Tuesday
+        assert_eq!((t.wday, t.yday), (3, 318));
+
+        // Two hours east it is already Wednesday
+        let t = LocalTime::with_offset(1_700_000_000, FixedOffset::east_opt(2 * 3600).unwrap());
+        assert_eq!((t.year, t.month, t.day, t.hour), (2023, 11, 15, 0));
+        assert_eq!((t.wday, t.yday), (4, 319));
+
+        // A leap day, and before the epoch
+        let t = LocalTime::with_offset(951_782_400, utc);
+        assert_eq!((t.month, t.day, t.yday), (2, 29, 60));
+        let t = LocalTime::with_offset(-1, utc);
+        assert_eq!((t.year, t.month, t.day, t.hour, t.wday, t.yday), (1969, 12, 31, 23, 4, 365));
+    }
+
+    #[test]
+    fn test_format_patterns() {
+        let t = LocalTime::with_offset(1_700_000_000, FixedOffset::east_opt(0).unwrap());
+        assert_eq!(t.format("%H:%M:%S"), "22:13:20");
+        assert_eq!(t.format("%Y-%m-%d"), "2023-11-14");
+        assert_eq!(t.format("%A %e %B"), "Tuesday 14 November");
+        assert_eq!(t.format("%a %b %y, day %j"), "Tue Nov 23, day 318");
+        assert_eq!(t.format("%F %T %p"), "2023-11-14 22:13:20 PM");
+        assert_eq!(t.format("%s 100%% %q"), "1700000000 100% %q");
+
+        let t = LocalTime::with_offset(1_700_000_000, FixedOffset::west_opt(5 * 3600).unwrap());
+        assert_eq!(t.format("%F %T %p"), "2023-11-14 17:13:20 PM");
+    }
+
+    #[test]
+    fn test_from_unix_uses_local_offset() {
+        let secs = 1_700_000_000;
+        let offset = Local.timestamp_opt(secs, 0).unwrap().offset().fix();
+        assert_eq!(LocalTime::from_unix(secs), LocalTime::with_offset(secs, offset));
+    }
+}
@@ -2,8 +2,11 @@
 
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
+use crate::icons::IconType;
+
 /// Main configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -23,14 +26,51 @@ pub struct Config {
     #[serde(default = "default_label_width")]
     pub label_width: usize,
 
+    /// Scale factor for icon buffers, overriding the output's (fractional
+    /// values round up to the next whole buffer scale)
+    #[serde(default)]
+    pub scale: Option<f32>,
+
     /// Directories to search for Lua scripts
     #[serde(default = "default_script_dirs")]
     pub script_dirs: Vec<PathBuf>,
 
+    /// Also show icons for entries inside desktop subfolders
+    #[serde(default)]
+    pub recursive_desktop: bool,
+
+    /// Deepest subfolder level shown when `recursive_desktop` is set
+    #[serde(default = "default_max_desktop_depth")]
+    pub max_desktop_depth: usize,
+
     /// Default icon theme
     #[serde(default = "default_icon_theme")]
     pub icon_theme: String,
 
+    /// Label font, tried before the fallbacks and built-in search paths
+    #[serde(default)]
+    pub font_path: Option<PathBuf>,
+
+    /// Fonts tried in order when `font_path` is unset or fails to load
+    #[serde(default)]
+    pub font_fallbacks: Vec<PathBuf>,
+
+    /// Idle time after which an icon's Lua process is pinged (0 disables)
+    #[serde(default = "default_ping_interval_ms")]
+    pub ping_interval_ms: u64,
+
+    /// How long a ping may go unanswered before the process is restarted
+    #[serde(default = "default_ping_timeout_ms")]
+    pub ping_timeout_ms: u64,
+
+    /// Threads rendering icons in parallel (0 uses one per CPU)
+    #[serde(default)]
+    pub render_threads: usize,
+
+    /// Per-type overrides, e.g. `[icon_types.folder]` with `size = 96`
+    #[serde(default)]
+    pub icon_types: HashMap<IconType, IconTypeConfig>,
+
     /// Sandbox configuration
     #[serde(default)]
     pub sandbox: SandboxConfig,
@@ -40,6 +80,18 @@ pub struct Config {
     pub colors: Colors,
 }
 
+/// Settings for one icon type; unset fields fall back to the global ones
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IconTypeConfig {
+    /// Icon size in pixels
+    #[serde(default)]
+    pub size: Option<u32>,
+
+    /// Draw the name below the icon
+    #[serde(default)]
+    pub show_label: Option<bool>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SandboxConfig {
     /// Enable sandboxing
@@ -57,6 +109,54 @@ pub struct SandboxConfig {
     /// Read-write paths (in addition to defaults)
     #[serde(default)]
     pub read_write_paths: Vec<PathBuf>,
+
+    /// Restrict icon scripts to the syscalls the interpreter needs
+    #[serde(default = "default_true")]
+    pub seccomp: bool,
+
+    /// Confine icon scripts to the configured paths with Landlock
+    #[serde(default = "default_true")]
+    pub landlock: bool,
+
+    /// CPU seconds an icon process may use before it is killed and
+    /// restarted (0 = unlimited)
+    #[serde(default = "default_max_cpu_secs")]
+    pub max_cpu_secs: u64,
+
+    /// Address space limit for icon processes in bytes (0 = unlimited)
+    #[serde(default = "default_max_memory_bytes")]
+    pub max_memory_bytes: u64,
+
+    /// Refuse to run icon scripts without bubblewrap; when false they run
+    /// unsandboxed (with a warning) if bwrap is missing
+    #[serde(default = "default_true")]
+    pub require_sandbox: bool,
+
+    /// Environment variables icon scripts may read
+    #[serde(default = "default_env_allowlist")]
+    pub env_allowlist: Vec<String>,
+
+    /// Programs icon scripts may ask the daemon to spawn
+    #[serde(default = "default_allowed_commands")]
+    pub allowed_commands: Vec<String>,
+
+    /// URI schemes icon scripts may ask the daemon to open
+    #[serde(default = "default_allowed_uri_schemes")]
+    pub allowed_uri_schemes: Vec<String>,
+
+    /// Let icon scripts move files on the desktop to the trash
+    #[serde(default = "default_true")]
+    pub allow_trash: bool,
+
+    /// Lua interpreters looked for in /usr/bin, in order; the first one
+    /// installed runs icon scripts
+    #[serde(default = "default_lua_interpreters")]
+    pub lua_interpreters: Vec<String>,
+
+    /// Where icon scripts keep `cvh.state`, one directory per icon
+    /// (default: `$XDG_STATE_HOME/cvh-icons`)
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -79,7 +179,28 @@ fn default_icon_size() -> u32 { 64 }
 fn default_grid_spacing() -> u32 { 20 }
 fn default_font_size() -> f32 { 12.0 }
 fn default_label_width() -> usize { 12 }
+fn default_max_desktop_depth() -> usize { 3 }
+fn default_ping_interval_ms() -> u64 { 5000 }
+fn default_ping_timeout_ms() -> u64 { 500 }
 fn default_true() -> bool { true }
+fn default_max_cpu_secs() -> u64 { 60 }
+fn default_max_memory_bytes() -> u64 { 256 * 1024 * 1024 }
+
+fn default_env_allowlist() -> Vec<String> {
+    crate::sandbox::DEFAULT_ENV_ALLOWLIST.iter().map(|name| name.to_string()).collect()
+}
+
+fn default_allowed_commands() -> Vec<String> {
+    crate::sandbox::actions::DEFAULT_ALLOWED_COMMANDS.iter().map(|c| c.to_string()).collect()
+}
+
+fn default_allowed_uri_schemes() -> Vec<String> {
+    crate::sandbox::actions::DEFAULT_ALLOWED_URI_SCHEMES.iter().map(|s| s.to_string()).collect()
+}
+
+fn default_lua_interpreters() -> Vec<String> {
+    crate::sandbox::DEFAULT_LUA_INTERPRETERS.iter().map(|name| name.to_string()).collect()
+}
 
 fn default_script_dirs() -> Vec<PathBuf> {
     let mut dirs = vec![
@@ -110,8 +231,17 @@ impl Default for Config {
             grid_spacing: default_grid_spacing(),
             font_size: default_font_size(),
             label_width: default_label_width(),
+            scale: None,
             script_dirs: default_script_dirs(),
+            recursive_desktop: false,
+            max_desktop_depth: default_max_desktop_depth(),
             icon_theme: default_icon_theme(),
+            font_path: None,
+            font_fallbacks: Vec::new(),
+            ping_interval_ms: default_ping_interval_ms(),
+            ping_timeout_ms: default_ping_timeout_ms(),
+            render_threads: 0,
+            icon_types: HashMap::new(),
             sandbox: SandboxConfig::default(),
             colors: Colors::default(),
         }
@@ -125,6 +255,17 @@ impl Default for SandboxConfig {
             allow_network: false,
             read_only_paths: Vec::new(),
             read_write_paths: Vec::new(),
+            seccomp: true,
+            landlock: true,
+            max_cpu_secs: default_max_cpu_secs(),
+            max_memory_bytes: default_max_memory_bytes(),
+            require_sandbox: true,
+            env_allowlist: default_env_allowlist(),
+            allowed_commands: default_allowed_commands(),
+            allowed_uri_schemes: default_allowed_uri_schemes(),
+            allow_trash: true,
+            lua_interpreters: default_lua_interpreters(),
+            state_dir: None,
         }
     }
 }
@@ -163,4 +304,14 @@ impl Config {
         // Use defaults
         Ok(Self::default())
     }
+
+    /// Icon size for icons of `icon_type`
+    pub fn icon_size_for(&self, icon_type: IconType) -> u32 {
+        self.icon_types.get(&icon_type).and_then(|t| t.size).unwrap_or(self.icon_size)
+    }
+
+    /// Whether icons of `icon_type` get a label
+    pub fn show_label_for(&self, icon_type: IconType) -> bool {
+        self.icon_types.get(&icon_type).and_then(|t| t.show_label).unwrap_or(true)
+    }
 }
@@ -5,25 +5,61 @@
 
 use anyhow::{Context, Result};
 use calloop::channel::{Channel, Sender};
+use calloop::generic::Generic;
 use calloop::timer::{TimeoutAction, Timer};
-use calloop::EventLoop;
+use calloop::{EventLoop, Interest, LoopHandle, Mode, PostAction};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::{SfdFlags, SignalFd};
 use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
-use std::collections::HashMap;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 use tracing::{debug, error, info, warn};
 
 use crate::config::Config;
-use crate::icons::{DesktopIcon, IconType};
+use crate::icons::navigation::{self, Direction};
+use crate::icons::{ClickAction, DesktopIcon, IconType};
+use crate::ipc::RenderResult;
+use crate::renderer::trace::RenderTrace;
 use crate::renderer::IconRenderer;
-use crate::wayland::{InputEvent, SurfaceId, WaylandManager};
+use crate::sandbox::{self, trash};
+use crate::wayland::{buffer_scale, InputEvent, Key, SurfaceId, WaylandManager};
+
+mod pacer;
+
+use pacer::{Pace, Pacer};
 
 /// Height reserved for the label area below the icon
 const LABEL_HEIGHT: u32 = 24;
 
+/// How often icons are checked and idle Lua processes pinged
+///
+/// Rendering doesn't wait for this: it follows file and input events, and
+/// each animating script's own frame delay. An idle desktop stretches it to
+/// `pacer::SLOW_TICK`.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Order `IconDaemon::arrange` lays icons out in
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArrangeMode {
+    /// Alphabetically, ignoring case
+    Name,
+    /// Most recently modified first
+    ModifiedDesc,
+    /// Folders first, then grouped by icon type
+    Type,
+    /// Largest first
+    SizeDesc,
+}
+
 /// Icon daemon that manages desktop icons
 pub struct IconDaemon {
     config: Config,
+    /// Explicit config file, reloaded on SIGHUP (None uses the XDG location)
+    config_path: Option<PathBuf>,
     desktop_dir: PathBuf,
     icons: HashMap<PathBuf, DesktopIcon>,
     watcher: Option<RecommendedWatcher>,
@@ -39,8 +75,16 @@ pub struct IconDaemon {
     /// Default screen dimensions (will be updated from outputs)
     screen_width: u32,
     screen_height: u32,
+    /// Integer scale of the icon buffers (logical size times this in pixels)
+    buffer_scale: u32,
     /// Flag indicating icons need to be re-rendered
     needs_render: bool,
+    /// Icon with the keyboard focus, shown as hovered
+    focused: Option<PathBuf>,
+    /// Where to write the draw commands of each render, if tracing
+    render_trace: Option<RenderTrace>,
+    /// Threads icons are rendered on
+    render_pool: ThreadPool,
 }
 
 impl IconDaemon {
@@ -60,8 +104,14 @@ impl IconDaemon {
             }
         };
 
+        // Without an interpreter every icon would quietly fall back to a
+        // placeholder, so refuse to start instead
+        let interpreter = sandbox::lua_interpreter(&config.sandbox.lua_interpreters)?;
+        info!("Running icon scripts with {}", interpreter);
+
         // Create renderer
-        let renderer = IconRenderer::new(config.icon_size, config.font_size);
+        let renderer = IconRenderer::from_config(&config);
+        let render_pool = render_pool(config.render_threads)?;
 
         // Get initial screen dimensions from Wayland if available
         let (screen_width, screen_height) = if let Some(ref wm) = wayland {
@@ -72,6 +122,7 @@ impl IconDaemon {
 
         let mut daemon = Self {
             config,
+            config_path: None,
             desktop_dir,
             icons: HashMap::new(),
             watcher: None,
@@ -82,8 +133,13 @@ impl IconDaemon {
             path_to_surface: HashMap::new(),
             screen_width,
             screen_height,
+            buffer_scale: 1,
             needs_render: true, // Initial render needed
+            focused: None,
+            render_trace: None,
+            render_pool,
         };
+        daemon.buffer_scale = daemon.current_buffer_scale();
 
         // Initial scan of desktop directory
         daemon.scan_desktop()?;
@@ -91,6 +147,148 @@ impl IconDaemon {
         Ok(daemon)
     }
 
+    /// Set the config file reloaded on SIGHUP
+    pub fn set_config_path(&mut self, path: Option<PathBuf>) {
+        self.config_path = path;
+    }
+
+    /// Write the draw commands of every icon render to `trace`
+    pub fn set_render_trace(&mut self, trace: RenderTrace) {
+        self.render_trace = Some(trace);
+    }
+
+    /// Register a SIGHUP source that requests a config reload
+    fn setup_reload_signal(&self, handle: &LoopHandle<'_, DaemonState>) -> Result<()> {
+        let mut mask = SigSet::empty();
+        mask.add(Signal::SIGHUP);
+
+        // Block SIGHUP so it is only delivered through the signalfd; threads
+        // spawned afterwards (like the file watcher) inherit the mask
+        mask.thread_block().context("Failed to block SIGHUP")?;
+        let signals = SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)
+            .context("Failed to create signalfd")?;
+
+        handle
+            .insert_source(
+                Generic::new(signals, Interest::READ, Mode::Level),
+                |_, signals, state: &mut DaemonState| {
+                    while let Ok(Some(_)) = signals.read_signal() {
+                        state.reload_requested = true;
+                    }
+                    Ok(PostAction::Continue)
+                },
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to register SIGHUP source: {:?}", e))?;
+
+        Ok(())
+    }
+
+    /// Reload the config file, keeping the current config if it fails to load
+    fn reload_config(&mut self) {
+        match Config::load(self.config_path.as_deref()) {
+            Ok(config) => {
+                // The current list was checked at startup
+                let interpreters = &config.sandbox.lua_interpreters;
+                if *interpreters != self.config.sandbox.lua_interpreters {
+                    if let Err(e) = sandbox::lua_interpreter(interpreters) {
+                        error!("Failed to reload config: {:#} (keeping current config)", e);
+                        return;
+                    }
+                }
+                info!("Reloaded configuration");
+                self.apply_config(config);
+            }
+            Err(e) => {
+                error!("Failed to reload config: {} (keeping current config)", e);
+            }
+        }
+    }
+
+    /// Re-apply a config to the renderer and all icons
+    ///
+    /// Icons whose widget script resolution or sandbox changed get a new Lua
+    /// process. Surfaces are sized when created, so a size change rebuilds
+    /// every icon.
+    fn apply_config(&mut self, config: Config) {
+        let layout_changed = config.icon_size != self.config.icon_size
+            || config.icon_types != self.config.icon_types
+            || config.grid_spacing != self.config.grid_spacing;
+        let scope_changed = config.recursive_desktop != self.config.recursive_desktop
+            || config.max_desktop_depth != self.config.max_desktop_depth;
+
+        if config.render_threads != self.config.render_threads {
+            match render_pool(config.render_threads) {
+                Ok(pool) => self.render_pool = pool,
+                Err(e) => warn!("Keeping the current render threads: {:#}", e),
+            }
+        }
+
+        self.config = config;
+        self.renderer = IconRenderer::from_config(&self.config);
+        self.needs_render = true;
+
+        if scope_changed {
+            self.rescan_desktop();
+        }
+
+        let mut paths: Vec<PathBuf> = self.icons.keys().cloned().collect();
+
+        if layout_changed {
+            paths.sort();
+            for path in &paths {
+                self.remove_icon(path);
+            }
+            for path in &paths {
+                if let Err(e) = self.add_icon(path) {
+                    warn!("Failed to re-add icon {}: {}", path.display(), e);
+                }
+            }
+            return;
+        }
+
+        for path in paths {
+            let sandbox_changed = self
+                .icons
+                .get_mut(&path)
+                .is_some_and(|icon| icon.apply_config(&self.config));
+            self.refresh_scripts(&path, sandbox_changed);
+        }
+    }
+
+    /// Re-resolve an icon's widget script, respawning its Lua process if the
+    /// resolution changed or `force` is set
+    fn refresh_scripts(&mut self, path: &Path, force: bool) {
+        let Some(icon) = self.icons.get(path) else {
+            return;
+        };
+        let resolved = self.find_script_for_icon(icon);
+        let current = icon
+            .handler_path()
+            .zip(icon.script_path())
+            .map(|(handler, script)| (handler.to_path_buf(), script.to_path_buf()));
+
+        if resolved == current && !force {
+            return;
+        }
+
+        let Some(icon) = self.icons.get_mut(path) else {
+            return;
+        };
+        match resolved {
+            Some((handler_path, widget_script_path)) => {
+                debug!(
+                    "Respawning Lua process for {} with {}",
+                    path.display(),
+                    widget_script_path.display()
+                );
+                if let Err(e) = icon.spawn_lua_process(&handler_path, &widget_script_path) {
+                    warn!("Failed to respawn Lua process for {}: {}", path.display(), e);
+                }
+            }
+            None => icon.clear_scripts(),
+        }
+    }
+
     /// Set up file system watcher with calloop channel
     fn setup_watcher(&mut self, sender: Sender<notify::Result<Event>>) -> Result<()> {
         let tx = sender.clone();
@@ -106,14 +304,24 @@ impl IconDaemon {
         self.event_sender = Some(sender);
 
         // Start watching
+        let mode = self.watch_mode();
         if let Some(ref mut watcher) = self.watcher {
-            watcher.watch(&self.desktop_dir, RecursiveMode::NonRecursive)?;
+            watcher.watch(&self.desktop_dir, mode)?;
             info!("Watching desktop directory: {}", self.desktop_dir.display());
         }
 
         Ok(())
     }
 
+    /// Watch subfolders too when the desktop is shown recursively
+    fn watch_mode(&self) -> RecursiveMode {
+        if self.config.recursive_desktop {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        }
+    }
+
     /// Scan the desktop directory for files/folders
     fn scan_desktop(&mut self) -> Result<()> {
         if !self.desktop_dir.exists() {
@@ -121,28 +329,91 @@ impl IconDaemon {
             return Ok(());
         }
 
-        let entries = std::fs::read_dir(&self.desktop_dir)
+        let desktop_dir = self.desktop_dir.clone();
+        self.scan_dir(&desktop_dir, 0)
             .context("Failed to read desktop directory")?;
 
+        info!("Loaded {} desktop icons", self.icons.len());
+        Ok(())
+    }
+
+    /// Add icons for the entries of `dir`, `depth` subfolders below the
+    /// desktop, descending further when the desktop is shown recursively
+    fn scan_dir(&mut self, dir: &Path, depth: usize) -> Result<()> {
+        let entries = std::fs::read_dir(dir)
+            .with_context(|| format!("Failed to read directory {}", dir.display()))?;
+
         for entry in entries.filter_map(|e| e.ok()) {
             let path = entry.path();
 
-            // Skip hidden files
-            if path.file_name()
-                .and_then(|n| n.to_str())
-                .map(|n| n.starts_with('.'))
-                .unwrap_or(false)
-            {
+            // Skip hidden files (and never descend into hidden folders)
+            if is_hidden(&path) {
                 continue;
             }
 
             self.add_icon(&path)?;
+
+            // file_type() doesn't follow symlinks, so linked folders can't loop
+            let descend = self.config.recursive_desktop
+                && depth < self.config.max_desktop_depth
+                && entry.file_type().is_ok_and(|t| t.is_dir());
+            if descend {
+                if let Err(e) = self.scan_dir(&path, depth + 1) {
+                    warn!("Skipping {}: {:#}", path.display(), e);
+                }
+            }
         }
 
-        info!("Loaded {} desktop icons", self.icons.len());
         Ok(())
     }
 
+    /// How many subfolders below the desktop `path` is, if it should have
+    /// an icon: not hidden at any level and within the configured depth
+    fn desktop_depth(&self, path: &Path) -> Option<usize> {
+        let relative = path.strip_prefix(&self.desktop_dir).ok()?;
+        let mut components = 0usize;
+        for component in relative.components() {
+            if component.as_os_str().to_string_lossy().starts_with('.') {
+                return None;
+            }
+            components += 1;
+        }
+
+        let max_depth = if self.config.recursive_desktop {
+            self.config.max_desktop_depth
+        } else {
+            0
+        };
+        let depth = components.checked_sub(1)?;
+        (depth <= max_depth).then_some(depth)
+    }
+
+    /// Rescan after the recursion settings changed, dropping icons that are
+    /// now out of scope and re-watching with the new mode
+    fn rescan_desktop(&mut self) {
+        let out_of_scope: Vec<PathBuf> = self
+            .icons
+            .keys()
+            .filter(|path| self.desktop_depth(path).is_none())
+            .cloned()
+            .collect();
+        for path in out_of_scope {
+            self.remove_icon(&path);
+        }
+
+        if let Err(e) = self.scan_desktop() {
+            warn!("Failed to rescan desktop: {:#}", e);
+        }
+
+        let mode = self.watch_mode();
+        if let Some(ref mut watcher) = self.watcher {
+            let _ = watcher.unwatch(&self.desktop_dir);
+            if let Err(e) = watcher.watch(&self.desktop_dir, mode) {
+                error!("Failed to re-watch desktop directory: {}", e);
+            }
+        }
+    }
+
     /// Add an icon for a file/folder
     fn add_icon(&mut self, path: &Path) -> Result<()> {
         if self.icons.contains_key(path) {
@@ -175,9 +446,8 @@ impl IconDaemon {
 
         // Calculate position for this icon
         // Use full height including label area for grid calculations
-        let surface_height = self.config.icon_size + LABEL_HEIGHT;
-        let cell_width = self.config.icon_size + self.config.grid_spacing;
-        let cell_height = surface_height + self.config.grid_spacing;
+        let (surface_width, surface_height) = surface_size(&icon);
+        let (cell_width, cell_height) = self.grid_cell(Some(&icon));
 
         let icon_count = self.icons.len() as u32;
         let icon_index = icon_count;
@@ -195,7 +465,7 @@ impl IconDaemon {
             match wayland.create_surface(
                 position.x,
                 position.y,
-                self.config.icon_size,
+                surface_width,
                 surface_height,
             ) {
                 Ok(surface_id) => {
@@ -288,20 +558,124 @@ impl IconDaemon {
         }
     }
 
+    /// Move an icon to a renamed file's new path
+    ///
+    /// Keeps the icon's grid position, surface and Lua process; the widget
+    /// script is only re-resolved if the rename changed the icon type.
+    fn move_icon(&mut self, from: &Path, to: &Path) -> Result<()> {
+        if from == to {
+            return Ok(());
+        }
+
+        // Renamed to a hidden name (or out of scope): the icon disappears
+        // like in a scan
+        if self.desktop_depth(to).is_none() {
+            self.remove_icon_tree(from);
+            return Ok(());
+        }
+
+        let Some(mut icon) = self.icons.remove(from) else {
+            return self.add_tree(to);
+        };
+
+        // Renaming over an existing file replaces its icon
+        self.remove_icon(to);
+
+        let type_changed = icon.set_path(to);
+        let mut resized = false;
+        if type_changed {
+            // Pick up the size and label settings of the new type
+            let before = surface_size(&icon);
+            icon.apply_config(&self.config);
+            resized = surface_size(&icon) != before;
+        }
+
+        if resized {
+            // Surfaces are sized when created, so the icon starts over
+            self.icons.insert(from.to_path_buf(), icon);
+            self.remove_icon(from);
+            self.add_icon(to)?;
+        } else {
+            if let Some(surface_id) = self.path_to_surface.remove(from) {
+                self.path_to_surface.insert(to.to_path_buf(), surface_id);
+                self.surface_to_path.insert(surface_id, to.to_path_buf());
+            }
+            self.icons.insert(to.to_path_buf(), icon);
+
+            if type_changed {
+                self.refresh_scripts(to, false);
+            }
+        }
+
+        // Entries of a moved folder move along with it (each child then
+        // carries its own entries)
+        let children: Vec<PathBuf> = self
+            .icons
+            .keys()
+            .filter(|path| path.parent() == Some(from))
+            .cloned()
+            .collect();
+        for child in children {
+            if let Ok(relative) = child.strip_prefix(from) {
+                self.move_icon(&child, &to.join(relative))?;
+            }
+        }
+
+        debug!("Moved icon {} -> {}", from.display(), to.display());
+        Ok(())
+    }
+
+    /// Add an icon for `path` and, for a folder in recursive mode, its entries
+    fn add_tree(&mut self, path: &Path) -> Result<()> {
+        let Some(depth) = self.desktop_depth(path) else {
+            return Ok(());
+        };
+
+        self.add_icon(path)?;
+
+        // A folder moved in brings entries that won't get their own events
+        let descend = self.config.recursive_desktop
+            && depth < self.config.max_desktop_depth
+            && path.is_dir()
+            && !path.is_symlink();
+        if descend {
+            self.scan_dir(path, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    /// Remove the icon for `path` and any icons for entries below it
+    fn remove_icon_tree(&mut self, path: &Path) {
+        let paths: Vec<PathBuf> = self
+            .icons
+            .keys()
+            .filter(|p| p.starts_with(path))
+            .cloned()
+            .collect();
+        for p in paths {
+            self.remove_icon(&p);
+        }
+    }
+
     /// Handle a file system event
     fn handle_fs_event(&mut self, event: Event) -> Result<()> {
+        use notify::event::{ModifyKind, RenameMode};
         use notify::EventKind;
 
         match event.kind {
-            EventKind::Create(_) => {
+            EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if event.paths.len() == 2 => {
+                self.move_icon(&event.paths[0], &event.paths[1])?;
+                self.needs_render = true;
+            }
+            EventKind::Modify(ModifyKind::Name(RenameMode::From)) | EventKind::Remove(_) => {
                 for path in event.paths {
-                    self.add_icon(&path)?;
+                    self.remove_icon_tree(&path);
                 }
                 self.needs_render = true;
             }
-            EventKind::Remove(_) => {
+            EventKind::Modify(ModifyKind::Name(RenameMode::To)) | EventKind::Create(_) => {
                 for path in event.paths {
-                    self.remove_icon(&path);
+                    self.add_tree(&path)?;
                 }
                 self.needs_render = true;
             }
@@ -339,54 +713,77 @@ impl IconDaemon {
         }
     }
 
-    /// Render all icons to their Wayland surfaces
+    /// Ping idle Lua processes, restarting any that stopped answering
+    fn check_heartbeats(&mut self) {
+        let now = std::time::Instant::now();
+        for icon in self.icons.values_mut() {
+            if !icon.heartbeat(now) {
+                self.needs_render = true;
+            }
+        }
+    }
+
+    /// Render icons to their Wayland surfaces
+    ///
+    /// Everything is rendered when the dirty flag is set; otherwise only
+    /// animating icons whose next frame is due.
     fn render_icons_to_surfaces(&mut self) {
-        // Only render if we have a Wayland connection and something needs rendering
-        if self.wayland.is_none() || !self.needs_render {
+        // Only render if we have a Wayland connection
+        if self.wayland.is_none() {
             return;
         }
 
-        let icon_size = self.config.icon_size;
-        let surface_height = icon_size + LABEL_HEIGHT;
+        let paths = if self.needs_render {
+            self.icons.keys().cloned().collect()
+        } else {
+            self.due_frames(std::time::Instant::now())
+        };
+        if paths.is_empty() {
+            return;
+        }
 
-        // Collect paths to render (to avoid borrowing conflicts)
-        let paths: Vec<PathBuf> = self.icons.keys().cloned().collect();
+        let scale = self.buffer_scale;
 
-        for path in paths {
+        // Get render commands for those icons, each on a canvas its
+        // surface's size
+        let rendered = self.render_icons(&paths, scale as f32);
+        self.trace_render(&rendered);
+
+        // Rasterize at buffer resolution on the render threads
+        let lists: Vec<(&[crate::lua::DrawCommand], u32, u32)> = rendered
+            .iter()
+            .map(|(path, commands)| {
+                let (width, height) = surface_size(&self.icons[path]);
+                (commands.as_slice(), width, height)
+            })
+            .collect();
+        let pixmaps = self.renderer.render_many(&self.render_pool, &lists, scale);
+
+        for ((path, _), pixmap) in rendered.iter().zip(pixmaps) {
             // Get the surface ID for this icon
-            let surface_id = match self.path_to_surface.get(&path) {
+            let surface_id = match self.path_to_surface.get(path) {
                 Some(&id) => id,
                 None => continue,
             };
 
-            // Get render commands from the icon (use full height including label)
-            let commands = if let Some(icon) = self.icons.get_mut(&path) {
-                icon.request_render(icon_size, surface_height, 1.0)
-            } else {
-                continue;
-            };
-
-            // Create pixmap and render commands (use full height including label)
-            if let Some(mut pixmap) = tiny_skia::Pixmap::new(icon_size, surface_height) {
-                // Execute draw commands
-                if let Err(e) = self.renderer.execute_commands(&mut pixmap, &commands) {
+            let pixmap = match pixmap {
+                Ok(pixmap) => pixmap,
+                Err(e) => {
                     warn!("Failed to execute draw commands for {}: {}", path.display(), e);
                     continue;
                 }
+            };
 
-                // Get pixel data
-                let pixels = pixmap.data();
-
-                // Attach buffer to surface
-                if let Some(ref mut wayland) = self.wayland {
-                    if let Err(e) = wayland.attach_buffer(
-                        surface_id,
-                        pixels,
-                        icon_size,
-                        surface_height,
-                    ) {
-                        warn!("Failed to attach buffer to surface {}: {}", surface_id, e);
-                    }
+            // Attach buffer to surface
+            if let Some(ref mut wayland) = self.wayland {
+                if let Err(e) = wayland.attach_buffer(
+                    surface_id,
+                    pixmap.data(),
+                    pixmap.width(),
+                    pixmap.height(),
+                    scale,
+                ) {
+                    warn!("Failed to attach buffer to surface {}: {}", surface_id, e);
                 }
             }
         }
@@ -395,14 +792,41 @@ impl IconDaemon {
         self.needs_render = false;
     }
 
-    /// Handle Wayland input events
-    fn handle_wayland_input(&mut self) {
+    /// The earliest frame any icon's script asked for
+    fn next_frame(&self) -> Option<std::time::Instant> {
+        self.icons.values().filter_map(DesktopIcon::next_frame).min()
+    }
+
+    /// Icons whose scripts asked for a frame at or before `now`
+    fn due_frames(&self, now: std::time::Instant) -> Vec<PathBuf> {
+        self.icons
+            .iter()
+            .filter(|(_, icon)| icon.frame_due(now))
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Append a render to the trace; a failing trace is switched off
+    fn trace_render(&mut self, rendered: &[(PathBuf, Vec<crate::lua::DrawCommand>)]) {
+        let Some(trace) = self.render_trace.as_mut() else {
+            return;
+        };
+        let result = rendered.iter().try_for_each(|(path, commands)| trace.record(path, commands));
+        if let Err(e) = result {
+            warn!("Failed to write render trace, disabling it: {}", e);
+            self.render_trace = None;
+        }
+    }
+
+    /// Handle Wayland input events, returning whether there were any
+    fn handle_wayland_input(&mut self) -> bool {
         // Only process if we have a Wayland connection
         let events = if let Some(ref mut wayland) = self.wayland {
             wayland.take_input_events()
         } else {
-            return;
+            return false;
         };
+        let had_events = !events.is_empty();
 
         for event in events {
             match event {
@@ -427,39 +851,194 @@ impl IconDaemon {
                     }
                 }
                 InputEvent::PointerMotion { surface_id, x, y } => {
-                    // Could track position for hover effects
-                    debug!("Pointer motion on surface {} at ({}, {})", surface_id, x, y);
+                    // The surface may be larger than the icon it shows
+                    let hit = self.icon_at(surface_id, x, y).is_some();
+                    if let Some(icon) = self.surface_to_path.get(&surface_id).and_then(|p| self.icons.get_mut(p)) {
+                        if icon.is_hovered() != hit {
+                            icon.set_hovered(hit);
+                            self.needs_render = true;
+                        }
+                    }
+                }
+                InputEvent::PointerButton { surface_id, button, pressed, x, y } => {
+                    // Clicks are acted on once complete, see InputEvent::Click
+                    debug!(
+                        "Button {} {} on surface {} at ({}, {})",
+                        button,
+                        if pressed { "pressed" } else { "released" },
+                        surface_id,
+                        x,
+                        y
+                    );
                 }
-                InputEvent::PointerButton { surface_id, button, pressed, .. } => {
-                    if pressed {
-                        // Button pressed - handle click
-                        if let Some(path) = self.surface_to_path.get(&surface_id).cloned() {
-                            if let Some(icon) = self.icons.get_mut(&path) {
-                                // Linux mouse button codes: 272 = left, 273 = right, 274 = middle
-                                let button_num = match button {
-                                    272 => 1, // Left button
-                                    273 => 3, // Right button
-                                    274 => 2, // Middle button
-                                    _ => button,
-                                };
-                                match icon.on_click(button_num) {
-                                    Ok(action) => {
-                                        self.needs_render = true;
-                                        debug!(
-                                            "Click on icon {} button {}: {:?}",
-                                            path.display(),
-                                            button_num,
-                                            action
-                                        );
-                                    }
-                                    Err(e) => {
-                                        warn!("Error handling click on {}: {}", path.display(), e);
-                                    }
-                                }
-                            }
+                InputEvent::Click { surface_id, button, x, y, double } => {
+                    let Some(path) = self.icon_at(surface_id, x, y) else {
+                        continue;
+                    };
+                    let Some(icon) = self.icons.get_mut(&path) else {
+                        continue;
+                    };
+
+                    // Linux mouse button codes: 272 = left, 273 = right, 274 = middle
+                    let button_num = match button {
+                        272 => 1, // Left button
+                        273 => 3, // Right button
+                        274 => 2, // Middle button
+                        _ => button,
+                    };
+                    let result = if double && button_num == 1 {
+                        icon.on_double_click()
+                    } else {
+                        icon.on_click(button_num, x, y)
+                    };
+                    match result {
+                        Ok(action) => {
+                            self.needs_render = true;
+                            debug!(
+                                "Click on icon {} button {} (double: {}): {:?}",
+                                path.display(),
+                                button_num,
+                                double,
+                                action
+                            );
+                            self.launch_action(&path, &action);
+                        }
+                        Err(e) => {
+                            warn!("Error handling click on {}: {}", path.display(), e);
                         }
                     }
                 }
+                InputEvent::Key(key) => self.handle_key(key),
+            }
+        }
+        had_events
+    }
+
+    /// Move the keyboard focus with the arrows, open the focused icon with
+    /// Enter and toggle its selection with Space
+    fn handle_key(&mut self, key: Key) {
+        let direction = match key {
+            Key::Up => Direction::Up,
+            Key::Down => Direction::Down,
+            Key::Left => Direction::Left,
+            Key::Right => Direction::Right,
+            Key::Enter => {
+                let Some(path) = self.focused.clone() else {
+                    return;
+                };
+                match self.icons.get(&path).map(|icon| icon.on_double_click()) {
+                    Some(Ok(action)) => self.launch_action(&path, &action),
+                    Some(Err(e)) => warn!("Error opening {}: {}", path.display(), e),
+                    None => {}
+                }
+                return;
+            }
+            Key::Space => {
+                if let Some(icon) = self.focused.as_ref().and_then(|path| self.icons.get_mut(path)) {
+                    icon.set_selected(!icon.is_selected());
+                    self.needs_render = true;
+                }
+                return;
+            }
+        };
+        self.move_focus(direction);
+    }
+
+    /// Focus the icon next to the focused one in `direction`
+    ///
+    /// Without a focused icon, the top left one gets the focus.
+    fn move_focus(&mut self, direction: Direction) {
+        let mut icons: Vec<(PathBuf, (u32, u32))> =
+            self.icons.iter().map(|(path, icon)| (path.clone(), icon.position())).collect();
+        let next = match self.focused.as_ref().filter(|path| self.icons.contains_key(*path)) {
+            Some(focused) => navigation::neighbor(&icons, focused, direction).cloned(),
+            None => {
+                icons.sort_by(|(a, (ax, ay)), (b, (bx, by))| (ay, ax, a).cmp(&(by, bx, b)));
+                icons.into_iter().next().map(|(path, _)| path)
+            }
+        };
+        let Some(next) = next else {
+            return;
+        };
+
+        // Scripts hear about it from flush_hover_events once the focus rests
+        if let Some(icon) = self.focused.take().and_then(|path| self.icons.get_mut(&path)) {
+            icon.set_focus_hovered(false);
+        }
+        if let Some(icon) = self.icons.get_mut(&next) {
+            icon.set_focus_hovered(true);
+        }
+        debug!("Keyboard focus on {}", next.display());
+        self.focused = Some(next);
+        self.needs_render = true;
+    }
+
+    /// Run the program behind a click action, if it launches one
+    ///
+    /// The icon's action policy decides what may run, and its rate limit
+    /// how many notifications it shows; the child is reaped on a background
+    /// thread. Trash requests are carried out here directly.
+    fn launch_action(&mut self, path: &Path, action: &ClickAction) {
+        let trash_target = match action {
+            ClickAction::Trash => Some(path),
+            ClickAction::TrashPath(target) => Some(Path::new(target)),
+            _ => None,
+        };
+        if let Some(target) = trash_target {
+            self.trash_file(target);
+            return;
+        }
+        let Some(icon) = self.icons.get_mut(path) else {
+            return;
+        };
+        let command = match action {
+            ClickAction::Notify(notification) => {
+                icon.notify_command(notification, std::time::Instant::now()).map(Ok)
+            }
+            _ => icon.launch_command(action),
+        };
+        match command {
+            None => {}
+            Some(Ok(mut cmd)) => match cmd.spawn() {
+                Ok(mut child) => {
+                    std::thread::spawn(move || child.wait());
+                }
+                Err(e) => warn!("Failed to launch {:?} for {}: {}", action, path.display(), e),
+            },
+            Some(Err(e)) => warn!("Refusing {:?} for {}: {}", action, path.display(), e),
+        }
+    }
+
+    /// Move `target` to the home trash if it lies on the desktop; the
+    /// watcher then removes its icon
+    fn trash_file(&self, target: &Path) {
+        let result = trash::check_target(target, &self.desktop_dir).and_then(|()| {
+            let trash_dir = trash::home_trash().context("No data directory to keep the trash in")?;
+            trash::trash(target, &trash_dir)
+        });
+        match result {
+            Ok(trashed) => info!("Moved {} to the trash as {}", target.display(), trashed.display()),
+            Err(e) => warn!("Could not trash {}: {:#}", target.display(), e),
+        }
+    }
+
+    /// Find the icon under a surface-local point
+    ///
+    /// Each icon has its own surface, laid out as the icon with its label
+    /// below; points outside that area (e.g. in a surface the compositor
+    /// configured larger) don't hit anything.
+    fn icon_at(&self, surface_id: SurfaceId, x: f64, y: f64) -> Option<PathBuf> {
+        let path = self.surface_to_path.get(&surface_id)?;
+        let (width, height) = surface_size(self.icons.get(path)?);
+        let inside = (0.0..width as f64).contains(&x) && (0.0..height as f64).contains(&y);
+        inside.then(|| path.clone())
+    }
+
+    /// Send pending hover events so scripts see the final hover state
+    fn flush_hover_events(&mut self) {
+        for icon in self.icons.values_mut() {
+            if icon.flush_hover() {
+                self.needs_render = true;
             }
         }
     }
@@ -503,13 +1082,41 @@ impl IconDaemon {
             self.reposition_all_icons();
             self.needs_render = true;
         }
+
+        let scale = self.current_buffer_scale();
+        if scale != self.buffer_scale {
+            info!("Icon buffer scale changed from {} to {}", self.buffer_scale, scale);
+            self.buffer_scale = scale;
+            self.needs_render = true;
+        }
+    }
+
+    /// Buffer scale from the config override, else the primary output
+    fn current_buffer_scale(&self) -> u32 {
+        if let Some(scale) = self.config.scale {
+            return buffer_scale(scale);
+        }
+        self.wayland
+            .as_ref()
+            .and_then(|wayland| wayland.get_output_scale())
+            .unwrap_or(1)
+    }
+
+    /// Grid cell fitting the largest icon surface, `extra` included, plus
+    /// the spacing between cells
+    fn grid_cell(&self, extra: Option<&DesktopIcon>) -> (u32, u32) {
+        let (width, height) = self
+            .icons
+            .values()
+            .chain(extra)
+            .map(surface_size)
+            .fold((0, 0), |(w, h), (icon_w, icon_h)| (w.max(icon_w), h.max(icon_h)));
+        (width + self.config.grid_spacing, height + self.config.grid_spacing)
     }
 
     /// Reposition all icon surfaces based on current screen dimensions
     fn reposition_all_icons(&mut self) {
-        let surface_height = self.config.icon_size + LABEL_HEIGHT;
-        let cell_width = self.config.icon_size + self.config.grid_spacing;
-        let cell_height = surface_height + self.config.grid_spacing;
+        let (cell_width, cell_height) = self.grid_cell(None);
         let icon_count = self.icons.len() as u32;
 
         // Collect (path, surface_id) pairs to reposition
@@ -543,38 +1150,148 @@ impl IconDaemon {
         }
     }
 
+    /// Sort the icons by `mode` and lay them out on the grid in that order
+    ///
+    /// Ties, and files whose metadata can't be read, fall back to name
+    /// order, the latter sorting last.
+    #[allow(dead_code)]
+    pub fn arrange(&mut self, mode: ArrangeMode) {
+        let mut order: Vec<(&PathBuf, &DesktopIcon)> = self.icons.iter().collect();
+        let by_name = |a: &DesktopIcon, b: &DesktopIcon| {
+            a.name().to_lowercase().cmp(&b.name().to_lowercase()).then_with(|| a.name().cmp(b.name()))
+        };
+        order.sort_by(|(_, a), (_, b)| {
+            let primary = match mode {
+                ArrangeMode::Name => std::cmp::Ordering::Equal,
+                // None is the least Option, so descending puts it last
+                ArrangeMode::ModifiedDesc => b.modified().cmp(&a.modified()),
+                ArrangeMode::Type => {
+                    let rank = |icon: &DesktopIcon| (icon.icon_type() != IconType::Folder, icon.icon_type());
+                    rank(a).cmp(&rank(b))
+                }
+                ArrangeMode::SizeDesc => b.get_file_size().cmp(&a.get_file_size()),
+            };
+            primary.then_with(|| by_name(a, b))
+        });
+        let order: Vec<PathBuf> = order.into_iter().map(|(path, _)| path.clone()).collect();
+
+        let (cell_width, cell_height) = self.grid_cell(None);
+        let icon_count = order.len() as u32;
+
+        for (index, path) in order.iter().enumerate() {
+            let Some(icon) = self.icons.get_mut(path) else {
+                continue;
+            };
+            let position = icon.request_position(
+                self.screen_width,
+                self.screen_height,
+                icon_count,
+                index as u32,
+                Some(cell_width),
+                Some(cell_height),
+            );
+            if let (Some(wayland), Some(&surface_id)) = (self.wayland.as_mut(), self.path_to_surface.get(path)) {
+                wayland.set_surface_position(surface_id, position.x, position.y);
+            }
+        }
+        debug!("Arranged {} icons by {:?}", order.len(), mode);
+    }
+
     /// Request render for all icons (called when display needs update)
     ///
     /// Returns a vector of (path, draw_commands) pairs
     #[allow(dead_code)]
-    pub fn render_all_icons(
-        &mut self,
-        canvas_width: u32,
-        canvas_height: u32,
-        device_pixel_ratio: f32,
-    ) -> Vec<(PathBuf, Vec<crate::lua::DrawCommand>)> {
-        self.icons
-            .iter_mut()
-            .map(|(path, icon)| {
-                let commands = icon.request_render(canvas_width, canvas_height, device_pixel_ratio);
-                (path.clone(), commands)
-            })
-            .collect()
+    pub fn render_all_icons(&mut self, device_pixel_ratio: f32) -> Vec<(PathBuf, Vec<crate::lua::DrawCommand>)> {
+        let paths: Vec<PathBuf> = self.icons.keys().cloned().collect();
+        self.render_icons(&paths, device_pixel_ratio)
     }
 
-    /// Calculate positions for all icons
+    /// Request render for the icons at `paths`
     ///
-    /// Returns a vector of (path, position) pairs
-    #[allow(dead_code)]
-    pub fn position_all_icons(
+    /// Each icon's canvas is the size of its surface (see `surface_size`).
+    ///
+    /// Icons served by the same process are rendered in one `RenderBatch`
+    /// round-trip; icons left out of a batch fall back to their own render
+    /// request. Those requests run in parallel on the render threads, each
+    /// icon talking only to its own process. Icons with separate processes
+    /// never share one, even when they run the same script, as their state
+    /// and sandboxes differ.
+    fn render_icons(
         &mut self,
-        screen_width: u32,
-        screen_height: u32,
-        cell_width: Option<u32>,
-        cell_height: Option<u32>,
-    ) -> Vec<(PathBuf, crate::ipc::Position)> {
-        let icon_count = self.icons.len() as u32;
-
+        paths: &[PathBuf],
+        device_pixel_ratio: f32,
+    ) -> Vec<(PathBuf, Vec<crate::lua::DrawCommand>)> {
+        let mut groups: HashMap<u32, Vec<PathBuf>> = HashMap::new();
+        for path in paths {
+            if let Some(key) = self.icons.get(path).and_then(|icon| icon.batch_key()) {
+                groups.entry(key).or_default().push(path.clone());
+            }
+        }
+
+        let mut rendered: HashMap<PathBuf, RenderResult> = HashMap::new();
+        for paths in groups.into_values().filter(|paths| paths.len() > 1) {
+            let items = paths
+                .iter()
+                .enumerate()
+                .map(|(id, path)| {
+                    let icon = &self.icons[path];
+                    let (width, height) = surface_size(icon);
+                    icon.render_item(id as u32, width, height, device_pixel_ratio)
+                })
+                .collect();
+
+            let results = match self.icons.get_mut(&paths[0]).and_then(|icon| icon.request_render_batch(items)) {
+                Some(results) => results,
+                None => continue,
+            };
+            for result in results {
+                if let Some(path) = paths.get(result.id as usize) {
+                    rendered.insert(path.clone(), result);
+                }
+            }
+        }
+
+        let wanted: HashSet<&PathBuf> = paths.iter().collect();
+        let icons = &mut self.icons;
+        let mut commands: HashMap<PathBuf, Vec<crate::lua::DrawCommand>> = self.render_pool.install(|| {
+            icons
+                .par_iter_mut()
+                .filter(|(path, _)| wanted.contains(path))
+                .map(|(path, icon)| {
+                    let commands = match rendered.get(path) {
+                        Some(result) => {
+                            icon.set_rendered(result.commands.clone(), result.next_frame_ms);
+                            result.commands.clone()
+                        }
+                        None => {
+                            let (width, height) = surface_size(icon);
+                            icon.request_render(width, height, device_pixel_ratio)
+                        }
+                    };
+                    (path.clone(), commands)
+                })
+                .collect()
+        });
+
+        paths
+            .iter()
+            .filter_map(|path| commands.remove(path).map(|commands| (path.clone(), commands)))
+            .collect()
+    }
+
+    /// Calculate positions for all icons
+    ///
+    /// Returns a vector of (path, position) pairs
+    #[allow(dead_code)]
+    pub fn position_all_icons(
+        &mut self,
+        screen_width: u32,
+        screen_height: u32,
+        cell_width: Option<u32>,
+        cell_height: Option<u32>,
+    ) -> Vec<(PathBuf, crate::ipc::Position)> {
+        let icon_count = self.icons.len() as u32;
+
         self.icons
             .iter_mut()
             .enumerate()
@@ -592,6 +1309,29 @@ impl IconDaemon {
             .collect()
     }
 
+    /// Select the icons whose cell overlaps the rectangle at (`x`, `y`)
+    /// sized `w`x`h`, deselecting all others
+    ///
+    /// A cell is the icon with its label below, the area clicks hit; icons
+    /// only touching the rectangle's edge stay unselected.
+    #[allow(dead_code)]
+    pub fn select_in_region(&mut self, x: u32, y: u32, w: u32, h: u32) {
+        let (right, bottom) = (x.saturating_add(w), y.saturating_add(h));
+
+        for icon in self.icons.values_mut() {
+            let (cell_width, cell_height) = surface_size(icon);
+            let (icon_x, icon_y) = icon.position();
+            let inside = icon_x < right
+                && x < icon_x.saturating_add(cell_width)
+                && icon_y < bottom
+                && y < icon_y.saturating_add(cell_height);
+            if icon.is_selected() != inside {
+                icon.set_selected(inside);
+                self.needs_render = true;
+            }
+        }
+    }
+
     /// Get an icon by path
     #[allow(dead_code)]
     pub fn get_icon(&self, path: &Path) -> Option<&DesktopIcon> {
@@ -617,6 +1357,10 @@ impl IconDaemon {
         let (sender, channel): (Sender<notify::Result<Event>>, Channel<notify::Result<Event>>) =
             calloop::channel::channel();
 
+        // Reload the config on SIGHUP (before the watcher thread starts, so
+        // it inherits the blocked signal mask)
+        self.setup_reload_signal(&loop_handle)?;
+
         // Set up the file watcher with the calloop channel sender
         self.setup_watcher(sender)?;
 
@@ -638,12 +1382,23 @@ impl IconDaemon {
             })
             .map_err(|e| anyhow::anyhow!("Failed to register file watcher channel: {:?}", e))?;
 
-        // Register a timer for periodic icon updates (16ms = ~60 FPS)
-        let timer = Timer::from_duration(Duration::from_millis(16));
+        // Wake up as soon as the compositor sends something, rather than on
+        // the next tick
+        if let Some(ref wayland) = self.wayland {
+            loop_handle
+                .insert_source(
+                    Generic::new(wayland.wake_fd()?, Interest::READ, Mode::Level),
+                    |_, _, _: &mut DaemonState| Ok(PostAction::Continue),
+                )
+                .map_err(|e| anyhow::anyhow!("Failed to register Wayland wakeup source: {:?}", e))?;
+        }
+
+        // Register a timer for periodic icon maintenance
+        let timer = Timer::from_duration(MAINTENANCE_INTERVAL);
         loop_handle
             .insert_source(timer, |_, _, state: &mut DaemonState| {
                 state.should_update_icons = true;
-                TimeoutAction::ToDuration(Duration::from_millis(16))
+                TimeoutAction::ToDuration(state.maintenance_interval)
             })
             .map_err(|e| anyhow::anyhow!("Failed to register update timer: {:?}", e))?;
 
@@ -652,7 +1407,10 @@ impl IconDaemon {
             pending_events: Vec::new(),
             should_update_icons: false,
             should_stop: false,
+            reload_requested: false,
+            maintenance_interval: MAINTENANCE_INTERVAL,
         };
+        let mut pacer = Pacer::default();
 
         info!("Entering calloop dispatch loop");
 
@@ -665,30 +1423,55 @@ impl IconDaemon {
             self.dispatch_wayland();
 
             // Handle any Wayland input events
-            self.handle_wayland_input();
+            if self.handle_wayland_input() {
+                pacer.activity(std::time::Instant::now());
+            }
+
+            // Send hover changes held back by the debounce
+            self.flush_hover_events();
 
             // Check for screen dimension changes from Wayland outputs
             self.update_screen_dimensions();
 
-            // Dispatch calloop events (blocking with timeout)
+            // Dispatch calloop events, blocking for as long as the desktop's
+            // activity and the next animation frame allow
+            let now = std::time::Instant::now();
+            state.maintenance_interval = match pacer.pace(now) {
+                Pace::Fast => MAINTENANCE_INTERVAL,
+                Pace::Slow => pacer::SLOW_TICK,
+            };
             event_loop
-                .dispatch(Some(Duration::from_millis(16)), &mut state)
+                .dispatch(Some(pacer.timeout(now, self.next_frame())), &mut state)
                 .context("Event loop dispatch failed")?;
 
             // Process pending file system events
+            if !state.pending_events.is_empty() {
+                pacer.activity(std::time::Instant::now());
+            }
             for event in state.pending_events.drain(..) {
                 if let Err(e) = self.handle_fs_event(event) {
                     error!("Error handling fs event: {}", e);
                 }
             }
 
-            // Update icons if timer fired
+            // Reload the config if SIGHUP arrived
+            if state.reload_requested {
+                self.reload_config();
+                state.reload_requested = false;
+            }
+
+            // Check icons if the maintenance timer fired
             if state.should_update_icons {
                 self.update_icons();
+                self.check_heartbeats();
                 state.should_update_icons = false;
             }
 
-            // Only render if something changed (dirty flag is checked inside render_icons_to_surfaces)
+            // Only render if something changed or an animation frame is due
+            // (both are checked inside render_icons_to_surfaces)
+            if self.needs_render {
+                pacer.activity(std::time::Instant::now());
+            }
             self.render_icons_to_surfaces();
 
             // Check if we should stop
@@ -720,11 +1503,41 @@ impl IconDaemon {
     }
 }
 
+/// Thread pool for rendering icons; `threads` 0 uses one per CPU
+fn render_pool(threads: usize) -> Result<ThreadPool> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("cvh-render-{}", i))
+        .build()
+        .context("Failed to create render thread pool")
+}
+
+/// Size of an icon's surface: the icon, with the label area below it
+/// unless its type hides the label
+///
+/// Canvases are the same size, so a script's label drawing falls outside
+/// a hidden label's canvas.
+fn surface_size(icon: &DesktopIcon) -> (u32, u32) {
+    let label = if icon.show_label() { LABEL_HEIGHT } else { 0 };
+    (icon.size(), icon.size() + label)
+}
+
+/// Whether a path names a hidden (dot) file
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(|n| n.starts_with('.'))
+        .unwrap_or(false)
+}
+
 /// State passed to the calloop event loop callbacks
 struct DaemonState {
     pending_events: Vec<Event>,
     should_update_icons: bool,
     should_stop: bool,
+    reload_requested: bool,
+    /// Delay before the maintenance timer fires again, set from the pace
+    maintenance_interval: Duration,
 }
 
 #[cfg(test)]
@@ -736,7 +1549,10 @@ mod tests {
 
     /// Helper to create a test config
     fn test_config() -> Config {
-        Config::default()
+        let mut config = Config::default();
+        // Keep icons' cvh.state out of the user's state directory
+        config.sandbox.state_dir = Some(std::env::temp_dir().join("cvh-icons-test-state"));
+        config
     }
 
     /// Helper to create a test daemon without watchers (for unit testing)
@@ -745,6 +1561,7 @@ mod tests {
         let renderer = IconRenderer::new(config.icon_size, config.font_size);
         IconDaemon {
             config,
+            config_path: None,
             desktop_dir,
             icons: HashMap::new(),
             watcher: None,
@@ -755,7 +1572,11 @@ mod tests {
             path_to_surface: HashMap::new(),
             screen_width: 1920,
             screen_height: 1080,
+            buffer_scale: 1,
             needs_render: false,
+            focused: None,
+            render_trace: None,
+            render_pool: render_pool(2).unwrap(),
         }
     }
 
@@ -957,6 +1778,250 @@ mod tests {
     // Hidden File Filtering Tests
     // ========================================================================
 
+    fn rename_event(from: &Path, to: &Path) -> Event {
+        Event {
+            kind: EventKind::Modify(ModifyKind::Name(notify::event::RenameMode::Both)),
+            paths: vec![from.to_path_buf(), to.to_path_buf()],
+            attrs: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_rename_event_moves_icon_with_position_and_process() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        let old_path = desktop_path.join("draft.txt");
+        let new_path = desktop_path.join("final.txt");
+        fs::write(&old_path, "content").unwrap();
+        daemon.add_icon(&old_path).unwrap();
+
+        let mut sleeper = std::process::Command::new("sleep");
+        sleeper.arg("30");
+        let process = crate::lua::LuaProcess::spawn_unsandboxed(
+            sleeper,
+            PathBuf::from("/tmp/ipc_handler.lua"),
+            PathBuf::from("/tmp/document.lua"),
+        )
+        .unwrap();
+        let icon = daemon.get_icon_mut(&old_path).unwrap();
+        icon.set_position(3, 4);
+        icon.set_lua_process(process);
+        let pid = icon.lua_pid();
+
+        fs::rename(&old_path, &new_path).unwrap();
+        daemon.handle_fs_event(rename_event(&old_path, &new_path)).unwrap();
+
+        assert!(!daemon.has_icon(&old_path));
+        let icon = daemon.get_icon(&new_path).expect("icon should follow the rename");
+        assert_eq!(icon.name(), "final.txt");
+        assert_eq!(icon.path(), new_path.as_path());
+        assert_eq!(icon.position(), (3, 4));
+        assert!(pid.is_some());
+        assert_eq!(icon.lua_pid(), pid, "Lua process should survive the rename");
+        assert!(daemon.needs_render);
+    }
+
+    #[test]
+    fn test_keyboard_focus_and_selection() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        let names = ["a.txt", "b.txt", "c.txt"];
+        for (i, name) in names.iter().enumerate() {
+            let path = desktop_path.join(name);
+            fs::write(&path, "").unwrap();
+            daemon.add_icon(&path).unwrap();
+            daemon.get_icon_mut(&path).unwrap().set_position(i as u32 * 100, 0);
+        }
+        let [a, b, c] = names.map(|name| desktop_path.join(name));
+
+        // The first arrow focuses the top left icon
+        daemon.handle_key(Key::Down);
+        assert_eq!(daemon.focused.as_ref(), Some(&a));
+        assert!(daemon.get_icon(&a).unwrap().is_hovered());
+        assert!(daemon.needs_render);
+
+        daemon.handle_key(Key::Right);
+        assert_eq!(daemon.focused.as_ref(), Some(&b));
+        assert!(!daemon.get_icon(&a).unwrap().is_hovered());
+        assert!(daemon.get_icon(&b).unwrap().is_hovered());
+
+        daemon.handle_key(Key::Space);
+        assert!(daemon.get_icon(&b).unwrap().is_selected());
+        daemon.handle_key(Key::Left);
+        daemon.handle_key(Key::Left);
+        assert_eq!(daemon.focused.as_ref(), Some(&c), "Left wraps around");
+        daemon.handle_key(Key::Right);
+        daemon.handle_key(Key::Right);
+        daemon.handle_key(Key::Space);
+        assert!(!daemon.get_icon(&b).unwrap().is_selected());
+
+        // A removed focused icon hands the focus back to the top left
+        daemon.remove_icon(&b);
+        daemon.handle_key(Key::Right);
+        assert_eq!(daemon.focused.as_ref(), Some(&a));
+    }
+
+    #[test]
+    fn test_select_in_region() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+        let cell_height = daemon.config.icon_size + LABEL_HEIGHT;
+
+        // A 3x2 grid of cells 100px apart
+        let mut paths = Vec::new();
+        for row in 0..2 {
+            for column in 0..3 {
+                let path = desktop_path.join(format!("{}{}.txt", row, column));
+                fs::write(&path, "").unwrap();
+                daemon.add_icon(&path).unwrap();
+                daemon.get_icon_mut(&path).unwrap().set_position(column * 100, row * 100);
+                paths.push(path);
+            }
+        }
+        let selected = |daemon: &IconDaemon| -> Vec<usize> {
+            (0..paths.len()).filter(|&i| daemon.get_icon(&paths[i]).unwrap().is_selected()).collect()
+        };
+
+        // Entirely around the top left two
+        daemon.select_in_region(0, 0, 190, 50);
+        assert_eq!(selected(&daemon), [0, 1]);
+        assert!(daemon.needs_render);
+
+        // Clipping the bottom of 01's label and the left edge of 11
+        daemon.select_in_region(90, cell_height - 1, 20, 100);
+        assert_eq!(selected(&daemon), [1, 4]);
+
+        // Touching edges don't count, and a fresh region clears the rest
+        daemon.select_in_region(daemon.config.icon_size, cell_height, 100 - daemon.config.icon_size, 10);
+        assert!(selected(&daemon).is_empty());
+
+        daemon.select_in_region(0, 0, 1000, 1000);
+        assert_eq!(selected(&daemon), [0, 1, 2, 3, 4, 5]);
+
+        daemon.needs_render = false;
+        daemon.select_in_region(0, 0, 1000, 1000);
+        assert!(!daemon.needs_render, "nothing changed");
+    }
+
+    #[test]
+    fn test_arrange_modes() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        // (name, size, age in hours); None for a folder
+        let files = [
+            ("notes.txt", Some(300), 3),
+            ("Archive.zip", Some(5000), 1),
+            ("photo.png", Some(1200), 2),
+            ("projects", None, 4),
+            ("beta.txt", Some(10), 0),
+        ];
+        let now = std::time::SystemTime::now();
+        for (name, size, age) in files {
+            let path = desktop_path.join(name);
+            match size {
+                Some(size) => fs::write(&path, vec![0u8; size]).unwrap(),
+                None => fs::create_dir(&path).unwrap(),
+            }
+            let modified = now - Duration::from_secs(age * 3600);
+            fs::File::open(&path).unwrap().set_modified(modified).unwrap();
+            daemon.add_icon(&path).unwrap();
+        }
+
+        let arranged = |daemon: &mut IconDaemon, mode| -> Vec<String> {
+            daemon.arrange(mode);
+            let mut icons: Vec<(&PathBuf, &DesktopIcon)> = daemon.icons.iter().collect();
+            icons.sort_by_key(|(_, icon)| (icon.position().1, icon.position().0));
+            icons.iter().map(|(_, icon)| icon.name().to_string()).collect()
+        };
+
+        assert_eq!(
+            arranged(&mut daemon, ArrangeMode::Name),
+            ["Archive.zip", "beta.txt", "notes.txt", "photo.png", "projects"]
+        );
+        assert_eq!(
+            arranged(&mut daemon, ArrangeMode::ModifiedDesc),
+            ["beta.txt", "Archive.zip", "photo.png", "notes.txt", "projects"]
+        );
+        assert_eq!(
+            arranged(&mut daemon, ArrangeMode::Type),
+            ["projects", "photo.png", "beta.txt", "notes.txt", "Archive.zip"]
+        );
+        // The folder's size is that of its directory entry
+        let sized = arranged(&mut daemon, ArrangeMode::SizeDesc);
+        let files_only: Vec<&String> = sized.iter().filter(|name| *name != "projects").collect();
+        assert_eq!(files_only, ["Archive.zip", "photo.png", "notes.txt", "beta.txt"]);
+
+        // Each arrangement fills the grid from the top left, row by row
+        let mut positions: Vec<(u32, u32)> = daemon.icons.values().map(|icon| icon.position()).collect();
+        positions.sort();
+        let cell_width = daemon.config.icon_size + daemon.config.grid_spacing;
+        let expected: Vec<(u32, u32)> = (0..5).map(|i| (20 + i * cell_width, 20)).collect();
+        assert_eq!(positions, expected);
+    }
+
+    #[test]
+    fn test_rename_over_existing_icon_replaces_it() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        let a = desktop_path.join("a.txt");
+        let b = desktop_path.join("b.txt");
+        fs::write(&a, "a").unwrap();
+        fs::write(&b, "b").unwrap();
+        daemon.add_icon(&a).unwrap();
+        daemon.add_icon(&b).unwrap();
+        daemon.get_icon_mut(&a).unwrap().set_position(7, 1);
+
+        fs::rename(&a, &b).unwrap();
+        daemon.handle_fs_event(rename_event(&a, &b)).unwrap();
+
+        assert_eq!(daemon.icon_count(), 1);
+        assert_eq!(daemon.get_icon(&b).unwrap().position(), (7, 1));
+    }
+
+    #[test]
+    fn test_rename_to_hidden_or_from_unknown() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        let visible = desktop_path.join("visible.txt");
+        let hidden = desktop_path.join(".hidden.txt");
+        fs::write(&visible, "content").unwrap();
+        daemon.add_icon(&visible).unwrap();
+
+        // Hiding a file drops its icon
+        daemon.handle_fs_event(rename_event(&visible, &hidden)).unwrap();
+        assert_eq!(daemon.icon_count(), 0);
+
+        // Un-hiding a file (no icon yet) adds one
+        daemon.handle_fs_event(rename_event(&hidden, &visible)).unwrap();
+        assert!(daemon.has_icon(&visible));
+    }
+
+    #[test]
+    fn test_rename_changing_type_updates_icon_type() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        let text = desktop_path.join("notes.txt");
+        let archive = desktop_path.join("notes.zip");
+        fs::write(&text, "content").unwrap();
+        daemon.add_icon(&text).unwrap();
+
+        daemon.handle_fs_event(rename_event(&text, &archive)).unwrap();
+        assert_eq!(daemon.get_icon(&archive).unwrap().icon_type(), IconType::Archive);
+    }
+
     #[test]
     fn test_scan_desktop_ignores_hidden_files() {
         let temp_dir = TempDir::new().unwrap();
@@ -1038,6 +2103,118 @@ mod tests {
     // Icon Count Tests
     // ========================================================================
 
+    /// Desktop with entries nested three folders deep, plus hidden ones
+    fn nested_desktop(root: &Path) {
+        fs::create_dir_all(root.join("a/b/c")).unwrap();
+        fs::create_dir_all(root.join("a/.secret")).unwrap();
+        fs::write(root.join("top.txt"), "").unwrap();
+        fs::write(root.join("a/one.txt"), "").unwrap();
+        fs::write(root.join("a/.hidden.txt"), "").unwrap();
+        fs::write(root.join("a/.secret/inside.txt"), "").unwrap();
+        fs::write(root.join("a/b/two.txt"), "").unwrap();
+        fs::write(root.join("a/b/c/three.txt"), "").unwrap();
+    }
+
+    fn icon_names(daemon: &IconDaemon) -> Vec<String> {
+        let mut names: Vec<String> = daemon
+            .icons()
+            .keys()
+            .map(|p| p.strip_prefix(&daemon.desktop_dir).unwrap().display().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_scan_desktop_is_flat_by_default() {
+        let temp_dir = TempDir::new().unwrap();
+        nested_desktop(temp_dir.path());
+
+        let mut daemon = create_test_daemon(temp_dir.path().to_path_buf());
+        daemon.scan_desktop().unwrap();
+        assert_eq!(icon_names(&daemon), vec!["a", "top.txt"]);
+    }
+
+    #[test]
+    fn test_recursive_scan_honors_depth_cap() {
+        let temp_dir = TempDir::new().unwrap();
+        nested_desktop(temp_dir.path());
+
+        let mut daemon = create_test_daemon(temp_dir.path().to_path_buf());
+        daemon.config.recursive_desktop = true;
+        daemon.config.max_desktop_depth = 1;
+        daemon.scan_desktop().unwrap();
+        assert_eq!(icon_names(&daemon), vec!["a", "a/b", "a/one.txt", "top.txt"]);
+
+        let mut daemon = create_test_daemon(temp_dir.path().to_path_buf());
+        daemon.config.recursive_desktop = true;
+        daemon.config.max_desktop_depth = 3;
+        daemon.scan_desktop().unwrap();
+        assert_eq!(
+            icon_names(&daemon),
+            vec!["a", "a/b", "a/b/c", "a/b/c/three.txt", "a/b/two.txt", "a/one.txt", "top.txt"]
+        );
+    }
+
+    #[test]
+    fn test_events_respect_depth_cap_and_hidden_levels() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        nested_desktop(&desktop_path);
+
+        let mut daemon = create_test_daemon(desktop_path.clone());
+        daemon.config.recursive_desktop = true;
+        daemon.config.max_desktop_depth = 1;
+
+        let create = |path: PathBuf| Event {
+            kind: EventKind::Create(CreateKind::File),
+            paths: vec![path],
+            attrs: Default::default(),
+        };
+        daemon.handle_fs_event(create(desktop_path.join("a/one.txt"))).unwrap();
+        daemon.handle_fs_event(create(desktop_path.join("a/b/two.txt"))).unwrap();
+        daemon.handle_fs_event(create(desktop_path.join("a/.secret/inside.txt"))).unwrap();
+        daemon.handle_fs_event(create(desktop_path.join(".hidden"))).unwrap();
+        assert_eq!(icon_names(&daemon), vec!["a/one.txt"]);
+    }
+
+    #[test]
+    fn test_recursive_folder_events_add_and_remove_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        nested_desktop(&desktop_path);
+
+        let mut daemon = create_test_daemon(desktop_path.clone());
+        daemon.config.recursive_desktop = true;
+
+        // A folder appearing at once brings its entries along
+        daemon
+            .handle_fs_event(Event {
+                kind: EventKind::Create(CreateKind::Folder),
+                paths: vec![desktop_path.join("a")],
+                attrs: Default::default(),
+            })
+            .unwrap();
+        assert_eq!(daemon.icon_count(), 6);
+
+        // Moving the folder moves every entry below it
+        fs::rename(desktop_path.join("a"), desktop_path.join("z")).unwrap();
+        daemon.handle_fs_event(rename_event(&desktop_path.join("a"), &desktop_path.join("z"))).unwrap();
+        assert_eq!(
+            icon_names(&daemon),
+            vec!["z", "z/b", "z/b/c", "z/b/c/three.txt", "z/b/two.txt", "z/one.txt"]
+        );
+
+        daemon
+            .handle_fs_event(Event {
+                kind: EventKind::Remove(RemoveKind::Folder),
+                paths: vec![desktop_path.join("z")],
+                attrs: Default::default(),
+            })
+            .unwrap();
+        assert_eq!(daemon.icon_count(), 0);
+    }
+
     #[test]
     fn test_icon_count_accuracy() {
         let temp_dir = TempDir::new().unwrap();
@@ -1080,4 +2257,359 @@ mod tests {
 
         assert_eq!(daemon.icon_count(), 1, "Should still have only 1 icon after duplicate add");
     }
+
+    // ========================================================================
+    // Config reload tests
+    // ========================================================================
+
+    /// Create a script directory with an IPC handler and a file widget
+    fn script_dir(root: &Path, name: &str) -> PathBuf {
+        let dir = root.join(name);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("ipc_handler.lua"), "-- handler").unwrap();
+        fs::write(dir.join("file.lua"), "-- widget").unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_apply_config_re_resolves_scripts() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().join("Desktop");
+        fs::create_dir_all(&desktop_path).unwrap();
+        let scripts_a = script_dir(temp_dir.path(), "a");
+        let scripts_b = script_dir(temp_dir.path(), "b");
+
+        let mut daemon = create_test_daemon(desktop_path.clone());
+        daemon.config.script_dirs = vec![scripts_a.clone()];
+
+        let test_file = desktop_path.join("notes.dat");
+        fs::write(&test_file, "content").unwrap();
+        daemon.add_icon(&test_file).unwrap();
+        assert_eq!(daemon.icons()[&test_file].script_path(), Some(scripts_a.join("file.lua").as_path()));
+
+        // Swapping script_dirs moves the icon to the new widget script
+        let mut config = daemon.config.clone();
+        config.script_dirs = vec![scripts_b.clone()];
+        daemon.apply_config(config);
+        let icon = &daemon.icons()[&test_file];
+        assert_eq!(icon.script_path(), Some(scripts_b.join("file.lua").as_path()));
+        assert_eq!(icon.handler_path(), Some(scripts_b.join("ipc_handler.lua").as_path()));
+
+        // With no scripts left the icon falls back to built-in rendering
+        let mut config = daemon.config.clone();
+        config.script_dirs = Vec::new();
+        daemon.apply_config(config);
+        let icon = &daemon.icons()[&test_file];
+        assert!(icon.script_path().is_none());
+        assert!(!icon.has_lua_process());
+        assert!(daemon.needs_render);
+    }
+
+    #[test]
+    fn test_apply_config_icon_size_rebuilds_icons() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        for name in ["a.txt", "b.txt"] {
+            fs::write(desktop_path.join(name), "content").unwrap();
+        }
+        daemon.scan_desktop().unwrap();
+
+        let mut config = daemon.config.clone();
+        config.icon_size = 96;
+        daemon.apply_config(config);
+
+        assert_eq!(daemon.icon_count(), 2);
+        assert_eq!(daemon.config.icon_size, 96);
+        assert!(daemon.has_icon(&desktop_path.join("a.txt")));
+    }
+
+    #[test]
+    fn test_reload_failure_keeps_current_config() {
+        let temp_dir = TempDir::new().unwrap();
+        let config_path = temp_dir.path().join("config.toml");
+        fs::write(&config_path, "icon_size = \"not a number\"").unwrap();
+
+        let mut daemon = create_test_daemon(temp_dir.path().to_path_buf());
+        daemon.set_config_path(Some(config_path.clone()));
+        daemon.reload_config();
+        assert_eq!(daemon.config.icon_size, 64);
+
+        // An interpreter list with nothing installed is refused
+        fs::write(&config_path, "icon_size = 32\n[sandbox]\nlua_interpreters = [\"no-such-lua\"]\n").unwrap();
+        daemon.reload_config();
+        assert_eq!(daemon.config.icon_size, 64);
+
+        fs::write(&config_path, "icon_size = 48").unwrap();
+        daemon.reload_config();
+        assert_eq!(daemon.config.icon_size, 48);
+    }
+
+    // ========================================================================
+    // Render batch tests
+    // ========================================================================
+
+    #[test]
+    fn test_same_script_icons_render_through_their_own_processes() {
+        use crate::ipc::{IpcEncoding, Request, Response};
+        use crate::lua::{DrawCommand, LuaProcess};
+
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        // Both mock processes report the same handler and widget script
+        let mut peers = Vec::new();
+        for name in ["a.txt", "b.txt"] {
+            let path = desktop_path.join(name);
+            fs::write(&path, name).unwrap();
+            daemon.add_icon(&path).unwrap();
+            let (process, peer) = LuaProcess::mock().unwrap();
+            daemon.get_icon_mut(&path).unwrap().set_lua_process(process);
+            peers.push((path, peer));
+        }
+
+        // A click changes the state held by a.txt's script only
+        let (clicked, peer) = &mut peers[0];
+        peer.send_response(&Response::Event { handled: true, action: None });
+        daemon.get_icon_mut(clicked).unwrap().on_click(1, 0.0, 0.0).unwrap();
+        peer.try_read_raw(Duration::from_millis(50)).unwrap();
+
+        for (path, peer) in &mut peers {
+            let state = if *path == desktop_path.join("a.txt") { "clicked" } else { "idle" };
+            peer.send_response(&Response::Render {
+                commands: vec![DrawCommand::Clear { color: state.to_string() }],
+                next_frame_ms: None,
+            });
+        }
+        let rendered = daemon.render_all_icons(1.0);
+
+        // Each process was asked about its own icon alone, and each icon got
+        // its own script's drawing
+        for (path, peer) in &mut peers {
+            let raw = peer.try_read_raw(Duration::from_millis(50)).unwrap();
+            match Request::deserialize(&raw[4..], IpcEncoding::Json).unwrap() {
+                Request::Render { metadata, .. } => assert_eq!(metadata.path, path.to_string_lossy()),
+                other => panic!("Expected Render request, got {:?}", other),
+            }
+            assert!(peer.try_read_raw(Duration::from_millis(10)).is_none());
+        }
+        let color = |name: &str| match &rendered.iter().find(|(path, _)| path.ends_with(name)).unwrap().1[..] {
+            [DrawCommand::Clear { color }] => color.clone(),
+            other => panic!("Unexpected commands {:?}", other),
+        };
+        assert_eq!(color("a.txt"), "clicked");
+        assert_eq!(color("b.txt"), "idle");
+    }
+
+    #[test]
+    fn test_icon_type_size_reaches_canvas_and_layout() {
+        use crate::config::IconTypeConfig;
+        use crate::ipc::{IpcEncoding, Request, Response};
+        use crate::lua::{DrawCommand, LuaProcess};
+
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+        daemon.config.icon_types.insert(
+            IconType::Folder,
+            IconTypeConfig { size: Some(96), show_label: Some(false) },
+        );
+
+        let folder = desktop_path.join("Projects");
+        fs::create_dir(&folder).unwrap();
+        let file = desktop_path.join("notes.txt");
+        fs::write(&file, "notes").unwrap();
+        daemon.add_icon(&folder).unwrap();
+        daemon.add_icon(&file).unwrap();
+        let (process, mut peer) = LuaProcess::mock().unwrap();
+        daemon.get_icon_mut(&folder).unwrap().set_lua_process(process);
+        peer.send_response(&Response::Render {
+            commands: vec![DrawCommand::Clear { color: "#000000".to_string() }],
+            next_frame_ms: None,
+        });
+
+        let rendered = daemon.render_all_icons(1.0);
+        assert_eq!(rendered.len(), 2);
+
+        // The folder's script draws on a 96x96 canvas, without label area
+        let raw = peer.try_read_raw(Duration::from_millis(50)).unwrap();
+        match Request::deserialize(&raw[4..], IpcEncoding::Json).unwrap() {
+            Request::Render { metadata, context } => {
+                assert_eq!((metadata.width, metadata.height), (96, 96));
+                assert_eq!((context.canvas_width, context.canvas_height), (96, 96));
+            }
+            other => panic!("Expected Render request, got {:?}", other),
+        }
+
+        // The grid fits the largest icon, and clicks hit each icon's own area
+        let spacing = daemon.config.grid_spacing;
+        assert_eq!(daemon.grid_cell(None), (96 + spacing, 96 + spacing));
+        daemon.surface_to_path.insert(1, folder.clone());
+        daemon.surface_to_path.insert(2, file.clone());
+        assert_eq!(daemon.icon_at(1, 90.0, 90.0), Some(folder.clone()));
+        assert_eq!(daemon.icon_at(1, 10.0, 97.0), None, "no label area");
+        assert_eq!(daemon.icon_at(2, 10.0, 70.0), Some(file.clone()));
+        assert_eq!(daemon.icon_at(2, 70.0, 10.0), None);
+    }
+
+    #[test]
+    fn test_render_all_icons_without_processes_uses_fallback() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        for name in ["a.txt", "b.txt"] {
+            let path = desktop_path.join(name);
+            fs::write(&path, name).unwrap();
+            daemon.add_icon(&path).unwrap();
+        }
+
+        let rendered = daemon.render_all_icons(1.0);
+        assert_eq!(rendered.len(), 2);
+        assert!(rendered.iter().all(|(_, commands)| !commands.is_empty()));
+    }
+
+    #[test]
+    fn test_animating_icons_are_rerendered_when_due() {
+        use crate::ipc::Response;
+        use crate::lua::{DrawCommand, LuaProcess};
+        use std::time::Instant;
+
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        let animated = desktop_path.join("clock.txt");
+        let still = desktop_path.join("notes.txt");
+        for path in [&animated, &still] {
+            fs::write(path, "x").unwrap();
+            daemon.add_icon(path).unwrap();
+        }
+        let (process, mut peer) = LuaProcess::mock().unwrap();
+        daemon.get_icon_mut(&animated).unwrap().set_lua_process(process);
+        let frame = |next_frame_ms| Response::Render {
+            commands: vec![DrawCommand::Clear { color: "#000000".to_string() }],
+            next_frame_ms,
+        };
+
+        // Nothing is due before the first render
+        let start = Instant::now();
+        assert!(daemon.due_frames(start + Duration::from_secs(1)).is_empty());
+
+        peer.send_response(&frame(Some(100)));
+        assert_eq!(daemon.render_all_icons(1.0).len(), 2);
+        assert!(daemon.due_frames(start).is_empty());
+
+        // Only the animating icon comes due, and only it is rendered again
+        let due = daemon.due_frames(start + Duration::from_secs(1));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0], animated);
+        peer.send_response(&frame(None));
+        let rendered = daemon.render_icons(&due, 1.0);
+        assert_eq!(rendered.len(), 1);
+        assert_eq!(rendered[0].0, animated);
+
+        // It stopped asking for frames
+        assert!(daemon.due_frames(start + Duration::from_secs(1)).is_empty());
+    }
+
+    #[test]
+    fn test_render_trace_records_each_icon() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        for name in ["a.txt", "b.txt"] {
+            let path = desktop_path.join(name);
+            fs::write(&path, name).unwrap();
+            daemon.add_icon(&path).unwrap();
+        }
+
+        let trace_path = temp_dir.path().join("trace.jsonl");
+        daemon.set_render_trace(RenderTrace::open(&trace_path).unwrap());
+
+        let rendered = daemon.render_all_icons(1.0);
+        daemon.trace_render(&rendered);
+
+        let records = crate::renderer::trace::parse(&fs::read_to_string(&trace_path).unwrap()).unwrap();
+        assert_eq!(records.len(), 2);
+        for (path, commands) in &rendered {
+            let record = records.iter().find(|r| r.path == path.to_string_lossy()).unwrap();
+            assert_eq!(&record.commands, commands);
+        }
+    }
+
+    // ========================================================================
+    // Buffer Scale Tests
+    // ========================================================================
+
+    #[test]
+    fn test_buffer_scale_defaults_to_one_without_output() {
+        let temp_dir = TempDir::new().unwrap();
+        let daemon = create_test_daemon(temp_dir.path().to_path_buf());
+        assert_eq!(daemon.current_buffer_scale(), 1);
+    }
+
+    #[test]
+    fn test_buffer_scale_from_config_override() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut daemon = create_test_daemon(temp_dir.path().to_path_buf());
+
+        daemon.config.scale = Some(2.0);
+        assert_eq!(daemon.current_buffer_scale(), 2);
+
+        daemon.config.scale = Some(1.5);
+        assert_eq!(daemon.current_buffer_scale(), 2);
+    }
+
+    // ========================================================================
+    // Hit Test Tests
+    // ========================================================================
+
+    #[test]
+    fn test_icon_at_maps_surface_point_to_icon() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        let path = desktop_path.join("a.txt");
+        fs::write(&path, "a").unwrap();
+        daemon.add_icon(&path).unwrap();
+        daemon.surface_to_path.insert(7, path.clone());
+
+        let size = daemon.config.icon_size as f64;
+        assert_eq!(daemon.icon_at(7, 0.0, 0.0), Some(path.clone()));
+        assert_eq!(daemon.icon_at(7, size / 2.0, size / 2.0), Some(path.clone()));
+        // The label below the icon belongs to it too
+        assert_eq!(daemon.icon_at(7, size / 2.0, size + 1.0), Some(path));
+    }
+
+    #[test]
+    fn test_icon_at_misses_outside_icon_area() {
+        let temp_dir = TempDir::new().unwrap();
+        let desktop_path = temp_dir.path().to_path_buf();
+        let mut daemon = create_test_daemon(desktop_path.clone());
+
+        let path = desktop_path.join("a.txt");
+        fs::write(&path, "a").unwrap();
+        daemon.add_icon(&path).unwrap();
+        daemon.surface_to_path.insert(7, path);
+
+        let size = daemon.config.icon_size as f64;
+        let height = (daemon.config.icon_size + LABEL_HEIGHT) as f64;
+        assert_eq!(daemon.icon_at(7, size, 10.0), None);
+        assert_eq!(daemon.icon_at(7, 10.0, height), None);
+        assert_eq!(daemon.icon_at(7, -1.0, 10.0), None);
+        assert_eq!(daemon.icon_at(8, 10.0, 10.0), None, "unknown surface");
+    }
+
+    #[test]
+    fn test_icon_at_ignores_removed_icon() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut daemon = create_test_daemon(temp_dir.path().to_path_buf());
+        daemon.surface_to_path.insert(7, temp_dir.path().join("gone.txt"));
+        assert_eq!(daemon.icon_at(7, 10.0, 10.0), None);
+    }
 }
@@ -0,0 +1,107 @@
+//! Pacing of the daemon's main loop
+//!
+//! File, signal and Wayland events wake the loop through calloop, so its
+//! timeout only bounds work nothing announces: animation frames, debounced
+//! hover events and maintenance. While something is happening the loop
+//! ticks at frame rate; once the desktop has been idle for a while it backs
+//! off to a slow tick.
+
+use std::time::{Duration, Instant};
+
+/// Tick while there is activity (~60 FPS)
+pub const FAST_TICK: Duration = Duration::from_millis(16);
+
+/// Tick on an idle desktop
+pub const SLOW_TICK: Duration = Duration::from_secs(1);
+
+/// How long the loop stays fast after the last activity
+pub const ACTIVE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Whether the loop is ticking fast or slow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pace {
+    Fast,
+    Slow,
+}
+
+/// Picks how long the main loop may block
+#[derive(Debug, Default)]
+pub struct Pacer {
+    /// Last input, file event or render
+    last_activity: Option<Instant>,
+}
+
+impl Pacer {
+    /// Note that something happened at `now`, speeding the loop up
+    pub fn activity(&mut self, now: Instant) {
+        self.last_activity = Some(now);
+    }
+
+    /// Fast within `ACTIVE_PERIOD` of the last activity, slow otherwise
+    pub fn pace(&self, now: Instant) -> Pace {
+        match self.last_activity {
+            Some(at) if now.saturating_duration_since(at) < ACTIVE_PERIOD => Pace::Fast,
+            _ => Pace::Slow,
+        }
+    }
+
+    /// How long the loop may block at `now`
+    ///
+    /// The tick for the current pace, cut short for the earliest animation
+    /// frame but never below `FAST_TICK`, so a frame that is already overdue
+    /// can't make the loop spin.
+    pub fn timeout(&self, now: Instant, next_frame: Option<Instant>) -> Duration {
+        let tick = match self.pace(now) {
+            Pace::Fast => FAST_TICK,
+            Pace::Slow => SLOW_TICK,
+        };
+        match next_frame {
+            Some(at) => tick.min(at.saturating_duration_since(now).max(FAST_TICK)),
+            None => tick,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_backs_off_and_activity_speeds_up() {
+        let start = Instant::now();
+        let mut pacer = Pacer::default();
+
+        // Nothing has happened yet
+        assert_eq!(pacer.pace(start), Pace::Slow);
+        assert_eq!(pacer.timeout(start, None), SLOW_TICK);
+
+        pacer.activity(start);
+        assert_eq!(pacer.pace(start), Pace::Fast);
+        assert_eq!(pacer.timeout(start + ACTIVE_PERIOD / 2, None), FAST_TICK);
+
+        // Idle for the whole active period
+        let idle = start + ACTIVE_PERIOD;
+        assert_eq!(pacer.pace(idle), Pace::Slow);
+        assert_eq!(pacer.timeout(idle, None), SLOW_TICK);
+
+        // New activity is fast again straight away
+        pacer.activity(idle);
+        assert_eq!(pacer.timeout(idle, None), FAST_TICK);
+    }
+
+    #[test]
+    fn test_timeout_wakes_for_next_frame() {
+        let now = Instant::now();
+        let pacer = Pacer::default();
+
+        let frame = now + Duration::from_millis(200);
+        assert_eq!(pacer.timeout(now, Some(frame)), Duration::from_millis(200));
+
+        // Frames further out than the tick don't lengthen it
+        assert_eq!(pacer.timeout(now, Some(now + SLOW_TICK * 3)), SLOW_TICK);
+
+        // Due or overdue frames still wait one fast tick
+        assert_eq!(pacer.timeout(now, Some(now)), FAST_TICK);
+        assert_eq!(pacer.timeout(now + SLOW_TICK, Some(now)), FAST_TICK);
+    }
+}
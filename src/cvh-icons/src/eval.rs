@@ -0,0 +1,140 @@
+//! Evaluating Lua snippets against the icon sandbox
+//!
+//! `--eval` and `--repl` run code in the same sandboxed runtime icon scripts
+//! get, with the same globals removed and the same time and memory limits,
+//! so script authors can try things out without writing a widget.
+
+use anyhow::Result;
+use mlua::Error as LuaError;
+use std::io::{BufRead, Write};
+
+use crate::config::Config;
+use crate::lua::LuaRuntime;
+
+/// Prompt for a new statement
+const PROMPT: &str = "> ";
+
+/// Prompt while a statement spans several lines
+const CONTINUATION_PROMPT: &str = ">> ";
+
+/// Evaluate `code` in a fresh sandboxed runtime set up from `config` and
+/// print its results
+///
+/// `print` inside the snippet goes to the log, as it does for icon scripts.
+/// Opens, spawns and notifications are listed rather than carried out.
+pub fn run(code: &str, config: &Config) -> Result<()> {
+    let runtime = LuaRuntime::for_config(config)?;
+    let mut output = std::io::stdout();
+    for value in runtime.eval(code)? {
+        writeln!(output, "{}", value)?;
+    }
+    report_actions(&runtime, &mut output)
+}
+
+/// Read statements from stdin until end of input, evaluating each in one
+/// shared runtime set up from `config`
+pub fn repl(config: &Config) -> Result<()> {
+    let runtime = LuaRuntime::for_config(config)?;
+    run_repl(&runtime, std::io::stdin().lock(), &mut std::io::stdout())
+}
+
+/// The REPL loop over any input and output
+///
+/// A statement that ends early (an open `function` or string, say) keeps
+/// reading lines until it is complete. Errors are reported and the loop
+/// goes on.
+fn run_repl(runtime: &LuaRuntime, input: impl BufRead, output: &mut impl Write) -> Result<()> {
+    let mut lines = input.lines();
+    let mut pending = String::new();
+
+    loop {
+        write!(output, "{}", if pending.is_empty() { PROMPT } else { CONTINUATION_PROMPT })?;
+        output.flush()?;
+
+        let Some(line) = lines.next().transpose()? else {
+            writeln!(output)?;
+            return Ok(());
+        };
+        if !pending.is_empty() {
+            pending.push('\n');
+        }
+        pending.push_str(&line);
+
+        match runtime.eval(&pending) {
+            Ok(values) => {
+                for value in values {
+                    writeln!(output, "{}", value)?;
+                }
+                report_actions(runtime, output)?;
+            }
+            Err(e) if is_incomplete(&e) => continue,
+            Err(e) => writeln!(output, "error: {:#}", e)?,
+        }
+        pending.clear();
+    }
+}
+
+/// List the actions the code asked the daemon for
+fn report_actions(runtime: &LuaRuntime, output: &mut impl Write) -> Result<()> {
+    for action in runtime.take_actions() {
+        writeln!(output, "({} {})", action.action, action.payload.unwrap_or_default())?;
+    }
+    Ok(())
+}
+
+/// Whether `error` is a syntax error that more input could fix
+fn is_incomplete(error: &anyhow::Error) -> bool {
+    matches!(
+        error.downcast_ref::<LuaError>(),
+        Some(LuaError::SyntaxError { incomplete_input: true, .. })
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repl_output(input: &str) -> String {
+        let runtime = LuaRuntime::new().unwrap();
+        let mut output = Vec::new();
+        run_repl(&runtime, input.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_eval_print_succeeds() {
+        assert!(run("print(1+1)", &Config::default()).is_ok());
+        assert_eq!(LuaRuntime::new().unwrap().eval("1 + 1, 'two'").unwrap(), ["2", "two"]);
+    }
+
+    #[test]
+    fn test_eval_removed_globals_error() {
+        let err = run("os.execute('x')", &Config::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("global 'os'"), "got: {:#}", err);
+    }
+
+    #[test]
+    fn test_eval_honors_time_budget() {
+        let err = run("while true do end", &Config::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("time budget"), "got: {:#}", err);
+    }
+
+    #[test]
+    fn test_repl_keeps_state_and_continues_statements() {
+        let output = repl_output("x = 20\nfunction double(n)\nreturn n * 2\nend\ndouble(x) + 2\n");
+        assert_eq!(output, "> > >> >> > 42\n> \n");
+    }
+
+    #[test]
+    fn test_repl_lists_requested_actions() {
+        let output = repl_output("cvh.open('/tmp/a.txt')\ncvh.spawn('rm -rf /')\n");
+        assert_eq!(output, "> true\n(open /tmp/a.txt)\n> false\n> \n");
+    }
+
+    #[test]
+    fn test_repl_reports_errors_and_goes_on() {
+        let output = repl_output("io.open('/etc/passwd')\n1\n");
+        assert!(output.contains("error: "), "got: {}", output);
+        assert!(output.ends_with("> 1\n> \n"), "got: {}", output);
+    }
+}
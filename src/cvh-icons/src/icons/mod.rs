@@ -3,21 +3,38 @@
 //! Each icon represents a file or folder on the desktop.
 
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
 use tracing::{debug, error, warn};
 
 use crate::config::Config;
 use crate::ipc::{
-    IconMetadata, IconType as IpcIconType, Position, PositionInput, RenderContext, Request,
-    Response,
+    EventAction, Feature, IconEvent, IconMetadata, IconType as IpcIconType, Position, PositionInput,
+    RenderContext, RenderItem, RenderResult, Request, Response,
 };
 use crate::lua::{DrawCommand, LuaProcess};
+use crate::sandbox::actions::ActionPolicy;
+use crate::sandbox::notify::{Notification, RateLimiter};
 use crate::sandbox::SandboxOptions;
 
+pub mod navigation;
+pub mod theme;
+pub mod thumbnail;
+
+pub use theme::IconTheme;
+pub use thumbnail::Thumbnailer;
+
 /// Timeout for IPC requests to Lua process
 const IPC_TIMEOUT: Duration = Duration::from_millis(500);
 
+/// Minimum time between hover events sent to the Lua process
+const HOVER_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Shortest delay a script may ask for before its next frame (~60 FPS)
+const MIN_FRAME_INTERVAL: Duration = Duration::from_millis(16);
+
 /// Represents a desktop icon
 #[allow(dead_code)]
 pub struct DesktopIcon {
@@ -40,6 +57,15 @@ pub struct DesktopIcon {
     /// Whether icon is hovered
     hovered: bool,
 
+    /// Hover state the Lua process was last told about
+    hover_notified: bool,
+
+    /// When the last hover event was sent
+    hover_sent_at: Option<Instant>,
+
+    /// Hover events wait until then, set when keyboard focus moves
+    hover_held_until: Option<Instant>,
+
     /// Lua process for custom scripts (sandboxed)
     lua_process: Option<LuaProcess>,
 
@@ -52,15 +78,37 @@ pub struct DesktopIcon {
     /// Cached draw commands for fallback rendering
     cached_draw_commands: Vec<DrawCommand>,
 
-    /// Icon size from config
+    /// Icon size from config, after any override for the icon type
     size: u32,
 
+    /// Whether the name is drawn below the icon
+    show_label: bool,
+
     /// Sandbox options for Lua process
     sandbox_options: SandboxOptions,
+
+    /// Preview generator, set for image files only
+    thumbnailer: Option<Thumbnailer>,
+
+    /// Idle time before the Lua process is pinged (zero disables pings)
+    ping_interval: Duration,
+
+    /// How long a ping may take before the process counts as wedged
+    ping_timeout: Duration,
+
+    /// Set when the Lua process stopped answering pings
+    wedged: bool,
+
+    /// When the script wants to be rendered again, if it is animating
+    next_frame: Option<Instant>,
+
+    /// Limit on notifications the script may show
+    notify_limiter: RateLimiter,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum IconType {
     File,
     Folder,
@@ -78,23 +126,10 @@ pub enum IconType {
 impl DesktopIcon {
     /// Create a new desktop icon
     pub fn new(path: &Path, config: &Config) -> Result<Self> {
-        let name = path
-            .file_name()
-            .and_then(|n| n.to_str())
-            .map(|s| s.to_string())
-            .unwrap_or_else(|| "Unknown".to_string());
-
+        let name = display_name(path);
         let icon_type = Self::determine_type(path);
-
-        // Build sandbox options from config
-        let mut sandbox_options = SandboxOptions::default();
-        sandbox_options.allow_network = config.sandbox.allow_network;
-        for p in &config.sandbox.read_only_paths {
-            sandbox_options.read_only_paths.push(p.clone());
-        }
-        for p in &config.sandbox.read_write_paths {
-            sandbox_options.read_write_paths.push(p.clone());
-        }
+        let sandbox_options = sandbox_options(config);
+        let thumbnailer = Self::thumbnailer_for(icon_type, &sandbox_options);
 
         Ok(Self {
             path: path.to_path_buf(),
@@ -104,16 +139,75 @@ impl DesktopIcon {
             grid_y: 0,
             selected: false,
             hovered: false,
+            hover_notified: false,
+            hover_sent_at: None,
+            hover_held_until: None,
             lua_process: None,
             handler_path: None,
             script_path: None,
             cached_draw_commands: Vec::new(),
-            size: config.icon_size,
+            size: config.icon_size_for(icon_type),
+            show_label: config.show_label_for(icon_type),
             sandbox_options,
+            thumbnailer,
+            ping_interval: Duration::from_millis(config.ping_interval_ms),
+            ping_timeout: Duration::from_millis(config.ping_timeout_ms),
+            wedged: false,
+            next_frame: None,
+            notify_limiter: RateLimiter::default(),
         })
     }
 
+    /// Apply a reloaded config, returning true if the sandbox options changed
+    ///
+    /// A running Lua process keeps its old sandbox until it is respawned.
+    pub fn apply_config(&mut self, config: &Config) -> bool {
+        self.size = config.icon_size_for(self.icon_type);
+        self.show_label = config.show_label_for(self.icon_type);
+        self.ping_interval = Duration::from_millis(config.ping_interval_ms);
+        self.ping_timeout = Duration::from_millis(config.ping_timeout_ms);
+
+        let sandbox_options = sandbox_options(config);
+        if sandbox_options == self.sandbox_options {
+            return false;
+        }
+
+        self.thumbnailer = Self::thumbnailer_for(self.icon_type, &sandbox_options);
+        self.sandbox_options = sandbox_options;
+        true
+    }
+
+    /// Point the icon at a renamed file, returning true if its type changed
+    ///
+    /// Position, selection and the Lua process are kept.
+    pub fn set_path(&mut self, path: &Path) -> bool {
+        self.path = path.to_path_buf();
+        self.name = display_name(path);
+
+        let icon_type = Self::determine_type(path);
+        if icon_type == self.icon_type {
+            return false;
+        }
+
+        self.icon_type = icon_type;
+        self.thumbnailer = Self::thumbnailer_for(icon_type, &self.sandbox_options);
+        true
+    }
+
+    /// Previews are limited to image files the sandbox may read anyway
+    fn thumbnailer_for(icon_type: IconType, sandbox_options: &SandboxOptions) -> Option<Thumbnailer> {
+        if icon_type == IconType::Image {
+            Thumbnailer::for_sandbox(sandbox_options)
+        } else {
+            None
+        }
+    }
+
     /// Determine the icon type based on the file
+    ///
+    /// cvh-fuzzy sorts its file list with a copy of the extension table
+    /// (`FileKind::from_extension` in src/cvh-fuzzy/src/filetype.rs); the
+    /// crates share no code, so change both together.
     fn determine_type(path: &Path) -> IconType {
         if path.is_symlink() {
             return IconType::Symlink;
@@ -193,6 +287,16 @@ impl DesktopIcon {
         self.icon_type
     }
 
+    /// Icon size in pixels
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Whether the name is drawn below the icon
+    pub fn show_label(&self) -> bool {
+        self.show_label
+    }
+
     /// Set grid position
     pub fn set_position(&mut self, x: u32, y: u32) {
         self.grid_x = x;
@@ -225,7 +329,14 @@ impl DesktopIcon {
     }
 
     /// Handle click event
-    pub fn on_click(&mut self, button: u32) -> Result<ClickAction> {
+    ///
+    /// The click (at `x`, `y` in surface coordinates) goes to the Lua script
+    /// first; if it isn't handled or IPC fails, the built-in behavior applies.
+    pub fn on_click(&mut self, button: u32, x: f64, y: f64) -> Result<ClickAction> {
+        if let Some(action) = self.send_event(IconEvent::Click { button, x, y }) {
+            return Ok(action);
+        }
+
         match button {
             1 => {
                 // Left click - select
@@ -244,14 +355,167 @@ impl DesktopIcon {
         }
     }
 
+    /// Send an event to the Lua process and return the action it chose
+    ///
+    /// Returns None if there is no process, the script didn't handle the
+    /// event, or the request failed or timed out.
+    fn send_event(&mut self, event: IconEvent) -> Option<ClickAction> {
+        if self.lua_process.is_none() || !self.ensure_process_running() {
+            return None;
+        }
+        let process = self.lua_process.as_mut()?;
+
+        if let Err(e) = process.send_request(&Request::Event { event }) {
+            error!("Failed to send event request: {}", e);
+            return None;
+        }
+
+        match process.receive_response_with_timeout(IPC_TIMEOUT) {
+            Ok(Response::Event { handled: true, action }) => {
+                let policy = &self.sandbox_options.action_policy;
+                Some(action.map_or(ClickAction::None, |action| ClickAction::from_event_action(action, policy)))
+            }
+            Ok(Response::Event { handled: false, .. }) => None,
+            Ok(Response::Error { message }) => {
+                warn!("Lua event error for {}: {}", self.name, message);
+                None
+            }
+            Ok(other) => {
+                warn!("Unexpected response from Lua: {:?}", other);
+                None
+            }
+            Err(e) => {
+                warn!("IPC timeout/error for {}: {}", self.name, e);
+                None
+            }
+        }
+    }
+
+    /// Handle files dropped onto the icon
+    ///
+    /// Paths that don't exist or lie outside the sandbox's read-only and
+    /// read-write paths are dropped before the rest go to the Lua script.
+    pub fn on_drop(&mut self, paths: Vec<String>) -> Result<ClickAction> {
+        let roots = self.sandbox_options.allowed_roots();
+        let paths: Vec<String> = paths
+            .into_iter()
+            .filter(|path| {
+                let allowed = Path::new(path)
+                    .canonicalize()
+                    .map(|p| roots.iter().any(|root| p.starts_with(root)))
+                    .unwrap_or(false);
+                if !allowed {
+                    warn!("Ignoring dropped path {} for {}", path, self.name);
+                }
+                allowed
+            })
+            .collect();
+
+        if paths.is_empty() {
+            return Ok(ClickAction::None);
+        }
+
+        Ok(self
+            .send_event(IconEvent::Drop { paths })
+            .unwrap_or(ClickAction::None))
+    }
+
     /// Handle double-click
     pub fn on_double_click(&self) -> Result<ClickAction> {
         Ok(ClickAction::Open)
     }
 
-    /// Set the hover state
-    pub fn set_hovered(&mut self, hovered: bool) {
+    /// The command that carries out `action`, checked against this icon's
+    /// action policy
+    ///
+    /// Returns None for actions that don't launch anything (selection,
+    /// menus, notifications).
+    pub fn launch_command(&self, action: &ClickAction) -> Option<Result<Command>> {
+        let (kind, payload) = match action {
+            ClickAction::Open => ("open", self.path.to_string_lossy().to_string()),
+            ClickAction::OpenPath(path) => ("open", path.clone()),
+            ClickAction::Spawn(command) => ("spawn", command.clone()),
+            _ => return None,
+        };
+        let action = EventAction {
+            action: kind.to_string(),
+            payload: Some(payload),
+        };
+        Some(self.sandbox_options.action_policy.command(&action))
+    }
+
+    /// The `notify-send` command showing `notification`, or None if this
+    /// icon has shown too many recently
+    pub fn notify_command(&mut self, notification: &Notification, now: Instant) -> Option<Command> {
+        if !self.notify_limiter.allow(now) {
+            warn!("Dropping notification from {}: rate limit reached", self.name);
+            return None;
+        }
+        Some(notification.command())
+    }
+
+    /// Set the hover state, notifying the Lua process on a transition
+    ///
+    /// Returns true if a hover event was sent.
+    pub fn set_hovered(&mut self, hovered: bool) -> bool {
+        self.set_hovered_at(hovered, Instant::now())
+    }
+
+    fn set_hovered_at(&mut self, hovered: bool, now: Instant) -> bool {
         self.hovered = hovered;
+        self.hover_held_until = None;
+        self.notify_hover(now)
+    }
+
+    /// Set the hover state for keyboard focus without notifying yet
+    ///
+    /// Focus moves on every key repeat, so the event waits for
+    /// `flush_hover` until the focus has rested for `HOVER_DEBOUNCE`;
+    /// icons the focus only passes over send nothing.
+    pub fn set_focus_hovered(&mut self, hovered: bool) {
+        self.set_focus_hovered_at(hovered, Instant::now())
+    }
+
+    fn set_focus_hovered_at(&mut self, hovered: bool, now: Instant) {
+        self.hovered = hovered;
+        self.hover_held_until = Some(now + HOVER_DEBOUNCE);
+    }
+
+    /// Send a hover change held back by the debounce, once it has expired
+    ///
+    /// Returns true if a hover event was sent.
+    pub fn flush_hover(&mut self) -> bool {
+        self.notify_hover(Instant::now())
+    }
+
+    /// Tell the Lua process about the current hover state if it changed
+    ///
+    /// Within `HOVER_DEBOUNCE` of the last event nothing is sent, so a
+    /// pointer flickering across the edge produces at most one event per
+    /// interval and toggles that cancel out produce none.
+    fn notify_hover(&mut self, now: Instant) -> bool {
+        if self.hovered == self.hover_notified {
+            return false;
+        }
+        if self.hover_held_until.is_some_and(|until| now < until) {
+            return false;
+        }
+        if let Some(sent_at) = self.hover_sent_at {
+            if now.saturating_duration_since(sent_at) < HOVER_DEBOUNCE {
+                return false;
+            }
+        }
+
+        self.hover_notified = self.hovered;
+        self.hover_sent_at = Some(now);
+
+        let event = if self.hovered {
+            IconEvent::HoverEnter
+        } else {
+            IconEvent::HoverExit
+        };
+        self.send_event(event);
+        true
     }
 
     /// Check if hovered
@@ -281,7 +545,7 @@ impl DesktopIcon {
         match LuaProcess::spawn(
             handler_path.to_path_buf(),
             icon_script_path.to_path_buf(),
-            &self.sandbox_options,
+            &self.script_sandbox_options(),
         ) {
             Ok(process) => {
                 debug!(
@@ -305,6 +569,25 @@ impl DesktopIcon {
         }
     }
 
+    /// Sandbox options for this icon's Lua process: the configured ones
+    /// plus the icon's state directory, bound read-write and passed to the
+    /// handler as `CVH_STATE_DIR` for `cvh.state`
+    fn script_sandbox_options(&self) -> SandboxOptions {
+        let mut options = self.sandbox_options.clone();
+        let (Some(root), Some(name)) = (&options.state_dir, self.path.file_name()) else {
+            return options;
+        };
+        let dir = root.join(name);
+        match std::fs::create_dir_all(&dir).and_then(|()| dir.canonicalize()) {
+            Ok(dir) => {
+                options.env_vars.push(("CVH_STATE_DIR".to_string(), dir.to_string_lossy().to_string()));
+                options.read_write_paths.push(dir);
+            }
+            Err(e) => warn!("Cannot create state directory {}: {}", dir.display(), e),
+        }
+        options
+    }
+
     /// Kill the Lua process if it exists
     pub fn kill_lua_process(&mut self) {
         if let Some(mut process) = self.lua_process.take() {
@@ -315,14 +598,31 @@ impl DesktopIcon {
         }
     }
 
+    /// Kill the Lua process and forget its scripts, reverting to fallback rendering
+    pub fn clear_scripts(&mut self) {
+        self.kill_lua_process();
+        self.handler_path = None;
+        self.script_path = None;
+        self.cached_draw_commands.clear();
+    }
+
     /// Check if the Lua process is still running and restart if crashed
     fn ensure_process_running(&mut self) -> bool {
-        if let Some(ref mut process) = self.lua_process {
-            if process.is_running() {
+        if std::mem::take(&mut self.wedged) {
+            // Alive but unresponsive: drop (and kill) it, then restart
+            error!("Lua process for {} is wedged, attempting restart", self.name);
+            self.lua_process = None;
+        } else if let Some(ref mut process) = self.lua_process {
+            if process.is_stalled() {
+                // Stopped reading requests: drop (and kill) it, then restart
+                error!("Lua process for {} stopped reading requests, attempting restart", self.name);
+                self.lua_process = None;
+            } else if process.is_running() {
                 return true;
+            } else {
+                // Process crashed, log and attempt restart
+                error!("Lua process for {} crashed, attempting restart", self.name);
             }
-            // Process crashed, log and attempt restart
-            error!("Lua process for {} crashed, attempting restart", self.name);
         }
 
         // Try to restart if we have both handler and script paths
@@ -344,6 +644,34 @@ impl DesktopIcon {
         }
     }
 
+    /// Ping the Lua process if it has been idle for the ping interval
+    ///
+    /// A process that doesn't answer within the ping timeout is treated as
+    /// wedged and restarted. Returns false if the process had to be restarted
+    /// or is gone.
+    pub fn heartbeat(&mut self, now: Instant) -> bool {
+        let Some(process) = self.lua_process.as_mut() else {
+            return true;
+        };
+        // Handlers without ping are only watched for exiting
+        if self.ping_interval.is_zero()
+            || !process.supports(Feature::Ping)
+            || process.idle_for(now) < self.ping_interval
+        {
+            return true;
+        }
+
+        match process.ping(self.ping_timeout) {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("Lua process for {} failed heartbeat: {}", self.name, e);
+                self.wedged = true;
+                self.ensure_process_running();
+                false
+            }
+        }
+    }
+
     /// Convert local IconType to IPC IconType
     fn to_ipc_icon_type(&self) -> IpcIconType {
         match self.icon_type {
@@ -378,6 +706,9 @@ impl DesktopIcon {
         canvas_height: u32,
         device_pixel_ratio: f32,
     ) -> Vec<DrawCommand> {
+        // Only a successful render keeps the icon animating
+        self.next_frame = None;
+
         // Check if we have a Lua process
         if self.lua_process.is_none() {
             return self.fallback_render();
@@ -393,25 +724,8 @@ impl DesktopIcon {
         }
 
         // Build the render request
-        let metadata = IconMetadata {
-            path: self.path.to_string_lossy().to_string(),
-            name: self.name.clone(),
-            mime_type: self.get_mime_type(),
-            is_directory: self.icon_type == IconType::Folder,
-            size: self.get_file_size(),
-            width: self.size,
-            height: self.size,
-            icon_type: self.to_ipc_icon_type(),
-            selected: self.selected,
-            hovered: self.hovered,
-        };
-
-        let context = RenderContext {
-            canvas_width,
-            canvas_height,
-            device_pixel_ratio,
-        };
-
+        let RenderItem { metadata, context, .. } =
+            self.render_item(0, canvas_width, canvas_height, device_pixel_ratio);
         let request = Request::Render { metadata, context };
 
         // Send request and receive response
@@ -419,9 +733,9 @@ impl DesktopIcon {
             match process.send_request(&request) {
                 Ok(()) => {
                     match process.receive_response_with_timeout(IPC_TIMEOUT) {
-                        Ok(Response::Render { commands }) => {
+                        Ok(Response::Render { commands, next_frame_ms }) => {
                             // Cache the commands for fallback
-                            self.cached_draw_commands = commands.clone();
+                            self.set_rendered(commands.clone(), next_frame_ms);
                             return commands;
                         }
                         Ok(Response::Error { message }) => {
@@ -449,6 +763,99 @@ impl DesktopIcon {
         }
     }
 
+    /// Describe this icon for a render request
+    pub fn render_item(
+        &self,
+        id: u32,
+        canvas_width: u32,
+        canvas_height: u32,
+        device_pixel_ratio: f32,
+    ) -> RenderItem {
+        RenderItem {
+            id,
+            metadata: IconMetadata {
+                path: self.path.to_string_lossy().to_string(),
+                name: self.name.clone(),
+                mime_type: self.get_mime_type(),
+                is_directory: self.icon_type == IconType::Folder,
+                size: self.get_file_size(),
+                width: self.size,
+                height: self.size,
+                icon_type: self.to_ipc_icon_type(),
+                selected: self.selected,
+                hovered: self.hovered,
+            },
+            context: RenderContext {
+                canvas_width,
+                canvas_height,
+                device_pixel_ratio,
+            },
+        }
+    }
+
+    /// Id of the running Lua process, if any
+    ///
+    /// Only icons with the same key may share a `RenderBatch`: a process
+    /// holds its icon's script state (clicks, hover) and sees only its own
+    /// sandbox, so running the same script isn't enough.
+    pub fn batch_key(&self) -> Option<u32> {
+        self.lua_process.as_ref().map(LuaProcess::pid)
+    }
+
+    /// Render `items` (this icon's and others') through this icon's process
+    ///
+    /// Returns None if there is no process, the handler doesn't support
+    /// batches, or the request fails; callers then render one by one.
+    pub fn request_render_batch(&mut self, items: Vec<RenderItem>) -> Option<Vec<RenderResult>> {
+        if self.lua_process.is_none() || !self.ensure_process_running() {
+            return None;
+        }
+        let process = self.lua_process.as_mut()?;
+        if !process.supports(Feature::RenderBatch) {
+            return None;
+        }
+
+        if let Err(e) = process.send_request(&Request::RenderBatch { items }) {
+            error!("Failed to send render batch request: {}", e);
+            return None;
+        }
+
+        match process.receive_response_with_timeout(IPC_TIMEOUT) {
+            Ok(Response::RenderBatch { results }) => Some(results),
+            Ok(Response::Error { message }) => {
+                debug!("Lua render batch failed for {}: {}", self.name, message);
+                None
+            }
+            Ok(other) => {
+                warn!("Unexpected response from Lua: {:?}", other);
+                None
+            }
+            Err(e) => {
+                warn!("IPC timeout/error for {}: {}", self.name, e);
+                None
+            }
+        }
+    }
+
+    /// Take draw commands rendered for this icon, and the script's delay
+    /// before its next frame
+    pub fn set_rendered(&mut self, commands: Vec<DrawCommand>, next_frame_ms: Option<u32>) {
+        self.cached_draw_commands = commands;
+        self.next_frame = next_frame_ms.map(|ms| {
+            Instant::now() + Duration::from_millis(ms.into()).max(MIN_FRAME_INTERVAL)
+        });
+    }
+
+    /// When the script asked to be rendered again; None for static icons
+    pub fn next_frame(&self) -> Option<Instant> {
+        self.next_frame
+    }
+
+    /// Whether an animation frame is due at `now`
+    pub fn frame_due(&self, now: Instant) -> bool {
+        self.next_frame.is_some_and(|at| at <= now)
+    }
+
     /// Request position calculation from the Lua process
     ///
     /// # Arguments
@@ -472,7 +879,9 @@ impl DesktopIcon {
     ) -> Position {
         // If no Lua process, use default positioning
         if self.lua_process.is_none() || !self.ensure_process_running() {
-            return self.default_position(screen_width, icon_index, cell_width, cell_height);
+            let position = self.default_position(screen_width, icon_index, cell_width, cell_height);
+            self.set_position(position.x as u32, position.y as u32);
+            return position;
         }
 
         let input = PositionInput {
@@ -513,7 +922,9 @@ impl DesktopIcon {
             }
         }
 
-        self.default_position(screen_width, icon_index, cell_width, cell_height)
+        let position = self.default_position(screen_width, icon_index, cell_width, cell_height);
+        self.set_position(position.x as u32, position.y as u32);
+        position
     }
 
     /// Calculate default position using grid layout
@@ -540,6 +951,10 @@ impl DesktopIcon {
 
     /// Generate fallback render commands when Lua is not available
     fn fallback_render(&self) -> Vec<DrawCommand> {
+        if let Some(commands) = self.thumbnail_render() {
+            return commands;
+        }
+
         // Simple fallback: just a colored rectangle based on icon type
         let color = match self.icon_type {
             IconType::Folder => "#4A90D9",
@@ -566,6 +981,29 @@ impl DesktopIcon {
         ]
     }
 
+    /// Draw a thumbnail of the image, centered and fit to the icon area
+    fn thumbnail_render(&self) -> Option<Vec<DrawCommand>> {
+        let area = self.size.saturating_sub(8);
+        let thumbnail = self.thumbnailer.as_ref()?.thumbnail(&self.path, area)?;
+        let (width, height) = image::image_dimensions(&thumbnail).ok()?;
+
+        let scale = area as f32 / width.max(height) as f32;
+        let (w, h) = (width as f32 * scale, height as f32 * scale);
+
+        Some(vec![
+            DrawCommand::Clear {
+                color: "#00000000".to_string(),
+            },
+            DrawCommand::Image {
+                path: thumbnail.to_string_lossy().to_string(),
+                x: (self.size as f32 - w) / 2.0,
+                y: (self.size as f32 - h) / 2.0,
+                w,
+                h,
+            },
+        ])
+    }
+
     /// Get MIME type for the file (if known)
     fn get_mime_type(&self) -> Option<String> {
         // Simple extension-based MIME type detection
@@ -596,10 +1034,15 @@ impl DesktopIcon {
     }
 
     /// Get file size in bytes
-    fn get_file_size(&self) -> Option<u64> {
+    pub fn get_file_size(&self) -> Option<u64> {
         self.path.metadata().ok().map(|m| m.len())
     }
 
+    /// Get the file's last modification time
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.path.metadata().and_then(|m| m.modified()).ok()
+    }
+
     /// Check if icon has a Lua process
     pub fn has_lua_process(&self) -> bool {
         self.lua_process.is_some()
@@ -609,17 +1052,112 @@ impl DesktopIcon {
     pub fn script_path(&self) -> Option<&Path> {
         self.script_path.as_deref()
     }
+
+    /// Get the IPC handler path if set
+    pub fn handler_path(&self) -> Option<&Path> {
+        self.handler_path.as_deref()
+    }
+
+    /// Get the Lua process ID, if one is running
+    pub fn lua_pid(&self) -> Option<u32> {
+        self.lua_process.as_ref().map(LuaProcess::pid)
+    }
+
+    /// Attach an already running Lua process (for testing)
+    #[cfg(test)]
+    pub fn set_lua_process(&mut self, process: LuaProcess) {
+        self.handler_path = Some(process.handler_path().clone());
+        self.script_path = Some(process.icon_script_path().clone());
+        self.lua_process = Some(process);
+    }
+}
+
+/// Display name for a file: its file name, or "Unknown"
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .map(|s| s.to_string())
+        .unwrap_or_else(|| "Unknown".to_string())
+}
+
+/// Build sandbox options for icon scripts from config
+pub fn sandbox_options(config: &Config) -> SandboxOptions {
+    let mut sandbox_options = SandboxOptions::default();
+    sandbox_options.allow_network = config.sandbox.allow_network;
+    sandbox_options.seccomp = config.sandbox.seccomp;
+    sandbox_options.landlock = config.sandbox.landlock;
+    sandbox_options.require_sandbox = config.sandbox.require_sandbox;
+    sandbox_options.max_cpu_secs = Some(config.sandbox.max_cpu_secs).filter(|&secs| secs > 0);
+    sandbox_options.max_memory_bytes = Some(config.sandbox.max_memory_bytes).filter(|&bytes| bytes > 0);
+    sandbox_options.env_allowlist = config.sandbox.env_allowlist.clone();
+    sandbox_options.action_policy = ActionPolicy {
+        allowed_commands: config.sandbox.allowed_commands.clone(),
+        allowed_uri_schemes: config.sandbox.allowed_uri_schemes.iter().map(|s| s.to_ascii_lowercase()).collect(),
+        allow_trash: config.sandbox.allow_trash,
+    };
+    for p in &config.sandbox.read_only_paths {
+        sandbox_options.read_only_paths.push(p.clone());
+    }
+    for p in &config.sandbox.read_write_paths {
+        sandbox_options.read_write_paths.push(p.clone());
+    }
+    // The daemon won't start without one, so a miss means Lua was removed
+    // since; spawning then fails under the default name as it would anyway
+    if let Ok(interpreter) = crate::sandbox::lua_interpreter(&config.sandbox.lua_interpreters) {
+        sandbox_options.lua_interpreter = interpreter;
+    }
+    sandbox_options.state_dir = config.sandbox.state_dir.clone()
+        .or_else(|| dirs::state_dir().map(|dir| dir.join("cvh-icons")));
+    sandbox_options
 }
 
 /// Action to take after a click
 #[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ClickAction {
     None,
     Select,
     Open,
     OpenInTerminal,
     ContextMenu,
+    /// Open a path chosen by the script
+    OpenPath(String),
+    /// Run a command chosen by the script
+    Spawn(String),
+    /// Show a notification
+    Notify(Notification),
+    /// Move this icon's file to the trash
+    Trash,
+    /// Move a path chosen by the script to the trash
+    TrashPath(String),
+}
+
+impl ClickAction {
+    /// Interpret an action returned by a Lua script
+    ///
+    /// Actions the policy forbids become `None`.
+    fn from_event_action(action: EventAction, policy: &ActionPolicy) -> Self {
+        if let Err(e) = policy.check(&action) {
+            warn!("Rejected Lua action '{}': {}", action.action, e);
+            return ClickAction::None;
+        }
+
+        match (action.action.as_str(), action.payload) {
+            ("open", Some(path)) => ClickAction::OpenPath(path),
+            ("open", None) => ClickAction::Open,
+            ("spawn", Some(command)) => ClickAction::Spawn(command),
+            ("notify", payload) => {
+                ClickAction::Notify(Notification::from_payload(payload.as_deref().unwrap_or_default()))
+            }
+            ("trash", Some(path)) => ClickAction::TrashPath(path),
+            ("trash", None) => ClickAction::Trash,
+            ("none", _) => ClickAction::None,
+            (other, payload) => {
+                warn!("Ignoring unsupported Lua action '{}' (payload: {:?})", other, payload);
+                ClickAction::None
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -646,6 +1184,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_script_sandbox_options_add_icon_state_dir() {
+        let state = tempfile::tempdir().unwrap();
+        let mut config = test_config();
+        config.sandbox.state_dir = Some(state.path().to_path_buf());
+        let icon = DesktopIcon::new(Path::new("/tmp/notes.txt"), &config).unwrap();
+
+        let options = icon.script_sandbox_options();
+        let dir = state.path().canonicalize().unwrap().join("notes.txt");
+        assert!(dir.is_dir(), "state directory should be created");
+        assert_eq!(options.read_write_paths.last(), Some(&dir));
+        assert!(options.env_vars.contains(&("CVH_STATE_DIR".to_string(), dir.to_string_lossy().to_string())));
+        // The icon's own options stay as configured
+        assert!(icon.sandbox_options.read_write_paths.is_empty());
+    }
+
     #[test]
     fn test_icon_creation_with_new_fields() {
         let config = test_config();
@@ -718,6 +1272,42 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_fallback_render_uses_image_thumbnail() {
+        let source = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let photo = source.path().join("wide.png");
+        image::RgbaImage::new(200, 100).save(&photo).unwrap();
+
+        let mut config = test_config();
+        config.sandbox.read_only_paths.push(source.path().to_path_buf());
+        let mut icon = DesktopIcon::new(&photo, &config).unwrap();
+        assert!(icon.thumbnailer.is_some());
+        let roots = vec![source.path().canonicalize().unwrap()];
+        icon.thumbnailer = Some(Thumbnailer::new(cache.path().to_path_buf(), roots));
+
+        let commands = icon.fallback_render();
+        match &commands[1] {
+            DrawCommand::Image { path, x, y, w, h } => {
+                assert!(path.starts_with(cache.path().to_str().unwrap()), "{}", path);
+                assert_eq!((*x, *y, *w, *h), (4.0, 18.0, 56.0, 28.0));
+            }
+            other => panic!("Expected Image command, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_fallback_render_without_thumbnail() {
+        let config = test_config();
+
+        // Outside the sandbox read paths, so no preview is generated
+        let icon = DesktopIcon::new(Path::new("/tmp/photo.png"), &config).unwrap();
+        assert!(matches!(icon.fallback_render()[1], DrawCommand::FillRect { .. }));
+
+        let text = DesktopIcon::new(Path::new("/tmp/test.txt"), &config).unwrap();
+        assert!(text.thumbnailer.is_none());
+    }
+
     #[test]
     fn test_icon_type_to_ipc_conversion() {
         let config = test_config();
@@ -737,6 +1327,31 @@ mod tests {
         assert_eq!(image.to_ipc_icon_type(), IpcIconType::Custom("image".to_string()));
     }
 
+    #[test]
+    fn test_icon_type_overrides() {
+        let mut config = test_config();
+        config.icon_types.insert(
+            IconType::Folder,
+            crate::config::IconTypeConfig { size: Some(96), show_label: Some(false) },
+        );
+
+        let folder = DesktopIcon::new(Path::new("/tmp"), &config).unwrap();
+        let metadata = folder.render_item(0, 96, 120, 1.0).metadata;
+        assert_eq!((metadata.width, metadata.height), (96, 96));
+        assert!(!folder.show_label());
+
+        // Types without an override keep the global settings
+        let mut file = DesktopIcon::new(Path::new("/tmp/test.unknown_ext"), &config).unwrap();
+        let metadata = file.render_item(0, 64, 88, 1.0).metadata;
+        assert_eq!((metadata.width, metadata.height), (64, 64));
+        assert!(file.show_label());
+
+        config.icon_types.insert(IconType::File, crate::config::IconTypeConfig { size: Some(48), show_label: None });
+        file.apply_config(&config);
+        assert_eq!(file.size(), 48);
+        assert!(file.show_label());
+    }
+
     #[test]
     fn test_mime_type_detection() {
         let config = test_config();
@@ -791,5 +1406,427 @@ mod tests {
         let pos = icon.request_position(1920, 1080, 25, 5, Some(96), Some(96));
         assert_eq!(pos.x, 20 + 5 * 96);
         assert_eq!(pos.y, 20);
+        assert_eq!(icon.position(), (20 + 5 * 96, 20));
+    }
+
+    // ========================================================================
+    // Event IPC tests
+    // ========================================================================
+
+    fn icon_with_mock_process() -> (DesktopIcon, crate::lua::process::MockPeer) {
+        let mut icon = DesktopIcon::new(Path::new("/tmp/test.txt"), &test_config()).unwrap();
+        let (process, peer) = LuaProcess::mock().unwrap();
+        icon.set_lua_process(process);
+        (icon, peer)
+    }
+
+    fn event_response(handled: bool, action: Option<(&str, Option<&str>)>) -> Response {
+        Response::Event {
+            handled,
+            action: action.map(|(action, payload)| EventAction {
+                action: action.to_string(),
+                payload: payload.map(str::to_string),
+            }),
+        }
+    }
+
+    #[test]
+    fn test_click_sends_event_request() {
+        let (mut icon, mut peer) = icon_with_mock_process();
+        peer.send_response(&event_response(true, Some(("spawn", Some("xdg-open /tmp/a")))));
+
+        let action = icon.on_click(1, 10.0, 20.5).unwrap();
+        assert_eq!(action, ClickAction::Spawn("xdg-open /tmp/a".to_string()));
+
+        let json = br#"{"type":"Event","event":{"Click":{"button":1,"x":10.0,"y":20.5}}}"#;
+        let mut expected = (json.len() as u32).to_le_bytes().to_vec();
+        expected.extend_from_slice(json);
+        assert_eq!(peer.try_read_raw(IPC_TIMEOUT), Some(expected));
+
+        // The script's action replaces the built-in selection toggle
+        assert!(!icon.is_selected());
+    }
+
+    #[test]
+    fn test_click_unhandled_or_failed_uses_builtin() {
+        let (mut icon, mut peer) = icon_with_mock_process();
+
+        peer.send_response(&event_response(false, None));
+        assert_eq!(icon.on_click(1, 0.0, 0.0).unwrap(), ClickAction::Select);
+        assert!(icon.is_selected());
+
+        peer.send_response(&Response::Error { message: "boom".to_string() });
+        assert_eq!(icon.on_click(3, 0.0, 0.0).unwrap(), ClickAction::ContextMenu);
+
+        // No response at all: gives up after IPC_TIMEOUT
+        assert_eq!(icon.on_click(2, 0.0, 0.0).unwrap(), ClickAction::OpenInTerminal);
+    }
+
+    #[test]
+    fn test_notify_command_is_rate_limited() {
+        let mut icon = DesktopIcon::new(Path::new("/tmp/test.txt"), &test_config()).unwrap();
+        let notification = Notification::from_payload("ping");
+        let start = Instant::now();
+
+        let shown = (0..10)
+            .filter_map(|i| icon.notify_command(&notification, start + Duration::from_millis(i)))
+            .count();
+        assert_eq!(shown, RateLimiter::DEFAULT_MAX);
+
+        let later = start + RateLimiter::DEFAULT_WINDOW + Duration::from_secs(1);
+        let cmd = icon.notify_command(&notification, later).unwrap();
+        assert_eq!(cmd.get_program(), crate::sandbox::notify::NOTIFIER);
+    }
+
+    #[test]
+    fn test_click_without_process_uses_builtin() {
+        let mut icon = DesktopIcon::new(Path::new("/tmp/test.txt"), &test_config()).unwrap();
+        assert_eq!(icon.on_click(1, 0.0, 0.0).unwrap(), ClickAction::Select);
+        assert_eq!(icon.on_click(9, 0.0, 0.0).unwrap(), ClickAction::None);
+    }
+
+    #[test]
+    fn test_event_action_interpretation() {
+        let cases = [
+            (("open", Some("/home/user/doc.pdf")), ClickAction::OpenPath("/home/user/doc.pdf".to_string())),
+            (("open", None), ClickAction::Open),
+            (("spawn", Some("xdg-open /tmp/a")), ClickAction::Spawn("xdg-open /tmp/a".to_string())),
+            (("spawn", Some("foot")), ClickAction::None),
+            (("spawn", None), ClickAction::None),
+            (("open", Some("../etc/passwd")), ClickAction::None),
+            (("notify", Some("hi")), ClickAction::Notify(Notification::from_payload("hi"))),
+            (
+                ("notify", Some(r#"{"title":"Mail","body":"2 new","urgency":"critical","icon":"mail"}"#)),
+                ClickAction::Notify(Notification {
+                    title: "Mail".to_string(),
+                    body: "2 new".to_string(),
+                    urgency: crate::sandbox::notify::Urgency::Critical,
+                    icon: Some("mail".to_string()),
+                }),
+            ),
+            (("trash", Some("/home/user/old.txt")), ClickAction::TrashPath("/home/user/old.txt".to_string())),
+            (("trash", None), ClickAction::Trash),
+            (("none", Some("ignored")), ClickAction::None),
+            (("explode", None), ClickAction::None),
+        ];
+        for ((action, payload), expected) in cases {
+            let event_action = EventAction {
+                action: action.to_string(),
+                payload: payload.map(str::to_string),
+            };
+            let interpreted = ClickAction::from_event_action(event_action, &ActionPolicy::default());
+            assert_eq!(interpreted, expected, "{}", action);
+        }
+
+        // Trash requests go through the policy as well
+        let no_trash = ActionPolicy { allow_trash: false, ..Default::default() };
+        for payload in [None, Some("/home/user/old.txt".to_string())] {
+            let event_action = EventAction { action: "trash".to_string(), payload };
+            assert_eq!(ClickAction::from_event_action(event_action, &no_trash), ClickAction::None);
+        }
+
+        // Handled without an action: the script consumed the click
+        let (mut icon, mut peer) = icon_with_mock_process();
+        peer.send_response(&event_response(true, None));
+        assert_eq!(icon.on_click(1, 0.0, 0.0).unwrap(), ClickAction::None);
+    }
+
+    #[test]
+    fn test_launch_command_goes_through_policy() {
+        let icon = DesktopIcon::new(Path::new("/tmp/test.txt"), &test_config()).unwrap();
+        let program = |action: &ClickAction| {
+            icon.launch_command(action)
+                .map(|cmd| cmd.map(|cmd| cmd.get_program().to_string_lossy().to_string()).ok())
+        };
+
+        assert_eq!(program(&ClickAction::Open), Some(Some("xdg-open".to_string())));
+        assert_eq!(program(&ClickAction::OpenPath("/tmp/x".to_string())), Some(Some("xdg-open".to_string())));
+        assert_eq!(program(&ClickAction::Spawn("rm -rf /".to_string())), Some(None));
+        assert_eq!(program(&ClickAction::Select), None);
+    }
+
+    /// Icon whose sandbox exposes `root` read-only
+    fn drop_target(root: &Path) -> (DesktopIcon, crate::lua::process::MockPeer) {
+        let mut config = test_config();
+        config.sandbox.read_only_paths.push(root.to_path_buf());
+        let mut icon = DesktopIcon::new(Path::new("/tmp/test.txt"), &config).unwrap();
+        let (process, peer) = LuaProcess::mock().unwrap();
+        icon.set_lua_process(process);
+        (icon, peer)
+    }
+
+    #[test]
+    fn test_drop_sends_event_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("photo.png");
+        std::fs::write(&file, b"png").unwrap();
+        let file = file.to_str().unwrap().to_string();
+
+        let (mut icon, mut peer) = drop_target(dir.path());
+        peer.send_response(&event_response(true, Some(("open", Some(file.as_str())))));
+
+        let action = icon.on_drop(vec![file.clone()]).unwrap();
+        assert_eq!(action, ClickAction::OpenPath(file.clone()));
+
+        let json = format!(r#"{{"type":"Event","event":{{"Drop":{{"paths":["{}"]}}}}}}"#, file);
+        let mut expected = (json.len() as u32).to_le_bytes().to_vec();
+        expected.extend_from_slice(json.as_bytes());
+        assert_eq!(peer.try_read_raw(IPC_TIMEOUT), Some(expected));
+    }
+
+    #[test]
+    fn test_drop_filters_invalid_paths() {
+        let allowed = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let kept = allowed.path().join("kept.txt");
+        let foreign = outside.path().join("foreign.txt");
+        std::fs::write(&kept, b"").unwrap();
+        std::fs::write(&foreign, b"").unwrap();
+        let kept = kept.to_str().unwrap().to_string();
+
+        let (mut icon, mut peer) = drop_target(allowed.path());
+        peer.send_response(&event_response(true, None));
+
+        let paths = vec![
+            foreign.to_str().unwrap().to_string(),
+            allowed.path().join("missing.txt").to_str().unwrap().to_string(),
+            kept.clone(),
+        ];
+        assert_eq!(icon.on_drop(paths).unwrap(), ClickAction::None);
+
+        let json = format!(r#"{{"type":"Event","event":{{"Drop":{{"paths":["{}"]}}}}}}"#, kept);
+        let raw = peer.try_read_raw(IPC_TIMEOUT).unwrap();
+        assert_eq!(&raw[4..], json.as_bytes());
+    }
+
+    #[test]
+    fn test_drop_without_valid_paths_sends_nothing() {
+        let allowed = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let foreign = outside.path().join("foreign.txt");
+        std::fs::write(&foreign, b"").unwrap();
+
+        let (mut icon, mut peer) = drop_target(allowed.path());
+        let paths = vec![foreign.to_str().unwrap().to_string(), "relative/../x".to_string()];
+        assert_eq!(icon.on_drop(paths).unwrap(), ClickAction::None);
+        assert_eq!(peer.try_read_raw(Duration::from_millis(50)), None);
+    }
+
+    /// Read the next event request off the wire, or None if nothing was sent
+    fn next_event(peer: &mut crate::lua::process::MockPeer) -> Option<IconEvent> {
+        let raw = peer.try_read_raw(Duration::from_millis(50))?;
+        match Request::deserialize(&raw[4..], crate::ipc::IpcEncoding::Json).unwrap() {
+            Request::Event { event } => Some(event),
+            other => panic!("Expected event request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_hover_enter_sent_once() {
+        let (mut icon, mut peer) = icon_with_mock_process();
+        let start = Instant::now();
+        peer.send_response(&event_response(true, None));
+
+        assert!(icon.set_hovered_at(true, start));
+        assert!(!icon.set_hovered_at(true, start + HOVER_DEBOUNCE * 2));
+        assert!(!icon.flush_hover());
+
+        assert_eq!(next_event(&mut peer), Some(IconEvent::HoverEnter));
+        assert_eq!(next_event(&mut peer), None);
+    }
+
+    #[test]
+    fn test_hover_exit_sent_on_leave() {
+        let (mut icon, mut peer) = icon_with_mock_process();
+        let start = Instant::now();
+        peer.send_response(&event_response(true, None));
+        peer.send_response(&event_response(true, None));
+
+        assert!(icon.set_hovered_at(true, start));
+        assert!(icon.set_hovered_at(false, start + HOVER_DEBOUNCE));
+
+        assert_eq!(next_event(&mut peer), Some(IconEvent::HoverEnter));
+        assert_eq!(next_event(&mut peer), Some(IconEvent::HoverExit));
+        assert_eq!(next_event(&mut peer), None);
+    }
+
+    #[test]
+    fn test_hover_toggles_are_debounced() {
+        let (mut icon, mut peer) = icon_with_mock_process();
+        let start = Instant::now();
+        peer.send_response(&event_response(true, None));
+        peer.send_response(&event_response(true, None));
+
+        assert!(icon.set_hovered_at(true, start));
+        // Flicker within the debounce window: nothing is sent
+        let flicker = start + HOVER_DEBOUNCE / 4;
+        assert!(!icon.set_hovered_at(false, flicker));
+        assert!(!icon.set_hovered_at(true, flicker));
+        assert!(!icon.set_hovered_at(false, flicker));
+        assert!(!icon.is_hovered());
+
+        // The held back exit goes out once the window has passed
+        assert!(!icon.notify_hover(start + HOVER_DEBOUNCE / 2));
+        assert!(icon.notify_hover(start + HOVER_DEBOUNCE));
+
+        assert_eq!(next_event(&mut peer), Some(IconEvent::HoverEnter));
+        assert_eq!(next_event(&mut peer), Some(IconEvent::HoverExit));
+        assert_eq!(next_event(&mut peer), None);
+    }
+
+    #[test]
+    fn test_focus_hover_waits_for_focus_to_rest() {
+        let (mut icon, mut peer) = icon_with_mock_process();
+        let start = Instant::now();
+        peer.send_response(&event_response(true, None));
+
+        // Focus passing over the icon sends nothing
+        icon.set_focus_hovered_at(true, start);
+        icon.set_focus_hovered_at(false, start + HOVER_DEBOUNCE / 4);
+        assert!(!icon.notify_hover(start + HOVER_DEBOUNCE * 2));
+        assert_eq!(next_event(&mut peer), None);
+
+        // Focus that stays goes out once the debounce has passed
+        icon.set_focus_hovered_at(true, start);
+        assert!(!icon.notify_hover(start + HOVER_DEBOUNCE / 2));
+        assert!(icon.notify_hover(start + HOVER_DEBOUNCE));
+        assert_eq!(next_event(&mut peer), Some(IconEvent::HoverEnter));
+        assert_eq!(next_event(&mut peer), None);
+    }
+
+    #[test]
+    fn test_request_render_batch() {
+        let (mut icon, mut peer) = icon_with_mock_process();
+        peer.send_response(&Response::RenderBatch {
+            results: vec![RenderResult {
+                id: 4,
+                commands: vec![DrawCommand::Clear { color: "#ffffff".to_string() }],
+                next_frame_ms: None,
+            }],
+        });
+
+        let items = vec![icon.render_item(4, 64, 84, 1.0)];
+        let results = icon.request_render_batch(items).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].id, 4);
+
+        let raw = peer.try_read_raw(IPC_TIMEOUT).unwrap();
+        match Request::deserialize(&raw[4..], crate::ipc::IpcEncoding::Json).unwrap() {
+            Request::RenderBatch { items } => {
+                assert_eq!(items.len(), 1);
+                assert_eq!(items[0].id, 4);
+                assert_eq!(items[0].metadata.name, "test.txt");
+                assert_eq!(items[0].context.canvas_height, 84);
+            }
+            other => panic!("Expected RenderBatch request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_request_render_schedules_next_frame() {
+        let (mut icon, mut peer) = icon_with_mock_process();
+        let render = |next_frame_ms| Response::Render {
+            commands: vec![DrawCommand::Clear { color: "#ffffff".to_string() }],
+            next_frame_ms,
+        };
+
+        let before = Instant::now();
+        peer.send_response(&render(Some(40)));
+        icon.request_render(64, 84, 1.0);
+        let next = icon.next_frame().unwrap();
+        assert!(next >= before + Duration::from_millis(40));
+        assert!(!icon.frame_due(before));
+        assert!(icon.frame_due(next));
+
+        // Requests for faster frames are held to the minimum interval
+        let before = Instant::now();
+        peer.send_response(&render(Some(0)));
+        icon.request_render(64, 84, 1.0);
+        assert!(icon.next_frame().unwrap() >= before + MIN_FRAME_INTERVAL);
+
+        // A static render, or a failed one, stops the animation
+        peer.send_response(&render(None));
+        icon.request_render(64, 84, 1.0);
+        assert_eq!(icon.next_frame(), None);
+
+        icon.set_rendered(Vec::new(), Some(100));
+        peer.send_response(&Response::Error { message: "render() failed".to_string() });
+        icon.request_render(64, 84, 1.0);
+        assert_eq!(icon.next_frame(), None);
+    }
+
+    #[test]
+    fn test_request_render_batch_unsupported() {
+        let (mut icon, mut peer) = icon_with_mock_process();
+        peer.send_response(&Response::Error { message: "Unknown request type: RenderBatch".to_string() });
+        assert!(icon.request_render_batch(vec![icon.render_item(0, 64, 84, 1.0)]).is_none());
+
+        let mut icon = DesktopIcon::new(Path::new("/tmp/test.txt"), &test_config()).unwrap();
+        assert!(icon.batch_key().is_none());
+        assert!(icon.request_render_batch(Vec::new()).is_none());
+    }
+
+    // ========================================================================
+    // Heartbeat tests
+    // ========================================================================
+
+    fn icon_with_ping(interval_ms: u64) -> (DesktopIcon, crate::lua::process::MockPeer) {
+        let mut config = test_config();
+        config.ping_interval_ms = interval_ms;
+        config.ping_timeout_ms = 50;
+        let mut icon = DesktopIcon::new(Path::new("/tmp/test.txt"), &config).unwrap();
+        let (process, peer) = LuaProcess::mock().unwrap();
+        icon.set_lua_process(process);
+        (icon, peer)
+    }
+
+    #[test]
+    fn test_heartbeat_pings_idle_process() {
+        let (mut icon, mut peer) = icon_with_ping(1000);
+        let pid = icon.lua_pid();
+        peer.send_response(&Response::Pong);
+
+        // Not idle long enough yet: nothing is sent
+        assert!(icon.heartbeat(Instant::now()));
+        assert_eq!(peer.try_read_raw(Duration::from_millis(50)), None);
+
+        assert!(icon.heartbeat(Instant::now() + Duration::from_secs(2)));
+        let raw = peer.try_read_raw(IPC_TIMEOUT).unwrap();
+        assert_eq!(&raw[4..], br#"{"type":"Ping"}"#);
+        assert_eq!(icon.lua_pid(), pid);
+    }
+
+    #[test]
+    fn test_heartbeat_timeout_restarts_process() {
+        // The peer never answers, so the ping times out
+        let (mut icon, _peer) = icon_with_ping(1000);
+        assert!(icon.lua_pid().is_some());
+
+        assert!(!icon.heartbeat(Instant::now() + Duration::from_secs(2)));
+        // The wedged process went through the restart path, which can't
+        // respawn a mock, so the icon is left without a process
+        assert!(icon.lua_pid().is_none());
+        assert!(!icon.wedged);
+    }
+
+    #[test]
+    fn test_stalled_process_is_restarted() {
+        let (mut icon, _peer) = icon_with_ping(0);
+        let process = icon.lua_process.as_mut().unwrap();
+        let request = Request::Event { event: IconEvent::Drop { paths: vec!["x".repeat(1000); 900] } };
+        assert!(process.send_request_with_timeout(&request, Duration::from_millis(50)).is_err());
+        assert!(process.is_stalled());
+
+        // As for a wedged process, the mock can't be respawned
+        assert!(!icon.ensure_process_running());
+        assert!(icon.lua_pid().is_none());
+    }
+
+    #[test]
+    fn test_heartbeat_disabled() {
+        let (mut icon, mut peer) = icon_with_ping(0);
+        assert!(icon.heartbeat(Instant::now() + Duration::from_secs(3600)));
+        assert_eq!(peer.try_read_raw(Duration::from_millis(50)), None);
+        assert!(icon.lua_pid().is_some());
     }
 }
@@ -0,0 +1,119 @@
+//! Keyboard navigation between icons
+//!
+//! Icons sit wherever their scripts placed them, so moving focus picks the
+//! closest icon in the pressed direction rather than stepping a fixed grid.
+
+/// Direction of an arrow key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Direction {
+    /// Split the offset from `from` to `to` into the distance along this
+    /// direction (negative if behind) and the distance across it
+    fn offsets(self, from: (u32, u32), to: (u32, u32)) -> (i64, u64) {
+        let dx = to.0 as i64 - from.0 as i64;
+        let dy = to.1 as i64 - from.1 as i64;
+        match self {
+            Self::Right => (dx, dy.unsigned_abs()),
+            Self::Left => (-dx, dy.unsigned_abs()),
+            Self::Down => (dy, dx.unsigned_abs()),
+            Self::Up => (-dy, dx.unsigned_abs()),
+        }
+    }
+}
+
+/// The icon focus moves to from `from` when pressing `dir`
+///
+/// `icons` pairs each icon with its position. Only icons within 45 degrees
+/// of the direction count, the one most in line winning and then the
+/// nearest. At the edge focus wraps around to the far end of the row or
+/// column. Returns None if there is nowhere to go or `from` isn't among
+/// `icons`.
+pub fn neighbor<'a, K: Ord>(icons: &'a [(K, (u32, u32))], from: &K, dir: Direction) -> Option<&'a K> {
+    let &(_, origin) = icons.iter().find(|(key, _)| key == from)?;
+    let others = icons.iter().filter(|(key, _)| key != from).map(|(key, position)| {
+        let (along, across) = dir.offsets(origin, *position);
+        (key, along, across)
+    });
+
+    let ahead = others
+        .clone()
+        .filter(|&(_, along, across)| (across as i64) < along)
+        .min_by(|a, b| (a.2, a.1, a.0).cmp(&(b.2, b.1, b.0)));
+    let wrapped = || {
+        others
+            .filter(|&(_, along, across)| (across as i64) < -along)
+            .min_by(|a, b| (a.2, a.1, a.0).cmp(&(b.2, b.1, b.0)))
+    };
+    ahead.or_else(wrapped).map(|(key, _, _)| key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 3x2 grid, 100px apart, missing its bottom right cell:
+    ///
+    /// ```text
+    /// a b c
+    /// d e
+    /// ```
+    fn grid() -> Vec<(char, (u32, u32))> {
+        vec![
+            ('a', (0, 0)),
+            ('b', (100, 0)),
+            ('c', (200, 0)),
+            ('d', (0, 100)),
+            ('e', (100, 100)),
+        ]
+    }
+
+    #[test]
+    fn test_moves_to_adjacent_icon() {
+        let icons = grid();
+        assert_eq!(neighbor(&icons, &'a', Direction::Right), Some(&'b'));
+        assert_eq!(neighbor(&icons, &'b', Direction::Left), Some(&'a'));
+        assert_eq!(neighbor(&icons, &'b', Direction::Down), Some(&'e'));
+        assert_eq!(neighbor(&icons, &'e', Direction::Up), Some(&'b'));
+    }
+
+    #[test]
+    fn test_prefers_same_row_and_column() {
+        // f is nearer to b than c is, but out of line
+        let mut icons = grid();
+        icons.push(('f', (150, 40)));
+        assert_eq!(neighbor(&icons, &'b', Direction::Right), Some(&'c'));
+        assert_eq!(neighbor(&icons, &'c', Direction::Left), Some(&'b'));
+
+        // Without an icon in line, the one least out of line is taken
+        icons.push(('g', (200, 130)));
+        assert_eq!(neighbor(&icons, &'e', Direction::Right), Some(&'g'));
+    }
+
+    #[test]
+    fn test_wraps_around_at_edges() {
+        let icons = grid();
+        assert_eq!(neighbor(&icons, &'c', Direction::Right), Some(&'a'));
+        assert_eq!(neighbor(&icons, &'a', Direction::Left), Some(&'c'));
+        assert_eq!(neighbor(&icons, &'e', Direction::Down), Some(&'b'));
+        assert_eq!(neighbor(&icons, &'a', Direction::Up), Some(&'d'));
+
+        // c is up and to the right of e, so Right from e wraps to d
+        assert_eq!(neighbor(&icons, &'e', Direction::Right), Some(&'d'));
+        // Nothing at all in c's column
+        assert_eq!(neighbor(&icons, &'c', Direction::Down), None);
+    }
+
+    #[test]
+    fn test_no_neighbor() {
+        let icons = vec![('a', (0, 0))];
+        assert_eq!(neighbor(&icons, &'a', Direction::Right), None);
+        assert_eq!(neighbor(&grid(), &'z', Direction::Right), None);
+        assert_eq!(neighbor::<char>(&[], &'a', Direction::Up), None);
+    }
+}
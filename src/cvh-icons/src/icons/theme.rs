@@ -0,0 +1,450 @@
+//! Freedesktop icon theme lookup
+//!
+//! Resolves icon names such as `folder` to image files following the
+//! freedesktop Icon Theme Specification.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Theme every other theme implicitly falls back to
+const FALLBACK_THEME: &str = "hicolor";
+
+/// Image formats we can load, in order of preference
+const ICON_EXTENSIONS: &[&str] = &["png", "svg"];
+
+/// How a theme directory matches requested sizes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum DirKind {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+/// One size directory listed in a theme's index.theme
+#[derive(Debug, Clone)]
+struct ThemeDir {
+    path: String,
+    size: u32,
+    kind: DirKind,
+    min_size: u32,
+    max_size: u32,
+    threshold: u32,
+}
+
+impl ThemeDir {
+    /// Whether icons in this directory can be used at `size` unscaled
+    fn matches(&self, size: u32) -> bool {
+        match self.kind {
+            DirKind::Fixed => self.size == size,
+            DirKind::Scalable => (self.min_size..=self.max_size).contains(&size),
+            DirKind::Threshold => self.size.abs_diff(size) <= self.threshold,
+        }
+    }
+
+    /// How far this directory is from `size`, used to pick the closest match
+    fn distance(&self, size: u32) -> u32 {
+        match self.kind {
+            DirKind::Fixed => self.size.abs_diff(size),
+            DirKind::Scalable => {
+                if size < self.min_size {
+                    self.min_size - size
+                } else {
+                    size.saturating_sub(self.max_size)
+                }
+            }
+            DirKind::Threshold => {
+                if size + self.threshold < self.size {
+                    self.size - self.threshold - size
+                } else {
+                    size.saturating_sub(self.size + self.threshold)
+                }
+            }
+        }
+    }
+}
+
+/// Parsed contents of a theme's index.theme
+#[derive(Debug, Default)]
+struct ThemeIndex {
+    inherits: Vec<String>,
+    dirs: Vec<ThemeDir>,
+}
+
+impl ThemeIndex {
+    /// Parse an index.theme file, ignoring keys we don't use
+    fn parse(content: &str) -> Self {
+        let mut directories = Vec::new();
+        let mut inherits = Vec::new();
+        let mut sections: Vec<(String, Vec<(String, String)>)> = Vec::new();
+
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                sections.push((name.to_string(), Vec::new()));
+                continue;
+            }
+            let (Some((_, entries)), Some((key, value))) = (sections.last_mut(), line.split_once('='))
+            else {
+                continue;
+            };
+            entries.push((key.trim().to_string(), value.trim().to_string()));
+        }
+
+        for (section, entries) in &sections {
+            if section != "Icon Theme" {
+                continue;
+            }
+            for (key, value) in entries {
+                let list = || value.split(',').map(str::trim).filter(|s| !s.is_empty()).map(String::from);
+                match key.as_str() {
+                    "Directories" | "ScaledDirectories" => directories.extend(list()),
+                    "Inherits" => inherits.extend(list()),
+                    _ => {}
+                }
+            }
+        }
+
+        let dirs = directories
+            .into_iter()
+            .filter_map(|path| {
+                let (_, entries) = sections.iter().find(|(name, _)| *name == path)?;
+                let get = |key: &str| entries.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+                let num = |key: &str| get(key).and_then(|v| v.parse::<u32>().ok());
+
+                // Only unscaled directories; HiDPI variants are looked up by size instead
+                if num("Scale").unwrap_or(1) != 1 {
+                    return None;
+                }
+
+                let size = num("Size")?;
+                let kind = match get("Type") {
+                    Some("Fixed") => DirKind::Fixed,
+                    Some("Scalable") => DirKind::Scalable,
+                    _ => DirKind::Threshold,
+                };
+                Some(ThemeDir {
+                    min_size: num("MinSize").unwrap_or(size),
+                    max_size: num("MaxSize").unwrap_or(size),
+                    threshold: num("Threshold").unwrap_or(2),
+                    path,
+                    size,
+                    kind,
+                })
+            })
+            .collect();
+
+        Self { inherits, dirs }
+    }
+}
+
+/// An icon theme and the base directories it is searched in
+#[derive(Debug, Clone)]
+pub struct IconTheme {
+    name: String,
+    base_dirs: Vec<PathBuf>,
+}
+
+impl IconTheme {
+    /// Create a theme searched in the standard XDG icon directories
+    pub fn new(name: &str) -> Self {
+        Self::with_base_dirs(name, default_base_dirs())
+    }
+
+    /// Create a theme searched in `base_dirs`, earlier entries taking priority
+    pub fn with_base_dirs(name: &str, base_dirs: Vec<PathBuf>) -> Self {
+        Self {
+            name: name.to_string(),
+            base_dirs,
+        }
+    }
+
+    /// Find the file for `icon` closest to `size` pixels
+    ///
+    /// Searches the theme, the themes it inherits from and finally
+    /// `hicolor`, then unthemed icons directly inside the base directories.
+    pub fn lookup(&self, icon: &str, size: u32) -> Option<PathBuf> {
+        let mut visited = HashSet::new();
+        self.lookup_in(&self.name, icon, size, &mut visited)
+            .or_else(|| self.lookup_in(FALLBACK_THEME, icon, size, &mut visited))
+            .or_else(|| self.lookup_unthemed(icon))
+    }
+
+    /// Look `icon` up in `theme`, then in its parents depth-first
+    fn lookup_in(
+        &self,
+        theme: &str,
+        icon: &str,
+        size: u32,
+        visited: &mut HashSet<String>,
+    ) -> Option<PathBuf> {
+        // Guards against inheritance cycles and rescanning shared parents
+        if !visited.insert(theme.to_string()) {
+            return None;
+        }
+
+        let index = self.load_index(theme)?;
+        if let Some(path) = self.lookup_icon(theme, &index, icon, size) {
+            return Some(path);
+        }
+
+        index
+            .inherits
+            .iter()
+            .find_map(|parent| self.lookup_in(parent, icon, size, visited))
+    }
+
+    /// Read index.theme from the first base directory that has the theme
+    fn load_index(&self, theme: &str) -> Option<ThemeIndex> {
+        self.base_dirs
+            .iter()
+            .find_map(|base| std::fs::read_to_string(base.join(theme).join("index.theme")).ok())
+            .map(|content| ThemeIndex::parse(&content))
+    }
+
+    /// Look `icon` up in a single theme, preferring exact size matches
+    fn lookup_icon(&self, theme: &str, index: &ThemeIndex, icon: &str, size: u32) -> Option<PathBuf> {
+        let exact = index
+            .dirs
+            .iter()
+            .filter(|dir| dir.matches(size))
+            .find_map(|dir| self.find_file(theme, &dir.path, icon));
+        if exact.is_some() {
+            return exact;
+        }
+
+        let mut closest: Option<(u32, PathBuf)> = None;
+        for dir in &index.dirs {
+            let distance = dir.distance(size);
+            if closest.as_ref().is_some_and(|(best, _)| distance >= *best) {
+                continue;
+            }
+            if let Some(path) = self.find_file(theme, &dir.path, icon) {
+                closest = Some((distance, path));
+            }
+        }
+        closest.map(|(_, path)| path)
+    }
+
+    /// Find `<base>/<theme>/<dir>/<icon>.<ext>` in any base directory
+    fn find_file(&self, theme: &str, dir: &str, icon: &str) -> Option<PathBuf> {
+        self.base_dirs
+            .iter()
+            .find_map(|base| find_with_extension(&base.join(theme).join(dir), icon))
+    }
+
+    /// Look for `<base>/<icon>.<ext>`, the spec's fallback for unthemed icons
+    fn lookup_unthemed(&self, icon: &str) -> Option<PathBuf> {
+        self.base_dirs
+            .iter()
+            .find_map(|base| find_with_extension(base, icon))
+    }
+}
+
+/// First existing `<dir>/<icon>.<ext>` for the supported extensions
+fn find_with_extension(dir: &Path, icon: &str) -> Option<PathBuf> {
+    ICON_EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!("{}.{}", icon, ext)))
+        .find(|path| path.is_file())
+}
+
+/// Icon base directories in lookup order, as defined by the spec
+fn default_base_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    if let Some(home) = dirs::home_dir() {
+        dirs.push(home.join(".icons"));
+    }
+    if let Some(data) = dirs::data_dir() {
+        dirs.push(data.join("icons"));
+    }
+
+    let data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    for dir in data_dirs.split(':').filter(|d| !d.is_empty()) {
+        dirs.push(Path::new(dir).join("icons"));
+    }
+
+    dirs.push(PathBuf::from("/usr/share/pixmaps"));
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Write `index` and empty icon files into `<base>/<theme>`
+    fn write_theme(base: &Path, theme: &str, index: &str, icons: &[&str]) {
+        let root = base.join(theme);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("index.theme"), index).unwrap();
+        for icon in icons {
+            let path = root.join(icon);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+    }
+
+    const SIZED_INDEX: &str = "\
+[Icon Theme]
+Name=Test
+Inherits=Parent
+Directories=16x16/places,48x48/places,64x64/places,scalable/places,64x64@2/places
+
+[16x16/places]
+Size=16
+Type=Fixed
+
+[48x48/places]
+Size=48
+Type=Threshold
+
+[64x64/places]
+Size=64
+Type=Fixed
+
+[64x64@2/places]
+Size=64
+Scale=2
+Type=Fixed
+
+[scalable/places]
+Size=128
+MinSize=8
+MaxSize=512
+Type=Scalable
+";
+
+    fn fixture() -> (tempfile::TempDir, IconTheme) {
+        let dir = tempfile::tempdir().unwrap();
+        write_theme(
+            dir.path(),
+            "Test",
+            SIZED_INDEX,
+            &[
+                "16x16/places/folder.png",
+                "48x48/places/folder.png",
+                "64x64/places/folder.png",
+                "64x64@2/places/folder.png",
+                "16x16/places/user-home.png",
+                "scalable/places/user-trash.svg",
+            ],
+        );
+        write_theme(
+            dir.path(),
+            "Parent",
+            "[Icon Theme]\nDirectories=32x32/mimetypes\n\n[32x32/mimetypes]\nSize=32\n",
+            &["32x32/mimetypes/text-x-generic.png"],
+        );
+        write_theme(
+            dir.path(),
+            "hicolor",
+            "[Icon Theme]\nDirectories=48x48/apps\n\n[48x48/apps]\nSize=48\nType=Fixed\n",
+            &["48x48/apps/application-x-executable.png"],
+        );
+        let theme = IconTheme::with_base_dirs("Test", vec![dir.path().to_path_buf()]);
+        (dir, theme)
+    }
+
+    fn relative(dir: &tempfile::TempDir, path: Option<PathBuf>) -> Option<String> {
+        path.map(|p| p.strip_prefix(dir.path()).unwrap().display().to_string())
+    }
+
+    #[test]
+    fn test_lookup_prefers_exact_size() {
+        let (dir, theme) = fixture();
+        assert_eq!(relative(&dir, theme.lookup("folder", 64)).as_deref(), Some("Test/64x64/places/folder.png"));
+        assert_eq!(relative(&dir, theme.lookup("folder", 16)).as_deref(), Some("Test/16x16/places/folder.png"));
+    }
+
+    #[test]
+    fn test_lookup_honors_threshold_and_scalable_ranges() {
+        let (dir, theme) = fixture();
+        // 48 +/- the default threshold of 2
+        assert_eq!(relative(&dir, theme.lookup("folder", 50)).as_deref(), Some("Test/48x48/places/folder.png"));
+        assert_eq!(relative(&dir, theme.lookup("user-trash", 96)).as_deref(), Some("Test/scalable/places/user-trash.svg"));
+    }
+
+    #[test]
+    fn test_lookup_falls_back_to_closest_size() {
+        let (dir, theme) = fixture();
+        assert_eq!(relative(&dir, theme.lookup("folder", 40)).as_deref(), Some("Test/48x48/places/folder.png"));
+        assert_eq!(relative(&dir, theme.lookup("user-home", 64)).as_deref(), Some("Test/16x16/places/user-home.png"));
+    }
+
+    #[test]
+    fn test_lookup_skips_scaled_directories() {
+        let (_dir, theme) = fixture();
+        let found = theme.lookup("folder", 128).unwrap();
+        assert!(!found.to_string_lossy().contains("@2"), "{}", found.display());
+    }
+
+    #[test]
+    fn test_lookup_follows_inherits_then_hicolor() {
+        let (dir, theme) = fixture();
+        assert_eq!(
+            relative(&dir, theme.lookup("text-x-generic", 64)).as_deref(),
+            Some("Parent/32x32/mimetypes/text-x-generic.png")
+        );
+        assert_eq!(
+            relative(&dir, theme.lookup("application-x-executable", 64)).as_deref(),
+            Some("hicolor/48x48/apps/application-x-executable.png")
+        );
+    }
+
+    #[test]
+    fn test_lookup_missing_icon_or_theme() {
+        let (dir, theme) = fixture();
+        assert!(theme.lookup("does-not-exist", 64).is_none());
+
+        let missing = IconTheme::with_base_dirs("Nope", vec![dir.path().to_path_buf()]);
+        assert_eq!(
+            relative(&dir, missing.lookup("application-x-executable", 48)).as_deref(),
+            Some("hicolor/48x48/apps/application-x-executable.png")
+        );
+    }
+
+    #[test]
+    fn test_earlier_base_dir_overrides_later() {
+        let (system, _) = fixture();
+        let user = tempfile::tempdir().unwrap();
+        write_theme(
+            user.path(),
+            "Test",
+            SIZED_INDEX,
+            &["64x64/places/folder.png"],
+        );
+
+        let theme = IconTheme::with_base_dirs("Test", vec![user.path().to_path_buf(), system.path().to_path_buf()]);
+        assert_eq!(theme.lookup("folder", 64), Some(user.path().join("Test/64x64/places/folder.png")));
+        // Icons the user copy lacks still resolve from the system copy
+        assert_eq!(theme.lookup("user-home", 16), Some(system.path().join("Test/16x16/places/user-home.png")));
+    }
+
+    #[test]
+    fn test_unthemed_fallback_and_inheritance_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        write_theme(dir.path(), "A", "[Icon Theme]\nInherits=B\n", &[]);
+        write_theme(dir.path(), "B", "[Icon Theme]\nInherits=A\n", &[]);
+        fs::write(dir.path().join("unknown.png"), b"").unwrap();
+
+        let theme = IconTheme::with_base_dirs("A", vec![dir.path().to_path_buf()]);
+        assert_eq!(theme.lookup("unknown", 64), Some(dir.path().join("unknown.png")));
+        assert!(theme.lookup("folder", 64).is_none());
+    }
+
+    #[test]
+    fn test_parse_index_defaults() {
+        let index = ThemeIndex::parse("# comment\n[Icon Theme]\nDirectories=a, b ,c\n\n[a]\nSize=24\n\n[b]\nType=Fixed\n");
+        // `b` has no Size and `c` has no section, so both are dropped
+        assert_eq!(index.dirs.len(), 1);
+        let dir = &index.dirs[0];
+        assert_eq!((dir.kind, dir.size, dir.threshold), (DirKind::Threshold, 24, 2));
+        assert!(dir.matches(26) && !dir.matches(27));
+    }
+}
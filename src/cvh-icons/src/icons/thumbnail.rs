@@ -0,0 +1,340 @@
+//! Image thumbnails
+//!
+//! Generates and caches previews for image files following the freedesktop
+//! Thumbnail Managing Standard, so thumbnails are shared with file managers.
+
+use image::{ImageReader, Limits, RgbaImage};
+use md5::{Digest, Md5};
+use std::io::{BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use tracing::{debug, warn};
+
+use crate::sandbox::SandboxOptions;
+
+/// Largest source file we decode, in bytes
+const MAX_SOURCE_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Largest source image dimension we decode, in pixels
+const MAX_SOURCE_DIMENSION: u32 = 8192;
+
+/// Decoder allocation cap, covering the decoded source image
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// Thumbnail size classes from the spec: cache subdirectory and edge length
+const FLAVORS: &[(&str, u32)] = &[("normal", 128), ("large", 256)];
+
+/// PNG text keys the spec requires: the source URI and its mtime in seconds
+const KEY_URI: &str = "Thumb::URI";
+const KEY_MTIME: &str = "Thumb::MTime";
+
+/// Generates thumbnails for files under a set of allowed roots
+#[derive(Debug, Clone)]
+pub struct Thumbnailer {
+    /// Thumbnail cache root, normally `$XDG_CACHE_HOME/thumbnails`
+    cache_dir: PathBuf,
+
+    /// Only files below one of these are thumbnailed
+    allowed_roots: Vec<PathBuf>,
+
+    /// Files larger than this are skipped
+    max_bytes: u64,
+}
+
+impl Thumbnailer {
+    /// Create a thumbnailer writing to `cache_dir`
+    pub fn new(cache_dir: PathBuf, allowed_roots: Vec<PathBuf>) -> Self {
+        Self {
+            cache_dir,
+            allowed_roots,
+            max_bytes: MAX_SOURCE_BYTES,
+        }
+    }
+
+    /// Create a thumbnailer for the paths a sandbox may read, using the
+    /// shared XDG thumbnail cache
+    pub fn for_sandbox(options: &SandboxOptions) -> Option<Self> {
+        let cache_dir = dirs::cache_dir()?.join("thumbnails");
+        Some(Self::new(cache_dir, options.allowed_roots()))
+    }
+
+    /// Get a thumbnail of `path` at most `size` pixels on each side,
+    /// generating it if the cache has no up to date copy
+    ///
+    /// Returns None for files outside the allowed roots, files over the byte
+    /// limit and images that fail to decode.
+    pub fn thumbnail(&self, path: &Path, size: u32) -> Option<PathBuf> {
+        let path = path.canonicalize().ok()?;
+        if !self.allowed_roots.iter().any(|root| path.starts_with(root)) {
+            return None;
+        }
+
+        let metadata = path.metadata().ok()?;
+        if !metadata.is_file() {
+            return None;
+        }
+        if metadata.len() > self.max_bytes {
+            debug!("Not thumbnailing {}: {} bytes", path.display(), metadata.len());
+            return None;
+        }
+
+        let (flavor, edge) = flavor(size);
+        let uri = file_uri(&path);
+        let target = cache_path(&self.cache_dir, flavor, &uri);
+
+        // Up to date when the stored URI and mtime match the source
+        let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        if thumb_source(&target).is_some_and(|(thumb_uri, thumb_mtime)| thumb_uri == uri && thumb_mtime == mtime) {
+            return Some(target);
+        }
+
+        match generate(&path, &target, edge, &uri, mtime) {
+            Ok(()) => Some(target),
+            Err(e) => {
+                warn!("Failed to thumbnail {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+}
+
+/// Pick the smallest spec size class that covers `size`
+fn flavor(size: u32) -> (&'static str, u32) {
+    FLAVORS
+        .iter()
+        .copied()
+        .find(|(_, edge)| size <= *edge)
+        .unwrap_or(FLAVORS[FLAVORS.len() - 1])
+}
+
+/// Decode `source`, shrink it to fit `edge` and write it atomically to
+/// `target`, recording the source's `uri` and `mtime`
+fn generate(source: &Path, target: &Path, edge: u32, uri: &str, mtime: u64) -> anyhow::Result<()> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSION);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+
+    let mut reader = ImageReader::open(source)?.with_guessed_format()?;
+    reader.limits(limits);
+    let img = reader.decode()?;
+
+    // Never upscale: small images are stored at their own size
+    let img = if img.width() > edge || img.height() > edge {
+        img.thumbnail(edge, edge)
+    } else {
+        img
+    };
+
+    let dir = target.parent().ok_or_else(|| anyhow::anyhow!("invalid thumbnail path"))?;
+    create_private_dir(dir)?;
+
+    // Write to a temporary name first so readers never see a partial file
+    let tmp = target.with_extension(format!("{}.tmp", std::process::id()));
+    write_thumbnail(&tmp, &img.to_rgba8(), uri, mtime)?;
+    set_mode(&tmp, 0o600)?;
+    std::fs::rename(&tmp, target)?;
+
+    Ok(())
+}
+
+/// Save `img` as a PNG carrying the spec's `Thumb::URI` and `Thumb::MTime`
+fn write_thumbnail(path: &Path, img: &RgbaImage, uri: &str, mtime: u64) -> anyhow::Result<()> {
+    let file = BufWriter::new(std::fs::File::create(path)?);
+    let mut encoder = png::Encoder::new(file, img.width(), img.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.add_text_chunk(KEY_URI.to_string(), uri.to_string())?;
+    encoder.add_text_chunk(KEY_MTIME.to_string(), mtime.to_string())?;
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(img.as_raw())?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// `Thumb::URI` and `Thumb::MTime` stored in the thumbnail at `path`, if
+/// it exists and has both
+fn thumb_source(path: &Path) -> Option<(String, u64)> {
+    let file = BufReader::new(std::fs::File::open(path).ok()?);
+    let reader = png::Decoder::new(file).read_info().ok()?;
+    let text = &reader.info().uncompressed_latin1_text;
+    let field = |key: &str| text.iter().find(|chunk| chunk.keyword == key).map(|chunk| chunk.text.clone());
+    Some((field(KEY_URI)?, field(KEY_MTIME)?.parse().ok()?))
+}
+
+/// Create `dir` (and parents) readable only by the user, as the spec asks
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+    std::fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)
+}
+
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+/// Thumbnail location for `uri`: `<cache>/<flavor>/<md5 of uri>.png`
+pub fn cache_path(cache_dir: &Path, flavor: &str, uri: &str) -> PathBuf {
+    cache_dir.join(flavor).join(format!("{}.png", md5_hex(uri.as_bytes())))
+}
+
+/// `file://` URI for an absolute path, percent-encoding reserved bytes
+pub fn file_uri(path: &Path) -> String {
+    use std::os::unix::ffi::OsStrExt;
+
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
+
+/// Hex MD5 digest, which the spec uses to name thumbnails
+fn md5_hex(data: &[u8]) -> String {
+    Md5::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_png(path: &Path, width: u32, height: u32) {
+        let mut img = image::RgbaImage::new(width, height);
+        for pixel in img.pixels_mut() {
+            *pixel = image::Rgba([255, 0, 0, 255]);
+        }
+        img.save(path).unwrap();
+    }
+
+    /// Source directory (the allowed root) and a separate cache directory
+    fn fixture() -> (tempfile::TempDir, tempfile::TempDir, Thumbnailer) {
+        let source = tempfile::tempdir().unwrap();
+        let cache = tempfile::tempdir().unwrap();
+        let root = source.path().canonicalize().unwrap();
+        let thumbnailer = Thumbnailer::new(cache.path().to_path_buf(), vec![root]);
+        (source, cache, thumbnailer)
+    }
+
+    #[test]
+    fn test_file_uri_percent_encodes() {
+        assert_eq!(file_uri(Path::new("/home/jens/photos/me.png")), "file:///home/jens/photos/me.png");
+        assert_eq!(file_uri(Path::new("/tmp/a b#1.png")), "file:///tmp/a%20b%231.png");
+        assert_eq!(file_uri(Path::new("/tmp/café.png")), "file:///tmp/caf%C3%A9.png");
+    }
+
+    #[test]
+    fn test_cache_path_matches_spec_example() {
+        let uri = file_uri(Path::new("/home/jens/photos/me.png"));
+        assert_eq!(
+            cache_path(Path::new("/home/jens/.cache/thumbnails"), "normal", &uri),
+            PathBuf::from("/home/jens/.cache/thumbnails/normal/c6ee772d9e49320e97ec29a7eb5b1697.png")
+        );
+    }
+
+    #[test]
+    fn test_flavor_by_size() {
+        assert_eq!(flavor(48), ("normal", 128));
+        assert_eq!(flavor(128), ("normal", 128));
+        assert_eq!(flavor(129), ("large", 256));
+        assert_eq!(flavor(1024), ("large", 256));
+    }
+
+    #[test]
+    fn test_thumbnail_is_downscaled_and_cached() {
+        let (source, cache, thumbnailer) = fixture();
+        let photo = source.path().join("photo.png");
+        write_png(&photo, 400, 200);
+
+        let thumb = thumbnailer.thumbnail(&photo, 64).unwrap();
+        let uri = file_uri(&photo.canonicalize().unwrap());
+        assert_eq!(thumb, cache_path(cache.path(), "normal", &uri));
+        assert_eq!(image::image_dimensions(&thumb).unwrap(), (128, 64));
+
+        // A second request reuses the cached file
+        let modified = thumb.metadata().unwrap().modified().unwrap();
+        assert_eq!(thumbnailer.thumbnail(&photo, 64).unwrap(), thumb);
+        assert_eq!(thumb.metadata().unwrap().modified().unwrap(), modified);
+    }
+
+    #[test]
+    fn test_thumbnail_records_source_and_follows_its_mtime() {
+        let (source, _cache, thumbnailer) = fixture();
+        let photo = source.path().join("photo.png");
+        write_png(&photo, 16, 16);
+        let set_mtime = |secs: u64| {
+            let file = std::fs::File::options().write(true).open(&photo).unwrap();
+            file.set_modified(UNIX_EPOCH + std::time::Duration::from_secs(secs)).unwrap();
+        };
+        set_mtime(1_700_000_000);
+
+        let thumb = thumbnailer.thumbnail(&photo, 64).unwrap();
+        let uri = file_uri(&photo.canonicalize().unwrap());
+        assert_eq!(thumb_source(&thumb), Some((uri.clone(), 1_700_000_000)));
+
+        // An older mtime still counts as a change, although the thumbnail
+        // file itself is newer
+        set_mtime(1_600_000_000);
+        assert_eq!(thumbnailer.thumbnail(&photo, 64).unwrap(), thumb);
+        assert_eq!(thumb_source(&thumb), Some((uri, 1_600_000_000)));
+    }
+
+    #[test]
+    fn test_thumbnails_without_source_info_are_regenerated() {
+        let (source, cache, thumbnailer) = fixture();
+        let photo = source.path().join("photo.png");
+        write_png(&photo, 16, 16);
+        let target = cache_path(cache.path(), "normal", &file_uri(&photo.canonicalize().unwrap()));
+        std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+        write_png(&target, 4, 4);
+
+        assert_eq!(thumbnailer.thumbnail(&photo, 64).unwrap(), target);
+        assert_eq!(image::image_dimensions(&target).unwrap(), (16, 16));
+        assert!(thumb_source(&target).is_some());
+    }
+
+    #[test]
+    fn test_small_images_are_not_upscaled() {
+        let (source, _cache, thumbnailer) = fixture();
+        let photo = source.path().join("tiny.png");
+        write_png(&photo, 10, 20);
+
+        let thumb = thumbnailer.thumbnail(&photo, 64).unwrap();
+        assert_eq!(image::image_dimensions(&thumb).unwrap(), (10, 20));
+    }
+
+    #[test]
+    fn test_skips_oversized_files() {
+        let (source, cache, mut thumbnailer) = fixture();
+        let photo = source.path().join("big.png");
+        write_png(&photo, 64, 64);
+        thumbnailer.max_bytes = photo.metadata().unwrap().len() - 1;
+
+        assert!(thumbnailer.thumbnail(&photo, 64).is_none());
+        assert!(!cache.path().join("normal").exists());
+    }
+
+    #[test]
+    fn test_skips_files_outside_allowed_roots() {
+        let (_source, _cache, thumbnailer) = fixture();
+        let elsewhere = tempfile::tempdir().unwrap();
+        let photo = elsewhere.path().join("photo.png");
+        write_png(&photo, 16, 16);
+
+        assert!(thumbnailer.thumbnail(&photo, 64).is_none());
+    }
+
+    #[test]
+    fn test_undecodable_image_is_skipped() {
+        let (source, _cache, thumbnailer) = fixture();
+        let photo = source.path().join("broken.png");
+        std::fs::write(&photo, b"not an image").unwrap();
+
+        assert!(thumbnailer.thumbnail(&photo, 64).is_none());
+    }
+}
@@ -5,7 +5,9 @@
 //!
 //! Supports both bincode (efficient binary) and JSON (compatible with Lua) serialization.
 
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
 use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
 
 use crate::lua::DrawCommand;
 
@@ -19,10 +21,178 @@ pub enum IpcEncoding {
     Json,
 }
 
+#[allow(dead_code)]
+impl IpcEncoding {
+    /// Encodings this side of the connection understands, in preference order
+    pub const SUPPORTED: [IpcEncoding; 2] = [IpcEncoding::Bincode, IpcEncoding::Json];
+
+    /// Name used to advertise the encoding during the handshake
+    pub fn name(self) -> &'static str {
+        match self {
+            IpcEncoding::Bincode => "bincode",
+            IpcEncoding::Json => "json",
+        }
+    }
+
+    /// Names of all supported encodings, for the handshake
+    pub fn supported_names() -> Vec<String> {
+        Self::SUPPORTED.iter().map(|e| e.name().to_string()).collect()
+    }
+
+    /// Pick the encoding for a connection given the peer's advertised names
+    ///
+    /// The first of our supported encodings the peer also lists wins; a peer
+    /// that advertises nothing (or nothing we know) gets JSON.
+    pub fn negotiate(remote: &[String]) -> IpcEncoding {
+        Self::SUPPORTED
+            .into_iter()
+            .find(|e| remote.iter().any(|name| name == e.name()))
+            .unwrap_or(IpcEncoding::Json)
+    }
+}
+
 /// Protocol version for compatibility checking
+///
+/// Both sides must speak exactly this version; requests added on top of it
+/// are optional `Feature`s instead.
 #[allow(dead_code)]
 pub const PROTOCOL_VERSION: u32 = 1;
 
+/// Optional request a peer may or may not handle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Feature {
+    /// `Request::RenderBatch`
+    RenderBatch,
+    /// `Request::Ping`
+    Ping,
+    /// Framed messages, deflated when large (see `encode_frame`)
+    Compression,
+}
+
+#[allow(dead_code)]
+impl Feature {
+    /// Features this side of the connection understands
+    pub const SUPPORTED: [Feature; 3] = [Feature::RenderBatch, Feature::Ping, Feature::Compression];
+
+    /// Name used to advertise the feature during the handshake
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::RenderBatch => "render_batch",
+            Feature::Ping => "ping",
+            Feature::Compression => "deflate",
+        }
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// Set of optional features, as agreed on during the handshake
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Features(u32);
+
+#[allow(dead_code)]
+impl Features {
+    /// No optional features: only the core protocol
+    pub const NONE: Features = Features(0);
+
+    /// Every feature this side supports
+    pub fn supported() -> Self {
+        Feature::SUPPORTED.into_iter().fold(Self::NONE, |set, feature| set.with(feature))
+    }
+
+    /// This set plus `feature`
+    pub fn with(self, feature: Feature) -> Self {
+        Features(self.0 | feature.bit())
+    }
+
+    /// This set without `feature`
+    pub fn without(self, feature: Feature) -> Self {
+        Features(self.0 & !feature.bit())
+    }
+
+    /// Whether `feature` is in the set
+    pub fn contains(self, feature: Feature) -> bool {
+        self.0 & feature.bit() != 0
+    }
+
+    /// Names of the features in the set, for the handshake
+    pub fn names(self) -> Vec<String> {
+        Feature::SUPPORTED
+            .into_iter()
+            .filter(|&feature| self.contains(feature))
+            .map(|feature| feature.name().to_string())
+            .collect()
+    }
+
+    /// The features both we and the peer support, given its advertised names
+    ///
+    /// Names we don't know are ignored, so either side can add features
+    /// without breaking the other.
+    pub fn negotiate(remote: &[String]) -> Self {
+        Feature::SUPPORTED
+            .into_iter()
+            .filter(|feature| remote.iter().any(|name| name == feature.name()))
+            .fold(Self::NONE, |set, feature| set.with(feature))
+    }
+}
+
+/// Payloads larger than this are deflated on connections with
+/// `Feature::Compression`
+pub const COMPRESSION_THRESHOLD: usize = 8 * 1024;
+
+/// Frame flag of a payload sent as is
+const FRAME_PLAIN: u8 = 0;
+
+/// Frame flag of a deflated payload
+const FRAME_DEFLATE: u8 = 1;
+
+/// Frame a serialized message for a connection with `Feature::Compression`
+///
+/// The frame is a flag byte followed by the payload, deflated if it is over
+/// `COMPRESSION_THRESHOLD` and that makes it smaller. It goes after the
+/// length prefix like an unframed message.
+#[allow(dead_code)]
+pub fn encode_frame(payload: &[u8]) -> Vec<u8> {
+    if payload.len() > COMPRESSION_THRESHOLD {
+        let mut encoder = DeflateEncoder::new(vec![FRAME_DEFLATE], Compression::fast());
+        if let Ok(frame) = encoder.write_all(payload).and_then(|()| encoder.finish()) {
+            if frame.len() < payload.len() {
+                return frame;
+            }
+        }
+    }
+    let mut frame = Vec::with_capacity(payload.len() + 1);
+    frame.push(FRAME_PLAIN);
+    frame.extend_from_slice(payload);
+    frame
+}
+
+/// Unwrap a frame made by `encode_frame`
+///
+/// Fails if a deflated payload would come to more than `max_size` bytes,
+/// without inflating more than that.
+#[allow(dead_code)]
+pub fn decode_frame(frame: &[u8], max_size: usize) -> Result<Vec<u8>, String> {
+    match frame.split_first() {
+        Some((&FRAME_PLAIN, payload)) => Ok(payload.to_vec()),
+        Some((&FRAME_DEFLATE, payload)) => {
+            let mut data = Vec::new();
+            DeflateDecoder::new(payload)
+                .take(max_size as u64 + 1)
+                .read_to_end(&mut data)
+                .map_err(|e| format!("Failed to inflate message: {}", e))?;
+            if data.len() > max_size {
+                return Err(format!("Inflated message exceeds {} bytes", max_size));
+            }
+            Ok(data)
+        }
+        Some((flag, _)) => Err(format!("Unknown frame flag {}", flag)),
+        None => Err("Empty frame".to_string()),
+    }
+}
+
 /// Position of an icon on screen
 #[allow(dead_code)]
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -74,8 +244,11 @@ pub struct IconMetadata {
 }
 
 /// Events that can be sent to an icon script
+///
+/// Externally tagged (`"HoverEnter"`, `{"Click":{...}}`) so bincode can
+/// decode it too; the Lua IPC handler normalizes it to `{type = ...}`.
 #[allow(dead_code)]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 pub enum IconEvent {
     /// Mouse click event
     Click {
@@ -127,10 +300,32 @@ pub struct PositionInput {
     pub cell_height: Option<u32>,
 }
 
+/// One icon in a `Request::RenderBatch`
+#[allow(dead_code)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RenderItem {
+    /// Caller-chosen id, echoed back in the matching `RenderResult`
+    pub id: u32,
+    pub metadata: IconMetadata,
+    pub context: RenderContext,
+}
+
+/// Draw commands for one `RenderItem`
+#[allow(dead_code)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RenderResult {
+    pub id: u32,
+    pub commands: Vec<DrawCommand>,
+    /// Delay before the icon wants to be rendered again, as in `Response::Render`
+    #[serde(default)]
+    pub next_frame_ms: Option<u32>,
+}
+
 /// Request messages sent from Rust to Lua process
 ///
 /// Uses internally tagged JSON serialization to produce `{"type":"Handshake", ...}`
-/// format that the Lua IPC handler expects.
+/// format that the Lua IPC handler expects. Bincode can't decode internally
+/// tagged enums, so it goes through the externally tagged `RequestDef` instead.
 #[allow(dead_code)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -138,6 +333,12 @@ pub enum Request {
     /// Handshake to verify protocol version
     Handshake {
         version: u32,
+        /// Encodings the daemon can use after the handshake
+        #[serde(default)]
+        encodings: Vec<String>,
+        /// Optional features the daemon supports
+        #[serde(default)]
+        features: Vec<String>,
     },
     /// Request to render the icon
     Render {
@@ -145,6 +346,10 @@ pub enum Request {
         /// Canvas dimensions and render context
         context: RenderContext,
     },
+    /// Request to render several icons in one round-trip
+    RenderBatch {
+        items: Vec<RenderItem>,
+    },
     /// Send an event to the icon script
     Event {
         event: IconEvent,
@@ -154,6 +359,8 @@ pub enum Request {
         /// Inputs for position calculation
         input: PositionInput,
     },
+    /// Liveness check for idle processes
+    Ping,
     /// Request to shutdown the Lua process
     Shutdown,
 }
@@ -171,7 +378,8 @@ pub struct EventAction {
 /// Response messages sent from Lua process to Rust
 ///
 /// Uses internally tagged JSON serialization to produce `{"type":"HandshakeAck", ...}`
-/// format that matches the Lua IPC handler's response format.
+/// format that matches the Lua IPC handler's response format. Bincode goes
+/// through the externally tagged `ResponseDef`, as for `Request`.
 #[allow(dead_code)]
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
@@ -180,10 +388,24 @@ pub enum Response {
     HandshakeAck {
         version: u32,
         success: bool,
+        /// Encodings the handler can use; JSON only if absent
+        #[serde(default)]
+        encodings: Vec<String>,
+        /// Optional features the handler supports; none if absent
+        #[serde(default)]
+        features: Vec<String>,
     },
     /// Render result with draw commands
     Render {
         commands: Vec<DrawCommand>,
+        /// Milliseconds until the script wants its next frame; None renders
+        /// again only on events and file changes
+        #[serde(default)]
+        next_frame_ms: Option<u32>,
+    },
+    /// Results for a `RenderBatch`; items that failed to render are missing
+    RenderBatch {
+        results: Vec<RenderResult>,
     },
     /// Event handling result with action to perform
     Event {
@@ -201,10 +423,51 @@ pub enum Response {
     Error {
         message: String,
     },
+    /// Reply to `Request::Ping`
+    Pong,
     /// Shutdown acknowledgement
     ShutdownAck,
 }
 
+/// Externally tagged mirror of `Request` for bincode
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Request")]
+enum RequestDef {
+    Handshake { version: u32, encodings: Vec<String>, features: Vec<String> },
+    Render { metadata: IconMetadata, context: RenderContext },
+    RenderBatch { items: Vec<RenderItem> },
+    Event { event: IconEvent },
+    Position { input: PositionInput },
+    Ping,
+    Shutdown,
+}
+
+#[derive(Serialize)]
+struct BincodeRequestRef<'a>(#[serde(with = "RequestDef")] &'a Request);
+
+#[derive(Deserialize)]
+struct BincodeRequest(#[serde(with = "RequestDef")] Request);
+
+/// Externally tagged mirror of `Response` for bincode
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Response")]
+enum ResponseDef {
+    HandshakeAck { version: u32, success: bool, encodings: Vec<String>, features: Vec<String> },
+    Render { commands: Vec<DrawCommand>, next_frame_ms: Option<u32> },
+    RenderBatch { results: Vec<RenderResult> },
+    Event { handled: bool, action: Option<EventAction> },
+    Position { position: Position },
+    Error { message: String },
+    Pong,
+    ShutdownAck,
+}
+
+#[derive(Serialize)]
+struct BincodeResponseRef<'a>(#[serde(with = "ResponseDef")] &'a Response);
+
+#[derive(Deserialize)]
+struct BincodeResponse(#[serde(with = "ResponseDef")] Response);
+
 /// IPC serialization helpers
 #[allow(dead_code)]
 impl Request {
@@ -212,7 +475,7 @@ impl Request {
     pub fn serialize(&self, encoding: IpcEncoding) -> Result<Vec<u8>, String> {
         match encoding {
             IpcEncoding::Bincode => {
-                bincode::serialize(self).map_err(|e| e.to_string())
+                bincode::serialize(&BincodeRequestRef(self)).map_err(|e| e.to_string())
             }
             IpcEncoding::Json => {
                 serde_json::to_vec(self).map_err(|e| e.to_string())
@@ -224,7 +487,9 @@ impl Request {
     pub fn deserialize(data: &[u8], encoding: IpcEncoding) -> Result<Self, String> {
         match encoding {
             IpcEncoding::Bincode => {
-                bincode::deserialize(data).map_err(|e| e.to_string())
+                bincode::deserialize::<BincodeRequest>(data)
+                    .map(|wrapped| wrapped.0)
+                    .map_err(|e| e.to_string())
             }
             IpcEncoding::Json => {
                 serde_json::from_slice(data).map_err(|e| e.to_string())
@@ -239,7 +504,7 @@ impl Response {
     pub fn serialize(&self, encoding: IpcEncoding) -> Result<Vec<u8>, String> {
         match encoding {
             IpcEncoding::Bincode => {
-                bincode::serialize(self).map_err(|e| e.to_string())
+                bincode::serialize(&BincodeResponseRef(self)).map_err(|e| e.to_string())
             }
             IpcEncoding::Json => {
                 serde_json::to_vec(self).map_err(|e| e.to_string())
@@ -251,7 +516,9 @@ impl Response {
     pub fn deserialize(data: &[u8], encoding: IpcEncoding) -> Result<Self, String> {
         match encoding {
             IpcEncoding::Bincode => {
-                bincode::deserialize(data).map_err(|e| e.to_string())
+                bincode::deserialize::<BincodeResponse>(data)
+                    .map(|wrapped| wrapped.0)
+                    .map_err(|e| e.to_string())
             }
             IpcEncoding::Json => {
                 serde_json::from_slice(data).map_err(|e| e.to_string())
@@ -264,6 +531,9 @@ impl Response {
 mod tests {
     use super::*;
 
+    /// Size limit for the frame tests
+    const MAX: usize = 1024 * 1024;
+
     #[test]
     fn test_protocol_version_is_set() {
         assert_eq!(PROTOCOL_VERSION, 1);
@@ -401,11 +671,12 @@ mod tests {
                     color: "#0000FF".to_string(),
                 },
             ],
+            next_frame_ms: None,
         };
         let encoded = response.serialize(IpcEncoding::Json).unwrap();
         let decoded = Response::deserialize(&encoded, IpcEncoding::Json).unwrap();
         match decoded {
-            Response::Render { commands } => {
+            Response::Render { commands, .. } => {
                 assert_eq!(commands.len(), 2);
             }
             _ => panic!("Expected Render response"),
@@ -417,11 +688,13 @@ mod tests {
         // Note: Request uses internally tagged JSON for Lua IPC compatibility
         let request = Request::Handshake {
             version: PROTOCOL_VERSION,
+            encodings: Vec::new(),
+            features: Vec::new(),
         };
         let encoded = request.serialize(IpcEncoding::Json).unwrap();
         let decoded = Request::deserialize(&encoded, IpcEncoding::Json).unwrap();
         match decoded {
-            Request::Handshake { version } => {
+            Request::Handshake { version, .. } => {
                 assert_eq!(version, PROTOCOL_VERSION);
             }
             _ => panic!("Expected Handshake request"),
@@ -577,11 +850,11 @@ mod tests {
 
     #[test]
     fn test_request_json_serialization() {
-        let request = Request::Handshake { version: PROTOCOL_VERSION };
+        let request = Request::Handshake { version: PROTOCOL_VERSION, encodings: Vec::new(), features: Vec::new() };
         let json_data = request.serialize(IpcEncoding::Json).unwrap();
         let decoded = Request::deserialize(&json_data, IpcEncoding::Json).unwrap();
         match decoded {
-            Request::Handshake { version } => {
+            Request::Handshake { version, .. } => {
                 assert_eq!(version, PROTOCOL_VERSION);
             }
             _ => panic!("Expected Handshake request"),
@@ -593,11 +866,13 @@ mod tests {
         let response = Response::HandshakeAck {
             version: PROTOCOL_VERSION,
             success: true,
+            encodings: Vec::new(),
+            features: Vec::new(),
         };
         let json_data = response.serialize(IpcEncoding::Json).unwrap();
         let decoded = Response::deserialize(&json_data, IpcEncoding::Json).unwrap();
         match decoded {
-            Response::HandshakeAck { version, success } => {
+            Response::HandshakeAck { version, success, .. } => {
                 assert_eq!(version, PROTOCOL_VERSION);
                 assert!(success);
             }
@@ -650,11 +925,12 @@ mod tests {
                     color: "#FF0000".to_string(),
                 },
             ],
+            next_frame_ms: None,
         };
         let json_data = response.serialize(IpcEncoding::Json).unwrap();
         let decoded = Response::deserialize(&json_data, IpcEncoding::Json).unwrap();
         match decoded {
-            Response::Render { commands } => {
+            Response::Render { commands, .. } => {
                 assert_eq!(commands.len(), 2);
             }
             _ => panic!("Expected Render response"),
@@ -746,7 +1022,7 @@ mod tests {
 
     #[test]
     fn test_request_json_has_type_field() {
-        let request = Request::Handshake { version: PROTOCOL_VERSION };
+        let request = Request::Handshake { version: PROTOCOL_VERSION, encodings: Vec::new(), features: Vec::new() };
         let json_data = request.serialize(IpcEncoding::Json).unwrap();
         let json_str = String::from_utf8(json_data).unwrap();
 
@@ -773,6 +1049,8 @@ mod tests {
         let response = Response::HandshakeAck {
             version: PROTOCOL_VERSION,
             success: true,
+            encodings: Vec::new(),
+            features: Vec::new(),
         };
         let json_data = response.serialize(IpcEncoding::Json).unwrap();
         let json_str = String::from_utf8(json_data).unwrap();
@@ -858,6 +1136,7 @@ mod tests {
             commands: vec![
                 DrawCommand::Clear { color: "#000000".to_string() },
             ],
+            next_frame_ms: None,
         };
         let json_data = response.serialize(IpcEncoding::Json).unwrap();
         let json_str = String::from_utf8(json_data).unwrap();
@@ -868,6 +1147,56 @@ mod tests {
             "JSON should contain commands field at top level: {}", json_str);
     }
 
+    #[test]
+    fn test_render_response_next_frame_ms() {
+        use crate::lua::DrawCommand;
+        let response = Response::Render {
+            commands: vec![DrawCommand::Clear { color: "#000000".to_string() }],
+            next_frame_ms: Some(33),
+        };
+        let json_str = String::from_utf8(response.serialize(IpcEncoding::Json).unwrap()).unwrap();
+        assert!(json_str.contains(r#""next_frame_ms":33"#), "{}", json_str);
+
+        for encoding in [IpcEncoding::Json, IpcEncoding::Bincode] {
+            let data = response.serialize(encoding).unwrap();
+            match Response::deserialize(&data, encoding).unwrap() {
+                Response::Render { next_frame_ms, .. } => assert_eq!(next_frame_ms, Some(33)),
+                other => panic!("Expected Render response, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_response_without_next_frame_ms() {
+        // Handlers that predate animation leave the field out
+        let json = br#"{"type":"Render","commands":[]}"#;
+        match Response::deserialize(json, IpcEncoding::Json).unwrap() {
+            Response::Render { commands, next_frame_ms } => {
+                assert!(commands.is_empty());
+                assert_eq!(next_frame_ms, None);
+            }
+            other => panic!("Expected Render response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_event_request_json_shape() {
+        let request = Request::Event {
+            event: IconEvent::Click { button: 1, x: 2.0, y: 3.0 },
+        };
+        let json_str = String::from_utf8(request.serialize(IpcEncoding::Json).unwrap()).unwrap();
+
+        // The Lua handler relies on this shape when normalizing events
+        assert_eq!(
+            json_str,
+            r#"{"type":"Event","event":{"Click":{"button":1,"x":2.0,"y":3.0}}}"#
+        );
+
+        let request = Request::Event { event: IconEvent::HoverEnter };
+        let json_str = String::from_utf8(request.serialize(IpcEncoding::Json).unwrap()).unwrap();
+        assert_eq!(json_str, r#"{"type":"Event","event":"HoverEnter"}"#);
+    }
+
     #[test]
     fn test_event_response_json_shape() {
         let response = Response::Event {
@@ -920,7 +1249,7 @@ mod tests {
     fn test_json_roundtrip_all_request_variants() {
         // Test that all request variants can be serialized and deserialized with JSON
         let requests = vec![
-            Request::Handshake { version: 1 },
+            Request::Handshake { version: 1, encodings: Vec::new(), features: Vec::new() },
             Request::Render {
                 metadata: IconMetadata {
                     path: "/test".to_string(),
@@ -969,8 +1298,8 @@ mod tests {
 
         // Test that all response variants can be serialized and deserialized with JSON
         let responses = vec![
-            Response::HandshakeAck { version: 1, success: true },
-            Response::Render { commands: vec![DrawCommand::Clear { color: "#000".to_string() }] },
+            Response::HandshakeAck { version: 1, success: true, encodings: Vec::new(), features: Vec::new() },
+            Response::Render { commands: vec![DrawCommand::Clear { color: "#000".to_string() }], next_frame_ms: None },
             Response::Event { handled: true, action: None },
             Response::Position { position: Position { x: 0, y: 0 } },
             Response::Error { message: "test".to_string() },
@@ -983,4 +1312,254 @@ mod tests {
             assert!(decoded.is_ok(), "Failed to deserialize response: {:?}", response);
         }
     }
+
+    // ========================================================================
+    // Bincode and negotiation tests
+    // ========================================================================
+
+    #[test]
+    fn test_feature_negotiation() {
+        let names = |list: &[&str]| -> Vec<String> { list.iter().map(|n| n.to_string()).collect() };
+
+        assert_eq!(Features::negotiate(&[]), Features::NONE);
+        assert_eq!(Features::negotiate(&names(&["deflate", "ping", "render_batch"])), Features::supported());
+
+        let agreed = Features::negotiate(&names(&["ping", "zstd", "Render_Batch"]));
+        assert!(agreed.contains(Feature::Ping));
+        assert!(!agreed.contains(Feature::RenderBatch));
+        assert_eq!(agreed.names(), ["ping"]);
+
+        assert_eq!(Features::supported().names(), ["render_batch", "ping", "deflate"]);
+        assert_eq!(Features::supported().without(Feature::Compression).names(), ["render_batch", "ping"]);
+        assert!(Features::NONE.names().is_empty());
+    }
+
+    #[test]
+    fn test_frames_compress_only_large_payloads() {
+        let small = b"{\"type\":\"Ping\"}";
+        let frame = encode_frame(small);
+        assert_eq!(frame[0], FRAME_PLAIN);
+        assert_eq!(&frame[1..], small);
+        assert_eq!(decode_frame(&frame, MAX).unwrap(), small);
+
+        let large = b"{\"type\":\"Line\"}".repeat(COMPRESSION_THRESHOLD);
+        let frame = encode_frame(&large);
+        assert_eq!(frame[0], FRAME_DEFLATE);
+        assert!(frame.len() < large.len() / 10, "{} bytes", frame.len());
+        assert_eq!(decode_frame(&frame, MAX).unwrap(), large);
+
+        // Incompressible data isn't worth inflating later
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let noise: Vec<u8> = (0..COMPRESSION_THRESHOLD * 2)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        assert_eq!(encode_frame(&noise)[0], FRAME_PLAIN);
+    }
+
+    #[test]
+    fn test_decode_frame_limits_inflated_size() {
+        let zeros = vec![0u8; 4 * MAX];
+        let bomb = encode_frame(&zeros);
+        assert!(bomb.len() < MAX / 10);
+        assert!(decode_frame(&bomb, MAX).is_err());
+        assert_eq!(decode_frame(&bomb, 4 * MAX).unwrap().len(), 4 * MAX);
+
+        assert!(decode_frame(&[], MAX).is_err());
+        assert!(decode_frame(&[7, 1, 2], MAX).is_err());
+        // A final block of the reserved type
+        assert!(decode_frame(&[FRAME_DEFLATE, 0x07], MAX).is_err(), "corrupt stream");
+    }
+
+    #[test]
+    fn test_handshake_ack_features_default_to_none() {
+        let ack = br#"{"type":"HandshakeAck","version":1,"success":true}"#;
+        match Response::deserialize(ack, IpcEncoding::Json).unwrap() {
+            Response::HandshakeAck { features, .. } => assert!(features.is_empty()),
+            other => panic!("Expected HandshakeAck, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_request_bincode_roundtrip() {
+        let requests = vec![
+            Request::Handshake {
+                version: 1,
+                encodings: IpcEncoding::supported_names(),
+                features: Features::supported().names(),
+            },
+            Request::Event { event: IconEvent::Click { button: 1, x: 2.0, y: 3.0 } },
+            Request::Position {
+                input: PositionInput {
+                    screen_width: 1920,
+                    screen_height: 1080,
+                    icon_count: 3,
+                    icon_index: 1,
+                    cell_width: None,
+                    cell_height: Some(96),
+                },
+            },
+            Request::Shutdown,
+        ];
+        for request in requests {
+            let data = request.serialize(IpcEncoding::Bincode).unwrap();
+            let decoded = Request::deserialize(&data, IpcEncoding::Bincode).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", request));
+        }
+    }
+
+    #[test]
+    fn test_response_bincode_roundtrip() {
+        let responses = vec![
+            Response::HandshakeAck {
+                version: 1,
+                success: true,
+                encodings: vec!["bincode".to_string()],
+                features: vec!["ping".to_string()],
+            },
+            Response::Render {
+                commands: vec![DrawCommand::Clear { color: "#000000".to_string() }],
+                next_frame_ms: Some(250),
+            },
+            Response::Event {
+                handled: true,
+                action: Some(EventAction { action: "open".to_string(), payload: None }),
+            },
+            Response::Error { message: "boom".to_string() },
+            Response::ShutdownAck,
+        ];
+        for response in responses {
+            let data = response.serialize(IpcEncoding::Bincode).unwrap();
+            let decoded = Response::deserialize(&data, IpcEncoding::Bincode).unwrap();
+            assert_eq!(format!("{:?}", decoded), format!("{:?}", response));
+        }
+    }
+
+    #[test]
+    fn test_encoding_negotiation() {
+        let names = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+
+        assert_eq!(IpcEncoding::negotiate(&names(&["json", "bincode"])), IpcEncoding::Bincode);
+        assert_eq!(IpcEncoding::negotiate(&names(&["bincode"])), IpcEncoding::Bincode);
+        assert_eq!(IpcEncoding::negotiate(&names(&["json"])), IpcEncoding::Json);
+        assert_eq!(IpcEncoding::negotiate(&names(&["msgpack"])), IpcEncoding::Json);
+        assert_eq!(IpcEncoding::negotiate(&[]), IpcEncoding::Json);
+    }
+
+    // ========================================================================
+    // Render batch tests
+    // ========================================================================
+
+    fn batch_metadata(name: &str) -> IconMetadata {
+        IconMetadata {
+            path: format!("/home/user/Desktop/{}", name),
+            name: name.to_string(),
+            mime_type: None,
+            is_directory: false,
+            size: Some(42),
+            width: 64,
+            height: 64,
+            icon_type: IconType::File,
+            selected: false,
+            hovered: false,
+        }
+    }
+
+    fn batch_request() -> Request {
+        let context = RenderContext { canvas_width: 64, canvas_height: 84, device_pixel_ratio: 1.0 };
+        Request::RenderBatch {
+            items: vec![
+                RenderItem { id: 0, metadata: batch_metadata("a.txt"), context: context.clone() },
+                RenderItem { id: 7, metadata: batch_metadata("b.txt"), context },
+            ],
+        }
+    }
+
+    fn batch_response() -> Response {
+        Response::RenderBatch {
+            results: vec![
+                RenderResult {
+                    id: 0,
+                    commands: vec![DrawCommand::Clear { color: "#ffffff".to_string() }],
+                    next_frame_ms: Some(16),
+                },
+                RenderResult { id: 7, commands: Vec::new(), next_frame_ms: None },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_render_batch_request_roundtrip() {
+        for encoding in [IpcEncoding::Json, IpcEncoding::Bincode] {
+            let data = batch_request().serialize(encoding).unwrap();
+            match Request::deserialize(&data, encoding).unwrap() {
+                Request::RenderBatch { items } => {
+                    assert_eq!(items.len(), 2);
+                    assert_eq!(items[0].id, 0);
+                    assert_eq!(items[0].metadata.name, "a.txt");
+                    assert_eq!(items[1].id, 7);
+                    assert_eq!(items[1].metadata.path, "/home/user/Desktop/b.txt");
+                    assert_eq!(items[1].context.canvas_height, 84);
+                }
+                other => panic!("Expected RenderBatch request, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_batch_response_roundtrip() {
+        for encoding in [IpcEncoding::Json, IpcEncoding::Bincode] {
+            let data = batch_response().serialize(encoding).unwrap();
+            match Response::deserialize(&data, encoding).unwrap() {
+                Response::RenderBatch { results } => {
+                    assert_eq!(results.len(), 2);
+                    assert_eq!(results[0].id, 0);
+                    assert!(matches!(results[0].commands[0], DrawCommand::Clear { .. }));
+                    assert_eq!(results[0].next_frame_ms, Some(16));
+                    assert_eq!(results[1].id, 7);
+                    assert!(results[1].commands.is_empty());
+                    assert_eq!(results[1].next_frame_ms, None);
+                }
+                other => panic!("Expected RenderBatch response, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_batch_json_shape() {
+        let json: serde_json::Value =
+            serde_json::from_slice(&batch_request().serialize(IpcEncoding::Json).unwrap()).unwrap();
+        assert_eq!(json["type"], "RenderBatch");
+        assert_eq!(json["items"][1]["id"], 7);
+        assert_eq!(json["items"][1]["metadata"]["name"], "b.txt");
+
+        // What the Lua handler sends back
+        let lua = br#"{"type":"RenderBatch","results":[{"id":3,"commands":[]}]}"#;
+        match Response::deserialize(lua, IpcEncoding::Json).unwrap() {
+            Response::RenderBatch { results } => {
+                assert_eq!(results[0].id, 3);
+                assert_eq!(results[0].next_frame_ms, None);
+            }
+            other => panic!("Expected RenderBatch response, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ping_pong_serialization() {
+        let json = Request::Ping.serialize(IpcEncoding::Json).unwrap();
+        assert_eq!(json, br#"{"type":"Ping"}"#);
+        let json = Response::Pong.serialize(IpcEncoding::Json).unwrap();
+        assert_eq!(json, br#"{"type":"Pong"}"#);
+
+        for encoding in [IpcEncoding::Json, IpcEncoding::Bincode] {
+            let data = Request::Ping.serialize(encoding).unwrap();
+            assert!(matches!(Request::deserialize(&data, encoding).unwrap(), Request::Ping));
+            let data = Response::Pong.serialize(encoding).unwrap();
+            assert!(matches!(Response::deserialize(&data, encoding).unwrap(), Response::Pong));
+        }
+    }
 }
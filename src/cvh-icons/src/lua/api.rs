@@ -3,8 +3,151 @@
 //! Provides safe functions for icon scripts to interact with the system.
 
 use anyhow::Result;
-use mlua::{Lua, UserData, UserDataMethods};
+use fontdue::Font;
+use mlua::{Lua, Table, UserData, UserDataMethods, Value};
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Number, Value as JsonValue};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use tiny_skia::Color;
+use tracing::Level;
+
+use crate::clock::LocalTime;
+use crate::ipc::EventAction;
+use crate::renderer::{measure_text, parse_color, shared_default_font};
+use crate::sandbox::actions::ActionPolicy;
+use crate::sandbox::notify::{Notification, Urgency};
+
+/// Limits applied by `cvh.json` to keep scripts from exhausting the stack or heap
+#[derive(Debug, Clone, Copy)]
+pub struct JsonLimits {
+    /// Maximum nesting of arrays/objects (tables when encoding)
+    pub max_depth: usize,
+    /// Maximum length in bytes of a string passed to `decode`
+    pub max_input_len: usize,
+}
+
+impl Default for JsonLimits {
+    fn default() -> Self {
+        Self {
+            max_depth: 32,
+            max_input_len: 64 * 1024,
+        }
+    }
+}
+
+/// File name of the loaded icon script, stored as app data for log prefixes
+pub struct ScriptName(pub String);
+
+/// Opens and spawns requested by the script, waiting for the daemon to
+/// carry them out; stored as app data
+#[derive(Default)]
+pub struct PendingActions(pub Vec<EventAction>);
+
+/// Environment variables `cvh.env.get` may read, stored as app data
+pub struct EnvAllowlist(pub Vec<String>);
+
+impl Default for EnvAllowlist {
+    fn default() -> Self {
+        Self(crate::sandbox::DEFAULT_ENV_ALLOWLIST.iter().map(|name| name.to_string()).collect())
+    }
+}
+
+/// Canonical directories `cvh.file.mtime`, `size` and `mode` may look
+/// into, stored as app data
+pub struct ReadRoots(pub Vec<PathBuf>);
+
+impl Default for ReadRoots {
+    fn default() -> Self {
+        Self(crate::sandbox::SandboxOptions::default().allowed_roots())
+    }
+}
+
+/// Font `cvh.text.measure` lays text out with, stored as app data; None
+/// when no font could be loaded
+pub struct TextFont(pub Option<Arc<Font>>);
+
+/// Fixed UTC offset `cvh.time` dates use instead of the local time zone,
+/// stored as app data; tests set it to get the same dates everywhere
+pub struct TimeOffset(pub chrono::FixedOffset);
+
+/// The calendar time of `secs` (default now) for `cvh.time`
+fn lua_time(lua: &Lua, secs: Option<i64>) -> LocalTime {
+    match lua.app_data_ref::<TimeOffset>() {
+        Some(offset) => LocalTime::with_offset(secs.unwrap_or_else(|| chrono::Utc::now().timestamp()), offset.0),
+        None => secs.map_or_else(LocalTime::now, LocalTime::from_unix),
+    }
+}
+
+/// Key/value store behind `cvh.state`, stored as app data
+///
+/// Saved as one JSON object per script in a directory the sandbox may
+/// write to, so widgets keep their state across restarts and crashes.
+/// The daemon's icons get theirs from the IPC handler, which keeps the
+/// same file format.
+pub struct StateStore {
+    /// None keeps the state in memory only
+    path: Option<PathBuf>,
+    max_bytes: usize,
+    values: Map<String, JsonValue>,
+}
+
+impl StateStore {
+    /// Largest encoded state a script may keep by default
+    pub const DEFAULT_MAX_BYTES: usize = 16 * 1024;
+
+    /// State of `script` kept in `dir`, with what an earlier run saved
+    ///
+    /// A missing or unreadable state file starts out empty.
+    #[allow(dead_code)]
+    pub fn open(dir: &Path, script: &Path, max_bytes: usize) -> Self {
+        let name = script.file_name().unwrap_or(script.as_os_str()).to_string_lossy();
+        let path = dir.join(format!("{}.state.json", name));
+        let values = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice(&data).ok())
+            .unwrap_or_default();
+        Self { path: Some(path), max_bytes, values }
+    }
+
+    /// Empty state that is never saved, for one-off runs like `--validate`
+    pub fn in_memory(max_bytes: usize) -> Self {
+        Self { path: None, max_bytes, values: Map::new() }
+    }
+
+    fn get(&self, key: &str) -> Option<JsonValue> {
+        self.values.get(key).cloned()
+    }
+
+    /// Set `key` (null removes it) and save; state over the size cap is
+    /// refused and the old state kept
+    fn set(&mut self, key: String, value: JsonValue) -> Result<()> {
+        let mut values = self.values.clone();
+        if value.is_null() {
+            values.remove(&key);
+        } else {
+            values.insert(key, value);
+        }
+
+        let encoded = serde_json::to_vec(&values)?;
+        if encoded.len() > self.max_bytes {
+            anyhow::bail!("state too large ({} bytes, max {})", encoded.len(), self.max_bytes);
+        }
+
+        if let Some(path) = &self.path {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            // Write then rename so a crash never leaves half a state file
+            let tmp = path.with_extension("json.tmp");
+            std::fs::write(&tmp, &encoded)?;
+            std::fs::rename(&tmp, path)?;
+        }
+        self.values = values;
+        Ok(())
+    }
+}
 
 /// Canvas for drawing icons
 #[derive(Clone)]
@@ -16,16 +159,164 @@ pub struct Canvas {
 }
 
 #[allow(dead_code)]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum DrawCommand {
     FillRect { x: f32, y: f32, w: f32, h: f32, color: String },
-    StrokeRect { x: f32, y: f32, w: f32, h: f32, color: String, width: f32 },
+    /// Outlined rect; `dash` alternates on and off lengths, empty for solid
+    StrokeRect {
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        color: String,
+        width: f32,
+        #[serde(default)]
+        dash: Vec<f32>,
+    },
     FillCircle { cx: f32, cy: f32, r: f32, color: String },
     StrokeCircle { cx: f32, cy: f32, r: f32, color: String, width: f32 },
-    Line { x1: f32, y1: f32, x2: f32, y2: f32, color: String, width: f32 },
+    /// Stroked arc running clockwise from `start_deg` to `end_deg`, 0°
+    /// pointing right; a 360° sweep is a full circle
+    Arc { cx: f32, cy: f32, r: f32, start_deg: f32, end_deg: f32, color: String, width: f32 },
+    /// Straight line; `dash` works as for `StrokeRect`
+    Line {
+        x1: f32,
+        y1: f32,
+        x2: f32,
+        y2: f32,
+        color: String,
+        width: f32,
+        #[serde(default)]
+        dash: Vec<f32>,
+    },
     Text { text: String, x: f32, y: f32, size: f32, color: String, align: String },
+    /// Text word-wrapped to width `w`, `y` being the first line's baseline
+    TextBox { text: String, x: f32, y: f32, w: f32, size: f32, color: String, align: String },
     Image { path: String, x: f32, y: f32, w: f32, h: f32 },
     Clear { color: String },
+    RoundRect { x: f32, y: f32, w: f32, h: f32, r: f32, color: String },
+    /// Fills the rect with a left-to-right gradient
+    LinearGradient { x: f32, y: f32, w: f32, h: f32, stops: Vec<GradientStop> },
+    /// Restrict later commands to the rect, replacing any earlier clip
+    Clip { x: f32, y: f32, w: f32, h: f32 },
+    /// Remove the clip set by `Clip`
+    ResetClip,
+    /// Multiply the opacity of later commands by `alpha` (0 to 1)
+    SetAlpha { alpha: f32 },
+    /// Draw later commands at full opacity again
+    ResetAlpha,
+    /// Transform later commands: scale by `scale`, rotate clockwise by
+    /// `rotate_deg` and move the origin to (`tx`, `ty`), on top of any
+    /// transform already pushed
+    PushTransform { rotate_deg: f32, tx: f32, ty: f32, scale: f32 },
+    /// Go back to the transform before the last `PushTransform`
+    PopTransform,
+}
+
+impl DrawCommand {
+    /// The same command with every coordinate, size and width multiplied
+    /// by `scale`, for rendering logical commands into a HiDPI buffer
+    pub fn scaled(&self, scale: f32) -> Self {
+        let s = |v: &f32| v * scale;
+        match self {
+            Self::FillRect { x, y, w, h, color } => {
+                Self::FillRect { x: s(x), y: s(y), w: s(w), h: s(h), color: color.clone() }
+            }
+            Self::StrokeRect { x, y, w, h, color, width, dash } => Self::StrokeRect {
+                x: s(x),
+                y: s(y),
+                w: s(w),
+                h: s(h),
+                color: color.clone(),
+                width: s(width),
+                dash: dash.iter().map(s).collect(),
+            },
+            Self::FillCircle { cx, cy, r, color } => {
+                Self::FillCircle { cx: s(cx), cy: s(cy), r: s(r), color: color.clone() }
+            }
+            Self::StrokeCircle { cx, cy, r, color, width } => Self::StrokeCircle {
+                cx: s(cx),
+                cy: s(cy),
+                r: s(r),
+                color: color.clone(),
+                width: s(width),
+            },
+            Self::Arc { cx, cy, r, start_deg, end_deg, color, width } => Self::Arc {
+                cx: s(cx),
+                cy: s(cy),
+                r: s(r),
+                start_deg: *start_deg,
+                end_deg: *end_deg,
+                color: color.clone(),
+                width: s(width),
+            },
+            Self::Line { x1, y1, x2, y2, color, width, dash } => Self::Line {
+                x1: s(x1),
+                y1: s(y1),
+                x2: s(x2),
+                y2: s(y2),
+                color: color.clone(),
+                width: s(width),
+                dash: dash.iter().map(s).collect(),
+            },
+            Self::Text { text, x, y, size, color, align } => Self::Text {
+                text: text.clone(),
+                x: s(x),
+                y: s(y),
+                size: s(size),
+                color: color.clone(),
+                align: align.clone(),
+            },
+            Self::TextBox { text, x, y, w, size, color, align } => Self::TextBox {
+                text: text.clone(),
+                x: s(x),
+                y: s(y),
+                w: s(w),
+                size: s(size),
+                color: color.clone(),
+                align: align.clone(),
+            },
+            Self::Image { path, x, y, w, h } => {
+                Self::Image { path: path.clone(), x: s(x), y: s(y), w: s(w), h: s(h) }
+            }
+            Self::Clear { color } => Self::Clear { color: color.clone() },
+            Self::RoundRect { x, y, w, h, r, color } => Self::RoundRect {
+                x: s(x),
+                y: s(y),
+                w: s(w),
+                h: s(h),
+                r: s(r),
+                color: color.clone(),
+            },
+            Self::LinearGradient { x, y, w, h, stops } => Self::LinearGradient {
+                x: s(x),
+                y: s(y),
+                w: s(w),
+                h: s(h),
+                stops: stops.clone(),
+            },
+            Self::Clip { x, y, w, h } => Self::Clip { x: s(x), y: s(y), w: s(w), h: s(h) },
+            Self::ResetClip => Self::ResetClip,
+            Self::SetAlpha { alpha } => Self::SetAlpha { alpha: *alpha },
+            Self::ResetAlpha => Self::ResetAlpha,
+            // Only the offset is in canvas units; rotation and scale compose
+            // with the buffer scale as they are
+            Self::PushTransform { rotate_deg, tx, ty, scale: factor } => Self::PushTransform {
+                rotate_deg: *rotate_deg,
+                tx: s(tx),
+                ty: s(ty),
+                scale: *factor,
+            },
+            Self::PopTransform => Self::PopTransform,
+        }
+    }
+}
+
+/// A color stop of a gradient, `offset` running from 0 to 1
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GradientStop {
+    pub offset: f32,
+    pub color: String,
 }
 
 #[allow(dead_code)]
@@ -46,10 +337,14 @@ impl UserData for Canvas {
             Ok(())
         });
 
-        methods.add_method_mut("stroke_rect", |_, this, (x, y, w, h, color, width): (f32, f32, f32, f32, String, f32)| {
-            this.commands.push(DrawCommand::StrokeRect { x, y, w, h, color, width });
-            Ok(())
-        });
+        methods.add_method_mut(
+            "stroke_rect",
+            |_, this, (x, y, w, h, color, width, dash): (f32, f32, f32, f32, String, f32, Option<Vec<f32>>)| {
+                let dash = dash.unwrap_or_default();
+                this.commands.push(DrawCommand::StrokeRect { x, y, w, h, color, width, dash });
+                Ok(())
+            },
+        );
 
         methods.add_method_mut("fill_circle", |_, this, (cx, cy, r, color): (f32, f32, f32, String)| {
             this.commands.push(DrawCommand::FillCircle { cx, cy, r, color });
@@ -61,10 +356,22 @@ impl UserData for Canvas {
             Ok(())
         });
 
-        methods.add_method_mut("line", |_, this, (x1, y1, x2, y2, color, width): (f32, f32, f32, f32, String, f32)| {
-            this.commands.push(DrawCommand::Line { x1, y1, x2, y2, color, width });
-            Ok(())
-        });
+        methods.add_method_mut(
+            "arc",
+            |_, this, (cx, cy, r, start_deg, end_deg, color, width): (f32, f32, f32, f32, f32, String, f32)| {
+                this.commands.push(DrawCommand::Arc { cx, cy, r, start_deg, end_deg, color, width });
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut(
+            "line",
+            |_, this, (x1, y1, x2, y2, color, width, dash): (f32, f32, f32, f32, String, f32, Option<Vec<f32>>)| {
+                let dash = dash.unwrap_or_default();
+                this.commands.push(DrawCommand::Line { x1, y1, x2, y2, color, width, dash });
+                Ok(())
+            },
+        );
 
         methods.add_method_mut("text", |_, this, (text, x, y, size, color, align): (String, f32, f32, f32, String, Option<String>)| {
             this.commands.push(DrawCommand::Text {
@@ -78,11 +385,82 @@ impl UserData for Canvas {
             Ok(())
         });
 
+        methods.add_method_mut("text_box", |_, this, (text, x, y, w, size, color, align): (String, f32, f32, f32, f32, String, Option<String>)| {
+            this.commands.push(DrawCommand::TextBox {
+                text,
+                x,
+                y,
+                w,
+                size,
+                color,
+                align: align.unwrap_or_else(|| "left".to_string()),
+            });
+            Ok(())
+        });
+
         methods.add_method_mut("image", |_, this, (path, x, y, w, h): (String, f32, f32, f32, f32)| {
             this.commands.push(DrawCommand::Image { path, x, y, w, h });
             Ok(())
         });
 
+        methods.add_method_mut("clip", |_, this, (x, y, w, h): (f32, f32, f32, f32)| {
+            this.commands.push(DrawCommand::Clip { x, y, w, h });
+            Ok(())
+        });
+
+        methods.add_method_mut("reset_clip", |_, this, ()| {
+            this.commands.push(DrawCommand::ResetClip);
+            Ok(())
+        });
+
+        methods.add_method_mut("set_alpha", |_, this, alpha: f32| {
+            this.commands.push(DrawCommand::SetAlpha { alpha });
+            Ok(())
+        });
+
+        methods.add_method_mut("reset_alpha", |_, this, ()| {
+            this.commands.push(DrawCommand::ResetAlpha);
+            Ok(())
+        });
+
+        methods.add_method_mut(
+            "push_transform",
+            |_, this, (rotate_deg, tx, ty, scale): (Option<f32>, Option<f32>, Option<f32>, Option<f32>)| {
+                this.commands.push(DrawCommand::PushTransform {
+                    rotate_deg: rotate_deg.unwrap_or(0.0),
+                    tx: tx.unwrap_or(0.0),
+                    ty: ty.unwrap_or(0.0),
+                    scale: scale.unwrap_or(1.0),
+                });
+                Ok(())
+            },
+        );
+
+        methods.add_method_mut("pop_transform", |_, this, ()| {
+            this.commands.push(DrawCommand::PopTransform);
+            Ok(())
+        });
+
+        methods.add_method_mut("round_rect", |_, this, (x, y, w, h, r, color): (f32, f32, f32, f32, f32, String)| {
+            this.commands.push(DrawCommand::RoundRect { x, y, w, h, r, color });
+            Ok(())
+        });
+
+        // stops is a list of { offset, color } (or { offset = .., color = .. }) tables
+        methods.add_method_mut("linear_gradient", |_, this, (x, y, w, h, stops): (f32, f32, f32, f32, Vec<Table>)| {
+            let stops = stops
+                .iter()
+                .map(|stop| {
+                    Ok(GradientStop {
+                        offset: stop.get::<Option<f32>>("offset")?.map_or_else(|| stop.get(1), Ok)?,
+                        color: stop.get::<Option<String>>("color")?.map_or_else(|| stop.get(2), Ok)?,
+                    })
+                })
+                .collect::<mlua::Result<Vec<_>>>()?;
+            this.commands.push(DrawCommand::LinearGradient { x, y, w, h, stops });
+            Ok(())
+        });
+
         methods.add_method_mut("clear", |_, this, color: String| {
             this.commands.push(DrawCommand::Clear { color });
             Ok(())
@@ -95,7 +473,7 @@ impl UserData for Canvas {
 
 #[allow(dead_code)]
 /// Install the CVH API into Lua globals
-pub fn install(lua: &Lua) -> Result<()> {
+pub fn install(lua: &Lua, json_limits: JsonLimits) -> Result<()> {
     let globals = lua.globals();
 
     // Create the main 'cvh' table
@@ -115,29 +493,22 @@ pub fn install(lua: &Lua) -> Result<()> {
             .map(|d| d.as_millis() as u64)
             .unwrap_or(0))
     })?)?;
-    time.set("format", lua.create_function(|_, fmt: String| {
-        use std::time::SystemTime;
-        // Simple time formatting (production would use chrono)
-        let now = SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default();
-        let secs = now.as_secs();
-
-        // Very basic formatting for common patterns
-        let result = if fmt == "%H:%M:%S" {
-            let h = (secs % 86400) / 3600;
-            let m = (secs % 3600) / 60;
-            let s = secs % 60;
-            format!("{:02}:{:02}:{:02}", h, m, s)
-        } else if fmt == "%H:%M" {
-            let h = (secs % 86400) / 3600;
-            let m = (secs % 3600) / 60;
-            format!("{:02}:{:02}", h, m)
-        } else {
-            format!("{}", secs)
-        };
-
-        Ok(result)
+    // Dates are in the local time zone, of the given Unix time or else now
+    time.set("format", lua.create_function(|lua, (fmt, secs): (String, Option<i64>)| {
+        Ok(lua_time(lua, secs).format(&fmt))
+    })?)?;
+    time.set("date", lua.create_function(|lua, secs: Option<i64>| {
+        let date = lua_time(lua, secs);
+        let table = lua.create_table()?;
+        table.set("year", date.year)?;
+        table.set("month", date.month)?;
+        table.set("day", date.day)?;
+        table.set("hour", date.hour)?;
+        table.set("min", date.min)?;
+        table.set("sec", date.sec)?;
+        table.set("wday", date.wday)?;
+        table.set("yday", date.yday)?;
+        Ok(table)
     })?)?;
     cvh.set("time", time)?;
 
@@ -151,6 +522,7 @@ pub fn install(lua: &Lua) -> Result<()> {
     cvh.set("system", system)?;
 
     // File operations (sandboxed)
+    lua.set_app_data(ReadRoots::default());
     let file = lua.create_table()?;
     file.set("exists", lua.create_function(|_, path: String| {
         // Only allow checking existence, not reading
@@ -183,29 +555,355 @@ pub fn install(lua: &Lua) -> Result<()> {
             .map(|s| s.to_string())
             .unwrap_or_default())
     })?)?;
+    file.set("join", lua.create_function(|_, parts: mlua::Variadic<String>| {
+        // As PathBuf::push: an absolute part starts over from it
+        let path: PathBuf = parts.iter().collect();
+        Ok(path.to_string_lossy().into_owned())
+    })?)?;
+    file.set("normalize", lua.create_function(|_, path: String| {
+        Ok(normalize_path(Path::new(&path)).to_string_lossy().into_owned())
+    })?)?;
+    // Metadata only for paths the sandbox could read; nil otherwise
+    file.set("mtime", lua.create_function(|lua, path: String| {
+        Ok(readable_metadata(lua, Path::new(&path)).map(|metadata| metadata.mtime()))
+    })?)?;
+    file.set("size", lua.create_function(|lua, path: String| {
+        Ok(readable_metadata(lua, Path::new(&path)).map(|metadata| metadata.len()))
+    })?)?;
+    file.set("mode", lua.create_function(|lua, path: String| {
+        Ok(readable_metadata(lua, Path::new(&path)).map(|metadata| metadata.mode() & 0o7777))
+    })?)?;
     cvh.set("file", file)?;
 
-    // Spawn external commands (will be sandboxed by daemon)
-    cvh.set("open", lua.create_function(|_, path: String| {
-        // Open file/folder with default handler
-        // This is handled by the daemon, not executed directly
-        tracing::info!("Lua requested open: {}", path);
-        Ok(())
+    // JSON helpers
+    let json = lua.create_table()?;
+    json.set("encode", lua.create_function(move |_, value: Value| {
+        let encoded = lua_to_json(&value, json_limits.max_depth)?;
+        serde_json::to_string(&encoded).map_err(|e| mlua::Error::runtime(format!("json encode failed: {}", e)))
     })?)?;
+    json.set("decode", lua.create_function(move |lua, input: mlua::String| {
+        if input.as_bytes().len() > json_limits.max_input_len {
+            return Err(mlua::Error::runtime(format!(
+                "json input too large (max {} bytes)",
+                json_limits.max_input_len
+            )));
+        }
+        let parsed: JsonValue = serde_json::from_slice(&input.as_bytes())
+            .map_err(|e| mlua::Error::runtime(format!("invalid json: {}", e)))?;
+        json_to_lua(lua, &parsed, json_limits.max_depth)
+    })?)?;
+    cvh.set("json", json)?;
 
-    cvh.set("spawn", lua.create_function(|_, cmd: String| {
-        // Spawn command (handled by daemon)
-        tracing::info!("Lua requested spawn: {}", cmd);
-        Ok(())
+    // Color helpers, taking anything the renderer parses and returning hex
+    let color = lua.create_table()?;
+    color.set("lighten", lua.create_function(|_, (spec, amount): (String, f32)| {
+        let c = lua_color(&spec)?;
+        Ok(color_to_hex(mix_colors(c, Color::from_rgba(1.0, 1.0, 1.0, c.alpha()).unwrap_or(c), amount)))
+    })?)?;
+    color.set("darken", lua.create_function(|_, (spec, amount): (String, f32)| {
+        let c = lua_color(&spec)?;
+        Ok(color_to_hex(mix_colors(c, Color::from_rgba(0.0, 0.0, 0.0, c.alpha()).unwrap_or(c), amount)))
+    })?)?;
+    color.set("mix", lua.create_function(|_, (a, b, t): (String, String, f32)| {
+        Ok(color_to_hex(mix_colors(lua_color(&a)?, lua_color(&b)?, t)))
     })?)?;
+    color.set("with_alpha", lua.create_function(|_, (spec, alpha): (String, f32)| {
+        let mut c = lua_color(&spec)?;
+        c.set_alpha(alpha);
+        Ok(color_to_hex(c))
+    })?)?;
+    cvh.set("color", color)?;
 
-    // Notifications
-    cvh.set("notify", lua.create_function(|_, (title, body): (String, String)| {
-        tracing::info!("Lua notification: {} - {}", title, body);
-        Ok(())
+    // Text metrics, from the renderer's font unless the runtime sets another
+    lua.set_app_data(TextFont(shared_default_font()));
+    let text = lua.create_table()?;
+    text.set("measure", lua.create_function(|lua, (text, size): (String, f32)| {
+        let font = lua.app_data_ref::<TextFont>();
+        Ok(match font.as_ref().and_then(|font| font.0.as_deref()) {
+            Some(font) if size > 0.0 => measure_text(font, &text, size),
+            _ => 0.0,
+        })
+    })?)?;
+    cvh.set("text", text)?;
+
+    // Open/spawn only queue a request for the daemon, which runs it outside
+    // the sandbox; both return whether the action policy accepted it
+    lua.set_app_data(ActionPolicy::default());
+    lua.set_app_data(PendingActions::default());
+    for kind in ["open", "spawn"] {
+        cvh.set(kind, lua.create_function(move |lua, payload: String| {
+            let action = EventAction {
+                action: kind.to_string(),
+                payload: Some(payload),
+            };
+            let verdict = match lua.app_data_ref::<ActionPolicy>() {
+                Some(policy) => policy.command(&action).map(|_| ()),
+                None => Err(anyhow::anyhow!("no action policy")),
+            };
+            if let Err(e) = verdict {
+                tracing::warn!("Lua {} rejected: {}", kind, e);
+                return Ok(false);
+            }
+
+            tracing::info!("Lua requested {}: {}", kind, action.payload.as_deref().unwrap_or_default());
+            if let Some(mut pending) = lua.app_data_mut::<PendingActions>() {
+                pending.0.push(action);
+            }
+            Ok(true)
+        })?)?;
+    }
+
+    // Environment, limited to the allowlist so secrets stay out of reach
+    lua.set_app_data(EnvAllowlist::default());
+    let env = lua.create_table()?;
+    env.set("get", lua.create_function(|lua, name: String| {
+        let allowed = lua
+            .app_data_ref::<EnvAllowlist>()
+            .is_some_and(|allowlist| allowlist.0.contains(&name));
+        Ok(if allowed { std::env::var(&name).ok() } else { None })
+    })?)?;
+    cvh.set("env", env)?;
+
+    // Per-script state that survives restarts, once the runtime has a store;
+    // values go through JSON, so only JSON-representable ones are kept
+    let state = lua.create_table()?;
+    state.set("get", lua.create_function(move |lua, key: String| {
+        let value = lua.app_data_ref::<StateStore>().and_then(|store| store.get(&key));
+        match value {
+            Some(value) => json_to_lua(lua, &value, json_limits.max_depth),
+            None => Ok(Value::Nil),
+        }
+    })?)?;
+    state.set("set", lua.create_function(move |lua, (key, value): (String, Value)| {
+        let value = lua_to_json(&value, json_limits.max_depth)?;
+        let mut store = lua
+            .app_data_mut::<StateStore>()
+            .ok_or_else(|| mlua::Error::runtime("no state store for this script"))?;
+        store
+            .set(key, value)
+            .map_err(|e| mlua::Error::runtime(format!("state set failed: {:#}", e)))
+    })?)?;
+    cvh.set("state", state)?;
+
+    // Leveled logging, prefixed with the script name
+    let log = lua.create_table()?;
+    for level in [Level::DEBUG, Level::INFO, Level::WARN, Level::ERROR] {
+        let name = level.as_str().to_ascii_lowercase();
+        log.set(name, lua.create_function(move |lua, args: mlua::Variadic<String>| {
+            let msg = args.iter().map(|s| s.as_str()).collect::<Vec<_>>().join("\t");
+            let prefix = match lua.app_data_ref::<ScriptName>() {
+                Some(script) => format!("[Lua:{}]", script.0),
+                None => "[Lua]".to_string(),
+            };
+            match level {
+                Level::DEBUG => tracing::debug!("{} {}", prefix, msg),
+                Level::INFO => tracing::info!("{} {}", prefix, msg),
+                Level::WARN => tracing::warn!("{} {}", prefix, msg),
+                _ => tracing::error!("{} {}", prefix, msg),
+            }
+            Ok(())
+        })?)?;
+    }
+    cvh.set("log", log)?;
+
+    // Notifications are queued for the daemon like open/spawn, as
+    // cvh.notify{title=, body=, urgency=, icon=} or cvh.notify(title, body)
+    cvh.set("notify", lua.create_function(|lua, (spec, body): (Value, Option<String>)| {
+        let notification = notification_from_lua(spec, body)?;
+        tracing::info!("Lua notification: {} - {}", notification.title, notification.body);
+        if let Some(mut pending) = lua.app_data_mut::<PendingActions>() {
+            pending.0.push(EventAction {
+                action: "notify".to_string(),
+                payload: Some(notification.to_payload()),
+            });
+        }
+        Ok(true)
     })?)?;
 
     globals.set("cvh", cvh)?;
 
     Ok(())
 }
+
+/// Collapse `.` and `..` in `path` without looking at the filesystem
+///
+/// `..` past the start of a relative path is kept, past the root dropped;
+/// an empty result is `.`.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut parts: Vec<Component> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match parts.last() {
+                Some(Component::Normal(_)) => {
+                    parts.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {}
+                _ => parts.push(component),
+            },
+            _ => parts.push(component),
+        }
+    }
+    if parts.is_empty() {
+        return PathBuf::from(".");
+    }
+    parts.iter().collect()
+}
+
+/// Metadata of `path` if it exists and lies within the `ReadRoots`
+///
+/// Symlinks are followed before the check, so one can't point outside.
+fn readable_metadata(lua: &Lua, path: &Path) -> Option<std::fs::Metadata> {
+    let path = path.canonicalize().ok()?;
+    let roots = lua.app_data_ref::<ReadRoots>()?;
+    if !roots.0.iter().any(|root| path.starts_with(root)) {
+        return None;
+    }
+    path.metadata().ok()
+}
+
+/// Parse a color passed to `cvh.color`
+fn lua_color(spec: &str) -> mlua::Result<Color> {
+    parse_color(spec).ok_or_else(|| mlua::Error::runtime(format!("invalid color '{}'", spec)))
+}
+
+/// Blend from `a` (t = 0) to `b` (t = 1), alpha included
+fn mix_colors(a: Color, b: Color, t: f32) -> Color {
+    let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+    let lerp = |x: f32, y: f32| x + (y - x) * t;
+    Color::from_rgba(
+        lerp(a.red(), b.red()),
+        lerp(a.green(), b.green()),
+        lerp(a.blue(), b.blue()),
+        lerp(a.alpha(), b.alpha()),
+    )
+    .unwrap_or(a)
+}
+
+/// `#rrggbb`, or `#rrggbbaa` when not opaque
+fn color_to_hex(color: Color) -> String {
+    let c = color.to_color_u8();
+    if c.alpha() == 255 {
+        format!("#{:02x}{:02x}{:02x}", c.red(), c.green(), c.blue())
+    } else {
+        format!("#{:02x}{:02x}{:02x}{:02x}", c.red(), c.green(), c.blue(), c.alpha())
+    }
+}
+
+/// Build a notification from `cvh.notify` arguments
+fn notification_from_lua(spec: Value, body: Option<String>) -> mlua::Result<Notification> {
+    let (title, body, urgency, icon) = match spec {
+        Value::Table(fields) => (
+            fields.get::<Option<String>>("title")?,
+            fields.get::<Option<String>>("body")?,
+            fields.get::<Option<String>>("urgency")?,
+            fields.get::<Option<String>>("icon")?,
+        ),
+        Value::String(title) => (Some(title.to_str()?.to_string()), body, None, None),
+        other => {
+            return Err(mlua::Error::runtime(format!(
+                "cvh.notify expects a table or a title, got {}",
+                other.type_name()
+            )))
+        }
+    };
+
+    let title = title.ok_or_else(|| mlua::Error::runtime("cvh.notify needs a title"))?;
+    let urgency = match urgency {
+        Some(level) => Urgency::parse(&level)
+            .ok_or_else(|| mlua::Error::runtime(format!("unknown notification urgency '{}'", level)))?,
+        None => Urgency::default(),
+    };
+    Ok(Notification { title, body: body.unwrap_or_default(), urgency, icon })
+}
+
+/// Convert a Lua value to JSON, descending at most `depth` tables
+///
+/// Tables whose keys are exactly `1..=n` become arrays, any other table an
+/// object with string or number keys. Empty tables encode as objects.
+fn lua_to_json(value: &Value, depth: usize) -> mlua::Result<JsonValue> {
+    Ok(match value {
+        Value::Nil => JsonValue::Null,
+        Value::Boolean(b) => JsonValue::Bool(*b),
+        Value::Integer(n) => JsonValue::from(*n),
+        Value::Number(n) => Number::from_f64(*n)
+            .map(JsonValue::Number)
+            .ok_or_else(|| mlua::Error::runtime("cannot encode NaN or infinity as json"))?,
+        Value::String(s) => JsonValue::String(s.to_str()?.to_string()),
+        Value::Table(t) => {
+            if depth == 0 {
+                return Err(mlua::Error::runtime("json nesting too deep"));
+            }
+            table_to_json(t, depth - 1)?
+        }
+        other => {
+            return Err(mlua::Error::runtime(format!("cannot encode {} as json", other.type_name())))
+        }
+    })
+}
+
+fn table_to_json(table: &Table, depth: usize) -> mlua::Result<JsonValue> {
+    let len = table.raw_len();
+    let entries = table
+        .pairs::<Value, Value>()
+        .collect::<mlua::Result<Vec<_>>>()?;
+
+    if len > 0 && entries.len() == len {
+        let array = (1..=len)
+            .map(|i| lua_to_json(&table.raw_get(i)?, depth))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        return Ok(JsonValue::Array(array));
+    }
+
+    let mut object = Map::new();
+    for (key, value) in entries {
+        let key = match key {
+            Value::String(s) => s.to_str()?.to_string(),
+            Value::Integer(n) => n.to_string(),
+            Value::Number(n) => n.to_string(),
+            other => {
+                return Err(mlua::Error::runtime(format!(
+                    "cannot encode {} key as json",
+                    other.type_name()
+                )))
+            }
+        };
+        object.insert(key, lua_to_json(&value, depth)?);
+    }
+    Ok(JsonValue::Object(object))
+}
+
+/// Convert JSON to a Lua value, descending at most `depth` arrays/objects
+///
+/// `null` becomes `nil`, so nulls inside arrays leave holes.
+fn json_to_lua(lua: &Lua, value: &JsonValue, depth: usize) -> mlua::Result<Value> {
+    Ok(match value {
+        JsonValue::Null => Value::Nil,
+        JsonValue::Bool(b) => Value::Boolean(*b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => Value::Integer(i),
+            None => Value::Number(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        JsonValue::String(s) => Value::String(lua.create_string(s)?),
+        JsonValue::Array(items) => {
+            if depth == 0 {
+                return Err(mlua::Error::runtime("json nesting too deep"));
+            }
+            let table = lua.create_table_with_capacity(items.len(), 0)?;
+            for (i, item) in items.iter().enumerate() {
+                table.raw_set(i + 1, json_to_lua(lua, item, depth - 1)?)?;
+            }
+            Value::Table(table)
+        }
+        JsonValue::Object(fields) => {
+            if depth == 0 {
+                return Err(mlua::Error::runtime("json nesting too deep"));
+            }
+            let table = lua.create_table_with_capacity(0, fields.len())?;
+            for (key, field) in fields {
+                table.raw_set(key.as_str(), json_to_lua(lua, field, depth - 1)?)?;
+            }
+            Value::Table(table)
+        }
+    })
+}
@@ -3,8 +3,9 @@
 //! Provides a sandboxed Lua environment for icon customization.
 
 use anyhow::{Context, Result};
-use mlua::{Error as LuaError, Function, Lua, Table, Value};
+use mlua::{Error as LuaError, Function, HookTriggers, Lua, Table, Value, VmState};
 use std::path::Path;
+use std::time::{Duration, Instant};
 
 pub mod api;
 pub mod process;
@@ -14,6 +15,33 @@ pub use api::DrawCommand;
 #[allow(unused_imports)]
 pub use process::LuaProcess;
 
+/// how many VM instructions run between time budget checks
+const HOOK_INSTRUCTION_INTERVAL: u32 = 1_000;
+
+/// resource limits for a lua runtime
+#[derive(Debug, Clone, Copy)]
+pub struct LuaLimits {
+    /// wall-clock budget for one entry into lua (script load, exec or callback)
+    pub timeout: Duration,
+    /// maximum bytes the lua state may allocate
+    pub memory: usize,
+    /// depth and size caps for `cvh.json`
+    pub json: api::JsonLimits,
+}
+
+impl Default for LuaLimits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(1),
+            memory: 8 * 1024 * 1024,
+            json: api::JsonLimits::default(),
+        }
+    }
+}
+
+/// point in time after which the running script is aborted, stored as app data
+struct Deadline(Option<Instant>);
+
 /// sandboxed lua runtime for icon scripts
 pub struct LuaRuntime {
     lua: Lua,
@@ -23,15 +51,62 @@ pub struct LuaRuntime {
 impl LuaRuntime {
     /// create a new sandbox lua runtime
     pub fn new() -> Result<Self> {
+        Self::with_limits(LuaLimits::default())
+    }
+
+    /// create a sandbox lua runtime with the `[sandbox]` settings of
+    /// `config`, as icon scripts get under the daemon
+    pub fn for_config(config: &crate::config::Config) -> Result<Self> {
+        let runtime = Self::new()?;
+        let options = crate::icons::sandbox_options(config);
+        // scripts run here once, so their state isn't saved
+        runtime.set_state_store(api::StateStore::in_memory(api::StateStore::DEFAULT_MAX_BYTES));
+        runtime.set_read_roots(options.allowed_roots());
+        runtime.set_text_font(crate::renderer::configured_font(config));
+        runtime.set_env_allowlist(options.env_allowlist);
+        runtime.set_action_policy(options.action_policy);
+        Ok(runtime)
+    }
+
+    /// create a new sandbox lua runtime with custom resource limits
+    pub fn with_limits(limits: LuaLimits) -> Result<Self> {
         let lua = Lua::new();
 
         Self::sandbox(&lua)?;
         stdlib::install(&lua)?;
-        api::install(&lua)?;
+        api::install(&lua, limits.json)?;
+        Self::install_limits(&lua, limits)?;
 
         Ok(Self { lua })
     }
 
+    /// cap allocations and abort scripts that run past their time budget
+    ///
+    /// allocations over the memory cap fail with a lua memory error. the time hook
+    /// only fires between VM instructions, so time spent inside a single rust
+    /// callback is not interrupted
+    fn install_limits(lua: &Lua, limits: LuaLimits) -> Result<()> {
+        lua.set_memory_limit(limits.memory)
+            .context("failed to set Lua memory limit")?;
+        lua.set_app_data(limits);
+        lua.set_app_data(Deadline(None));
+
+        let triggers = HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTION_INTERVAL);
+        lua.set_hook(triggers, |lua, _debug| {
+            let expired = lua
+                .app_data_ref::<Deadline>()
+                .and_then(|deadline| deadline.0)
+                .is_some_and(|at| Instant::now() >= at);
+
+            if expired {
+                return Err(LuaError::RuntimeError("script exceeded its time budget".into()));
+            }
+            Ok(VmState::Continue)
+        });
+
+        Ok(())
+    }
+
     /// remove bugged globals from env
     fn sandbox(lua: &Lua) -> Result<()> {
         let globals = lua.globals();
@@ -64,10 +139,10 @@ impl LuaRuntime {
 
     /// restrict str library to safe subset
     ///
-    /// we keep a small set of harmless string helpers and provide a guarded `gsub`
-    /// we deliberately avoid exposing `gmatch` (iterator) to prevent uncontrolled iteration
-    /// or complex pattern based DoS vectors. ff iterator support is required, implement a
-    /// controlled wrapper that enforces iteration caps and timeouts
+    /// we keep a small set of harmless string helpers and provide guarded `gsub`,
+    /// `match` and `gmatch`. `gmatch` hands back an iterator, so besides the size caps
+    /// the wrapper counts produced matches and errors out past `MAX_GMATCH_ITERATIONS`
+    /// to prevent uncontrolled iteration
     fn restrict_strlib(lua: &Lua) -> Result<()> {
         let globals = lua.globals();
         let string: Table = globals.get("string")
@@ -89,6 +164,7 @@ impl LuaRuntime {
         // guarded gstub, for editing contact me (hachimamma) otherwise do NOT edit
         const MAX_STRING_LEN: usize = 10_000;   // tunable
         const MAX_PATTERN_LEN: usize = 1_000;   // tunable
+        const MAX_GMATCH_ITERATIONS: usize = 10_000; // tunable
 
         if let Ok(orig_gsub) = string.get::<Function>("gsub") {
             let orig = orig_gsub.clone();
@@ -118,6 +194,33 @@ impl LuaRuntime {
             new_str.set("match", safe_match)?;
         }
 
+        if let Ok(orig_gmatch) = string.get::<Function>("gmatch") {
+            let orig = orig_gmatch.clone();
+            let safe_gmatch = lua.create_function(move |lua, (s, pat, init): (String, String, Option<i64>)| {
+                if s.len() > MAX_STRING_LEN {
+                    return Err(LuaError::RuntimeError("input string too large".into()));
+                }
+                if pat.len() > MAX_PATTERN_LEN {
+                    return Err(LuaError::RuntimeError("pattern too large".into()));
+                }
+                let iter = orig.call::<Function>((s, pat, init))?;
+
+                // wrap the iterator so it fails once too many matches were produced
+                let mut count = 0usize;
+                lua.create_function_mut(move |_lua, ()| {
+                    let captures = iter.call::<mlua::MultiValue>(())?;
+                    if !matches!(captures.front(), None | Some(Value::Nil)) {
+                        count += 1;
+                        if count > MAX_GMATCH_ITERATIONS {
+                            return Err(LuaError::RuntimeError("gmatch iteration limit exceeded".into()));
+                        }
+                    }
+                    Ok(captures)
+                })
+            })?;
+            new_str.set("gmatch", safe_gmatch)?;
+        }
+
         globals.set("string", new_str)?;
 
         Ok(())
@@ -128,9 +231,10 @@ impl LuaRuntime {
         let content = std::fs::read_to_string(path)
             .with_context(|| format!("Failed to read script: {}", path.display()))?;
 
-        self.lua
-            .load(&content)
-            .exec()
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        self.lua.set_app_data(api::ScriptName(name.to_string_lossy().to_string()));
+
+        with_deadline(&self.lua, || self.lua.load(&content).exec())
             .with_context(|| format!("Failed to execute script: {}", path.display()))?;
 
         let globals = self.lua.globals();
@@ -142,16 +246,82 @@ impl LuaRuntime {
 
     /// execute a lua string for repl/testing
     pub fn exec(&self, code: &str) -> Result<()> {
-        self.lua.load(code).exec()?;
+        with_deadline(&self.lua, || self.lua.load(code).exec())?;
         Ok(())
     }
 
+    /// evaluate a lua string for the repl, returning its results as text
+    ///
+    /// code that parses as an expression is evaluated as one, so `1 + 1` gives
+    /// `2` like in the stock interpreter
+    pub fn eval(&self, code: &str) -> Result<Vec<String>> {
+        let chunk = match self.lua.load(format!("return {}", code)).into_function() {
+            Ok(chunk) => chunk,
+            Err(_) => self.lua.load(code).into_function()?,
+        };
+        let values: mlua::MultiValue = with_deadline(&self.lua, || chunk.call(()))?;
+        Ok(values.iter().map(Value::to_string).collect::<mlua::Result<_>>()?)
+    }
+
+    /// set the environment variables `cvh.env.get` may read
+    pub fn set_env_allowlist(&self, names: Vec<String>) {
+        self.lua.set_app_data(api::EnvAllowlist(names));
+    }
+
+    /// set the directories `cvh.file` may read metadata in, normally the
+    /// sandbox's `SandboxOptions::allowed_roots`
+    pub fn set_read_roots(&self, roots: Vec<std::path::PathBuf>) {
+        self.lua.set_app_data(api::ReadRoots(roots));
+    }
+
+    /// set what `cvh.open`/`cvh.spawn` may request
+    pub fn set_action_policy(&self, policy: crate::sandbox::actions::ActionPolicy) {
+        self.lua.set_app_data(policy);
+    }
+
+    /// set the font `cvh.text.measure` uses, to match the renderer's
+    pub fn set_text_font(&self, font: Option<std::sync::Arc<fontdue::Font>>) {
+        self.lua.set_app_data(api::TextFont(font));
+    }
+
+    /// set where `cvh.state` keeps the script's state
+    pub fn set_state_store(&self, store: api::StateStore) {
+        self.lua.set_app_data(store);
+    }
+
+    /// take the accepted open/spawn requests, oldest first
+    pub fn take_actions(&self) -> Vec<crate::ipc::EventAction> {
+        self.lua
+            .app_data_mut::<api::PendingActions>()
+            .map(|mut pending| std::mem::take(&mut pending.0))
+            .unwrap_or_default()
+    }
+
     /// get a reference to lua state
     pub fn lua(&self) -> &Lua {
         &self.lua
     }
 }
 
+/// run `f` with the time budget from the runtime limits armed
+///
+/// nested entries keep the earlier of the two deadlines
+fn with_deadline<R>(lua: &Lua, f: impl FnOnce() -> R) -> R {
+    let timeout = lua.app_data_ref::<LuaLimits>().map(|limits| limits.timeout);
+    let outer = lua.app_data_ref::<Deadline>().and_then(|deadline| deadline.0);
+
+    let armed = timeout.map(|t| Instant::now() + t);
+    let deadline = match (outer, armed) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    };
+
+    lua.set_app_data(Deadline(deadline));
+    let result = f();
+    lua.set_app_data(Deadline(outer));
+    result
+}
+
 /// represents a loaded icon script
 #[allow(dead_code)]
 pub struct IconScript<'lua> {
@@ -183,7 +353,7 @@ impl<'lua> IconScript<'lua> {
     /// call the init method (iff exists)
     pub fn call_init(&self) -> Result<()> {
         if let Ok(init_fn) = self.icon_table.get::<Function>("init") {
-            init_fn.call::<()>(self.icon_table.clone())?;
+            with_deadline(self.lua, || init_fn.call::<()>(self.icon_table.clone()))?;
         }
         Ok(())
     }
@@ -203,7 +373,9 @@ impl<'lua> IconScript<'lua> {
         let canvas_userdata = self.lua.create_userdata(canvas)?;
 
         // Call render(self, canvas) - Lua method call convention
-        render_fn.call::<()>((self.icon_table.clone(), canvas_userdata.clone()))?;
+        with_deadline(self.lua, || {
+            render_fn.call::<()>((self.icon_table.clone(), canvas_userdata.clone()))
+        })?;
 
         // Extract commands from the canvas UserData
         let canvas_ref = canvas_userdata.borrow::<api::Canvas>()?;
@@ -213,7 +385,8 @@ impl<'lua> IconScript<'lua> {
     /// call the on_click handler and return optional action
     pub fn call_on_click(&self, button: u32, x: f64, y: f64) -> Result<Option<String>> {
         if let Ok(handler) = self.icon_table.get::<Function>("on_click") {
-            let result: Value = handler.call((self.icon_table.clone(), button, x, y))?;
+            let result: Value =
+                with_deadline(self.lua, || handler.call((self.icon_table.clone(), button, x, y)))?;
             if let Value::String(s) = result {
                 return Ok(Some(s.to_str()?.to_string()));
             }
@@ -224,7 +397,7 @@ impl<'lua> IconScript<'lua> {
     /// call the on_hover handler
     pub fn call_on_hover(&self, entered: bool) -> Result<()> {
         if let Ok(handler) = self.icon_table.get::<Function>("on_hover") {
-            handler.call::<()>((self.icon_table.clone(), entered))?;
+            with_deadline(self.lua, || handler.call::<()>((self.icon_table.clone(), entered)))?;
         }
         Ok(())
     }
@@ -236,7 +409,8 @@ impl<'lua> IconScript<'lua> {
             for (i, path) in paths.iter().enumerate() {
                 paths_table.set(i + 1, path.as_str())?;
             }
-            let result: Value = handler.call((self.icon_table.clone(), paths_table))?;
+            let result: Value =
+                with_deadline(self.lua, || handler.call((self.icon_table.clone(), paths_table)))?;
             if let Value::String(s) = result {
                 return Ok(Some(s.to_str()?.to_string()));
             }
@@ -270,7 +444,8 @@ impl<'lua> IconScript<'lua> {
                 input.set("cell_height", ch)?;
             }
 
-            let result: Table = pos_fn.call((self.icon_table.clone(), input))?;
+            let result: Table =
+                with_deadline(self.lua, || pos_fn.call((self.icon_table.clone(), input)))?;
             let x: i32 = result.get("x").unwrap_or(0);
             let y: i32 = result.get("y").unwrap_or(0);
             return Ok((x, y));
@@ -509,6 +684,71 @@ mod tests {
         assert!(result.is_ok(), "string.match should accept patterns of exactly 1,000 chars");
     }
 
+    #[test]
+    fn test_string_gmatch_iterates_matches() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            words = {}
+            for w in string.gmatch("one two three", "%a+") do
+                words[#words + 1] = w
+            end
+            pairs_found = 0
+            for k, v in string.gmatch("a=1, b=2", "(%w+)=(%w+)") do
+                pairs_found = pairs_found + 1
+                last = k .. v
+            end
+        "#).unwrap();
+
+        let words: Table = rt.lua().globals().get("words").unwrap();
+        assert_eq!(words.len().unwrap(), 3, "gmatch should yield 3 words");
+        assert_eq!(words.get::<String>(3).unwrap(), "three");
+
+        let pairs_found: i64 = rt.lua().globals().get("pairs_found").unwrap();
+        let last: String = rt.lua().globals().get("last").unwrap();
+        assert_eq!(pairs_found, 2, "gmatch should yield 2 capture pairs");
+        assert_eq!(last, "b2", "gmatch should return all captures");
+    }
+
+    #[test]
+    fn test_string_gmatch_allows_exactly_max_iterations() {
+        let rt = create_test_runtime();
+        rt.lua().globals().set("test_str", "a".repeat(10_000)).unwrap();
+
+        let result = rt.exec("n = 0 for _ in string.gmatch(test_str, 'a') do n = n + 1 end");
+        assert!(result.is_ok(), "gmatch should allow 10,000 matches");
+        let n: i64 = rt.lua().globals().get("n").unwrap();
+        assert_eq!(n, 10_000);
+    }
+
+    #[test]
+    fn test_string_gmatch_hits_iteration_cap() {
+        let rt = create_test_runtime();
+        rt.lua().globals().set("test_str", "a".repeat(10_000)).unwrap();
+
+        // An empty pattern matches at every position, one more than the cap
+        let result = rt.exec("for _ in string.gmatch(test_str, '') do end");
+        assert!(result.is_err(), "gmatch should stop after 10,000 matches");
+
+        let err_msg = result.unwrap_err().to_string();
+        assert!(
+            err_msg.contains("gmatch iteration limit exceeded"),
+            "Error should mention 'gmatch iteration limit exceeded', got: {}", err_msg
+        );
+    }
+
+    #[test]
+    fn test_string_gmatch_rejects_oversized_input() {
+        let rt = create_test_runtime();
+        rt.lua().globals().set("test_str", "a".repeat(10_001)).unwrap();
+        rt.lua().globals().set("test_pattern", "a".repeat(1_001)).unwrap();
+
+        let err_msg = rt.exec("string.gmatch(test_str, 'a')").unwrap_err().to_string();
+        assert!(err_msg.contains("input string too large"), "got: {}", err_msg);
+
+        let err_msg = rt.exec("string.gmatch('hello', test_pattern)").unwrap_err().to_string();
+        assert!(err_msg.contains("pattern too large"), "got: {}", err_msg);
+    }
+
     // ========================================================================
     // Safe String Functions Tests
     // ========================================================================
@@ -610,6 +850,46 @@ mod tests {
         assert!(matches!(result, Value::Function(_)), "pairs should be a function");
     }
 
+    #[test]
+    fn test_pairs_visits_every_key_once() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            t = {}
+            for i = 1, 500 do t[i] = i end
+            for i = 1, 500 do t["k" .. i] = i end
+
+            seen = {}
+            visits = 0
+            for k, v in pairs(t) do
+                visits = visits + 1
+                seen[k] = (seen[k] or 0) + 1
+            end
+
+            duplicates = 0
+            distinct = 0
+            for k, n in pairs(seen) do
+                distinct = distinct + 1
+                if n ~= 1 then duplicates = duplicates + 1 end
+            end
+        "#).unwrap();
+
+        let globals = rt.lua().globals();
+        assert_eq!(globals.get::<i64>("visits").unwrap(), 1000, "pairs should visit 1000 entries");
+        assert_eq!(globals.get::<i64>("distinct").unwrap(), 1000, "every key should be visited");
+        assert_eq!(globals.get::<i64>("duplicates").unwrap(), 0, "no key should be visited twice");
+    }
+
+    #[test]
+    fn test_pairs_handles_false_values() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            count = 0
+            for k, v in pairs({ a = false, b = true }) do count = count + 1 end
+        "#).unwrap();
+        let count: i64 = rt.lua().globals().get("count").unwrap();
+        assert_eq!(count, 2, "pairs should not stop at false values");
+    }
+
     #[test]
     fn test_ipairs_is_available() {
         let rt = create_test_runtime();
@@ -650,6 +930,97 @@ mod tests {
         assert_eq!(result, 3.0, "math.floor(3.7) should return 3");
     }
 
+    #[test]
+    fn test_math_extended_functions() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            int_part, frac_part = math.modf(3.75)
+            neg_int, neg_frac = math.modf(-2.5)
+            log_e = math.log(math.exp(1))
+            log_2 = math.log(8, 2)
+            log_10 = math.log(1000, 10)
+            pow = math.pow(2, 10)
+            fmod = math.fmod(7, 3)
+            fmod_neg = math.fmod(-7, 3)
+            atan = math.atan(1)
+            atan2 = math.atan(1, -1)
+            asin = math.asin(1)
+            acos = math.acos(1)
+            huge_is_inf = math.huge > 1e308 and -math.huge < -1e308
+            max_int = math.maxinteger
+            min_int = math.mininteger
+        "#).unwrap();
+
+        let g = rt.lua().globals();
+        let f = |name: &str| g.get::<f64>(name).unwrap();
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+        assert_eq!((f("int_part"), f("frac_part")), (3.0, 0.75), "math.modf(3.75)");
+        assert_eq!((f("neg_int"), f("neg_frac")), (-2.0, -0.5), "math.modf(-2.5)");
+        assert!(close(f("log_e"), 1.0), "math.log(math.exp(1)) should be ~1");
+        assert!(close(f("log_2"), 3.0), "math.log(8, 2) should be 3");
+        assert!(close(f("log_10"), 3.0), "math.log(1000, 10) should be 3");
+        assert_eq!(f("pow"), 1024.0, "math.pow(2, 10)");
+        assert_eq!(f("fmod"), 1.0, "math.fmod(7, 3)");
+        assert_eq!(f("fmod_neg"), -1.0, "math.fmod keeps the dividend's sign");
+        assert!(close(f("atan"), std::f64::consts::FRAC_PI_4), "math.atan(1)");
+        assert!(close(f("atan2"), 3.0 * std::f64::consts::FRAC_PI_4), "math.atan(1, -1)");
+        assert!(close(f("asin"), std::f64::consts::FRAC_PI_2), "math.asin(1)");
+        assert!(close(f("acos"), 0.0), "math.acos(1)");
+        assert!(g.get::<bool>("huge_is_inf").unwrap(), "math.huge should be infinite");
+        assert_eq!(g.get::<i64>("max_int").unwrap(), i64::MAX);
+        assert_eq!(g.get::<i64>("min_int").unwrap(), i64::MIN);
+    }
+
+    #[test]
+    fn test_math_randomseed_is_deterministic() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            function sample()
+                local out = {}
+                for i = 1, 8 do out[i] = math.random() end
+                return out
+            end
+            math.randomseed(42)
+            first = sample()
+            math.randomseed(42)
+            second = sample()
+        "#).unwrap();
+
+        let first: Vec<f64> = rt.lua().globals().get("first").unwrap();
+        let second: Vec<f64> = rt.lua().globals().get("second").unwrap();
+        assert_eq!(first, second, "same seed should give the same sequence");
+        assert!(first.iter().all(|x| (0.0..1.0).contains(x)), "random() should be in [0, 1)");
+
+        let mut distinct = first.clone();
+        distinct.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        distinct.dedup();
+        assert_eq!(distinct.len(), first.len(), "sequence should not repeat values");
+    }
+
+    #[test]
+    fn test_math_random_ranges() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            math.randomseed(7)
+            ok = true
+            for i = 1, 1000 do
+                local a = math.random(6)
+                local b = math.random(-3, 3)
+                if a < 1 or a > 6 or b < -3 or b > 3 then ok = false end
+            end
+            single = math.random(5, 5)
+        "#).unwrap();
+
+        let ok: bool = rt.lua().globals().get("ok").unwrap();
+        let single: i64 = rt.lua().globals().get("single").unwrap();
+        assert!(ok, "math.random(m) and math.random(m, n) should stay in range");
+        assert_eq!(single, 5);
+
+        let result = rt.exec("math.random(5, 1)");
+        assert!(result.unwrap_err().to_string().contains("interval is empty"));
+    }
+
     #[test]
     fn test_table_library_available() {
         let rt = create_test_runtime();
@@ -664,10 +1035,6 @@ mod tests {
 
     #[test]
     fn test_table_insert_works() {
-        // NOTE: The current stdlib implementation requires 3 arguments for table.insert
-        // (table, position, value). The standard Lua 2-argument form table.insert(t, value)
-        // does NOT work correctly - this is a known bug in stdlib.rs:132-149.
-        // Using the 3-argument form as a workaround.
         let rt = create_test_runtime();
         rt.exec(r#"
             t = {}
@@ -688,21 +1055,154 @@ mod tests {
     }
 
     #[test]
-    fn test_table_insert_two_arg_form_is_broken() {
-        // BUG DOCUMENTATION: The 2-argument form of table.insert(t, value)
-        // does not work as expected in the current stdlib implementation.
-        // This test documents the current (buggy) behavior.
+    fn test_table_insert_two_arg_form_appends() {
         let rt = create_test_runtime();
-
-        // This should work in standard Lua but fails here
-        let result = rt.exec(r#"
+        rt.exec(r#"
             t = {}
             table.insert(t, "a")
-        "#);
+            table.insert(t, "b")
+            test_result = table.concat(t, ",")
+        "#).unwrap();
+
+        let result: String = rt.lua().globals().get("test_result").unwrap();
+        assert_eq!(result, "a,b", "table.insert(t, value) should append");
+    }
+
+    #[test]
+    fn test_table_insert_mixed_forms() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            t = {}
+            table.insert(t, "b")
+            table.insert(t, 1, "a")
+            table.insert(t, "d")
+            table.insert(t, 3, "c")
+            table.insert(t, #t + 1, "e")
+            test_result = table.concat(t, ",")
+        "#).unwrap();
+
+        let result: String = rt.lua().globals().get("test_result").unwrap();
+        assert_eq!(result, "a,b,c,d,e", "positional and appending inserts should mix");
+    }
+
+    #[test]
+    fn test_table_insert_rejects_bad_position() {
+        let rt = create_test_runtime();
+        let result = rt.exec("t = {1, 2} table.insert(t, 5, 'x')");
+        let err_msg = result.unwrap_err().to_string();
+        assert!(err_msg.contains("position out of bounds"), "got: {}", err_msg);
 
-        // Current behavior: the 2-argument form fails
-        assert!(result.is_err(),
-            "BUG: table.insert(t, value) two-argument form does not work correctly");
+        let result = rt.exec("table.insert({}, 1, 2, 3)");
+        let err_msg = result.unwrap_err().to_string();
+        assert!(err_msg.contains("wrong number of arguments"), "got: {}", err_msg);
+    }
+
+    #[test]
+    fn test_table_sort_numbers_and_strings() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            nums = {5, 2.5, 9, -1, 3}
+            table.sort(nums)
+            words = {"pear", "apple", "fig", "banana"}
+            table.sort(words)
+            nums_result = table.concat(nums, ",")
+            words_result = table.concat(words, ",")
+        "#).unwrap();
+
+        let nums: String = rt.lua().globals().get("nums_result").unwrap();
+        let words: String = rt.lua().globals().get("words_result").unwrap();
+        assert_eq!(nums, "-1,2.5,3,5,9");
+        assert_eq!(words, "apple,banana,fig,pear");
+    }
+
+    #[test]
+    fn test_table_sort_custom_comparator() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            t = {3, 1, 4, 1, 5, 9, 2, 6}
+            table.sort(t, function(a, b) return a > b end)
+            result = table.concat(t, ",")
+
+            people = {{name = "b", age = 30}, {name = "a", age = 20}, {name = "c", age = 30}}
+            table.sort(people, function(x, y) return x.age > y.age end)
+            order = people[1].name .. people[2].name .. people[3].name
+        "#).unwrap();
+
+        let result: String = rt.lua().globals().get("result").unwrap();
+        let order: String = rt.lua().globals().get("order").unwrap();
+        assert_eq!(result, "9,6,5,4,3,2,1,1", "comparator should sort descending");
+        assert_eq!(order, "bca", "sort should be stable for equal keys");
+    }
+
+    #[test]
+    fn test_table_sort_limits_and_errors() {
+        let rt = create_test_runtime();
+        let result = rt.exec("t = {} for i = 1, 10001 do t[i] = i end table.sort(t)");
+        assert!(result.unwrap_err().to_string().contains("table too large to sort"));
+
+        let result = rt.exec("table.sort({1, 'a'})");
+        assert!(result.unwrap_err().to_string().contains("attempt to compare"));
+
+        // An inconsistent comparator must not crash the host
+        rt.exec("t = {5, 3, 8, 1, 9, 2} table.sort(t, function() return true end)").unwrap();
+    }
+
+    #[test]
+    fn test_table_unpack_and_select() {
+        let rt = create_test_runtime();
+        rt.exec(r##"
+            a, b, c = table.unpack({1, 2, 3})
+            x, y = unpack({"p", "q", "r"}, 2)
+            count = select("#", 1, nil, 3)
+            second = select(2, "a", "b", "c")
+            last = select(-1, "a", "b", "c")
+            function sum(...)
+                local total = 0
+                for i = 1, select("#", ...) do total = total + select(i, ...) end
+                return total
+            end
+            total = sum(table.unpack({1, 2, 3, 4}))
+        "##).unwrap();
+
+        let g = rt.lua().globals();
+        assert_eq!(g.get::<i64>("a").unwrap() + g.get::<i64>("b").unwrap() + g.get::<i64>("c").unwrap(), 6);
+        assert_eq!(g.get::<String>("x").unwrap(), "q");
+        assert_eq!(g.get::<String>("y").unwrap(), "r");
+        assert_eq!(g.get::<i64>("count").unwrap(), 3);
+        assert_eq!(g.get::<String>("second").unwrap(), "b");
+        assert_eq!(g.get::<String>("last").unwrap(), "c");
+        assert_eq!(g.get::<i64>("total").unwrap(), 10);
+
+        let result = rt.exec("table.unpack({}, 1, 1e9)");
+        assert!(result.unwrap_err().to_string().contains("too many results"));
+
+        // Extreme bounds must not overflow
+        let result = rt.exec("table.unpack({}, math.mininteger, 0)");
+        assert!(result.unwrap_err().to_string().contains("too many results"));
+        let result = rt.exec("select(math.mininteger, 'a')");
+        assert!(result.unwrap_err().to_string().contains("index out of range"));
+    }
+
+    #[test]
+    fn test_next_matches_pairs() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            t = {a = 1, b = 2, c = 3}
+            iter = pairs(t)
+            same_next = iter == next
+            empty = next({}) == nil
+            count = 0
+            local k = next(t)
+            while k ~= nil do
+                count = count + 1
+                k = next(t, k)
+            end
+        "#).unwrap();
+
+        let g = rt.lua().globals();
+        assert!(g.get::<bool>("same_next").unwrap(), "pairs should iterate with the global next");
+        assert!(g.get::<bool>("empty").unwrap(), "next on an empty table should be nil");
+        assert_eq!(g.get::<i64>("count").unwrap(), 3);
     }
 
     #[test]
@@ -770,6 +1270,78 @@ mod tests {
         assert!(matches!(is_file, Value::Function(_)), "cvh.file.is_file should be a function");
     }
 
+    #[test]
+    fn test_cvh_file_join() {
+        let rt = create_test_runtime();
+        let join = |code: &str| rt.lua().load(code).eval::<String>().unwrap();
+        assert_eq!(join("return cvh.file.join('a', 'b', 'c.txt')"), "a/b/c.txt");
+        assert_eq!(join("return cvh.file.join('/home/u/', 'Desktop')"), "/home/u/Desktop");
+        assert_eq!(join("return cvh.file.join('a', '/etc', 'passwd')"), "/etc/passwd");
+        assert_eq!(join("return cvh.file.join()"), "");
+    }
+
+    #[test]
+    fn test_cvh_file_normalize() {
+        let rt = create_test_runtime();
+        let normalize = |path: &str| {
+            rt.lua().globals().set("path", path).unwrap();
+            rt.lua().load("return cvh.file.normalize(path)").eval::<String>().unwrap()
+        };
+        assert_eq!(normalize("a/b/../c"), "a/c");
+        assert_eq!(normalize("/home/./u//Desktop/"), "/home/u/Desktop");
+        assert_eq!(normalize("/../etc"), "/etc");
+        assert_eq!(normalize("../a/.."), "..");
+        assert_eq!(normalize("a/.."), ".");
+    }
+
+    #[test]
+    fn test_cvh_file_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        std::fs::write(&path, "twelve bytes").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        let rt = create_test_runtime();
+        rt.set_read_roots(vec![dir.path().canonicalize().unwrap()]);
+        rt.lua().globals().set("path", path.to_str().unwrap()).unwrap();
+        rt.exec("size, mode, mtime = cvh.file.size(path), cvh.file.mode(path), cvh.file.mtime(path)").unwrap();
+
+        let globals = rt.lua().globals();
+        assert_eq!(globals.get::<u64>("size").unwrap(), 12);
+        assert_eq!(globals.get::<u32>("mode").unwrap(), 0o640);
+        let mtime = std::fs::metadata(&path).unwrap().modified().unwrap();
+        let mtime = mtime.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() as i64;
+        assert_eq!(globals.get::<i64>("mtime").unwrap(), mtime);
+    }
+
+    #[test]
+    fn test_cvh_file_metadata_denied_paths_are_nil() {
+        let allowed = tempfile::tempdir().unwrap();
+        let denied = tempfile::tempdir().unwrap();
+        let secret = denied.path().join("secret.txt");
+        std::fs::write(&secret, "secret").unwrap();
+        // A link from an allowed directory doesn't help
+        let link = allowed.path().join("link.txt");
+        std::os::unix::fs::symlink(&secret, &link).unwrap();
+
+        let rt = create_test_runtime();
+        rt.set_read_roots(vec![allowed.path().canonicalize().unwrap()]);
+        let missing = allowed.path().join("missing.txt");
+        for path in [&secret, &link, &missing] {
+            rt.lua().globals().set("path", path.to_str().unwrap()).unwrap();
+            let results: Vec<Value> = rt
+                .lua()
+                .load("return cvh.file.size(path), cvh.file.mode(path), cvh.file.mtime(path)")
+                .eval::<mlua::MultiValue>()
+                .unwrap()
+                .into_iter()
+                .collect();
+            assert!(results.iter().all(Value::is_nil), "{} should be denied", path.display());
+        }
+    }
+
     #[test]
     fn test_cvh_time_now_available() {
         let rt = create_test_runtime();
@@ -806,6 +1378,59 @@ mod tests {
         assert!(matches!(format, Value::Function(_)), "cvh.time.format should be a function");
     }
 
+    #[test]
+    fn test_cvh_time_date_fields() {
+        let rt = create_test_runtime();
+        let date = |secs: i64| -> Vec<i64> {
+            let table: Table = rt.lua().load(format!("return cvh.time.date({})", secs)).eval().unwrap();
+            ["year", "month", "day", "hour", "min", "sec", "wday", "yday"]
+                .iter()
+                .map(|field| table.get(*field).unwrap())
+                .collect()
+        };
+        let at_offset = |hours: i32| {
+            let offset = chrono::FixedOffset::east_opt(hours * 3600).unwrap();
+            rt.lua().set_app_data(api::TimeOffset(offset));
+        };
+
+        at_offset(0);
+        // 2023-11-14 22:13:20 UTC, a Tuesday
+        assert_eq!(date(1_700_000_000), [2023, 11, 14, 22, 13, 20, 3, 318]);
+        // A leap day
+        assert_eq!(date(951_782_400), [2000, 2, 29, 0, 0, 0, 3, 60]);
+        assert_eq!(date(0), [1970, 1, 1, 0, 0, 0, 5, 1]);
+        // Before the epoch, a Wednesday
+        assert_eq!(date(-1), [1969, 12, 31, 23, 59, 59, 4, 365]);
+
+        // Five hours behind UTC the epoch is still New Year's Eve
+        at_offset(-5);
+        assert_eq!(date(0), [1969, 12, 31, 19, 0, 0, 4, 365]);
+
+        let now: Table = rt.lua().load("return cvh.time.date()").eval().unwrap();
+        assert!(now.get::<i64>("year").unwrap() >= 2024);
+    }
+
+    #[test]
+    fn test_cvh_time_format_patterns() {
+        let rt = create_test_runtime();
+        let format = |fmt: &str| {
+            rt.lua().globals().set("fmt", fmt).unwrap();
+            rt.lua().load("return cvh.time.format(fmt, 1700000000)").eval::<String>().unwrap()
+        };
+        rt.lua().set_app_data(api::TimeOffset(chrono::FixedOffset::east_opt(0).unwrap()));
+        assert_eq!(format("%H:%M:%S"), "22:13:20");
+        assert_eq!(format("%H:%M"), "22:13");
+        assert_eq!(format("%Y-%m-%d"), "2023-11-14");
+        assert_eq!(format("%A %e %B"), "Tuesday 14 November");
+        assert_eq!(format("%a %b %y, day %j"), "Tue Nov 23, day 318");
+        assert_eq!(format("%F %T %p"), "2023-11-14 22:13:20 PM");
+        assert_eq!(format("%s 100%% %q"), "1700000000 100% %q");
+
+        // Two hours ahead it is already the next day
+        rt.lua().set_app_data(api::TimeOffset(chrono::FixedOffset::east_opt(7200).unwrap()));
+        assert_eq!(format("%F %T %A"), "2023-11-15 00:13:20 Wednesday");
+    }
+
     #[test]
     fn test_cvh_system_hostname_available() {
         let rt = create_test_runtime();
@@ -847,6 +1472,508 @@ mod tests {
         assert!(matches!(notify, Value::Function(_)), "cvh.notify should be a function");
     }
 
+    #[test]
+    fn test_cvh_spawn_rejects_disallowed_command() {
+        let rt = create_test_runtime();
+        rt.exec("test_result = cvh.spawn('rm -rf /')").unwrap();
+        let accepted: bool = rt.lua().globals().get("test_result").unwrap();
+        assert!(!accepted);
+        assert!(rt.take_actions().is_empty(), "rejected actions are not queued");
+    }
+
+    #[test]
+    fn test_cvh_spawn_accepts_xdg_open() {
+        let rt = create_test_runtime();
+        rt.exec("test_result = cvh.spawn('xdg-open /tmp/notes.txt')").unwrap();
+        let accepted: bool = rt.lua().globals().get("test_result").unwrap();
+        assert!(accepted);
+        assert_eq!(
+            rt.take_actions(),
+            vec![crate::ipc::EventAction {
+                action: "spawn".to_string(),
+                payload: Some("xdg-open /tmp/notes.txt".to_string()),
+            }]
+        );
+        assert!(rt.take_actions().is_empty(), "actions are taken once");
+    }
+
+    #[test]
+    fn test_cvh_open_queues_action() {
+        let rt = create_test_runtime();
+        rt.exec("a = cvh.open('/tmp/notes.txt') b = cvh.open('relative.txt')").unwrap();
+        let globals = rt.lua().globals();
+        assert!(globals.get::<bool>("a").unwrap());
+        assert!(!globals.get::<bool>("b").unwrap());
+        assert_eq!(
+            rt.take_actions(),
+            vec![crate::ipc::EventAction {
+                action: "open".to_string(),
+                payload: Some("/tmp/notes.txt".to_string()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_cvh_notify_queues_structured_action() {
+        use crate::sandbox::notify::{Notification, Urgency};
+
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            a = cvh.notify{ title = "Backup", body = "Finished", urgency = "critical", icon = "drive-harddisk" }
+            b = cvh.notify("Hello", "from a widget")
+        "#).unwrap();
+        assert!(rt.lua().globals().get::<bool>("a").unwrap());
+
+        let notifications: Vec<Notification> = rt
+            .take_actions()
+            .into_iter()
+            .map(|action| {
+                assert_eq!(action.action, "notify");
+                Notification::from_payload(&action.payload.unwrap())
+            })
+            .collect();
+        assert_eq!(
+            notifications,
+            [
+                Notification {
+                    title: "Backup".to_string(),
+                    body: "Finished".to_string(),
+                    urgency: Urgency::Critical,
+                    icon: Some("drive-harddisk".to_string()),
+                },
+                Notification {
+                    title: "Hello".to_string(),
+                    body: "from a widget".to_string(),
+                    urgency: Urgency::Normal,
+                    icon: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cvh_notify_rejects_bad_arguments() {
+        let rt = create_test_runtime();
+        let err = rt.exec(r#"cvh.notify{ title = "x", urgency = "urgent" }"#).unwrap_err();
+        assert!(format!("{:#}", err).contains("unknown notification urgency"), "got: {:#}", err);
+
+        let err = rt.exec(r#"cvh.notify{ body = "no title" }"#).unwrap_err();
+        assert!(format!("{:#}", err).contains("needs a title"), "got: {:#}", err);
+
+        assert!(rt.take_actions().is_empty());
+    }
+
+    #[test]
+    fn test_runtime_for_config_applies_sandbox_settings() {
+        let mut config = crate::config::Config::default();
+        config.sandbox.allowed_commands = vec!["foot".to_string()];
+        config.sandbox.env_allowlist = vec!["CVH_TEST_FOR_CONFIG".to_string()];
+        std::env::set_var("CVH_TEST_FOR_CONFIG", "yes");
+
+        let rt = LuaRuntime::for_config(&config).unwrap();
+        rt.exec("a = cvh.spawn('foot') b = cvh.spawn('xdg-open /tmp') c = cvh.env.get('CVH_TEST_FOR_CONFIG')").unwrap();
+        let globals = rt.lua().globals();
+        assert!(globals.get::<bool>("a").unwrap());
+        assert!(!globals.get::<bool>("b").unwrap());
+        assert_eq!(globals.get::<String>("c").unwrap(), "yes");
+    }
+
+    #[test]
+    fn test_runtime_for_config_reads_in_configured_roots() {
+        let allowed = tempfile::tempdir().unwrap();
+        let other = tempfile::tempdir().unwrap();
+        std::fs::write(allowed.path().join("notes.txt"), "hello").unwrap();
+        std::fs::write(other.path().join("notes.txt"), "hello").unwrap();
+        let mut config = crate::config::Config::default();
+        config.sandbox.read_only_paths = vec![allowed.path().to_path_buf()];
+
+        let rt = LuaRuntime::for_config(&config).unwrap();
+        let globals = rt.lua().globals();
+        globals.set("allowed", allowed.path().join("notes.txt").to_str().unwrap()).unwrap();
+        globals.set("other", other.path().join("notes.txt").to_str().unwrap()).unwrap();
+        rt.exec("a = cvh.file.size(allowed) b = cvh.file.size(other)").unwrap();
+        assert_eq!(globals.get::<u64>("a").unwrap(), 5);
+        assert!(globals.get::<Value>("b").unwrap().is_nil());
+    }
+
+    #[test]
+    fn test_runtime_for_config_measures_with_configured_font() {
+        let config = crate::config::Config {
+            font_path: Some(std::path::PathBuf::from("/nonexistent/font.ttf")),
+            ..Default::default()
+        };
+        let rt = LuaRuntime::for_config(&config).unwrap();
+        let font = rt.lua().app_data_ref::<api::TextFont>().unwrap().0.clone();
+        match (font, crate::renderer::configured_font(&config)) {
+            (Some(a), Some(b)) => assert!(std::sync::Arc::ptr_eq(&a, &b), "should use the renderer's font"),
+            (None, None) => {} // No font available
+            _ => panic!("runtime and renderer disagree on the font"),
+        }
+    }
+
+    #[test]
+    fn test_runtime_for_config_keeps_state_in_memory() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut config = crate::config::Config::default();
+        config.sandbox.read_write_paths = vec![dir.path().to_path_buf()];
+        config.sandbox.state_dir = Some(dir.path().to_path_buf());
+        let script = dir.path().join("toggle.lua");
+        std::fs::write(&script, r#"
+            Icon = {}
+            cvh.state.set("on", not cvh.state.get("on"))
+        "#).unwrap();
+
+        let rt = LuaRuntime::for_config(&config).unwrap();
+        rt.load_script(&script).unwrap();
+        rt.exec(r#"on = cvh.state.get("on")"#).unwrap();
+        assert!(rt.lua().globals().get::<bool>("on").unwrap());
+
+        // Validating a script must not write anything
+        let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().file_name()).collect();
+        assert_eq!(files, ["toggle.lua"]);
+    }
+
+    #[test]
+    fn test_cvh_spawn_follows_configured_policy() {
+        let rt = create_test_runtime();
+        rt.set_action_policy(crate::sandbox::actions::ActionPolicy {
+            allowed_commands: vec!["foot".to_string()],
+            ..Default::default()
+        });
+        rt.exec("a = cvh.spawn('foot') b = cvh.spawn('xdg-open /tmp')").unwrap();
+        let globals = rt.lua().globals();
+        assert!(globals.get::<bool>("a").unwrap());
+        assert!(!globals.get::<bool>("b").unwrap());
+    }
+
+    #[test]
+    fn test_cvh_log_levels_available() {
+        let rt = create_test_runtime();
+        let cvh: Table = rt.lua().globals().get("cvh").unwrap();
+        let log: Table = cvh.get("log").unwrap();
+        for level in ["debug", "info", "warn", "error"] {
+            let func: Value = log.get(level).unwrap();
+            assert!(matches!(func, Value::Function(_)), "cvh.log.{} should be a function", level);
+        }
+    }
+
+    #[test]
+    fn test_cvh_log_accepts_strings() {
+        let rt = create_test_runtime();
+        rt.exec("cvh.log.debug('d') cvh.log.info('i') cvh.log.warn('w', 'more') cvh.log.error('e')")
+            .unwrap();
+        rt.exec("print('still works')").unwrap();
+    }
+
+    #[test]
+    fn test_cvh_log_uses_loaded_script_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clock.lua");
+        std::fs::write(&path, "Icon = {}\ncvh.log.info('loaded')\n").unwrap();
+
+        let rt = create_test_runtime();
+        rt.load_script(&path).unwrap();
+        let name = rt.lua().app_data_ref::<api::ScriptName>().map(|name| name.0.clone());
+        assert_eq!(name.as_deref(), Some("clock.lua"));
+    }
+
+    #[test]
+    fn test_cvh_env_get_reads_allowlisted_var() {
+        std::env::set_var("CVH_TEST_ENV_THEME", "nord");
+        let rt = create_test_runtime();
+        rt.set_env_allowlist(vec!["CVH_TEST_ENV_THEME".to_string()]);
+
+        rt.exec("test_result = cvh.env.get('CVH_TEST_ENV_THEME')").unwrap();
+        let result: String = rt.lua().globals().get("test_result").unwrap();
+        assert_eq!(result, "nord");
+    }
+
+    #[test]
+    fn test_cvh_env_get_hides_other_vars() {
+        std::env::set_var("CVH_TEST_ENV_TOKEN", "secret");
+        let rt = create_test_runtime();
+
+        rt.exec("test_result = cvh.env.get('CVH_TEST_ENV_TOKEN')").unwrap();
+        let result: Value = rt.lua().globals().get("test_result").unwrap();
+        assert!(result.is_nil(), "non-allowlisted vars should read as nil");
+
+        rt.exec("test_result = cvh.env.get('PATH')").unwrap();
+        let result: Value = rt.lua().globals().get("test_result").unwrap();
+        assert!(result.is_nil(), "PATH is not in the default allowlist");
+    }
+
+    #[test]
+    fn test_cvh_env_get_unset_allowlisted_var_is_nil() {
+        let rt = create_test_runtime();
+        rt.set_env_allowlist(vec!["CVH_TEST_ENV_UNSET".to_string()]);
+        rt.exec("test_result = cvh.env.get('CVH_TEST_ENV_UNSET')").unwrap();
+        let result: Value = rt.lua().globals().get("test_result").unwrap();
+        assert!(result.is_nil());
+    }
+
+    // ========================================================================
+    // Execution Limit Tests
+    // ========================================================================
+
+    fn create_limited_runtime(timeout_ms: u64) -> LuaRuntime {
+        LuaRuntime::with_limits(LuaLimits {
+            timeout: Duration::from_millis(timeout_ms),
+            ..LuaLimits::default()
+        })
+        .expect("Failed to create Lua runtime")
+    }
+
+    #[test]
+    fn test_infinite_loop_is_aborted() {
+        let rt = create_limited_runtime(50);
+        let start = Instant::now();
+
+        let result = rt.exec("while true do end");
+        assert!(result.is_err(), "infinite loop should be aborted");
+        assert!(start.elapsed() < Duration::from_secs(5), "abort should happen near the budget");
+
+        let err_msg = format!("{:#}", result.unwrap_err());
+        assert!(
+            err_msg.contains("script exceeded its time budget"),
+            "Error should mention the time budget, got: {}", err_msg
+        );
+    }
+
+    #[test]
+    fn test_budget_resets_between_calls() {
+        let rt = create_limited_runtime(50);
+        assert!(rt.exec("while true do end").is_err());
+
+        // A fresh call gets a fresh budget
+        rt.exec("n = 0 for i = 1, 1000 do n = n + i end").unwrap();
+        let n: i64 = rt.lua().globals().get("n").unwrap();
+        assert_eq!(n, 500_500);
+    }
+
+    #[test]
+    fn test_infinite_loop_in_callback_is_aborted() {
+        let rt = create_limited_runtime(50);
+        rt.exec(r#"
+            Icon = { name = "test" }
+            function Icon:render(canvas)
+                while true do end
+            end
+        "#).unwrap();
+
+        let icon_table: Table = rt.lua().globals().get("Icon").unwrap();
+        let script = IconScript { lua: rt.lua(), icon_table };
+        assert!(script.call_render(64, 64).is_err(), "render loop should be aborted");
+    }
+
+    #[test]
+    fn test_unbounded_allocation_hits_memory_limit() {
+        let rt = LuaRuntime::with_limits(LuaLimits {
+            timeout: Duration::from_secs(30),
+            memory: 2 * 1024 * 1024,
+            ..LuaLimits::default()
+        })
+        .expect("Failed to create Lua runtime");
+
+        let result = rt.exec("local t = {} while true do t[#t + 1] = 1 end");
+        let err = result.expect_err("allocation growth should hit the memory limit");
+        assert!(
+            matches!(err.downcast_ref::<LuaError>(), Some(LuaError::MemoryError(_))),
+            "Error should be a Lua memory error, got: {:#}", err
+        );
+
+        // The runtime stays usable once the table is garbage
+        rt.exec("x = 1 + 1").unwrap();
+    }
+
+    #[test]
+    fn test_default_memory_limit_allows_normal_scripts() {
+        let rt = create_test_runtime();
+        rt.exec("t = {} for i = 1, 10000 do t[i] = i end n = #t").unwrap();
+        let n: i64 = rt.lua().globals().get("n").unwrap();
+        assert_eq!(n, 10_000);
+    }
+
+    #[test]
+    fn test_cvh_json_roundtrip_nested() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            data = {
+                name = "icons",
+                count = 3,
+                ratio = 0.5,
+                enabled = true,
+                tags = {"a", "b", "c"},
+                nested = { list = { {x = 1}, {x = 2} } },
+            }
+            encoded = cvh.json.encode(data)
+            decoded = cvh.json.decode(encoded)
+            ok = decoded.name == "icons" and decoded.count == 3 and decoded.ratio == 0.5
+                and decoded.enabled == true and #decoded.tags == 3 and decoded.tags[3] == "c"
+                and decoded.nested.list[2].x == 2
+            array_json = cvh.json.encode({1, 2, 3})
+            from_text = cvh.json.decode('{"items": [10, 20], "missing": null}')
+        "#).unwrap();
+
+        let g = rt.lua().globals();
+        assert!(g.get::<bool>("ok").unwrap(), "decoded data should match the original");
+        assert_eq!(g.get::<String>("array_json").unwrap(), "[1,2,3]", "sequences encode as arrays");
+
+        let from_text: Table = g.get("from_text").unwrap();
+        let items: Table = from_text.get("items").unwrap();
+        assert_eq!(items.get::<i64>(2).unwrap(), 20);
+        assert!(matches!(from_text.get::<Value>("missing").unwrap(), Value::Nil));
+    }
+
+    #[test]
+    fn test_cvh_json_rejects_abuse() {
+        let rt = LuaRuntime::with_limits(LuaLimits {
+            json: api::JsonLimits { max_depth: 4, max_input_len: 64 },
+            ..LuaLimits::default()
+        })
+        .expect("Failed to create Lua runtime");
+
+        let err = rt.exec(&format!("cvh.json.decode('[{}]')", "0,".repeat(40) + "0")).unwrap_err();
+        assert!(format!("{:#}", err).contains("json input too large"), "got: {:#}", err);
+
+        let err = rt.exec("cvh.json.decode('[[[[[1]]]]]')").unwrap_err();
+        assert!(format!("{:#}", err).contains("json nesting too deep"), "got: {:#}", err);
+
+        let err = rt.exec("t = {} t.self = t cvh.json.encode(t)").unwrap_err();
+        assert!(format!("{:#}", err).contains("json nesting too deep"), "got: {:#}", err);
+
+        let err = rt.exec("cvh.json.decode('{not json')").unwrap_err();
+        assert!(format!("{:#}", err).contains("invalid json"), "got: {:#}", err);
+
+        let err = rt.exec("cvh.json.encode({f = print})").unwrap_err();
+        assert!(format!("{:#}", err).contains("cannot encode function"), "got: {:#}", err);
+    }
+
+    #[test]
+    fn test_cvh_color_helpers() {
+        let rt = create_test_runtime();
+        rt.exec(r#"
+            mid = cvh.color.darken('#ffffff', 0.5)
+            light = cvh.color.lighten('#000', 1)
+            quarter = cvh.color.mix('black', '#ffffff', 0.25)
+            clamped = cvh.color.mix('#000000', '#ffffff', 7)
+            faded = cvh.color.with_alpha('#ff0000', 0.5)
+            kept = cvh.color.darken('#ffffff80', 0.5)
+            opaque = cvh.color.with_alpha('rgba(0, 0, 255, 0.2)', 1)
+            half_alpha = cvh.color.mix('#00000000', '#000000ff', 0.5)
+        "#).unwrap();
+
+        let g = rt.lua().globals();
+        let get = |name: &str| g.get::<String>(name).unwrap();
+        assert_eq!(get("mid"), "#808080");
+        assert_eq!(get("light"), "#ffffff");
+        assert_eq!(get("quarter"), "#404040");
+        assert_eq!(get("clamped"), "#ffffff");
+        assert_eq!(get("faded"), "#ff000080");
+        assert_eq!(get("kept"), "#80808080", "darkening keeps the alpha");
+        assert_eq!(get("opaque"), "#0000ff");
+        assert_eq!(get("half_alpha"), "#00000080");
+        for name in ["mid", "faded", "kept"] {
+            assert!(crate::renderer::parse_color(&get(name)).is_some(), "{} should parse", name);
+        }
+
+        let err = rt.exec("cvh.color.lighten('not a color', 0.1)").unwrap_err();
+        assert!(format!("{:#}", err).contains("invalid color 'not a color'"), "got: {:#}", err);
+    }
+
+    #[test]
+    fn test_cvh_text_measure() {
+        let rt = create_test_runtime();
+        let Some(font) = crate::renderer::shared_default_font() else {
+            return; // No system font available
+        };
+        rt.set_text_font(Some(font));
+        rt.exec(r#"
+            short = cvh.text.measure("Docs", 12)
+            long = cvh.text.measure("Documents and Settings", 12)
+            bigger = cvh.text.measure("Docs", 24)
+            empty = cvh.text.measure("", 12)
+        "#).unwrap();
+
+        let g = rt.lua().globals();
+        let get = |name: &str| g.get::<f32>(name).unwrap();
+        assert!(get("short") > 0.0);
+        assert!(get("long") > get("short"), "longer text should measure wider");
+        assert!(get("bigger") > get("short"), "larger size should measure wider");
+        assert_eq!(get("empty"), 0.0);
+    }
+
+    #[test]
+    fn test_cvh_text_measure_without_font_is_zero() {
+        let rt = create_test_runtime();
+        rt.set_text_font(None);
+        rt.exec("width = cvh.text.measure('Docs', 12)").unwrap();
+        assert_eq!(rt.lua().globals().get::<f32>("width").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_cvh_state_roundtrip_across_runtimes() {
+        let dir = tempfile::tempdir().unwrap();
+        let script = Path::new("/usr/share/cvh-icons/widgets/toggle.lua");
+
+        let rt = create_test_runtime();
+        rt.set_state_store(api::StateStore::open(dir.path(), script, api::StateStore::DEFAULT_MAX_BYTES));
+        rt.exec(r#"
+            cvh.state.set("on", true)
+            cvh.state.set("history", {1, 2, 3})
+            cvh.state.set("window", { x = 10, label = "main" })
+            cvh.state.set("scratch", "gone soon")
+            cvh.state.set("scratch", nil)
+            same_run = cvh.state.get("on") == true and cvh.state.get("history")[3] == 3
+        "#).unwrap();
+        assert!(rt.lua().globals().get::<bool>("same_run").unwrap());
+        assert!(dir.path().join("toggle.lua.state.json").exists());
+
+        // A restarted script sees what the last run saved
+        let rt = create_test_runtime();
+        rt.set_state_store(api::StateStore::open(dir.path(), script, api::StateStore::DEFAULT_MAX_BYTES));
+        rt.exec(r#"
+            window = cvh.state.get("window")
+            restored = cvh.state.get("on") == true and #cvh.state.get("history") == 3
+                and window.x == 10 and window.label == "main"
+                and cvh.state.get("scratch") == nil and cvh.state.get("missing") == nil
+        "#).unwrap();
+        assert!(rt.lua().globals().get::<bool>("restored").unwrap());
+    }
+
+    #[test]
+    fn test_cvh_state_enforces_size_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = api::StateStore::open(dir.path(), Path::new("big.lua"), 64);
+        let path = dir.path().join("big.lua.state.json");
+
+        let rt = create_test_runtime();
+        rt.set_state_store(store);
+        rt.exec(r#"cvh.state.set("small", "ok")"#).unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+
+        let err = rt.exec(r#"cvh.state.set("big", string.rep("x", 100))"#).unwrap_err();
+        assert!(format!("{:#}", err).contains("state too large"), "got: {:#}", err);
+
+        // The refused value left the stored state alone
+        rt.exec(r#"kept = cvh.state.get("small") == "ok" and cvh.state.get("big") == nil"#).unwrap();
+        assert!(rt.lua().globals().get::<bool>("kept").unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), saved);
+    }
+
+    #[test]
+    fn test_cvh_state_rejects_bad_values_and_missing_store() {
+        let rt = create_test_runtime();
+        rt.exec(r#"missing = cvh.state.get("anything")"#).unwrap();
+        assert!(matches!(rt.lua().globals().get::<Value>("missing").unwrap(), Value::Nil));
+        let err = rt.exec(r#"cvh.state.set("on", true)"#).unwrap_err();
+        assert!(format!("{:#}", err).contains("no state store"), "got: {:#}", err);
+
+        let dir = tempfile::tempdir().unwrap();
+        rt.set_state_store(api::StateStore::open(dir.path(), Path::new("w.lua"), 1024));
+        let err = rt.exec(r#"cvh.state.set("f", print)"#).unwrap_err();
+        assert!(format!("{:#}", err).contains("cannot encode function"), "got: {:#}", err);
+    }
+
     // ========================================================================
     // IconScript Tests
     // ========================================================================
@@ -1035,4 +2162,104 @@ mod tests {
         let commands = script.call_render(64, 80).unwrap();
         assert_eq!(commands.len(), 8, "Should have 8 draw commands");
     }
+
+    #[test]
+    fn test_iconscript_canvas_round_rect_and_gradient() {
+        let rt = create_test_runtime();
+        rt.exec(r##"
+            Icon = { name = "test" }
+            function Icon:render(canvas)
+                canvas:round_rect(4, 4, 56, 56, 8, "#336699")
+                canvas:linear_gradient(0, 0, 64, 64, {
+                    { 0, "#000000" },
+                    { offset = 1, color = "#ffffff" },
+                })
+            end
+        "##).unwrap();
+
+        let icon_table: Table = rt.lua().globals().get("Icon").unwrap();
+        let script = IconScript { lua: rt.lua(), icon_table };
+
+        let commands = script.call_render(64, 64).unwrap();
+        assert!(matches!(commands[0], DrawCommand::RoundRect { r, .. } if r == 8.0));
+        match &commands[1] {
+            DrawCommand::LinearGradient { stops, .. } => {
+                assert_eq!(stops.len(), 2);
+                assert_eq!(stops[1].offset, 1.0);
+                assert_eq!(stops[1].color, "#ffffff");
+            }
+            other => panic!("Expected LinearGradient, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_iconscript_canvas_arc() {
+        let rt = create_test_runtime();
+        rt.exec(r##"
+            Icon = { name = "battery" }
+            function Icon:render(canvas)
+                canvas:arc(32, 32, 20, -90, 180, "#a3be8c", 4)
+            end
+        "##).unwrap();
+
+        let icon_table: Table = rt.lua().globals().get("Icon").unwrap();
+        let script = IconScript { lua: rt.lua(), icon_table };
+
+        let commands = script.call_render(64, 64).unwrap();
+        assert_eq!(
+            commands,
+            vec![DrawCommand::Arc {
+                cx: 32.0,
+                cy: 32.0,
+                r: 20.0,
+                start_deg: -90.0,
+                end_deg: 180.0,
+                color: "#a3be8c".to_string(),
+                width: 4.0,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_iconscript_canvas_dash() {
+        let rt = create_test_runtime();
+        rt.exec(r##"
+            Icon = { name = "separator" }
+            function Icon:render(canvas)
+                canvas:line(0, 40, 64, 40, "#4c566a", 1, { 4, 2 })
+                canvas:stroke_rect(2, 2, 60, 60, "#88c0d0", 1)
+            end
+        "##).unwrap();
+
+        let icon_table: Table = rt.lua().globals().get("Icon").unwrap();
+        let script = IconScript { lua: rt.lua(), icon_table };
+
+        let commands = script.call_render(64, 64).unwrap();
+        assert!(matches!(&commands[0], DrawCommand::Line { dash, .. } if dash == &[4.0, 2.0]));
+        assert!(matches!(&commands[1], DrawCommand::StrokeRect { dash, .. } if dash.is_empty()));
+    }
+
+    #[test]
+    fn test_iconscript_canvas_transform() {
+        let rt = create_test_runtime();
+        rt.exec(r##"
+            Icon = { name = "clock" }
+            function Icon:render(canvas)
+                canvas:push_transform(30, 32, 32)
+                canvas:line(0, 0, 0, -20, "#eceff4", 2)
+                canvas:pop_transform()
+            end
+        "##).unwrap();
+
+        let icon_table: Table = rt.lua().globals().get("Icon").unwrap();
+        let script = IconScript { lua: rt.lua(), icon_table };
+
+        let commands = script.call_render(64, 64).unwrap();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(
+            commands[0],
+            DrawCommand::PushTransform { rotate_deg: 30.0, tx: 32.0, ty: 32.0, scale: 1.0 }
+        );
+        assert_eq!(commands[2], DrawCommand::PopTransform);
+    }
 }
\ No newline at end of file
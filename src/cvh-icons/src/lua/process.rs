@@ -1,28 +1,115 @@
 //! Lua Process management for sandboxed icon scripts
 //!
 //! Manages long-lived bubblewrap sandboxed processes for executing Lua icon scripts.
-//! Communication happens via stdin/stdout with JSON serialization (length-prefixed).
-
-use std::io::{Read, Write};
-use std::os::fd::{AsFd, BorrowedFd};
-use std::path::PathBuf;
-use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
-use std::time::Duration;
+//! Communication happens via stdin/stdout with length-prefixed messages, JSON
+//! unless the handler advertises bincode during the handshake.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 use anyhow::{bail, Context, Result};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
 use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::resource::{getrlimit, setrlimit, Resource};
+use tracing::{debug, warn};
 
-use crate::ipc::{IpcEncoding, Request, Response, PROTOCOL_VERSION};
-use crate::sandbox::SandboxOptions;
+use crate::ipc::{decode_frame, encode_frame, Feature, Features, IpcEncoding, Request, Response, PROTOCOL_VERSION};
+use crate::sandbox::landlock::{self, Ruleset};
+use crate::sandbox::{self, seccomp, SandboxOptions};
 
 /// Default timeout for receiving responses (1 second)
 #[allow(dead_code)]
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
 
-/// Maximum message size (1 MB)
+/// Maximum message size (1 MB), before compression
 #[allow(dead_code)]
 const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
 
+/// Longest stderr line logged as one; longer output is split
+const MAX_STDERR_LINE: usize = 1024;
+
+/// Number of recent stderr lines kept for `LuaProcess::stderr_tail`
+const STDERR_TAIL_LINES: usize = 32;
+
+/// Where the Landlock trampoline is bound inside the sandbox
+const LANDLOCK_EXEC_PATH: &str = "/run/cvh-icons/landlock-exec";
+
+/// How long `kill` waits for the last stderr output of an exited process
+const STDERR_DRAIN_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Forwards a child's stderr to the log as it arrives
+///
+/// A thread reads the pipe so it never fills up and blocks the child; each
+/// line is logged as a warning tagged with the widget script.
+struct StderrBridge {
+    thread: Option<JoinHandle<()>>,
+    /// Most recent lines, oldest first
+    tail: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl StderrBridge {
+    fn spawn(stderr: ChildStderr, script: &Path) -> Result<Self> {
+        let tail = Arc::new(Mutex::new(VecDeque::with_capacity(STDERR_TAIL_LINES)));
+        let script = script.display().to_string();
+        let lines = Arc::clone(&tail);
+
+        let thread = std::thread::Builder::new()
+            .name("lua-stderr".to_string())
+            .spawn(move || {
+                let mut reader = BufReader::new(stderr);
+                let mut buf = Vec::new();
+                loop {
+                    buf.clear();
+                    match (&mut reader).take(MAX_STDERR_LINE as u64).read_until(b'\n', &mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(_) => {}
+                    }
+                    let line = String::from_utf8_lossy(&buf).trim_end().to_string();
+                    if line.is_empty() {
+                        continue;
+                    }
+                    warn!("[{}] {}", script, line);
+
+                    let mut lines = lines.lock().unwrap_or_else(|e| e.into_inner());
+                    if lines.len() == STDERR_TAIL_LINES {
+                        lines.pop_front();
+                    }
+                    lines.push_back(line);
+                }
+            })
+            .context("Failed to start stderr reader")?;
+
+        Ok(Self { thread: Some(thread), tail })
+    }
+
+    /// Wait up to `timeout` for the child's stderr to close, so lines
+    /// written just before it exited are logged too
+    ///
+    /// Gives up rather than block if something else still holds the pipe.
+    fn drain(&mut self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        while self.thread.as_ref().is_some_and(|t| !t.is_finished()) && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        if self.thread.as_ref().is_some_and(|t| t.is_finished()) {
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    fn tail(&self) -> Vec<String> {
+        self.tail.lock().unwrap_or_else(|e| e.into_inner()).iter().cloned().collect()
+    }
+}
+
 /// Manages a sandboxed Lua process for icon rendering
 #[allow(dead_code)]
 pub struct LuaProcess {
@@ -38,6 +125,16 @@ pub struct LuaProcess {
     icon_script_path: PathBuf,
     /// Whether the handshake has been completed
     handshake_complete: bool,
+    /// Encoding for messages, negotiated during the handshake
+    encoding: IpcEncoding,
+    /// Optional features both sides support, negotiated during the handshake
+    features: Features,
+    /// When the last response was received
+    last_activity: Instant,
+    /// Logs what the child writes to stderr (None if it isn't piped)
+    stderr: Option<StderrBridge>,
+    /// Set when the child stopped taking requests, possibly mid-message
+    stalled: bool,
 }
 
 #[allow(dead_code)]
@@ -47,7 +144,7 @@ impl LuaProcess {
     /// Spawns a bubblewrap-sandboxed process that will run the Lua interpreter
     /// with the IPC handler script. The icon widget script path is passed via
     /// the CVH_ICON_SCRIPT environment variable. Communication happens via
-    /// stdin/stdout using JSON serialization with a u32 length prefix.
+    /// stdin/stdout using the negotiated encoding with a u32 length prefix.
     ///
     /// # Arguments
     /// * `handler_path` - Path to the IPC handler script (ipc_handler.lua)
@@ -58,8 +155,27 @@ impl LuaProcess {
         icon_script_path: PathBuf,
         sandbox_options: &SandboxOptions,
     ) -> Result<Self> {
-        // Build the bubblewrap command
-        let mut cmd = Self::build_bwrap_command(sandbox_options, &handler_path, &icon_script_path);
+        // Refuse misconfigured sandboxes before anything is launched
+        let sandbox_options = &sandbox::validate_config(sandbox_options)?;
+
+        // Build the command; the seccomp filter fd only has to outlive spawn()
+        let bwrap_available = sandbox::is_bubblewrap_available();
+        let seccomp_filter = if bwrap_available {
+            Self::seccomp_filter(sandbox_options)
+        } else {
+            None
+        };
+        let mut cmd = Self::build_command(
+            sandbox_options,
+            &handler_path,
+            &icon_script_path,
+            seccomp_filter.as_ref().map(|fd| fd.as_raw_fd()),
+            bwrap_available,
+        )?;
+        Self::apply_resource_limits(&mut cmd, sandbox_options);
+        if let Some(fd) = &seccomp_filter {
+            Self::inherit_fd(&mut cmd, fd.as_raw_fd());
+        }
 
         cmd.stdin(Stdio::piped())
             .stdout(Stdio::piped())
@@ -75,6 +191,10 @@ impl LuaProcess {
             .context("Failed to get child stdin")?;
         let stdout = child.stdout.take()
             .context("Failed to get child stdout")?;
+        let stderr = child.stderr.take()
+            .context("Failed to get child stderr")?;
+        let stderr = StderrBridge::spawn(stderr, &icon_script_path)?;
+        set_nonblocking(&stdin)?;
 
         let mut process = Self {
             child,
@@ -83,6 +203,11 @@ impl LuaProcess {
             handler_path,
             icon_script_path,
             handshake_complete: false,
+            encoding: IpcEncoding::Json,
+            features: Features::NONE,
+            last_activity: Instant::now(),
+            stderr: Some(stderr),
+            stalled: false,
         };
 
         // Perform protocol handshake
@@ -91,16 +216,223 @@ impl LuaProcess {
         Ok(process)
     }
 
+    /// Create the seccomp filter if enabled, or None if disabled or setup failed
+    fn seccomp_filter(options: &SandboxOptions) -> Option<OwnedFd> {
+        if !options.seccomp {
+            return None;
+        }
+        match seccomp::filter_fd() {
+            Ok(fd) => Some(fd),
+            Err(e) => {
+                warn!("Running Lua process without seccomp filter: {:#}", e);
+                None
+            }
+        }
+    }
+
+    /// Resource limits for the sandboxed process as (resource, soft, hard)
+    ///
+    /// The CPU hard limit is one second past the soft limit: SIGXCPU at the
+    /// soft limit normally kills the process, SIGKILL follows if it doesn't.
+    fn resource_limits(options: &SandboxOptions) -> Vec<(Resource, u64, u64)> {
+        let mut limits = Vec::new();
+        if let Some(secs) = options.max_cpu_secs {
+            limits.push((Resource::RLIMIT_CPU, secs, secs.saturating_add(1)));
+        }
+        if let Some(bytes) = options.max_memory_bytes {
+            limits.push((Resource::RLIMIT_AS, bytes, bytes));
+        }
+        limits
+    }
+
+    /// Set the sandbox's resource limits in the child right before exec
+    ///
+    /// Limits are inherited through bwrap by the Lua interpreter. They are
+    /// capped at the daemon's own hard limits, which can't be raised.
+    fn apply_resource_limits(cmd: &mut Command, options: &SandboxOptions) {
+        let limits = Self::resource_limits(options);
+        if limits.is_empty() {
+            return;
+        }
+
+        // SAFETY: the hook only calls getrlimit/setrlimit, which are
+        // async-signal-safe, and doesn't allocate
+        unsafe {
+            cmd.pre_exec(move || {
+                for &(resource, soft, hard) in &limits {
+                    let (_, max) = getrlimit(resource)?;
+                    let hard = hard.min(max);
+                    setrlimit(resource, soft.min(hard), hard)?;
+                }
+                Ok(())
+            });
+        }
+    }
+
+    /// Let the child keep the close-on-exec `fd` across its exec
+    fn inherit_fd(cmd: &mut Command, fd: RawFd) {
+        // SAFETY: the hook only calls fcntl, which is async-signal-safe
+        unsafe {
+            cmd.pre_exec(move || {
+                fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+                Ok(())
+            });
+        }
+    }
+
+    /// Build the command for the Lua process according to the sandbox policy
+    ///
+    /// Uses bubblewrap when available. Without it, fails if
+    /// `require_sandbox` is set and otherwise runs the interpreter directly.
+    fn build_command(
+        options: &SandboxOptions,
+        handler_path: &PathBuf,
+        icon_script_path: &PathBuf,
+        seccomp_fd: Option<RawFd>,
+        bwrap_available: bool,
+    ) -> Result<Command> {
+        if bwrap_available {
+            let landlock_exec = Self::landlock_exec_binary(options);
+            return Ok(Self::build_bwrap_command(
+                options,
+                handler_path,
+                icon_script_path,
+                seccomp_fd,
+                landlock_exec.as_deref(),
+            ));
+        }
+        if options.require_sandbox {
+            bail!(
+                "bubblewrap (bwrap) is not installed and the sandbox is required; \
+                 install bubblewrap or set sandbox.require_sandbox = false"
+            );
+        }
+
+        warn!(
+            "bubblewrap (bwrap) is not installed: running {} WITHOUT A SANDBOX",
+            icon_script_path.display()
+        );
+        let mut cmd = Self::build_direct_command(options, handler_path, icon_script_path);
+        Self::apply_landlock(&mut cmd, options, handler_path, icon_script_path);
+        Ok(cmd)
+    }
+
+    /// Confine a directly launched interpreter to the sandbox paths
+    ///
+    /// Skipped with a warning if Landlock is disabled or unsupported.
+    fn apply_landlock(
+        cmd: &mut Command,
+        options: &SandboxOptions,
+        handler_path: &Path,
+        icon_script_path: &Path,
+    ) {
+        if !options.landlock {
+            warn!("Landlock disabled: the Lua process has full access to your files");
+            return;
+        }
+        if landlock::abi_version().is_none() {
+            warn!("Landlock is not supported by this kernel: the Lua process has full access to your files");
+            return;
+        }
+
+        let ruleset = Ruleset::from_options(options, &Self::script_dirs(handler_path, icon_script_path));
+
+        // SAFETY: Ruleset::apply only makes raw syscalls and doesn't allocate
+        unsafe {
+            cmd.pre_exec(move || ruleset.apply());
+        }
+    }
+
+    /// The cvh-icons binary to bind into the sandbox as the Landlock
+    /// trampoline, or None if Landlock is off or unsupported
+    ///
+    /// Bubblewrap's namespaces and mounts still apply without it.
+    fn landlock_exec_binary(options: &SandboxOptions) -> Option<PathBuf> {
+        if !options.landlock {
+            debug!("Landlock disabled: relying on the bubblewrap mounts alone");
+            return None;
+        }
+        if landlock::abi_version().is_none() {
+            debug!("Landlock is not supported by this kernel: relying on the bubblewrap mounts alone");
+            return None;
+        }
+        match std::env::current_exe() {
+            Ok(exe) => Some(exe),
+            Err(e) => {
+                warn!("Cannot locate the cvh-icons binary, skipping Landlock in the sandbox: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Directories holding the handler and widget scripts, which the
+    /// interpreter reads
+    fn script_dirs<'a>(handler_path: &'a Path, icon_script_path: &'a Path) -> Vec<&'a Path> {
+        [handler_path, icon_script_path]
+            .iter()
+            .filter_map(|path| path.parent())
+            .collect()
+    }
+
+    /// Build a plain interpreter command with the same environment as the
+    /// sandbox
+    fn build_direct_command(
+        options: &SandboxOptions,
+        handler_path: &PathBuf,
+        icon_script_path: &PathBuf,
+    ) -> Command {
+        let mut cmd = Command::new(&options.lua_interpreter);
+        cmd.arg(handler_path);
+
+        if let Some(ref work_dir) = options.work_dir {
+            cmd.current_dir(work_dir);
+        }
+
+        cmd.env_clear();
+        cmd.envs(Self::script_env(options, icon_script_path));
+        cmd
+    }
+
+    /// Environment for the interpreter, in the order it is applied
+    ///
+    /// Allowlisted variables are copied from the daemon's environment;
+    /// caller-provided variables come last so they can override defaults.
+    fn script_env(options: &SandboxOptions, icon_script_path: &PathBuf) -> Vec<(String, String)> {
+        let mut env = vec![
+            ("PATH".to_string(), "/usr/bin:/bin".to_string()),
+            ("HOME".to_string(), "/tmp".to_string()),
+            ("LANG".to_string(), "C.UTF-8".to_string()),
+            // The handler loads the widget script named here
+            ("CVH_ICON_SCRIPT".to_string(), icon_script_path.to_string_lossy().to_string()),
+            // The handler checks cvh.open/cvh.spawn against the same policy
+            ("CVH_ALLOWED_COMMANDS".to_string(), options.action_policy.allowed_commands.join(" ")),
+            ("CVH_ALLOWED_URI_SCHEMES".to_string(), options.action_policy.allowed_uri_schemes.join(" ")),
+        ];
+        for name in &options.env_allowlist {
+            if let Ok(value) = std::env::var(name) {
+                env.push((name.clone(), value));
+            }
+        }
+        env.extend(options.env_vars.iter().cloned());
+        env
+    }
+
     /// Build the bubblewrap command with appropriate arguments
     ///
     /// # Arguments
     /// * `options` - Sandbox configuration options
     /// * `handler_path` - Path to the IPC handler script (executed by Lua)
     /// * `icon_script_path` - Path to the icon widget script (passed via CVH_ICON_SCRIPT env var)
+    /// * `seccomp_fd` - Fd holding a seccomp BPF program, if any; the child
+    ///   must keep it across exec (see `inherit_fd`)
+    /// * `landlock_exec` - cvh-icons binary that applies the Landlock
+    ///   ruleset inside the sandbox before exec'ing the interpreter, if any
     fn build_bwrap_command(
         options: &SandboxOptions,
         handler_path: &PathBuf,
         icon_script_path: &PathBuf,
+        seccomp_fd: Option<RawFd>,
+        landlock_exec: Option<&Path>,
     ) -> Command {
         let mut cmd = Command::new("bwrap");
 
@@ -185,6 +517,11 @@ impl LuaProcess {
             }
         }
 
+        // Bind the Landlock trampoline under the private /run
+        if let Some(exe) = landlock_exec {
+            cmd.arg("--ro-bind").arg(exe).arg(LANDLOCK_EXEC_PATH);
+        }
+
         // Set working directory
         if let Some(ref work_dir) = options.work_dir {
             cmd.args(["--chdir", &work_dir.to_string_lossy()]);
@@ -193,24 +530,24 @@ impl LuaProcess {
         // Clear environment FIRST, then set variables
         // This ensures our setenv calls are not cleared
         cmd.args(["--clearenv"]);
+        for (key, value) in Self::script_env(options, icon_script_path) {
+            cmd.args(["--setenv", &key, &value]);
+        }
 
-        // Set essential environment variables
-        cmd.args(["--setenv", "PATH", "/usr/bin:/bin"]);
-        cmd.args(["--setenv", "HOME", "/tmp"]);
-        cmd.args(["--setenv", "LANG", "C.UTF-8"]);
-
-        // Set the CVH_ICON_SCRIPT environment variable for the handler to load
-        cmd.args(["--setenv", "CVH_ICON_SCRIPT", &icon_script_path.to_string_lossy()]);
-
-        // Apply caller-provided environment variables AFTER essential ones
-        // This allows callers to override defaults if needed
-        for (key, value) in &options.env_vars {
-            cmd.args(["--setenv", key, value]);
+        // Load the syscall filter right before exec'ing the interpreter
+        if let Some(fd) = seccomp_fd {
+            cmd.args(["--seccomp", &fd.to_string()]);
         }
 
         // Add the actual Lua interpreter and IPC handler script
         cmd.arg("--");
-        cmd.arg("lua");
+        if landlock_exec.is_some() {
+            let ruleset = Ruleset::from_options(options, &Self::script_dirs(handler_path, icon_script_path));
+            cmd.arg(LANDLOCK_EXEC_PATH);
+            cmd.args(ruleset.exec_args());
+            cmd.arg("--landlock-exec");
+        }
+        cmd.arg(&options.lua_interpreter);
         cmd.arg(handler_path.to_string_lossy().as_ref());
 
         cmd
@@ -218,14 +555,20 @@ impl LuaProcess {
 
     /// Perform protocol version handshake
     fn perform_handshake(&mut self) -> Result<()> {
+        // The handshake itself is always plain JSON; the agreed encoding and
+        // features apply after it
+        self.encoding = IpcEncoding::Json;
+        self.features = Features::NONE;
         let request = Request::Handshake {
             version: PROTOCOL_VERSION,
+            encodings: IpcEncoding::supported_names(),
+            features: Features::supported().names(),
         };
 
         self.send_request(&request)?;
 
         match self.receive_response()? {
-            Response::HandshakeAck { version, success } => {
+            Response::HandshakeAck { version, success, encodings, features } => {
                 if !success {
                     bail!("Handshake failed: version mismatch (local: {}, remote: {})",
                           PROTOCOL_VERSION, version);
@@ -234,6 +577,8 @@ impl LuaProcess {
                     bail!("Protocol version mismatch: expected {}, got {}",
                           PROTOCOL_VERSION, version);
                 }
+                self.encoding = IpcEncoding::negotiate(&encodings);
+                self.features = Features::negotiate(&features);
                 self.handshake_complete = true;
                 Ok(())
             }
@@ -246,31 +591,83 @@ impl LuaProcess {
         }
     }
 
-    /// Send a request to the Lua process using JSON + length prefix over stdin
+    /// Send a request to the Lua process using the negotiated encoding +
+    /// length prefix over stdin
     pub fn send_request(&mut self, request: &Request) -> Result<()> {
-        let data = request.serialize(IpcEncoding::Json)
+        self.send_request_with_timeout(request, DEFAULT_TIMEOUT)
+    }
+
+    /// Send a request, giving the Lua process `timeout` to take it
+    ///
+    /// A process that doesn't read its stdin fills the pipe; rather than
+    /// block, the write gives up after `timeout` and the process counts as
+    /// stalled (see `is_stalled`).
+    pub fn send_request_with_timeout(&mut self, request: &Request, timeout: Duration) -> Result<()> {
+        let data = request.serialize(self.encoding)
             .map_err(|e| anyhow::anyhow!("Failed to serialize request: {}", e))?;
 
         if data.len() > MAX_MESSAGE_SIZE {
             bail!("Request too large: {} bytes (max: {})", data.len(), MAX_MESSAGE_SIZE);
         }
+        let data = if self.supports(Feature::Compression) { encode_frame(&data) } else { data };
 
-        // Write length prefix (4 bytes, little-endian)
-        let len_bytes = (data.len() as u32).to_le_bytes();
-        self.stdin
-            .write_all(&len_bytes)
-            .context("Failed to write message length")?;
+        // Length prefix (4 bytes, little-endian), then the data
+        let mut message = Vec::with_capacity(4 + data.len());
+        message.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        message.extend_from_slice(&data);
 
-        // Write the actual data
-        self.stdin
-            .write_all(&data)
-            .context("Failed to write message data")?;
+        self.write_all_with_timeout(&message, timeout)
+            .context("Failed to write message")
+    }
+
+    /// Write all of `data` to stdin, waiting at most `timeout` in total
+    ///
+    /// Stdin is non-blocking, so each write takes only what fits in the
+    /// pipe and poll() waits for the child to make room for the rest.
+    fn write_all_with_timeout(&mut self, data: &[u8], timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut written = 0;
+
+        while written < data.len() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let poll_timeout = PollTimeout::try_from(remaining.as_millis().min(i32::MAX as u128) as i32)
+                .unwrap_or(PollTimeout::MAX);
+            let mut poll_fds = [PollFd::new(self.stdin.as_fd(), PollFlags::POLLOUT)];
+
+            if poll(&mut poll_fds, poll_timeout).context("poll() failed")? == 0 {
+                self.stalled = true;
+                bail!(
+                    "Timeout writing to Lua process (waited {}ms, wrote {}/{})",
+                    timeout.as_millis(),
+                    written,
+                    data.len()
+                );
+            }
+            if let Some(revents) = poll_fds[0].revents() {
+                if revents.intersects(PollFlags::POLLERR | PollFlags::POLLHUP) {
+                    bail!("Lua process closed stdin");
+                }
+            }
 
-        self.stdin.flush().context("Failed to flush stdin")?;
+            match self.stdin.write(&data[written..]) {
+                Ok(0) => bail!("Lua process closed stdin"),
+                Ok(n) => written += n,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => {}
+                Err(e) => return Err(e).context("Failed to write to stdin"),
+            }
+        }
 
         Ok(())
     }
 
+    /// Whether a request timed out because the process stopped reading
+    ///
+    /// The request may have been cut off halfway, so the connection can't
+    /// be used any more and the process should be restarted.
+    pub fn is_stalled(&self) -> bool {
+        self.stalled
+    }
+
     /// Receive a response from the Lua process with timeout
     pub fn receive_response(&mut self) -> Result<Response> {
         self.receive_response_with_timeout(DEFAULT_TIMEOUT)
@@ -288,7 +685,9 @@ impl LuaProcess {
 
         let len = u32::from_le_bytes(len_bytes) as usize;
 
-        if len > MAX_MESSAGE_SIZE {
+        // A frame is one byte longer than the largest message it may carry
+        let framed = self.supports(Feature::Compression);
+        if len > MAX_MESSAGE_SIZE + framed as usize {
             bail!("Response too large: {} bytes (max: {})", len, MAX_MESSAGE_SIZE);
         }
 
@@ -297,9 +696,16 @@ impl LuaProcess {
         self.read_exact_with_timeout(&mut data, timeout)
             .context("Failed to read message data")?;
 
-        // Deserialize the response using JSON
-        let response = Response::deserialize(&data, IpcEncoding::Json)
+        // Inflated sizes are held to the same limit
+        if framed {
+            data = decode_frame(&data, MAX_MESSAGE_SIZE)
+                .map_err(|e| anyhow::anyhow!("Failed to decode response: {}", e))?;
+        }
+
+        // Deserialize the response using the negotiated encoding
+        let response = Response::deserialize(&data, self.encoding)
             .map_err(|e| anyhow::anyhow!("Failed to deserialize response: {}", e))?;
+        self.last_activity = Instant::now();
 
         Ok(response)
     }
@@ -353,9 +759,11 @@ impl LuaProcess {
             }
 
             // Data is available, read it
-            let n = self.stdout
-                .read(&mut buf[bytes_read..])
-                .context("Failed to read from stdout")?;
+            let n = match self.stdout.read(&mut buf[bytes_read..]) {
+                Ok(n) => n,
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted) => continue,
+                Err(e) => return Err(e).context("Failed to read from stdout"),
+            };
 
             if n == 0 {
                 bail!(
@@ -373,13 +781,15 @@ impl LuaProcess {
 
     /// Kill the Lua process and clean up resources
     pub fn kill(&mut self) -> Result<()> {
-        // Try to send a graceful shutdown request first
-        if self.handshake_complete {
+        // Try to send a graceful shutdown request first, unless the process
+        // stopped reading them
+        if self.handshake_complete && !self.stalled {
             let _ = self.send_request(&Request::Shutdown);
             // Give it a short time to respond
             if let Ok(Response::ShutdownAck) = self.receive_response_with_timeout(Duration::from_millis(100)) {
                 // Graceful shutdown succeeded
                 let _ = self.child.wait();
+                self.drain_stderr();
                 return Ok(());
             }
         }
@@ -393,20 +803,57 @@ impl LuaProcess {
         self.child
             .wait()
             .context("Failed to wait for Lua process")?;
+        self.drain_stderr();
 
         Ok(())
     }
 
+    /// Log what the exited child wrote to stderr last
+    fn drain_stderr(&mut self) {
+        if let Some(stderr) = self.stderr.as_mut() {
+            stderr.drain(STDERR_DRAIN_TIMEOUT);
+        }
+    }
+
+    /// The last lines the child wrote to stderr, oldest first
+    pub fn stderr_tail(&self) -> Vec<String> {
+        self.stderr.as_ref().map(StderrBridge::tail).unwrap_or_default()
+    }
+
     /// Check if the process is still running
     pub fn is_running(&mut self) -> bool {
         matches!(self.child.try_wait(), Ok(None))
     }
 
+    /// Time since the process last answered a request
+    pub fn idle_for(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.last_activity)
+    }
+
+    /// Check that the process still answers requests within `timeout`
+    pub fn ping(&mut self, timeout: Duration) -> Result<()> {
+        self.send_request(&Request::Ping)?;
+        match self.receive_response_with_timeout(timeout)? {
+            Response::Pong => Ok(()),
+            other => bail!("Unexpected response to ping: {:?}", other),
+        }
+    }
+
     /// Get the path to the IPC handler script
     pub fn handler_path(&self) -> &PathBuf {
         &self.handler_path
     }
 
+    /// Get the encoding negotiated during the handshake
+    pub fn encoding(&self) -> IpcEncoding {
+        self.encoding
+    }
+
+    /// Whether both sides agreed on `feature` during the handshake
+    pub fn supports(&self, feature: Feature) -> bool {
+        self.features.contains(feature)
+    }
+
     /// Get the path to the icon widget script
     pub fn icon_script_path(&self) -> &PathBuf {
         &self.icon_script_path
@@ -416,6 +863,78 @@ impl LuaProcess {
     pub fn pid(&self) -> u32 {
         self.child.id()
     }
+
+    /// Talk to an in-process mock peer instead of a sandbox (for testing)
+    ///
+    /// The handshake is skipped; a long-running `sleep` stands in for the child
+    /// so liveness checks pass.
+    #[cfg(test)]
+    pub fn mock() -> Result<(Self, MockPeer)> {
+        use std::os::fd::OwnedFd;
+        use std::os::unix::net::UnixStream;
+        use std::process::{ChildStdin, ChildStdout};
+
+        let (ours, theirs) = UnixStream::pair().context("Failed to create socket pair")?;
+        // Writes don't block, as on the stdin pipe of a real process
+        ours.set_nonblocking(true)?;
+        let child = Command::new("sleep")
+            .arg("60")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .context("Failed to spawn mock child")?;
+
+        let process = Self {
+            child,
+            stdin: ChildStdin::from(OwnedFd::from(ours.try_clone()?)),
+            stdout: ChildStdout::from(OwnedFd::from(ours)),
+            handler_path: PathBuf::from("/mock/ipc_handler.lua"),
+            icon_script_path: PathBuf::from("/mock/widget.lua"),
+            handshake_complete: false,
+            encoding: IpcEncoding::Json,
+            // No handshake: let tests use every request, sent unframed
+            features: Features::supported().without(Feature::Compression),
+            last_activity: Instant::now(),
+            stderr: None,
+            stalled: false,
+        };
+        Ok((process, MockPeer { stream: theirs }))
+    }
+
+    /// Wrap an arbitrary command without sandbox or handshake (for testing)
+    #[cfg(test)]
+    pub fn spawn_unsandboxed(
+        mut cmd: Command,
+        handler_path: PathBuf,
+        icon_script_path: PathBuf,
+    ) -> Result<Self> {
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .context("Failed to spawn test process")?;
+        let stdin = child.stdin.take().context("Failed to get child stdin")?;
+        let stdout = child.stdout.take().context("Failed to get child stdout")?;
+        let stderr = child.stderr.take().context("Failed to get child stderr")?;
+        let stderr = StderrBridge::spawn(stderr, &icon_script_path)?;
+        set_nonblocking(&stdin)?;
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+            handler_path,
+            icon_script_path,
+            handshake_complete: false,
+            encoding: IpcEncoding::Json,
+            // No handshake: let tests use every request, sent unframed
+            features: Features::supported().without(Feature::Compression),
+            last_activity: Instant::now(),
+            stderr: Some(stderr),
+            stalled: false,
+        })
+    }
 }
 
 impl Drop for LuaProcess {
@@ -425,6 +944,48 @@ impl Drop for LuaProcess {
     }
 }
 
+/// Make writes to `fd` return instead of blocking when it is full
+fn set_nonblocking(fd: &impl AsRawFd) -> Result<()> {
+    let flags = fcntl(fd.as_raw_fd(), FcntlArg::F_GETFL).context("Failed to get file flags")?;
+    let flags = OFlag::from_bits_truncate(flags) | OFlag::O_NONBLOCK;
+    fcntl(fd.as_raw_fd(), FcntlArg::F_SETFL(flags)).context("Failed to set file flags")?;
+    Ok(())
+}
+
+/// The Lua side of a mock IPC connection (see [`LuaProcess::mock`])
+#[cfg(test)]
+pub struct MockPeer {
+    stream: std::os::unix::net::UnixStream,
+}
+
+#[cfg(test)]
+impl MockPeer {
+    /// Read one raw message, including its length prefix, if one arrives
+    /// within `timeout`
+    pub fn try_read_raw(&mut self, timeout: Duration) -> Option<Vec<u8>> {
+        self.stream.set_read_timeout(Some(timeout)).ok()?;
+        let mut len_bytes = [0u8; 4];
+        self.stream.read_exact(&mut len_bytes).ok()?;
+
+        let mut message = len_bytes.to_vec();
+        message.resize(4 + u32::from_le_bytes(len_bytes) as usize, 0);
+        self.stream.read_exact(&mut message[4..]).ok()?;
+        Some(message)
+    }
+
+    /// Queue a JSON response for the process to read
+    pub fn send_response(&mut self, response: &Response) {
+        self.send_response_encoded(response, IpcEncoding::Json);
+    }
+
+    /// Queue a response in the given encoding for the process to read
+    pub fn send_response_encoded(&mut self, response: &Response, encoding: IpcEncoding) {
+        let data = response.serialize(encoding).unwrap();
+        self.stream.write_all(&(data.len() as u32).to_le_bytes()).unwrap();
+        self.stream.write_all(&data).unwrap();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -444,12 +1005,198 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let program = cmd.get_program().to_string_lossy();
         assert!(program.contains("bwrap"), "Should use bwrap");
     }
 
+    #[test]
+    fn test_build_bwrap_command_seccomp_enabled() {
+        let options = SandboxOptions::default();
+        assert!(options.seccomp, "seccomp should be on by default");
+        let filter = LuaProcess::seccomp_filter(&options).expect("filter should be created");
+        let fd = filter.as_raw_fd();
+
+        let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
+        let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, Some(fd), None);
+
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy().to_string()).collect();
+        let pos = args.iter().position(|a| a == "--seccomp").expect("Should pass --seccomp");
+        assert_eq!(args[pos + 1], fd.to_string());
+        let separator = args.iter().position(|a| a == "--").unwrap();
+        assert!(pos < separator, "--seccomp must come before the command");
+    }
+
+    #[test]
+    fn test_seccomp_fd_reaches_only_its_child() {
+        let filter = LuaProcess::seccomp_filter(&SandboxOptions::default()).expect("filter should be created");
+        let fd = filter.as_raw_fd();
+        let probe = || {
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", &format!("test -e /proc/self/fd/{}", fd)]);
+            cmd
+        };
+
+        assert!(!probe().status().unwrap().success(), "other children should not inherit the filter");
+        let mut cmd = probe();
+        LuaProcess::inherit_fd(&mut cmd, fd);
+        assert!(cmd.status().unwrap().success(), "the sandbox child should inherit the filter");
+    }
+
+    #[test]
+    fn test_build_command_uses_bwrap_when_available() {
+        let options = SandboxOptions::default();
+        let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
+        let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
+        let cmd = LuaProcess::build_command(&options, &handler_path, &icon_script_path, None, true).unwrap();
+        assert_eq!(cmd.get_program(), "bwrap");
+    }
+
+    #[test]
+    fn test_build_command_without_bwrap_when_required() {
+        let options = SandboxOptions::default();
+        assert!(options.require_sandbox, "sandbox should be required by default");
+        let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
+        let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
+
+        let err = LuaProcess::build_command(&options, &handler_path, &icon_script_path, None, false)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("bubblewrap"), "{}", err);
+        assert!(err.contains("require_sandbox"), "{}", err);
+    }
+
+    #[test]
+    fn test_build_command_without_bwrap_runs_lua_directly() {
+        let options = SandboxOptions {
+            require_sandbox: false,
+            env_vars: vec![("HOME".to_string(), "/custom".to_string())],
+            work_dir: Some(PathBuf::from("/tmp")),
+            ..Default::default()
+        };
+        let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
+        let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
+        let cmd = LuaProcess::build_command(&options, &handler_path, &icon_script_path, None, false).unwrap();
+
+        assert_eq!(cmd.get_program(), "lua");
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["/tmp/ipc_handler.lua"]);
+        assert_eq!(cmd.get_current_dir(), Some(Path::new("/tmp")));
+
+        // Same environment wiring as the bwrap path, caller overrides last
+        let envs: std::collections::HashMap<_, _> = cmd
+            .get_envs()
+            .map(|(k, v)| (k.to_string_lossy().to_string(), v.map(|v| v.to_string_lossy().to_string())))
+            .collect();
+        assert_eq!(envs["CVH_ICON_SCRIPT"].as_deref(), Some("/tmp/widgets/file.lua"));
+        assert_eq!(envs["PATH"].as_deref(), Some("/usr/bin:/bin"));
+        assert_eq!(envs["HOME"].as_deref(), Some("/custom"));
+    }
+
+    #[test]
+    fn test_script_env_passes_allowlisted_vars_only() {
+        std::env::set_var("CVH_TEST_PASSED_DESKTOP", "cvh");
+        std::env::set_var("CVH_TEST_WITHHELD_TOKEN", "secret");
+        let options = SandboxOptions {
+            env_allowlist: vec!["CVH_TEST_PASSED_DESKTOP".to_string()],
+            ..Default::default()
+        };
+
+        let env = LuaProcess::script_env(&options, &PathBuf::from("/tmp/widgets/file.lua"));
+        assert!(env.contains(&("CVH_TEST_PASSED_DESKTOP".to_string(), "cvh".to_string())));
+        assert!(!env.iter().any(|(name, _)| name == "CVH_TEST_WITHHELD_TOKEN"));
+    }
+
+    #[test]
+    fn test_script_env_passes_action_policy() {
+        let options = SandboxOptions {
+            action_policy: crate::sandbox::actions::ActionPolicy {
+                allowed_commands: vec!["xdg-open".to_string(), "foot".to_string()],
+                allowed_uri_schemes: vec!["https".to_string()],
+                allow_trash: true,
+            },
+            ..Default::default()
+        };
+
+        let env = LuaProcess::script_env(&options, &PathBuf::from("/tmp/widgets/file.lua"));
+        assert!(env.contains(&("CVH_ALLOWED_COMMANDS".to_string(), "xdg-open foot".to_string())));
+        assert!(env.contains(&("CVH_ALLOWED_URI_SCHEMES".to_string(), "https".to_string())));
+    }
+
+    #[test]
+    fn test_spawn_rejects_dangerous_read_write_path() {
+        let options = SandboxOptions {
+            read_write_paths: vec![PathBuf::from("/etc")],
+            ..Default::default()
+        };
+        let err = LuaProcess::spawn(PathBuf::from("/nonexistent/handler.lua"), PathBuf::from("/nonexistent/icon.lua"), &options)
+            .err()
+            .expect("spawn should refuse a writable /etc");
+        assert!(err.to_string().contains("/etc"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn test_resource_limits_from_options() {
+        let options = SandboxOptions::default();
+        assert!(LuaProcess::resource_limits(&options).is_empty());
+
+        let options = SandboxOptions {
+            max_cpu_secs: Some(5),
+            max_memory_bytes: Some(64 * 1024 * 1024),
+            ..Default::default()
+        };
+        assert_eq!(
+            LuaProcess::resource_limits(&options),
+            vec![
+                (Resource::RLIMIT_CPU, 5, 6),
+                (Resource::RLIMIT_AS, 64 * 1024 * 1024, 64 * 1024 * 1024),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resource_limits_applied_to_child() {
+        let options = SandboxOptions {
+            max_cpu_secs: Some(7),
+            max_memory_bytes: Some(512 * 1024 * 1024),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "ulimit -t; ulimit -v"]);
+        LuaProcess::apply_resource_limits(&mut cmd, &options);
+
+        let output = cmd.output().expect("failed to run sh");
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines, ["7", "524288"], "ulimit -t / -v (KiB) in child");
+    }
+
+    #[test]
+    fn test_build_bwrap_command_seccomp_disabled() {
+        let options = SandboxOptions {
+            seccomp: false,
+            ..Default::default()
+        };
+        let filter = LuaProcess::seccomp_filter(&options);
+        assert!(filter.is_none());
+
+        let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
+        let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
+        let cmd = LuaProcess::build_bwrap_command(
+            &options,
+            &handler_path,
+            &icon_script_path,
+            filter.as_ref().map(|fd| fd.as_raw_fd()),
+            None,
+        );
+
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        assert!(!args.iter().any(|a| a == "--seccomp"), "Should not pass --seccomp");
+    }
+
     #[test]
     fn test_build_bwrap_command_no_network() {
         let options = SandboxOptions {
@@ -458,7 +1205,7 @@ mod tests {
         };
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         assert!(args.iter().any(|a| a == "--unshare-all"),
@@ -473,7 +1220,7 @@ mod tests {
         };
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         assert!(!args.iter().any(|a| a == "--unshare-all"),
@@ -487,7 +1234,7 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         assert!(args.iter().any(|a| a == "--die-with-parent"),
@@ -499,7 +1246,7 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         assert!(args.iter().any(|a| a == "--new-session"),
@@ -511,7 +1258,7 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         // Find --ro-bind /usr /usr pattern
@@ -526,7 +1273,7 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         assert!(args.iter().any(|a| a == "--clearenv"),
@@ -538,7 +1285,7 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
 
@@ -560,7 +1307,7 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         let has_home_tmpfs = args.windows(2).any(|w| {
@@ -574,7 +1321,7 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         assert!(args.iter().any(|a| a == "/tmp/ipc_handler.lua"),
@@ -586,7 +1333,7 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         let has_cvh_icon_script = args.windows(3).any(|w| {
@@ -600,13 +1347,82 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         assert!(args.iter().any(|a| a == "lua"),
                 "Should include lua interpreter");
     }
 
+    #[test]
+    fn test_build_bwrap_command_runs_interpreter_through_landlock() {
+        let options = SandboxOptions {
+            read_only_paths: vec![PathBuf::from("/usr")],
+            read_write_paths: vec![PathBuf::from("/tmp")],
+            ..Default::default()
+        };
+        let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
+        let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
+        let exe = Path::new("/usr/bin/cvh-icons");
+
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, Some(exe));
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy().to_string()).collect();
+
+        let separator = args.iter().position(|a| a == "--").unwrap();
+        let bind = args
+            .windows(3)
+            .position(|w| w == ["--ro-bind", "/usr/bin/cvh-icons", LANDLOCK_EXEC_PATH])
+            .expect("should bind the trampoline");
+        let run = args.windows(2).position(|w| w == ["--tmpfs", "/run"]).unwrap();
+        assert!(run < bind && bind < separator, "trampoline must be bound over the /run tmpfs");
+
+        assert_eq!(
+            &args[separator + 1..],
+            [
+                LANDLOCK_EXEC_PATH,
+                "--landlock-read", "/usr",
+                "--landlock-write", "/tmp",
+                "--landlock-read", "/tmp/widgets",
+                "--landlock-exec",
+                "lua",
+                "/tmp/ipc_handler.lua",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_command_skips_landlock_when_disabled() {
+        let options = SandboxOptions { landlock: false, ..Default::default() };
+        let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
+        let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
+
+        let cmd = LuaProcess::build_command(&options, &handler_path, &icon_script_path, None, true).unwrap();
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy().to_string()).collect();
+        let separator = args.iter().position(|a| a == "--").unwrap();
+        assert_eq!(args[separator + 1], "lua");
+        assert!(!args.iter().any(|a| a == LANDLOCK_EXEC_PATH));
+    }
+
+    #[test]
+    fn test_commands_use_configured_interpreter() {
+        let options = SandboxOptions {
+            lua_interpreter: "lua5.4".to_string(),
+            require_sandbox: false,
+            ..Default::default()
+        };
+        let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
+        let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
+
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
+        let program = args.iter().position(|a| a == "--").map(|i| &args[i + 1]);
+        assert_eq!(program.map(|p| p.as_ref()), Some("lua5.4"));
+        assert!(!args.iter().any(|a| a == "lua"));
+
+        let cmd = LuaProcess::build_direct_command(&options, &handler_path, &icon_script_path);
+        assert_eq!(cmd.get_program(), "lua5.4");
+    }
+
     #[test]
     fn test_build_bwrap_command_with_custom_env_vars() {
         let options = SandboxOptions {
@@ -617,7 +1433,7 @@ mod tests {
         };
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         let has_custom_var = args.windows(3).any(|w| {
@@ -634,7 +1450,7 @@ mod tests {
         };
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         let has_chdir = args.windows(2).any(|w| {
@@ -649,7 +1465,7 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
         assert!(!args.iter().any(|a| a == "--fd"),
@@ -661,7 +1477,7 @@ mod tests {
         let options = SandboxOptions::default();
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
 
@@ -687,7 +1503,7 @@ mod tests {
         };
         let handler_path = PathBuf::from("/tmp/ipc_handler.lua");
         let icon_script_path = PathBuf::from("/tmp/widgets/file.lua");
-        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path);
+        let cmd = LuaProcess::build_bwrap_command(&options, &handler_path, &icon_script_path, None, None);
 
         let args: Vec<_> = cmd.get_args().map(|a| a.to_string_lossy()).collect();
 
@@ -745,7 +1561,7 @@ mod tests {
 
         // Send using JSON encoding (matching the new protocol)
         let send_thread = std::thread::spawn(move || {
-            let request = Request::Handshake { version: PROTOCOL_VERSION };
+            let request = Request::Handshake { version: PROTOCOL_VERSION, encodings: Vec::new(), features: Vec::new() };
             let data = request.serialize(IpcEncoding::Json).unwrap();
 
             let len_bytes = (data.len() as u32).to_le_bytes();
@@ -765,7 +1581,7 @@ mod tests {
 
         let request = Request::deserialize(&data, IpcEncoding::Json).unwrap();
         match request {
-            Request::Handshake { version } => {
+            Request::Handshake { version, .. } => {
                 assert_eq!(version, PROTOCOL_VERSION);
             }
             _ => panic!("Expected Handshake request"),
@@ -786,6 +1602,8 @@ mod tests {
             let response = Response::HandshakeAck {
                 version: PROTOCOL_VERSION,
                 success: true,
+                encodings: Vec::new(),
+                features: Vec::new(),
             };
             let data = response.serialize(IpcEncoding::Json).unwrap();
 
@@ -807,7 +1625,7 @@ mod tests {
 
         let response = Response::deserialize(&data, IpcEncoding::Json).unwrap();
         match response {
-            Response::HandshakeAck { version, success } => {
+            Response::HandshakeAck { version, success, .. } => {
                 assert_eq!(version, PROTOCOL_VERSION);
                 assert!(success);
             }
@@ -1167,4 +1985,565 @@ mod tests {
         assert!(timeout_ms > 0, "DEFAULT_TIMEOUT should be positive");
         assert!(timeout_ms <= 65535, "DEFAULT_TIMEOUT should fit in u16 for PollTimeout");
     }
+
+    // ========================================================================
+    // Encoding negotiation tests
+    // ========================================================================
+
+    fn handshake_ack(encodings: &[&str]) -> Response {
+        Response::HandshakeAck {
+            version: PROTOCOL_VERSION,
+            success: true,
+            encodings: encodings.iter().map(|e| e.to_string()).collect(),
+            features: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_handshake_negotiates_bincode() {
+        let (mut process, mut peer) = LuaProcess::mock().unwrap();
+        peer.send_response(&handshake_ack(&["json", "bincode"]));
+        process.perform_handshake().unwrap();
+        assert_eq!(process.encoding(), IpcEncoding::Bincode);
+
+        // The handshake itself went out as JSON, advertising both encodings
+        let raw = peer.try_read_raw(DEFAULT_TIMEOUT).unwrap();
+        match Request::deserialize(&raw[4..], IpcEncoding::Json).unwrap() {
+            Request::Handshake { encodings, .. } => assert_eq!(encodings, ["bincode", "json"]),
+            other => panic!("Expected Handshake request, got {:?}", other),
+        }
+
+        // Later traffic uses bincode both ways
+        process.send_request(&Request::Shutdown).unwrap();
+        let raw = peer.try_read_raw(DEFAULT_TIMEOUT).unwrap();
+        assert!(matches!(
+            Request::deserialize(&raw[4..], IpcEncoding::Bincode).unwrap(),
+            Request::Shutdown
+        ));
+
+        peer.send_response_encoded(&Response::ShutdownAck, IpcEncoding::Bincode);
+        assert!(matches!(process.receive_response().unwrap(), Response::ShutdownAck));
+    }
+
+    #[test]
+    fn test_handshake_falls_back_to_json() {
+        for advertised in [&[][..], &["json"][..], &["msgpack"][..]] {
+            let (mut process, mut peer) = LuaProcess::mock().unwrap();
+            peer.send_response(&handshake_ack(advertised));
+            process.perform_handshake().unwrap();
+            assert_eq!(process.encoding(), IpcEncoding::Json, "{:?}", advertised);
+
+            peer.try_read_raw(DEFAULT_TIMEOUT).unwrap();
+            process.send_request(&Request::Shutdown).unwrap();
+            let raw = peer.try_read_raw(DEFAULT_TIMEOUT).unwrap();
+            assert_eq!(&raw[4..], br#"{"type":"Shutdown"}"#);
+        }
+    }
+
+    #[test]
+    fn test_handshake_negotiates_features() {
+        let (mut process, mut peer) = LuaProcess::mock().unwrap();
+        let ack = Response::HandshakeAck {
+            version: PROTOCOL_VERSION,
+            success: true,
+            encodings: Vec::new(),
+            features: vec!["ping".to_string(), "compression".to_string()],
+        };
+        peer.send_response(&ack);
+        process.perform_handshake().unwrap();
+        assert!(process.supports(Feature::Ping));
+        assert!(!process.supports(Feature::RenderBatch));
+
+        let raw = peer.try_read_raw(DEFAULT_TIMEOUT).unwrap();
+        match Request::deserialize(&raw[4..], IpcEncoding::Json).unwrap() {
+            Request::Handshake { features, .. } => assert_eq!(features, ["render_batch", "ping", "deflate"]),
+            other => panic!("Expected Handshake request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_handshake_without_optional_features() {
+        // Handlers predating features don't send `features` at all
+        let (mut process, mut peer) = LuaProcess::mock().unwrap();
+        let ack = br#"{"type":"HandshakeAck","version":1,"success":true,"encodings":["json"]}"#;
+        peer.stream.write_all(&(ack.len() as u32).to_le_bytes()).unwrap();
+        peer.stream.write_all(ack).unwrap();
+
+        process.perform_handshake().unwrap();
+        assert!(!process.supports(Feature::Ping));
+        assert!(!process.supports(Feature::RenderBatch));
+
+        // The core protocol still works
+        peer.try_read_raw(DEFAULT_TIMEOUT).unwrap();
+        process.send_request(&Request::Shutdown).unwrap();
+        let raw = peer.try_read_raw(DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(&raw[4..], br#"{"type":"Shutdown"}"#);
+        peer.send_response(&Response::ShutdownAck);
+        assert!(matches!(process.receive_response().unwrap(), Response::ShutdownAck));
+    }
+
+    #[test]
+    fn test_compressed_messages_roundtrip() {
+        use crate::ipc::{IconMetadata, IconType, RenderContext, RenderItem};
+        use crate::lua::DrawCommand;
+
+        let (mut process, mut peer) = LuaProcess::mock().unwrap();
+        peer.send_response(&Response::HandshakeAck {
+            version: PROTOCOL_VERSION,
+            success: true,
+            encodings: Vec::new(),
+            features: vec!["deflate".to_string()],
+        });
+        process.perform_handshake().unwrap();
+        assert!(process.supports(Feature::Compression));
+        peer.try_read_raw(DEFAULT_TIMEOUT).unwrap();
+
+        // A render response of a few hundred kilobytes as plain JSON
+        let commands: Vec<DrawCommand> = (0..5_000)
+            .map(|i| DrawCommand::Line {
+                x1: i as f32,
+                y1: 0.0,
+                x2: i as f32,
+                y2: 64.0,
+                color: "#88C0D0".to_string(),
+                width: 1.0,
+                dash: Vec::new(),
+            })
+            .collect();
+        let response = Response::Render { commands: commands.clone(), next_frame_ms: None };
+        let json = response.serialize(IpcEncoding::Json).unwrap();
+        let frame = encode_frame(&json);
+        assert!(frame.len() < json.len() / 10, "{} of {} bytes", frame.len(), json.len());
+        peer.stream.write_all(&(frame.len() as u32).to_le_bytes()).unwrap();
+        peer.stream.write_all(&frame).unwrap();
+
+        match process.receive_response().unwrap() {
+            Response::Render { commands: received, .. } => assert_eq!(received, commands),
+            other => panic!("Expected Render response, got {:?}", other),
+        }
+
+        // Large requests go out deflated, small ones framed as is
+        let context = RenderContext { canvas_width: 64, canvas_height: 88, device_pixel_ratio: 1.0 };
+        let metadata = IconMetadata {
+            path: "/home/user/Desktop/notes.txt".to_string(),
+            name: "notes.txt".to_string(),
+            mime_type: Some("text/plain".to_string()),
+            is_directory: false,
+            size: Some(1024),
+            width: 64,
+            height: 64,
+            icon_type: IconType::File,
+            selected: false,
+            hovered: false,
+        };
+        let item = RenderItem { id: 0, metadata, context };
+        process.send_request(&Request::RenderBatch { items: vec![item; 200] }).unwrap();
+        let raw = peer.try_read_raw(DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(raw[4], 1, "deflated");
+        let data = decode_frame(&raw[4..], MAX_MESSAGE_SIZE).unwrap();
+        match Request::deserialize(&data, IpcEncoding::Json).unwrap() {
+            Request::RenderBatch { items } => assert_eq!(items.len(), 200),
+            other => panic!("Expected RenderBatch request, got {:?}", other),
+        }
+
+        process.send_request(&Request::Ping).unwrap();
+        let raw = peer.try_read_raw(DEFAULT_TIMEOUT).unwrap();
+        assert_eq!(&raw[4..], b"\x00{\"type\":\"Ping\"}");
+    }
+
+    #[test]
+    fn test_inflated_response_size_is_limited() {
+        let (mut process, mut peer) = LuaProcess::mock().unwrap();
+        process.features = Features::NONE.with(Feature::Compression);
+
+        let padding = " ".repeat(MAX_MESSAGE_SIZE);
+        let json = format!(r#"{{"type":"Error","message":"x"{}}}"#, padding);
+        let frame = encode_frame(json.as_bytes());
+        peer.stream.write_all(&(frame.len() as u32).to_le_bytes()).unwrap();
+        peer.stream.write_all(&frame).unwrap();
+
+        let err = process.receive_response().unwrap_err();
+        assert!(format!("{:#}", err).contains("exceeds"), "{:#}", err);
+    }
+
+    #[test]
+    fn test_write_times_out_when_peer_never_reads() {
+        let (mut process, _peer) = LuaProcess::mock().unwrap();
+        let request = Request::Event {
+            event: crate::ipc::IconEvent::Drop { paths: vec!["x".repeat(1000); 900] },
+        };
+
+        let start = Instant::now();
+        let err = process.send_request_with_timeout(&request, Duration::from_millis(100)).unwrap_err();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(format!("{:#}", err).contains("Timeout writing"), "{:#}", err);
+        assert!(process.is_stalled());
+    }
+
+    #[test]
+    fn test_large_write_completes_while_peer_reads() {
+        let (mut process, mut peer) = LuaProcess::mock().unwrap();
+        let paths = vec!["x".repeat(1000); 900];
+        let request = Request::Event { event: crate::ipc::IconEvent::Drop { paths: paths.clone() } };
+
+        let reader = std::thread::spawn(move || peer.try_read_raw(Duration::from_secs(2)));
+        process.send_request(&request).unwrap();
+        assert!(!process.is_stalled());
+
+        let raw = reader.join().unwrap().expect("peer should get the whole request");
+        match Request::deserialize(&raw[4..], IpcEncoding::Json).unwrap() {
+            Request::Event { event: crate::ipc::IconEvent::Drop { paths: received } } => assert_eq!(received, paths),
+            other => panic!("Expected Drop event, got {:?}", other),
+        }
+    }
+
+    // ========================================================================
+    // Stderr tests
+    // ========================================================================
+
+    fn stderr_process(script: &str) -> LuaProcess {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]);
+        LuaProcess::spawn_unsandboxed(cmd, PathBuf::from("/tmp/ipc_handler.lua"), PathBuf::from("/tmp/noisy.lua"))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_stderr_lines_are_captured() {
+        let process = stderr_process("echo 'lua: noisy.lua:3: attempt to index a nil value' >&2; echo >&2; echo done >&2; sleep 30");
+
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while process.stderr_tail().len() < 2 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(process.stderr_tail(), ["lua: noisy.lua:3: attempt to index a nil value", "done"]);
+    }
+
+    #[test]
+    fn test_stderr_is_drained_on_kill() {
+        // Much more than a pipe holds, then a last line right before exiting
+        let mut process = stderr_process("i=0; while [ $i -lt 2000 ]; do printf '%0100d\\n' $i >&2; i=$((i+1)); done; echo last >&2");
+        let _ = process.child.wait();
+        process.kill().unwrap();
+
+        let tail = process.stderr_tail();
+        assert_eq!(tail.len(), STDERR_TAIL_LINES);
+        assert_eq!(tail.last().map(String::as_str), Some("last"));
+    }
+
+    #[test]
+    fn test_long_stderr_lines_are_split() {
+        let mut process = stderr_process("head -c 2500 /dev/zero | tr '\\0' x >&2");
+        let _ = process.child.wait();
+        process.kill().unwrap();
+
+        let lengths: Vec<usize> = process.stderr_tail().iter().map(String::len).collect();
+        assert_eq!(lengths, [MAX_STDERR_LINE, MAX_STDERR_LINE, 2500 - 2 * MAX_STDERR_LINE]);
+    }
+
+    #[test]
+    fn test_handshake_version_mismatch_still_fails() {
+        let (mut process, mut peer) = LuaProcess::mock().unwrap();
+        peer.send_response(&Response::HandshakeAck {
+            version: PROTOCOL_VERSION + 1,
+            success: true,
+            encodings: Vec::new(),
+            features: Features::supported().names(),
+        });
+        assert!(process.perform_handshake().is_err());
+    }
+
+    #[test]
+    fn test_handshake_ack_without_encodings_field() {
+        // Handlers predating negotiation don't send `encodings` at all
+        let (mut process, mut peer) = LuaProcess::mock().unwrap();
+        let ack = br#"{"type":"HandshakeAck","version":1,"success":true}"#;
+        peer.stream.write_all(&(ack.len() as u32).to_le_bytes()).unwrap();
+        peer.stream.write_all(ack).unwrap();
+
+        process.perform_handshake().unwrap();
+        assert_eq!(process.encoding(), IpcEncoding::Json);
+    }
+
+    /// The IPC handler's request handlers, loaded into a plain Lua state
+    /// without entering its main loop
+    fn handler_state() -> (mlua::Lua, mlua::Table, mlua::Table) {
+        let (lua, modules) = handler_modules();
+        let handlers = modules.get("Handlers").unwrap();
+        let manager = modules.get("IconManager").unwrap();
+        (lua, handlers, manager)
+    }
+
+    /// The IPC handler's local tables by name: Handlers, IconManager, IPC,
+    /// Inflate, State and ActionPolicy
+    fn handler_modules() -> (mlua::Lua, mlua::Table) {
+        let source = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/lua/ipc_handler.lua")).unwrap();
+        let source = source.trim_end().strip_suffix("main()").unwrap();
+        let lua = mlua::Lua::new();
+        let modules = lua
+            .load(format!(
+                "{}\nreturn {{ Handlers = Handlers, IconManager = IconManager, IPC = IPC, Inflate = Inflate, State = State, \
+                 ActionPolicy = ActionPolicy }}",
+                source
+            ))
+            .eval::<mlua::Table>()
+            .unwrap();
+        (lua, modules)
+    }
+
+    #[test]
+    fn test_handler_state_persists_across_loads() {
+        let (lua, modules) = handler_modules();
+        let state_dir = tempfile::tempdir().unwrap();
+        let scripts = tempfile::tempdir().unwrap();
+        let script = scripts.path().join("toggle.lua");
+        std::fs::write(&script, r#"
+            Icon = {}
+            cvh.state.set("on", not cvh.state.get("on"))
+            cvh.state.set("history", {1, 2, 3})
+            cvh.state.set("window", { x = 10, label = "main" })
+            cvh.state.set("scratch", "gone soon")
+            cvh.state.set("scratch", nil)
+        "#).unwrap();
+
+        let state: mlua::Table = modules.get("State").unwrap();
+        state.set("dir", state_dir.path().to_str().unwrap()).unwrap();
+        let manager: mlua::Table = modules.get("IconManager").unwrap();
+        let load: mlua::Function = manager.get("load_script").unwrap();
+        let script = script.to_str().unwrap();
+        assert!(load.call::<bool>(script).unwrap());
+
+        // The daemon's StateStore reads the same file
+        let path = state_dir.path().join("toggle.lua.state.json");
+        let saved: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved, serde_json::json!({ "on": true, "history": [1, 2, 3], "window": { "x": 10, "label": "main" } }));
+
+        // Reloading sees what the last run saved and flips it back
+        assert!(load.call::<bool>(script).unwrap());
+        let restored: bool = lua
+            .load(r#"
+                local window = cvh.state.get("window")
+                return cvh.state.get("on") == false and #cvh.state.get("history") == 3
+                    and window.x == 10 and window.label == "main"
+                    and cvh.state.get("scratch") == nil and cvh.state.get("missing") == nil
+            "#)
+            .eval()
+            .unwrap();
+        assert!(restored);
+    }
+
+    #[test]
+    fn test_handler_state_rejects_bad_values_and_oversized_state() {
+        let (lua, modules) = handler_modules();
+        let state: mlua::Table = modules.get("State").unwrap();
+        let open: mlua::Function = state.get("open").unwrap();
+
+        // Without a state directory nothing can be saved
+        open.call::<()>((mlua::Value::Nil, "/widgets/w.lua")).unwrap();
+        let err = lua.load(r#"cvh.state.set("on", true)"#).exec().unwrap_err();
+        assert!(err.to_string().contains("no state store"), "got: {}", err);
+
+        let dir = tempfile::tempdir().unwrap();
+        open.call::<()>((dir.path().to_str().unwrap(), "/widgets/w.lua")).unwrap();
+        let err = lua.load(r#"cvh.state.set("f", print)"#).exec().unwrap_err();
+        assert!(err.to_string().contains("cannot encode function"), "got: {}", err);
+
+        state.set("MAX_BYTES", 64).unwrap();
+        lua.load(r#"cvh.state.set("small", "ok")"#).exec().unwrap();
+        let path = dir.path().join("w.lua.state.json");
+        let saved = std::fs::read_to_string(&path).unwrap();
+        let err = lua.load(r#"cvh.state.set("big", string.rep("x", 100))"#).exec().unwrap_err();
+        assert!(err.to_string().contains("state too large"), "got: {}", err);
+
+        // The refused value left the stored state alone
+        let kept: bool = lua
+            .load(r#"return cvh.state.get("small") == "ok" and cvh.state.get("big") == nil"#)
+            .eval()
+            .unwrap();
+        assert!(kept);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), saved);
+    }
+
+    #[test]
+    fn test_handler_inflates_deflate_streams() {
+        use flate2::{write::DeflateEncoder, Compression};
+
+        let (lua, modules) = handler_modules();
+        let inflate: mlua::Function = modules.get::<mlua::Table>("Inflate").unwrap().get("inflate").unwrap();
+        let deflate = |data: &[u8], level: Compression| {
+            let mut encoder = DeflateEncoder::new(Vec::new(), level);
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        };
+
+        let json = br#"{"type":"RenderBatch","items":[{"id":0,"metadata":{"name":"notes.txt"}}]}"#.repeat(500);
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let noise: Vec<u8> = (0..20_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect();
+        // Stored, fixed and dynamic Huffman blocks, and an empty stream
+        let cases: [(&[u8], Compression); 5] = [
+            (&json, Compression::none()),
+            (b"Ping", Compression::fast()),
+            (&json, Compression::fast()),
+            (&noise, Compression::best()),
+            (b"", Compression::default()),
+        ];
+        for (data, level) in cases {
+            let compressed = lua.create_string(deflate(data, level)).unwrap();
+            let inflated: mlua::String = inflate.call((compressed, MAX_MESSAGE_SIZE)).unwrap();
+            assert_eq!(inflated.as_bytes().as_ref(), data, "{} bytes at {:?}", data.len(), level);
+        }
+
+        // Corrupt and truncated streams fail instead of returning garbage
+        let truncated = deflate(&json, Compression::fast());
+        let truncated = lua.create_string(&truncated[..truncated.len() / 2]).unwrap();
+        assert!(inflate.call::<mlua::String>((truncated, MAX_MESSAGE_SIZE)).is_err());
+        let reserved = lua.create_string([0x07]).unwrap();
+        assert!(inflate.call::<mlua::String>((reserved, MAX_MESSAGE_SIZE)).is_err());
+    }
+
+    #[test]
+    fn test_handler_negotiates_and_decodes_frames() {
+        let (lua, modules) = handler_modules();
+        let handlers: mlua::Table = modules.get("Handlers").unwrap();
+        let ipc: mlua::Table = modules.get("IPC").unwrap();
+
+        // Feed io.read from `input` and collect io.write into `output`
+        lua.load(r#"
+            input, pos, output = "", 1, {}
+            io.read = function(n) local s = input:sub(pos, pos + n - 1) pos = pos + n return s end
+            io.write = function(s) output[#output + 1] = s end
+            io.flush = function() end
+        "#)
+        .exec()
+        .unwrap();
+        let receive = |frame: &[u8]| -> (Option<mlua::Table>, Option<String>) {
+            let mut input = (frame.len() as u32).to_le_bytes().to_vec();
+            input.extend_from_slice(frame);
+            lua.globals().set("input", lua.create_string(input).unwrap()).unwrap();
+            lua.globals().set("pos", 1).unwrap();
+            ipc.get::<mlua::Function>("receive").unwrap().call(()).unwrap()
+        };
+
+        let request = lua
+            .load(r#"{ type = "Handshake", version = 1, encodings = { "json" }, features = { "ping", "deflate" } }"#)
+            .eval::<mlua::Table>()
+            .unwrap();
+        let ack: mlua::Table = handlers.get::<mlua::Function>("Handshake").unwrap().call(request).unwrap();
+        let features: Vec<String> = ack.get("features").unwrap();
+        assert_eq!(features, ["render_batch", "ping", "deflate"]);
+        // The main loop switches to frames once the acknowledgement is sent
+        assert!(ipc.get::<bool>("pending_framed").unwrap());
+        ipc.set("framed", true).unwrap();
+
+        // A deflated request and a plain one
+        let json = format!(r#"{{"type":"Event","event":{{"Drop":{{"paths":["{}"]}}}}}}"#, "x".repeat(20_000));
+        let frame = encode_frame(json.as_bytes());
+        assert_eq!(frame[0], 1, "deflated");
+        let (request, err) = receive(&frame);
+        assert_eq!(err, None);
+        assert_eq!(request.unwrap().get::<String>("type").unwrap(), "Event");
+        let (request, _) = receive(&encode_frame(br#"{"type":"Ping"}"#));
+        assert_eq!(request.unwrap().get::<String>("type").unwrap(), "Ping");
+
+        // Inflated sizes are held to the same limit
+        let json = format!(r#"{{"type":"Ping","pad":"{}"}}"#, " ".repeat(MAX_MESSAGE_SIZE));
+        let (request, err) = receive(&encode_frame(json.as_bytes()));
+        assert!(request.is_none());
+        assert!(err.unwrap().contains("exceeds"));
+
+        // Responses go out as plain frames
+        let pong = lua.create_table().unwrap();
+        pong.set("type", "Pong").unwrap();
+        ipc.get::<mlua::Function>("send").unwrap().call::<()>(pong).unwrap();
+        let written: Vec<mlua::String> = lua.globals().get("output").unwrap();
+        let written: Vec<u8> = written.iter().flat_map(|s| s.as_bytes().to_vec()).collect();
+        assert_eq!(decode_frame(&written[4..], MAX_MESSAGE_SIZE).unwrap(), br#"{"type":"Pong"}"#);
+        assert_eq!(written[4], 0, "plain");
+    }
+
+    #[test]
+    fn test_handler_turns_cvh_open_into_event_action() {
+        let (lua, handlers, manager) = handler_state();
+        let icon: mlua::Table = lua
+            .load(r#"{
+                path = "/home/u/Desktop/notes.txt",
+                on_click = function(self, button)
+                    if button == 1 then cvh.open(self.path) end
+                    if button == 2 then cvh.spawn("foot") cvh.open("/tmp") end
+                end,
+            }"#)
+            .eval()
+            .unwrap();
+        manager.set("icon", icon).unwrap();
+
+        let event = |button: u32| -> (bool, Option<(String, String)>) {
+            let request = lua.create_table().unwrap();
+            let click = lua.create_table().unwrap();
+            click.set("button", button).unwrap();
+            let event = lua.create_table().unwrap();
+            event.set("Click", click).unwrap();
+            request.set("event", event).unwrap();
+
+            let response: mlua::Table = handlers.get::<mlua::Function>("Event").unwrap().call(request).unwrap();
+            let action = response.get::<Option<mlua::Table>>("action").unwrap().map(|action| {
+                (action.get("action").unwrap(), action.get("payload").unwrap())
+            });
+            (response.get("handled").unwrap(), action)
+        };
+
+        assert_eq!(
+            event(1),
+            (true, Some(("open".to_string(), "/home/u/Desktop/notes.txt".to_string())))
+        );
+        // "foot" isn't allowed by default, so the open is the first request
+        assert_eq!(event(2), (true, Some(("open".to_string(), "/tmp".to_string()))));
+        assert_eq!(event(3), (false, None));
+    }
+
+    #[test]
+    fn test_handler_cvh_open_reports_policy_and_dropped_requests() {
+        let (lua, modules) = handler_modules();
+        let policy: mlua::Table = modules.get("ActionPolicy").unwrap();
+        let commands = lua.create_table().unwrap();
+        commands.set("foot", true).unwrap();
+        commands.set("xdg-open", true).unwrap();
+        policy.set("allowed_commands", commands).unwrap();
+
+        let manager: mlua::Table = modules.get("IconManager").unwrap();
+        let icon: mlua::Table = lua
+            .load(r#"{
+                on_click = function(self)
+                    results = {
+                        cvh.open("/home/u/../../etc/passwd"),
+                        cvh.open("javascript:alert(1)"),
+                        cvh.spawn("rm -rf /"),
+                        cvh.spawn("xdg-open /tmp /etc"),
+                        cvh.spawn("foot -e htop"),
+                        cvh.open("https://example.com"),
+                    }
+                end,
+            }"#)
+            .eval()
+            .unwrap();
+        manager.set("icon", icon).unwrap();
+
+        // Requests made outside an event would be dropped
+        let outside: bool = lua.load(r#"return cvh.open("/tmp")"#).eval().unwrap();
+        assert!(!outside);
+
+        let event = lua.load(r#"{ type = "Click", button = 1 }"#).eval::<mlua::Table>().unwrap();
+        let response: mlua::Table = manager.get::<mlua::Function>("call_event").unwrap().call(event).unwrap();
+        let results: Vec<bool> = lua.globals().get("results").unwrap();
+        // Rejected by the policy, then one accepted, then one dropped as an extra
+        assert_eq!(results, [false, false, false, false, true, false]);
+        let action: mlua::Table = response.get("action").unwrap();
+        assert_eq!(action.get::<String>("payload").unwrap(), "foot -e htop");
+    }
 }
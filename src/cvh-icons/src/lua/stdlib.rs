@@ -4,6 +4,13 @@
 
 use anyhow::Result;
 use mlua::{Lua, Table};
+use std::cmp::Ordering;
+
+/// Maximum number of elements `table.sort` accepts, bounding comparator calls
+const MAX_SORT_LEN: usize = 10_000;
+
+/// Maximum number of values `table.unpack` returns
+const MAX_UNPACK_LEN: i64 = 10_000;
 
 /// Install safe standard library extensions
 pub fn install(lua: &Lua) -> Result<()> {
@@ -60,27 +67,13 @@ pub fn install(lua: &Lua) -> Result<()> {
         })
     })?)?;
 
-    // Safe pairs iterator
-    globals.set("pairs", lua.create_function(|lua, table: Table| {
-        let iter = lua.create_function(|_, (t, k): (Table, mlua::Value)| {
-            let next = t.pairs::<mlua::Value, mlua::Value>();
-            let mut found_current = k == mlua::Value::Nil;
-
-            for pair in next {
-                if let Ok((key, value)) = pair {
-                    if found_current {
-                        return Ok((Some(key), Some(value)));
-                    }
-                    if key == k {
-                        found_current = true;
-                    }
-                }
-            }
-
-            Ok((None, None))
-        })?;
-
-        Ok((iter, table, mlua::Value::Nil))
+    // Safe pairs iterator, delegating to the VM's native `next` so iteration is
+    // linear. Like the rest of the sandbox it ignores `__pairs` metamethods.
+    // The same `next` stays exposed as a global, so both iterate identically.
+    let next: mlua::Function = globals.get("next")?;
+    globals.set("next", next.clone())?;
+    globals.set("pairs", lua.create_function(move |_, table: Table| {
+        Ok((next.clone(), table, mlua::Value::Nil))
     })?)?;
 
     // Safe ipairs iterator
@@ -108,42 +101,76 @@ pub fn install(lua: &Lua) -> Result<()> {
     math.set("sin", lua.create_function(|_, n: f64| Ok(n.sin()))?)?;
     math.set("cos", lua.create_function(|_, n: f64| Ok(n.cos()))?)?;
     math.set("tan", lua.create_function(|_, n: f64| Ok(n.tan()))?)?;
-    math.set("pi", std::f64::consts::PI)?;
-    math.set("random", lua.create_function(|_, (a, b): (Option<i64>, Option<i64>)| {
-        use std::time::{SystemTime, UNIX_EPOCH};
-        // Simple pseudo-random (not cryptographic!)
-        let seed = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .map(|d| d.as_nanos())
-            .unwrap_or(0) as u64;
-        let rand = ((seed.wrapping_mul(1103515245).wrapping_add(12345)) >> 16) as f64 / 32768.0;
-
-        Ok(match (a, b) {
-            (None, None) => rand,
-            (Some(max), None) => (rand * max as f64).floor() + 1.0,
-            (Some(min), Some(max)) => (rand * (max - min + 1) as f64).floor() + min as f64,
-            (None, Some(max)) => (rand * max as f64).floor() + 1.0,
+    math.set("asin", lua.create_function(|_, n: f64| Ok(n.asin()))?)?;
+    math.set("acos", lua.create_function(|_, n: f64| Ok(n.acos()))?)?;
+    math.set("atan", lua.create_function(|_, (y, x): (f64, Option<f64>)| Ok(y.atan2(x.unwrap_or(1.0))))?)?;
+    math.set("exp", lua.create_function(|_, n: f64| Ok(n.exp()))?)?;
+    math.set("log", lua.create_function(|_, (n, base): (f64, Option<f64>)| {
+        Ok(match base {
+            None => n.ln(),
+            Some(2.0) => n.log2(),
+            Some(10.0) => n.log10(),
+            Some(b) => n.ln() / b.ln(),
         })
     })?)?;
+    math.set("pow", lua.create_function(|_, (a, b): (f64, f64)| Ok(a.powf(b)))?)?;
+    math.set("fmod", lua.create_function(|_, (a, b): (f64, f64)| Ok(a % b))?)?;
+    math.set("modf", lua.create_function(|_, n: f64| {
+        // Infinities have no fractional part
+        let int = n.trunc();
+        let frac = if n.is_infinite() { 0.0 } else { n - int };
+        Ok((int, frac))
+    })?)?;
+    math.set("pi", std::f64::consts::PI)?;
+    math.set("huge", f64::INFINITY)?;
+    math.set("maxinteger", i64::MAX)?;
+    math.set("mininteger", i64::MIN)?;
+    lua.set_app_data(Rng::new(clock_seed()));
+    math.set("random", lua.create_function(|lua, (m, n): (Option<i64>, Option<i64>)| {
+        let mut rng = lua
+            .app_data_mut::<Rng>()
+            .ok_or_else(|| mlua::Error::runtime("random generator not initialized"))?;
+
+        let (low, high) = match (m, n) {
+            (None, None) => return Ok(mlua::Value::Number(rng.next_f64())),
+            (Some(max), None) | (None, Some(max)) => (1, max),
+            (Some(min), Some(max)) => (min, max),
+        };
+        if low > high {
+            return Err(mlua::Error::runtime("bad argument to 'random' (interval is empty)"));
+        }
+        Ok(mlua::Value::Integer(rng.next_in_range(low, high)))
+    })?)?;
+    math.set("randomseed", lua.create_function(|lua, seed: Option<i64>| {
+        let seed = seed.map_or_else(clock_seed, |s| s as u64);
+        lua.set_app_data(Rng::new(seed));
+        Ok(())
+    })?)?;
     globals.set("math", math)?;
 
     // Table library (safe subset)
     let table_lib = lua.create_table()?;
-    table_lib.set("insert", lua.create_function(|_, (t, pos, value): (Table, Option<i64>, mlua::Value)| {
-        match pos {
-            Some(p) => {
-                // Shift elements and insert at position
-                let len = t.len()? as i64;
+    table_lib.set("insert", lua.create_function(|lua, (t, args): (Table, mlua::MultiValue)| {
+        let len = t.len()?;
+        let mut args = args.into_iter();
+        match (args.next(), args.next(), args.next()) {
+            // table.insert(t, value): append
+            (Some(value), None, None) => t.set(len + 1, value)?,
+            // table.insert(t, pos, value): shift elements up and insert at pos
+            (Some(pos), Some(value), None) => {
+                let p = lua.coerce_integer(pos)?.ok_or_else(|| {
+                    mlua::Error::runtime("bad argument #2 to 'insert' (number expected)")
+                })?;
+                if p < 1 || p > len + 1 {
+                    return Err(mlua::Error::runtime("bad argument #2 to 'insert' (position out of bounds)"));
+                }
                 for i in (p..=len).rev() {
                     let v: mlua::Value = t.get(i)?;
                     t.set(i + 1, v)?;
                 }
                 t.set(p, value)?;
             }
-            None => {
-                let len = t.len()?;
-                t.set(len + 1, value)?;
-            }
+            _ => return Err(mlua::Error::runtime("wrong number of arguments to 'insert'")),
         }
         Ok(())
     })?)?;
@@ -171,7 +198,65 @@ pub fn install(lua: &Lua) -> Result<()> {
         }
         Ok(parts.join(&sep))
     })?)?;
+    table_lib.set("sort", lua.create_function(|_, (t, comp): (Table, Option<mlua::Function>)| {
+        let len = t.raw_len();
+        if len > MAX_SORT_LEN {
+            return Err(mlua::Error::runtime(format!(
+                "table too large to sort ({} elements, max {})", len, MAX_SORT_LEN
+            )));
+        }
+
+        let values = (1..=len)
+            .map(|i| t.get::<mlua::Value>(i))
+            .collect::<mlua::Result<Vec<_>>>()?;
+
+        let sorted = merge_sort(values, &mut |a, b| match &comp {
+            Some(f) => Ok(is_truthy(&f.call::<mlua::Value>((a.clone(), b.clone()))?)),
+            None => Ok(compare_values(a, b)? == Ordering::Less),
+        })?;
+
+        for (i, v) in sorted.into_iter().enumerate() {
+            t.set(i + 1, v)?;
+        }
+        Ok(())
+    })?)?;
+    let unpack = lua.create_function(|_, (t, i, j): (Table, Option<i64>, Option<i64>)| {
+        let i = i.unwrap_or(1);
+        let j = match j {
+            Some(j) => j,
+            None => t.len()?,
+        };
+        if j >= i && j.checked_sub(i).is_none_or(|n| n >= MAX_UNPACK_LEN) {
+            return Err(mlua::Error::runtime("too many results to unpack"));
+        }
+        (i..=j).map(|k| t.get::<mlua::Value>(k)).collect::<mlua::Result<mlua::MultiValue>>()
+    })?;
+    table_lib.set("unpack", unpack.clone())?;
     globals.set("table", table_lib)?;
+    // Lua 5.1 name, still common in scripts
+    globals.set("unpack", unpack)?;
+
+    // select('#', ...) counts the varargs, select(n, ...) returns them from n on
+    globals.set("select", lua.create_function(|_, (n, args): (mlua::Value, mlua::MultiValue)| {
+        if let mlua::Value::String(s) = &n {
+            if s.as_bytes() == b"#" {
+                return Ok(mlua::MultiValue::from_iter([mlua::Value::Integer(args.len() as i64)]));
+            }
+        }
+        let n = match n {
+            mlua::Value::Integer(n) => n,
+            mlua::Value::Number(n) if n.fract() == 0.0 => n as i64,
+            _ => return Err(mlua::Error::runtime("bad argument #1 to 'select' (number expected)")),
+        };
+
+        let len = args.len() as i64;
+        let start = match n {
+            n if n < 0 && n.checked_neg().is_some_and(|back| back <= len) => len + n,
+            n if n > 0 => (n - 1).min(len),
+            _ => return Err(mlua::Error::runtime("bad argument #1 to 'select' (index out of range)")),
+        };
+        Ok(args.into_iter().skip(start as usize).collect())
+    })?)?;
 
     // Assert function
     globals.set("assert", lua.create_function(|_, (cond, msg): (bool, Option<String>)| {
@@ -189,3 +274,105 @@ pub fn install(lua: &Lua) -> Result<()> {
 
     Ok(())
 }
+
+/// Per-state pseudo-random generator backing `math.random` (splitmix64, not
+/// cryptographic)
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform float in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform integer in [low, high]
+    fn next_in_range(&mut self, low: i64, high: i64) -> i64 {
+        let span = (high.wrapping_sub(low) as u64).wrapping_add(1);
+        if span == 0 {
+            // The range covers every i64
+            return self.next_u64() as i64;
+        }
+        let offset = ((u128::from(self.next_u64()) * u128::from(span)) >> 64) as u64;
+        low.wrapping_add(offset as i64)
+    }
+}
+
+/// Seed derived from the system clock
+fn clock_seed() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+/// Lua truthiness: everything except `nil` and `false`
+fn is_truthy(value: &mlua::Value) -> bool {
+    !matches!(value, mlua::Value::Nil | mlua::Value::Boolean(false))
+}
+
+/// Default `table.sort` ordering: numbers numerically, strings bytewise
+fn compare_values(a: &mlua::Value, b: &mlua::Value) -> mlua::Result<Ordering> {
+    use mlua::Value::{Integer, Number, String};
+
+    let ordering = match (a, b) {
+        (Integer(x), Integer(y)) => Some(x.cmp(y)),
+        (Integer(x), Number(y)) => (*x as f64).partial_cmp(y),
+        (Number(x), Integer(y)) => x.partial_cmp(&(*y as f64)),
+        (Number(x), Number(y)) => x.partial_cmp(y),
+        (String(x), String(y)) => Some(x.as_bytes().cmp(&y.as_bytes())),
+        _ => {
+            return Err(mlua::Error::runtime(format!(
+                "attempt to compare {} with {}", a.type_name(), b.type_name()
+            )))
+        }
+    };
+
+    // NaN compares as neither less nor greater
+    Ok(ordering.unwrap_or(Ordering::Equal))
+}
+
+/// Stable merge sort with a fallible `less` comparator
+///
+/// Unlike `slice::sort_by` this never panics on inconsistent comparators,
+/// which user-supplied Lua functions may well be
+fn merge_sort(
+    mut values: Vec<mlua::Value>,
+    less: &mut impl FnMut(&mlua::Value, &mlua::Value) -> mlua::Result<bool>,
+) -> mlua::Result<Vec<mlua::Value>> {
+    if values.len() <= 1 {
+        return Ok(values);
+    }
+
+    let right = values.split_off(values.len() / 2);
+    let left = merge_sort(values, less)?;
+    let right = merge_sort(right, less)?;
+
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Take from the right only when strictly less, keeping the sort stable
+        if less(r, l)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+
+    Ok(merged)
+}
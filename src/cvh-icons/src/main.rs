@@ -8,13 +8,16 @@ use clap::Parser;
 use tracing::info;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+mod clock;
 mod config;
 mod daemon;
+mod eval;
 mod icons;
 mod ipc;
 mod lua;
 mod renderer;
 mod sandbox;
+mod validate;
 mod wayland;
 
 /// CVH Icons - Desktop icon manager
@@ -43,11 +46,51 @@ struct Args {
     /// List available icon scripts
     #[arg(long)]
     list_scripts: bool,
+
+    /// Load and render a single icon script once, report the draw
+    /// commands and exit
+    #[arg(long, value_name = "SCRIPT")]
+    validate: Option<std::path::PathBuf>,
+
+    /// Run a Lua snippet in the icon sandbox, print its results and exit
+    #[arg(long, value_name = "CODE", conflicts_with = "repl")]
+    eval: Option<String>,
+
+    /// Read Lua statements from stdin and run them in the icon sandbox
+    #[arg(long)]
+    repl: bool,
+
+    /// Write each icon's draw commands as JSON lines to this file
+    /// (`-` for stdout)
+    #[arg(long, value_name = "FILE")]
+    trace_render: Option<std::path::PathBuf>,
+
+    /// Path the `--landlock-exec` command may read (sandbox internal)
+    #[arg(long, hide = true, value_name = "PATH")]
+    landlock_read: Vec<std::path::PathBuf>,
+
+    /// Path the `--landlock-exec` command may write (sandbox internal)
+    #[arg(long, hide = true, value_name = "PATH")]
+    landlock_write: Vec<std::path::PathBuf>,
+
+    /// Apply the Landlock rules and exec this command; the Lua sandbox
+    /// runs the interpreter through it
+    #[arg(long, hide = true, num_args = 1.., value_name = "COMMAND")]
+    landlock_exec: Vec<std::ffi::OsString>,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
+    // Inside the Lua sandbox: confine and exec before doing anything else
+    if !args.landlock_exec.is_empty() {
+        let ruleset = sandbox::landlock::Ruleset::from_paths(
+            args.landlock_read.iter().map(std::path::PathBuf::as_path),
+            args.landlock_write.iter().map(std::path::PathBuf::as_path),
+        );
+        return sandbox::landlock::exec_confined(&ruleset, &args.landlock_exec);
+    }
+
     // Initialize logging
     let filter = if args.verbose {
         "cvh_icons=debug,warn"
@@ -65,6 +108,18 @@ fn main() -> Result<()> {
     // Load configuration
     let config = config::Config::load(args.config.as_deref())?;
 
+    if let Some(script) = args.validate {
+        return validate::run(&script, args.trace_render.as_deref(), &config);
+    }
+
+    if let Some(code) = args.eval {
+        return eval::run(&code, &config);
+    }
+
+    if args.repl {
+        return eval::repl(&config);
+    }
+
     if args.list_scripts {
         // List available Lua scripts
         list_scripts(&config)?;
@@ -84,6 +139,10 @@ fn main() -> Result<()> {
 
     // Initialize the daemon
     let mut daemon = daemon::IconDaemon::new(config, desktop_dir)?;
+    daemon.set_config_path(args.config);
+    if let Some(target) = args.trace_render {
+        daemon.set_render_trace(renderer::trace::RenderTrace::open(&target)?);
+    }
 
     // Run the main loop (uses calloop event loop)
     daemon.run()?;
@@ -5,16 +5,24 @@
 use anyhow::Result;
 use fontdue::{Font, FontSettings};
 use image::imageops::FilterType;
-use std::path::Path;
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 use tiny_skia::{
-    Color, FillRule, LineCap, LineJoin, Paint, Pixmap, PixmapPaint, PathBuilder, Rect, Stroke,
-    Transform,
+    BlendMode, Color, FillRule, LineCap, LineJoin, LinearGradient, Mask, Paint, PathBuilder,
+    Pixmap, PixmapPaint, Point, Rect, SpreadMode, Stroke, StrokeDash, Transform,
 };
 use tracing::warn;
+use unicode_segmentation::UnicodeSegmentation;
 
-use crate::icons::DesktopIcon;
+use crate::config::Config;
+use crate::icons::{DesktopIcon, IconTheme};
 use crate::lua::DrawCommand;
 
+pub mod trace;
+
 /// Text alignment options
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum TextAlign {
@@ -66,17 +74,80 @@ fn load_default_font() -> Option<Font> {
     None
 }
 
+/// Horizontal space kept free on each side of a label
+const LABEL_PADDING: f32 = 2.0;
+
+/// Suffix marking a shortened label
+const ELLIPSIS: &str = "...";
+
+/// Space between the icon artwork and the edge of its cell
+const ICON_MARGIN: f32 = 8.0;
+
+/// Load a user-configured font, sharing it between renderers
+///
+/// Only fonts that loaded are cached, so a path that failed is tried again
+/// on the next config reload.
+fn load_configured_font(path: &Path) -> Option<Arc<Font>> {
+    static FONTS: OnceLock<Mutex<HashMap<PathBuf, Arc<Font>>>> = OnceLock::new();
+    let mut fonts = FONTS.get_or_init(Default::default).lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(font) = fonts.get(path) {
+        return Some(font.clone());
+    }
+
+    let font_data = std::fs::read(path)
+        .map_err(|e| warn!("Failed to read font {}: {}", path.display(), e))
+        .ok()?;
+    let font = Font::from_bytes(font_data, FontSettings::default())
+        .map_err(|e| warn!("Failed to parse font {}: {}", path.display(), e))
+        .ok()
+        .map(Arc::new)?;
+    fonts.insert(path.to_path_buf(), font.clone());
+    Some(font)
+}
+
+/// Default font shared by every renderer, loaded on first use
+pub(crate) fn shared_default_font() -> Option<Arc<Font>> {
+    static FONT: OnceLock<Option<Arc<Font>>> = OnceLock::new();
+    FONT.get_or_init(|| load_default_font().map(Arc::new)).clone()
+}
+
+/// Font `config` asks for: `font_path`, then each of `font_fallbacks`,
+/// then the built-in search paths
+pub fn configured_font(config: &Config) -> Option<Arc<Font>> {
+    config
+        .font_path
+        .iter()
+        .chain(&config.font_fallbacks)
+        .find_map(|path| load_configured_font(path))
+        .or_else(shared_default_font)
+}
+
+/// Width in pixels of `text` drawn at `size` with `font`, as `render_text`
+/// lays it out
+pub fn measure_text(font: &Font, text: &str, size: f32) -> f32 {
+    text_width(text, &|c| font.metrics(c, size).advance_width)
+}
+
+/// Theme icons by name and icon size
+type ThemeIconCache = HashMap<(&'static str, u32), Option<Arc<Pixmap>>>;
+
 /// Icon renderer
 #[allow(dead_code)]
 pub struct IconRenderer {
-    /// Icon size
+    /// Configured icon size (icons carry their own, see `DesktopIcon::size`)
     size: u32,
 
     /// Font for labels
     font_size: f32,
 
     /// Loaded font for text rendering (None if loading failed)
-    font: Option<Font>,
+    font: Option<Arc<Font>>,
+
+    /// Icon theme used instead of placeholder shapes (None draws placeholders)
+    theme: Option<IconTheme>,
+
+    /// Loaded theme icons, None when the theme lacks the icon
+    theme_icons: Mutex<ThemeIconCache>,
 
     /// Colors
     label_fg: Color,
@@ -90,19 +161,33 @@ impl IconRenderer {
         Self {
             size,
             font_size,
-            font: load_default_font(),
+            font: shared_default_font(),
+            theme: None,
+            theme_icons: Mutex::default(),
             label_fg: Color::WHITE,
             label_bg: Color::from_rgba8(0, 0, 0, 128),
             selection_color: Color::from_rgba8(136, 192, 208, 64),
         }
     }
 
+    /// Create a renderer using the configured font chain (see
+    /// `configured_font`) and icon theme
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            font: configured_font(config),
+            theme: Some(IconTheme::new(&config.icon_theme)),
+            ..Self::with_font(config.icon_size, config.font_size, None)
+        }
+    }
+
     /// Create a renderer with a specific font (useful for testing)
     pub fn with_font(size: u32, font_size: f32, font: Option<Font>) -> Self {
         Self {
             size,
             font_size,
-            font,
+            font: font.map(Arc::new),
+            theme: None,
+            theme_icons: Mutex::default(),
             label_fg: Color::WHITE,
             label_bg: Color::from_rgba8(0, 0, 0, 128),
             selection_color: Color::from_rgba8(136, 192, 208, 64),
@@ -119,6 +204,7 @@ impl IconRenderer {
     /// * `size` - Font size in pixels
     /// * `color` - Text color as tiny-skia Color
     /// * `align` - Text alignment (left, center, right)
+    #[allow(clippy::too_many_arguments)]
     pub fn render_text(
         &self,
         pixmap: &mut Pixmap,
@@ -128,6 +214,23 @@ impl IconRenderer {
         size: f32,
         color: Color,
         align: TextAlign,
+    ) {
+        self.draw_text(pixmap, text, x, y, size, color, align, Transform::identity(), None);
+    }
+
+    /// `render_text` under `transform`, drawing only where `mask` is set
+    #[allow(clippy::too_many_arguments)]
+    fn draw_text(
+        &self,
+        pixmap: &mut Pixmap,
+        text: &str,
+        x: f32,
+        y: f32,
+        size: f32,
+        color: Color,
+        align: TextAlign,
+        transform: Transform,
+        mask: Option<&Mask>,
     ) {
         let font = match &self.font {
             Some(f) => f,
@@ -201,8 +304,8 @@ impl IconRenderer {
                         glyph_y_int,
                         glyph_pixmap.as_ref(),
                         &PixmapPaint::default(),
-                        Transform::identity(),
-                        None,
+                        transform,
+                        mask,
                     );
                 }
             }
@@ -211,6 +314,60 @@ impl IconRenderer {
         }
     }
 
+    /// Render text word-wrapped to a box of width `w`
+    ///
+    /// `x` is the left edge of the box and `y` the baseline of the first line;
+    /// each line is aligned within the box.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_text_box(
+        &self,
+        pixmap: &mut Pixmap,
+        text: &str,
+        x: f32,
+        y: f32,
+        w: f32,
+        size: f32,
+        color: Color,
+        align: TextAlign,
+    ) {
+        self.draw_text_box(pixmap, text, x, y, w, size, color, align, Transform::identity(), None);
+    }
+
+    /// `render_text_box` under `transform`, drawing only where `mask` is set
+    #[allow(clippy::too_many_arguments)]
+    fn draw_text_box(
+        &self,
+        pixmap: &mut Pixmap,
+        text: &str,
+        x: f32,
+        y: f32,
+        w: f32,
+        size: f32,
+        color: Color,
+        align: TextAlign,
+        transform: Transform,
+        mask: Option<&Mask>,
+    ) {
+        let Some(font) = &self.font else {
+            return;
+        };
+
+        let line_height = font
+            .horizontal_line_metrics(size)
+            .map_or(size * 1.2, |m| m.new_line_size);
+        let anchor_x = match align {
+            TextAlign::Left => x,
+            TextAlign::Center => x + w / 2.0,
+            TextAlign::Right => x + w,
+        };
+
+        let lines = wrap_lines(text, w, |c| font.metrics(c, size).advance_width);
+        for (i, line) in lines.iter().enumerate() {
+            let baseline = y + i as f32 * line_height;
+            self.draw_text(pixmap, line, anchor_x, baseline, size, color, align, transform, mask);
+        }
+    }
+
     /// Render an image to a pixmap
     ///
     /// # Arguments
@@ -228,6 +385,24 @@ impl IconRenderer {
         y: f32,
         w: f32,
         h: f32,
+    ) {
+        self.draw_image(pixmap, path, x, y, w, h, 1.0, Transform::identity(), None);
+    }
+
+    /// `render_image` at `opacity` under `transform`, drawing only where
+    /// `mask` is set
+    #[allow(clippy::too_many_arguments)]
+    fn draw_image(
+        &self,
+        pixmap: &mut Pixmap,
+        path: &str,
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        opacity: f32,
+        transform: Transform,
+        mask: Option<&Mask>,
     ) {
         // Validate dimensions
         if w <= 0.0 || h <= 0.0 {
@@ -253,11 +428,8 @@ impl IconRenderer {
         // Scale the image to the requested dimensions using bilinear filter
         let scaled = img.resize_exact(target_width, target_height, FilterType::Triangle);
 
-        // Convert to RGBA8
-        let rgba = scaled.to_rgba8();
-
-        // Create a pixmap for the image
-        let img_pixmap = match Pixmap::new(target_width, target_height) {
+        // Convert to a premultiplied pixmap
+        let img_pixmap = match rgba_to_pixmap(&scaled.to_rgba8()) {
             Some(p) => p,
             None => {
                 warn!("Failed to create pixmap for image '{}'", path);
@@ -265,48 +437,22 @@ impl IconRenderer {
             }
         };
 
-        // We need a mutable pixmap to fill
-        let mut img_pixmap = img_pixmap;
-        let pixels = img_pixmap.pixels_mut();
-
-        // Copy image data to pixmap with premultiplied alpha
-        for (i, pixel) in rgba.pixels().enumerate() {
-            let [r, g, b, a] = pixel.0;
-
-            if a == 0 {
-                // Fully transparent, skip (pixmap is already zero-initialized)
-                continue;
-            }
-
-            // Premultiply alpha for tiny-skia
-            let alpha = a as f32 / 255.0;
-            let pm_r = (r as f32 * alpha) as u8;
-            let pm_g = (g as f32 * alpha) as u8;
-            let pm_b = (b as f32 * alpha) as u8;
-
-            if let Some(color) = tiny_skia::PremultipliedColorU8::from_rgba(pm_r, pm_g, pm_b, a) {
-                pixels[i] = color;
-            }
-        }
-
         // Blit image pixmap to main pixmap with alpha blending
         let x_int = x.round() as i32;
         let y_int = y.round() as i32;
 
-        pixmap.draw_pixmap(
-            x_int,
-            y_int,
-            img_pixmap.as_ref(),
-            &PixmapPaint::default(),
-            Transform::identity(),
-            None,
-        );
+        let paint = PixmapPaint { opacity, ..Default::default() };
+        pixmap.draw_pixmap(x_int, y_int, img_pixmap.as_ref(), &paint, transform, mask);
     }
 
-    /// Render an icon to a pixmap
+    /// Render an icon to a pixmap at the icon's own size
+    ///
+    /// The label space is kept even when the icon's label is hidden, so all
+    /// icons of one size line up.
     pub fn render(&self, icon: &DesktopIcon) -> Result<Pixmap> {
-        let total_height = self.size + 24; // Icon + label space
-        let mut pixmap = Pixmap::new(self.size, total_height)
+        let size = icon.size();
+        let total_height = size + 24; // Icon + label space
+        let mut pixmap = Pixmap::new(size, total_height)
             .ok_or_else(|| anyhow::anyhow!("Failed to create pixmap"))?;
 
         // Clear with transparent
@@ -317,21 +463,58 @@ impl IconRenderer {
             let mut paint = Paint::default();
             paint.set_color(self.selection_color);
 
-            let rect = Rect::from_xywh(0.0, 0.0, self.size as f32, total_height as f32)
+            let rect = Rect::from_xywh(0.0, 0.0, size as f32, total_height as f32)
                 .ok_or_else(|| anyhow::anyhow!("Invalid rect"))?;
 
             pixmap.fill_rect(rect, &paint, Transform::identity(), None);
         }
 
-        // Draw icon placeholder (would load actual icon in production)
-        self.draw_icon_placeholder(&mut pixmap, icon)?;
+        // Draw the themed icon, or a placeholder shape if the theme lacks it
+        if !self.draw_theme_icon(&mut pixmap, icon) {
+            self.draw_icon_placeholder(&mut pixmap, icon)?;
+        }
 
         // Draw label
-        self.draw_label(&mut pixmap, icon.name())?;
+        if icon.show_label() {
+            self.draw_label(&mut pixmap, icon.name(), size)?;
+        }
 
         Ok(pixmap)
     }
 
+    /// Draw the theme's icon for `icon`, returning false if there is none
+    fn draw_theme_icon(&self, pixmap: &mut Pixmap, icon: &DesktopIcon) -> bool {
+        let Some(image) = self.theme_icon(icon.icon_name(), icon.size()) else {
+            return false;
+        };
+
+        pixmap.draw_pixmap(
+            ICON_MARGIN as i32,
+            ICON_MARGIN as i32,
+            (*image).as_ref(),
+            &PixmapPaint::default(),
+            Transform::identity(),
+            None,
+        );
+        true
+    }
+
+    /// Look up and load a theme icon for an icon of `icon_size`, caching the
+    /// result (including misses)
+    fn theme_icon(&self, name: &'static str, icon_size: u32) -> Option<Arc<Pixmap>> {
+        let theme = self.theme.as_ref()?;
+        let mut icons = self.theme_icons.lock().unwrap_or_else(|e| e.into_inner());
+
+        icons
+            .entry((name, icon_size))
+            .or_insert_with(|| {
+                let path = theme.lookup(name, icon_size)?;
+                let size = (icon_size as f32 - ICON_MARGIN * 2.0).max(1.0) as u32;
+                load_icon_file(&path, size).map(Arc::new)
+            })
+            .clone()
+    }
+
     /// Draw a placeholder icon shape
     fn draw_icon_placeholder(&self, pixmap: &mut Pixmap, icon: &DesktopIcon) -> Result<()> {
         let mut paint = Paint::default();
@@ -351,8 +534,8 @@ impl IconRenderer {
 
         paint.set_color(color);
 
-        let margin = 8.0;
-        let icon_size = self.size as f32 - margin * 2.0;
+        let margin = ICON_MARGIN;
+        let icon_size = icon.size() as f32 - margin * 2.0;
 
         match icon.icon_type() {
             crate::icons::IconType::Folder => {
@@ -404,29 +587,30 @@ impl IconRenderer {
         Ok(())
     }
 
-    /// Draw the label below the icon
-    fn draw_label(&self, pixmap: &mut Pixmap, name: &str) -> Result<()> {
-        // Truncate name if too long
-        let max_chars = 12;
-        let display_name = if name.len() > max_chars {
-            format!("{}...", &name[..max_chars - 3])
-        } else {
-            name.to_string()
+    /// Draw the label below an icon of `size`
+    fn draw_label(&self, pixmap: &mut Pixmap, name: &str, size: u32) -> Result<()> {
+        // Shorten the name to the label width; without a font nothing is drawn
+        let display_name = match &self.font {
+            Some(font) => {
+                let max_width = size as f32 - LABEL_PADDING * 2.0;
+                ellipsize(name, max_width, |c| font.metrics(c, self.font_size).advance_width)
+            }
+            None => name.to_string(),
         };
 
         // Label background
-        let label_y = self.size as f32 + 2.0;
+        let label_y = size as f32 + 2.0;
         let label_height = 18.0;
 
         let mut bg_paint = Paint::default();
         bg_paint.set_color(self.label_bg);
 
-        if let Some(rect) = Rect::from_xywh(0.0, label_y, self.size as f32, label_height) {
+        if let Some(rect) = Rect::from_xywh(0.0, label_y, size as f32, label_height) {
             pixmap.fill_rect(rect, &bg_paint, Transform::identity(), None);
         }
 
         // Render text centered horizontally, with baseline near bottom of label area
-        let text_x = self.size as f32 / 2.0;
+        let text_x = size as f32 / 2.0;
         let text_y = label_y + label_height - 4.0; // Position baseline
         self.render_text(
             pixmap,
@@ -441,27 +625,129 @@ impl IconRenderer {
         Ok(())
     }
 
+    /// Render logical-size draw commands into a pixmap for a buffer of the
+    /// given scale
+    ///
+    /// The pixmap is `width * scale` by `height * scale` pixels; commands are
+    /// scaled rather than the finished pixmap, so text and images stay sharp.
+    pub fn render_commands(
+        &self,
+        commands: &[DrawCommand],
+        width: u32,
+        height: u32,
+        scale: u32,
+    ) -> Result<Pixmap> {
+        let mut pixmap = Pixmap::new(width * scale, height * scale)
+            .ok_or_else(|| anyhow::anyhow!("Invalid pixmap size {}x{} at scale {}", width, height, scale))?;
+
+        if scale == 1 {
+            self.execute_commands(&mut pixmap, commands)?;
+        } else {
+            let scaled: Vec<DrawCommand> = commands.iter().map(|cmd| cmd.scaled(scale as f32)).collect();
+            self.execute_commands(&mut pixmap, &scaled)?;
+        }
+        Ok(pixmap)
+    }
+
+    /// `render_commands` for several command lists at once on `pool`, each
+    /// with its canvas width and height
+    ///
+    /// Results are in the order of `lists`.
+    pub fn render_many(
+        &self,
+        pool: &ThreadPool,
+        lists: &[(&[DrawCommand], u32, u32)],
+        scale: u32,
+    ) -> Vec<Result<Pixmap>> {
+        pool.install(|| {
+            lists
+                .par_iter()
+                .map(|&(commands, width, height)| self.render_commands(commands, width, height, scale))
+                .collect()
+        })
+    }
+
     /// Execute Lua draw commands
     pub fn execute_commands(&self, pixmap: &mut Pixmap, commands: &[DrawCommand]) -> Result<()> {
+        self.execute_commands_clipped(pixmap, commands, None)
+    }
+
+    /// Execute Lua draw commands, drawing only inside `clip` (the whole
+    /// pixmap if None)
+    ///
+    /// `Clip` commands narrow drawing further to their rect within `clip`,
+    /// replacing any earlier `Clip`; `ResetClip` goes back to `clip`.
+    /// `SetAlpha` scales the opacity of later commands until `ResetAlpha`.
+    /// `PushTransform`/`PopTransform` keep a stack of transforms applied to
+    /// later primitives and clips; it starts empty on every call, and pops
+    /// without a matching push are ignored.
+    pub fn execute_commands_clipped(
+        &self,
+        pixmap: &mut Pixmap,
+        commands: &[DrawCommand],
+        clip: Option<Rect>,
+    ) -> Result<()> {
+        let (width, height) = (pixmap.width(), pixmap.height());
+        let base_clip = clip.map(|rect| clip_mask(width, height, None, rect, Transform::identity()));
+        let mut clip_state = base_clip.clone();
+        let mut alpha = 1.0;
+        let mut transforms: Vec<Transform> = Vec::new();
+
         for cmd in commands {
+            let mask = clip_state.as_ref();
+            let transform = transforms.last().copied().unwrap_or_default();
             match cmd {
+                DrawCommand::Clip { x, y, w, h } => {
+                    let rect = Rect::from_xywh(*x, *y, *w, *h);
+                    clip_state = Some(match rect {
+                        Some(rect) => clip_mask(width, height, base_clip.as_ref(), rect, transform),
+                        None => Mask::new(width, height).expect("pixmap size is valid for a mask"),
+                    });
+                }
+                DrawCommand::ResetClip => clip_state = base_clip.clone(),
+                DrawCommand::SetAlpha { alpha: value } => alpha = value.clamp(0.0, 1.0),
+                DrawCommand::ResetAlpha => alpha = 1.0,
+                DrawCommand::PushTransform { rotate_deg, tx, ty, scale } => {
+                    // An invalid transform still takes a stack slot, so the
+                    // pop that goes with it stays matched
+                    let local = local_transform(*rotate_deg, *tx, *ty, *scale).unwrap_or_else(|| {
+                        warn!("Ignoring invalid transform in draw commands");
+                        Transform::identity()
+                    });
+                    transforms.push(transform.pre_concat(local));
+                }
+                DrawCommand::PopTransform => {
+                    if transforms.pop().is_none() {
+                        warn!("Ignoring PopTransform without a matching PushTransform");
+                    }
+                }
                 DrawCommand::Clear { color } => {
-                    if let Some(c) = parse_color(color) {
-                        pixmap.fill(c);
+                    if let Some(c) = parse_color_with_alpha(color, alpha) {
+                        match mask {
+                            None => pixmap.fill(c),
+                            Some(mask) => {
+                                let mut paint = Paint::default();
+                                paint.set_color(c);
+                                paint.blend_mode = BlendMode::Source;
+                                let full = Rect::from_xywh(0.0, 0.0, width as f32, height as f32)
+                                    .expect("pixmap bounds are a valid rect");
+                                pixmap.fill_rect(full, &paint, Transform::identity(), Some(mask));
+                            }
+                        }
                     }
                 }
                 DrawCommand::FillRect { x, y, w, h, color } => {
                     if let (Some(rect), Some(color)) = (
                         Rect::from_xywh(*x, *y, *w, *h),
-                        parse_color(color),
+                        parse_color_with_alpha(color, alpha),
                     ) {
                         let mut paint = Paint::default();
                         paint.set_color(color);
-                        pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+                        pixmap.fill_rect(rect, &paint, transform, mask);
                     }
                 }
-                DrawCommand::StrokeRect { x, y, w, h, color, width } => {
-                    if let Some(color) = parse_color(color) {
+                DrawCommand::StrokeRect { x, y, w, h, color, width, dash } => {
+                    if let Some(color) = parse_color_with_alpha(color, alpha) {
                         let mut paint = Paint::default();
                         paint.set_color(color);
 
@@ -469,6 +755,7 @@ impl IconRenderer {
                             width: *width,
                             line_cap: LineCap::Square,
                             line_join: LineJoin::Miter,
+                            dash: stroke_dash(dash),
                             ..Default::default()
                         };
 
@@ -480,12 +767,12 @@ impl IconRenderer {
                         pb.close();
 
                         if let Some(path) = pb.finish() {
-                            pixmap.stroke_path(&path, &paint, &stroke, Transform::identity(), None);
+                            pixmap.stroke_path(&path, &paint, &stroke, transform, mask);
                         }
                     }
                 }
                 DrawCommand::FillCircle { cx, cy, r, color } => {
-                    if let Some(color) = parse_color(color) {
+                    if let Some(color) = parse_color_with_alpha(color, alpha) {
                         let mut paint = Paint::default();
                         paint.set_color(color);
 
@@ -494,18 +781,19 @@ impl IconRenderer {
                         pb.push_circle(*cx, *cy, *r);
 
                         if let Some(path) = pb.finish() {
-                            pixmap.fill_path(&path, &paint, FillRule::Winding, Transform::identity(), None);
+                            pixmap.fill_path(&path, &paint, FillRule::Winding, transform, mask);
                         }
                     }
                 }
-                DrawCommand::Line { x1, y1, x2, y2, color, width } => {
-                    if let Some(color) = parse_color(color) {
+                DrawCommand::Line { x1, y1, x2, y2, color, width, dash } => {
+                    if let Some(color) = parse_color_with_alpha(color, alpha) {
                         let mut paint = Paint::default();
                         paint.set_color(color);
 
                         let stroke = Stroke {
                             width: *width,
                             line_cap: LineCap::Round,
+                            dash: stroke_dash(dash),
                             ..Default::default()
                         };
 
@@ -514,21 +802,27 @@ impl IconRenderer {
                         pb.line_to(*x2, *y2);
 
                         if let Some(path) = pb.finish() {
-                            pixmap.stroke_path(&path, &paint, &stroke, Transform::identity(), None);
+                            pixmap.stroke_path(&path, &paint, &stroke, transform, mask);
                         }
                     }
                 }
                 DrawCommand::Text { text, x, y, size, color, align } => {
-                    if let Some(text_color) = parse_color(color) {
+                    if let Some(text_color) = parse_color_with_alpha(color, alpha) {
                         let alignment = TextAlign::from_str(align);
-                        self.render_text(pixmap, text, *x, *y, *size, text_color, alignment);
+                        self.draw_text(pixmap, text, *x, *y, *size, text_color, alignment, transform, mask);
+                    }
+                }
+                DrawCommand::TextBox { text, x, y, w, size, color, align } => {
+                    if let Some(text_color) = parse_color_with_alpha(color, alpha) {
+                        let alignment = TextAlign::from_str(align);
+                        self.draw_text_box(pixmap, text, *x, *y, *w, *size, text_color, alignment, transform, mask);
                     }
                 }
                 DrawCommand::Image { path, x, y, w, h } => {
-                    self.render_image(pixmap, path, *x, *y, *w, *h);
+                    self.draw_image(pixmap, path, *x, *y, *w, *h, alpha, transform, mask);
                 }
                 DrawCommand::StrokeCircle { cx, cy, r, color, width } => {
-                    if let Some(color) = parse_color(color) {
+                    if let Some(color) = parse_color_with_alpha(color, alpha) {
                         let mut paint = Paint::default();
                         paint.set_color(color);
 
@@ -543,10 +837,60 @@ impl IconRenderer {
                         pb.push_circle(*cx, *cy, *r);
 
                         if let Some(path) = pb.finish() {
-                            pixmap.stroke_path(&path, &paint, &stroke, Transform::identity(), None);
+                            pixmap.stroke_path(&path, &paint, &stroke, transform, mask);
                         }
                     }
                 }
+                DrawCommand::Arc { cx, cy, r, start_deg, end_deg, color, width } => {
+                    if let (Some(path), Some(color)) =
+                        (arc_path(*cx, *cy, *r, *start_deg, *end_deg), parse_color_with_alpha(color, alpha))
+                    {
+                        let mut paint = Paint::default();
+                        paint.set_color(color);
+                        paint.anti_alias = true;
+
+                        let stroke = Stroke {
+                            width: *width,
+                            line_cap: LineCap::Butt,
+                            line_join: LineJoin::Round,
+                            ..Default::default()
+                        };
+                        pixmap.stroke_path(&path, &paint, &stroke, transform, mask);
+                    }
+                }
+                DrawCommand::RoundRect { x, y, w, h, r, color } => {
+                    if let (Some(path), Some(color)) = (round_rect_path(*x, *y, *w, *h, *r), parse_color_with_alpha(color, alpha)) {
+                        let mut paint = Paint::default();
+                        paint.set_color(color);
+                        paint.anti_alias = true;
+                        pixmap.fill_path(&path, &paint, FillRule::Winding, transform, mask);
+                    }
+                }
+                DrawCommand::LinearGradient { x, y, w, h, stops } => {
+                    let stops: Vec<_> = stops
+                        .iter()
+                        .filter_map(|stop| {
+                            let color = parse_color_with_alpha(&stop.color, alpha)?;
+                            Some(tiny_skia::GradientStop::new(stop.offset.clamp(0.0, 1.0), color))
+                        })
+                        .collect();
+
+                    let shader = LinearGradient::new(
+                        Point::from_xy(*x, *y),
+                        Point::from_xy(x + w, *y),
+                        stops,
+                        SpreadMode::Pad,
+                        Transform::identity(),
+                    );
+
+                    if let (Some(rect), Some(shader)) = (Rect::from_xywh(*x, *y, *w, *h), shader) {
+                        let paint = Paint {
+                            shader,
+                            ..Default::default()
+                        };
+                        pixmap.fill_rect(rect, &paint, transform, mask);
+                    }
+                }
             }
         }
 
@@ -554,11 +898,326 @@ impl IconRenderer {
     }
 }
 
-/// Parse a color string (hex format)
-fn parse_color(s: &str) -> Option<Color> {
+/// Mask for a `width` x `height` pixmap covering `rect` under `transform`,
+/// within `within` if given
+///
+/// Not anti-aliased, so clip edges on pixel boundaries stay exact.
+fn clip_mask(width: u32, height: u32, within: Option<&Mask>, rect: Rect, transform: Transform) -> Mask {
+    let path = PathBuilder::from_rect(rect);
+    match within {
+        Some(base) => {
+            let mut mask = base.clone();
+            mask.intersect_path(&path, FillRule::Winding, false, transform);
+            mask
+        }
+        None => {
+            let mut mask = Mask::new(width, height).expect("pixmap size is valid for a mask");
+            mask.fill_path(&path, FillRule::Winding, false, transform);
+            mask
+        }
+    }
+}
+
+/// The transform a `PushTransform` adds: scale, then rotate clockwise by
+/// `rotate_deg`, then move the origin to (`tx`, `ty`)
+///
+/// None if any part is not finite.
+fn local_transform(rotate_deg: f32, tx: f32, ty: f32, scale: f32) -> Option<Transform> {
+    if ![rotate_deg, tx, ty, scale].iter().all(|v| v.is_finite()) {
+        return None;
+    }
+    Some(Transform::from_translate(tx, ty).pre_rotate(rotate_deg).pre_scale(scale, scale))
+}
+
+/// Copy straight-alpha RGBA pixels into a premultiplied pixmap
+fn rgba_to_pixmap(rgba: &image::RgbaImage) -> Option<Pixmap> {
+    let mut pixmap = Pixmap::new(rgba.width(), rgba.height())?;
+    let pixels = pixmap.pixels_mut();
+
+    for (i, pixel) in rgba.pixels().enumerate() {
+        let [r, g, b, a] = pixel.0;
+
+        if a == 0 {
+            // Fully transparent, skip (pixmap is already zero-initialized)
+            continue;
+        }
+
+        // Premultiply alpha for tiny-skia
+        let alpha = a as f32 / 255.0;
+        let pm_r = (r as f32 * alpha) as u8;
+        let pm_g = (g as f32 * alpha) as u8;
+        let pm_b = (b as f32 * alpha) as u8;
+
+        if let Some(color) = tiny_skia::PremultipliedColorU8::from_rgba(pm_r, pm_g, pm_b, a) {
+            pixels[i] = color;
+        }
+    }
+
+    Some(pixmap)
+}
+
+/// Load an icon file (SVG or raster) scaled to a `size` x `size` pixmap
+fn load_icon_file(path: &Path, size: u32) -> Option<Pixmap> {
+    let is_svg = path
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("svg"));
+
+    if is_svg {
+        let data = std::fs::read(path)
+            .map_err(|e| warn!("Failed to read icon {}: {}", path.display(), e))
+            .ok()?;
+        let tree = resvg::usvg::Tree::from_data(&data, &resvg::usvg::Options::default())
+            .map_err(|e| warn!("Failed to parse icon {}: {}", path.display(), e))
+            .ok()?;
+
+        let mut pixmap = Pixmap::new(size, size)?;
+        let svg_size = tree.size();
+        let transform = Transform::from_scale(
+            size as f32 / svg_size.width(),
+            size as f32 / svg_size.height(),
+        );
+        resvg::render(&tree, transform, &mut pixmap.as_mut());
+        return Some(pixmap);
+    }
+
+    let img = image::open(path)
+        .map_err(|e| warn!("Failed to load icon {}: {}", path.display(), e))
+        .ok()?;
+    rgba_to_pixmap(&img.resize_exact(size, size, FilterType::Triangle).to_rgba8())
+}
+
+/// Total advance width of `text`
+fn text_width(text: &str, advance: &impl Fn(char) -> f32) -> f32 {
+    text.chars().map(advance).sum()
+}
+
+/// Break `text` into lines no wider than `max_width`
+///
+/// Lines break at whitespace and explicit newlines; a word wider than a
+/// whole line is split between grapheme clusters.
+fn wrap_lines(text: &str, max_width: f32, advance: impl Fn(char) -> f32) -> Vec<String> {
+    let space = advance(' ');
+    let mut lines = Vec::new();
+
+    for paragraph in text.split('\n') {
+        let mut line = String::new();
+        let mut line_width = 0.0;
+
+        for word in paragraph.split_whitespace() {
+            let word_width = text_width(word, &advance);
+            let gap = if line.is_empty() { 0.0 } else { space };
+
+            if line_width + gap + word_width <= max_width {
+                if !line.is_empty() {
+                    line.push(' ');
+                }
+                line.push_str(word);
+                line_width += gap + word_width;
+                continue;
+            }
+
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+                line_width = 0.0;
+            }
+
+            for grapheme in word.graphemes(true) {
+                let w = text_width(grapheme, &advance);
+                if !line.is_empty() && line_width + w > max_width {
+                    lines.push(std::mem::take(&mut line));
+                    line_width = 0.0;
+                }
+                line.push_str(grapheme);
+                line_width += w;
+            }
+        }
+
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// Shorten `text` to fit `max_width`, ending it with an ellipsis
+///
+/// Cuts only between grapheme clusters, so neither multibyte chars nor
+/// combined sequences (accents, emoji with modifiers) are split.
+fn ellipsize(text: &str, max_width: f32, advance: impl Fn(char) -> f32) -> String {
+    if text_width(text, &advance) <= max_width {
+        return text.to_string();
+    }
+
+    let budget = max_width - text_width(ELLIPSIS, &advance);
+    let mut out = String::new();
+    let mut width = 0.0;
+    for grapheme in text.graphemes(true) {
+        width += text_width(grapheme, &advance);
+        if width > budget {
+            break;
+        }
+        out.push_str(grapheme);
+    }
+    out.push_str(ELLIPSIS);
+    out
+}
+
+/// Build a rectangle path with circular corners
+///
+/// The radius is clamped to half the smaller side; corners are cubic
+/// approximations of quarter circles.
+fn round_rect_path(x: f32, y: f32, w: f32, h: f32, r: f32) -> Option<tiny_skia::Path> {
+    if w <= 0.0 || h <= 0.0 {
+        return None;
+    }
+
+    let r = r.max(0.0).min(w.min(h) / 2.0);
+    // Distance of the control points from the corner ends
+    let k = r * (1.0 - 0.552_284_8);
+    let (right, bottom) = (x + w, y + h);
+
+    let mut pb = PathBuilder::new();
+    pb.move_to(x + r, y);
+    pb.line_to(right - r, y);
+    pb.cubic_to(right - k, y, right, y + k, right, y + r);
+    pb.line_to(right, bottom - r);
+    pb.cubic_to(right, bottom - k, right - k, bottom, right - r, bottom);
+    pb.line_to(x + r, bottom);
+    pb.cubic_to(x + k, bottom, x, bottom - k, x, bottom - r);
+    pb.line_to(x, y + r);
+    pb.cubic_to(x, y + k, x + k, y, x + r, y);
+    pb.close();
+    pb.finish()
+}
+
+/// Dash pattern for a stroke from alternating on and off lengths
+///
+/// An odd-length pattern is repeated once, as in SVG. Empty patterns and
+/// ones tiny-skia rejects (negative or non-finite entries, or nothing but
+/// zeros) give None, a solid stroke.
+fn stroke_dash(lengths: &[f32]) -> Option<StrokeDash> {
+    if lengths.is_empty() {
+        return None;
+    }
+    let mut pattern = lengths.to_vec();
+    if pattern.len() % 2 == 1 {
+        pattern.extend_from_slice(lengths);
+    }
+    StrokeDash::new(pattern, 0.0)
+}
+
+/// Path of an arc around (cx, cy), clockwise from `start_deg` to `end_deg`
+///
+/// Angles wrap, so 270° to 90° sweeps through 0°. Equal angles draw
+/// nothing, while a sweep of a whole number of turns is the same circle
+/// `push_circle` builds. Segments are cubic approximations of at most 90°.
+fn arc_path(cx: f32, cy: f32, r: f32, start_deg: f32, end_deg: f32) -> Option<tiny_skia::Path> {
+    if r <= 0.0 || start_deg == end_deg || !(start_deg.is_finite() && end_deg.is_finite()) {
+        return None;
+    }
+
+    let mut pb = PathBuilder::new();
+    let sweep = (end_deg - start_deg).rem_euclid(360.0);
+    if sweep == 0.0 {
+        pb.push_circle(cx, cy, r);
+        return pb.finish();
+    }
+
+    let segments = (sweep / 90.0).ceil().max(1.0) as usize;
+    let step = sweep.to_radians() / segments as f32;
+    // Control point distance along the tangent for a `step`-radian segment
+    let k = 4.0 / 3.0 * (step / 4.0).tan() * r;
+    let point = |angle: f32| (cx + r * angle.cos(), cy + r * angle.sin());
+
+    let start = start_deg.to_radians();
+    let (x0, y0) = point(start);
+    pb.move_to(x0, y0);
+    for i in 0..segments {
+        let a0 = start + step * i as f32;
+        let a1 = a0 + step;
+        let (x0, y0) = point(a0);
+        let (x1, y1) = point(a1);
+        pb.cubic_to(
+            x0 - k * a0.sin(),
+            y0 + k * a0.cos(),
+            x1 + k * a1.sin(),
+            y1 - k * a1.cos(),
+            x1,
+            y1,
+        );
+    }
+    pb.finish()
+}
+
+/// `parse_color` with the color's own alpha multiplied by `alpha`
+fn parse_color_with_alpha(s: &str, alpha: f32) -> Option<Color> {
+    let mut color = parse_color(s)?;
+    color.apply_opacity(alpha);
+    Some(color)
+}
+
+/// CSS named colors understood by `parse_color`
+const NAMED_COLORS: &[(&str, [u8; 4])] = &[
+    ("transparent", [0, 0, 0, 0]),
+    ("black", [0, 0, 0, 255]),
+    ("white", [255, 255, 255, 255]),
+    ("red", [255, 0, 0, 255]),
+    ("lime", [0, 255, 0, 255]),
+    ("green", [0, 128, 0, 255]),
+    ("blue", [0, 0, 255, 255]),
+    ("yellow", [255, 255, 0, 255]),
+    ("cyan", [0, 255, 255, 255]),
+    ("aqua", [0, 255, 255, 255]),
+    ("magenta", [255, 0, 255, 255]),
+    ("fuchsia", [255, 0, 255, 255]),
+    ("gray", [128, 128, 128, 255]),
+    ("grey", [128, 128, 128, 255]),
+    ("silver", [192, 192, 192, 255]),
+    ("maroon", [128, 0, 0, 255]),
+    ("olive", [128, 128, 0, 255]),
+    ("navy", [0, 0, 128, 255]),
+    ("teal", [0, 128, 128, 255]),
+    ("purple", [128, 0, 128, 255]),
+    ("orange", [255, 165, 0, 255]),
+    ("pink", [255, 192, 203, 255]),
+    ("brown", [165, 42, 42, 255]),
+];
+
+/// Parse a color string
+///
+/// Accepts hex (`#rgb`, `#rgba`, `#rrggbb`, `#rrggbbaa`, hash optional),
+/// `rgb(r, g, b)` / `rgba(r, g, b, a)` with alpha in 0..=1, and the CSS
+/// names in `NAMED_COLORS` (case-insensitive).
+pub(crate) fn parse_color(s: &str) -> Option<Color> {
+    let s = s.trim();
+
+    if let Some(args) = functional_args(s, "rgba") {
+        let [r, g, b, a] = args[..] else { return None };
+        let alpha: f32 = a.parse().ok().filter(|a| (0.0..=1.0).contains(a))?;
+        return Some(Color::from_rgba8(channel(r)?, channel(g)?, channel(b)?, (alpha * 255.0).round() as u8));
+    }
+    if let Some(args) = functional_args(s, "rgb") {
+        let [r, g, b] = args[..] else { return None };
+        return Some(Color::from_rgba8(channel(r)?, channel(g)?, channel(b)?, 255));
+    }
+    if let Some((_, [r, g, b, a])) = NAMED_COLORS.iter().find(|(name, _)| name.eq_ignore_ascii_case(s)) {
+        return Some(Color::from_rgba8(*r, *g, *b, *a));
+    }
+
     let s = s.trim_start_matches('#');
+    if !s.is_ascii() {
+        return None;
+    }
 
     match s.len() {
+        3 | 4 => {
+            // Shorthand: each digit is doubled, so `f` becomes `ff`
+            let mut digits = s.chars().map(|c| c.to_digit(16).map(|d| (d * 17) as u8));
+            let r = digits.next()??;
+            let g = digits.next()??;
+            let b = digits.next()??;
+            let a = digits.next().unwrap_or(Some(255))?;
+            Some(Color::from_rgba8(r, g, b, a))
+        }
         6 => {
             let r = u8::from_str_radix(&s[0..2], 16).ok()?;
             let g = u8::from_str_radix(&s[2..4], 16).ok()?;
@@ -576,6 +1235,21 @@ fn parse_color(s: &str) -> Option<Color> {
     }
 }
 
+/// Comma-separated arguments of `name(...)`, if `s` has that form
+fn functional_args<'a>(s: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    let prefix = s.get(..name.len())?;
+    if !prefix.eq_ignore_ascii_case(name) {
+        return None;
+    }
+    let inner = s[name.len()..].trim_start().strip_prefix('(')?.strip_suffix(')')?;
+    Some(inner.split(',').map(str::trim).collect())
+}
+
+/// Parse a 0-255 color channel
+fn channel(s: &str) -> Option<u8> {
+    s.parse().ok()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -654,8 +1328,6 @@ mod tests {
 
     #[test]
     fn test_parse_color_invalid_length() {
-        assert!(parse_color("#fff").is_none(), "3-digit hex should return None");
-        assert!(parse_color("#ffff").is_none(), "4-digit hex should return None");
         assert!(parse_color("#fffff").is_none(), "5-digit hex should return None");
         assert!(parse_color("#fffffff").is_none(), "7-digit hex should return None");
         assert!(parse_color("#fffffffff").is_none(), "9-digit hex should return None");
@@ -682,6 +1354,42 @@ mod tests {
         assert_eq!(lower.blue(), upper.blue());
     }
 
+    fn rgba(color: Color) -> [f32; 4] {
+        [color.red(), color.green(), color.blue(), color.alpha()]
+    }
+
+    #[test]
+    fn test_parse_color_shorthand_hex() {
+        assert_eq!(rgba(parse_color("#fff").unwrap()), rgba(parse_color("#ffffff").unwrap()));
+        assert_eq!(rgba(parse_color("a1c").unwrap()), rgba(parse_color("#aa11cc").unwrap()));
+        assert_eq!(rgba(parse_color("#f008").unwrap()), rgba(parse_color("#ff000088").unwrap()));
+        assert!(parse_color("#ffg").is_none(), "Invalid shorthand digits should return None");
+    }
+
+    #[test]
+    fn test_parse_color_functional_notation() {
+        let red = rgba(parse_color("#ff0000").unwrap());
+        assert_eq!(rgba(parse_color("rgb(255,0,0)").unwrap()), red);
+        assert_eq!(rgba(parse_color("RGB( 255 , 0 , 0 )").unwrap()), red);
+
+        let half = parse_color("rgba(0, 0, 255, 0.5)").unwrap();
+        assert!((half.alpha() - 128.0 / 255.0).abs() < 0.01, "Alpha should be ~0.5");
+
+        assert!(parse_color("rgb(256,0,0)").is_none(), "Channels above 255 should return None");
+        assert!(parse_color("rgb(1,2)").is_none(), "Missing channels should return None");
+        assert!(parse_color("rgba(1,2,3,2)").is_none(), "Alpha above 1 should return None");
+        assert!(parse_color("rgb(1,2,3").is_none(), "Unclosed parenthesis should return None");
+    }
+
+    #[test]
+    fn test_parse_color_named() {
+        assert_eq!(rgba(parse_color("red").unwrap()), rgba(parse_color("#ff0000").unwrap()));
+        assert_eq!(rgba(parse_color("White").unwrap()), rgba(parse_color("#ffffff").unwrap()));
+        assert_eq!(parse_color("transparent").unwrap().alpha(), 0.0);
+        assert!(parse_color("notacolor").is_none(), "Unknown names should return None");
+        assert!(parse_color("ünïcode").is_none(), "Non-ASCII input should return None");
+    }
+
     // ========================================================================
     // DrawCommand Clear Tests
     // ========================================================================
@@ -724,6 +1432,45 @@ mod tests {
         assert_eq!(pixel.alpha(), 0, "Pixel should be transparent after clear");
     }
 
+    // ========================================================================
+    // Scaled Rendering Tests
+    // ========================================================================
+
+    #[test]
+    fn test_render_commands_scale_doubles_pixmap() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let pixmap = renderer.render_commands(&[], 64, 84, 2).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (128, 168));
+
+        let pixmap = renderer.render_commands(&[], 64, 84, 1).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (64, 84));
+    }
+
+    #[test]
+    fn test_render_commands_scales_coordinates() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let commands = vec![DrawCommand::FillRect {
+            x: 10.0,
+            y: 10.0,
+            w: 20.0,
+            h: 20.0,
+            color: "#00ff00".to_string(),
+        }];
+
+        let pixmap = renderer.render_commands(&commands, 64, 64, 2).unwrap();
+        // Logical (10..30) becomes (20..60) in buffer pixels
+        assert_eq!(pixmap.pixel(21, 21).unwrap().green(), 255);
+        assert_eq!(pixmap.pixel(58, 58).unwrap().green(), 255);
+        assert_eq!(pixmap.pixel(18, 18).unwrap().alpha(), 0);
+        assert_eq!(pixmap.pixel(62, 62).unwrap().alpha(), 0);
+    }
+
+    #[test]
+    fn test_render_commands_rejects_zero_size() {
+        let renderer = IconRenderer::new(64, 12.0);
+        assert!(renderer.render_commands(&[], 0, 64, 2).is_err());
+    }
+
     // ========================================================================
     // DrawCommand FillRect Tests
     // ========================================================================
@@ -817,6 +1564,7 @@ mod tests {
             h: 40.0,
             color: "#ffffff".to_string(),
             width: 2.0,
+            dash: Vec::new(),
         }];
 
         renderer.execute_commands(&mut pixmap, &commands).unwrap();
@@ -845,6 +1593,7 @@ mod tests {
             h: 24.0,
             color: "#ff00ff".to_string(),
             width: 4.0,
+            dash: Vec::new(),
         }];
 
         renderer.execute_commands(&mut pixmap, &commands).unwrap();
@@ -923,6 +1672,7 @@ mod tests {
             y2: 63.0,
             color: "#ffffff".to_string(),
             width: 2.0,
+            dash: Vec::new(),
         }];
 
         renderer.execute_commands(&mut pixmap, &commands).unwrap();
@@ -951,6 +1701,7 @@ mod tests {
             y2: 32.0,
             color: "#ff0000".to_string(),
             width: 1.0,
+            dash: Vec::new(),
         }];
 
         renderer.execute_commands(&mut pixmap, &commands).unwrap();
@@ -974,6 +1725,7 @@ mod tests {
             y2: 54.0,
             color: "#00ff00".to_string(),
             width: 1.0,
+            dash: Vec::new(),
         }];
 
         renderer.execute_commands(&mut pixmap, &commands).unwrap();
@@ -995,6 +1747,7 @@ mod tests {
             y2: 32.0,
             color: "#0000ff".to_string(),
             width: 5.0,
+            dash: Vec::new(),
         }];
 
         renderer.execute_commands(&mut pixmap, &commands).unwrap();
@@ -1012,7 +1765,63 @@ mod tests {
     // Multiple Commands Tests
     // ========================================================================
 
-    #[test]
+    /// Background pixels left on row 32 between x 8 and 56 after drawing a
+    /// horizontal line across it with `dash`
+    fn line_gaps(dash: Vec<f32>) -> usize {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        pixmap.fill(Color::from_rgba8(0, 0, 0, 255));
+
+        let commands = vec![DrawCommand::Line {
+            x1: 0.0,
+            y1: 32.0,
+            x2: 64.0,
+            y2: 32.0,
+            color: "#ffffff".to_string(),
+            width: 2.0,
+            dash,
+        }];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        (8..56).filter(|&x| pixmap.pixel(x, 32).unwrap().red() == 0).count()
+    }
+
+    #[test]
+    fn test_dashed_line_leaves_gaps() {
+        assert_eq!(line_gaps(Vec::new()), 0, "solid line");
+        assert!(line_gaps(vec![4.0, 4.0]) > 0, "dashed line");
+        // An odd pattern repeats, so [3] is 3 on, 3 off
+        assert!(line_gaps(vec![3.0]) > 0, "single-entry dash");
+    }
+
+    #[test]
+    fn test_invalid_dash_draws_solid() {
+        for dash in [vec![-1.0, 2.0], vec![0.0, 0.0], vec![0.0], vec![f32::NAN, 1.0], vec![f32::INFINITY, 1.0]] {
+            assert_eq!(line_gaps(dash.clone()), 0, "dash {:?}", dash);
+        }
+    }
+
+    #[test]
+    fn test_dashed_stroke_rect_leaves_gaps() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        let commands = vec![DrawCommand::StrokeRect {
+            x: 8.0,
+            y: 8.0,
+            w: 48.0,
+            h: 48.0,
+            color: "#ffffff".to_string(),
+            width: 2.0,
+            dash: vec![2.0, 6.0],
+        }];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        let top_edge: Vec<u8> = (10..54).map(|x| pixmap.pixel(x, 8).unwrap().alpha()).collect();
+        assert!(top_edge.contains(&0));
+        assert!(top_edge.iter().any(|&a| a > 0));
+    }
+
+    #[test]
     fn test_multiple_commands_execute_in_order() {
         let renderer = IconRenderer::new(64, 12.0);
         let mut pixmap = Pixmap::new(64, 64).unwrap();
@@ -1282,6 +2091,87 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_renderers_share_default_font() {
+        let a = IconRenderer::new(64, 12.0);
+        let b = IconRenderer::new(96, 14.0);
+        match (&a.font, &b.font) {
+            (Some(fa), Some(fb)) => assert!(Arc::ptr_eq(fa, fb), "renderers should share one font"),
+            (None, None) => {} // No system font available
+            _ => panic!("renderers disagree on whether a font is available"),
+        }
+    }
+
+    /// First built-in font path that exists on this system
+    fn system_font_path() -> Option<PathBuf> {
+        FONT_SEARCH_PATHS.iter().map(PathBuf::from).find(|p| p.exists())
+    }
+
+    fn same_font(a: &Option<Arc<Font>>, b: &Option<Arc<Font>>) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_from_config_prefers_configured_font() {
+        let Some(valid) = system_font_path() else {
+            return; // No system font available
+        };
+        let config = Config {
+            font_path: Some(valid.clone()),
+            ..Config::default()
+        };
+
+        let renderer = IconRenderer::from_config(&config);
+        assert!(same_font(&renderer.font, &load_configured_font(&valid)), "configured font should be used");
+        assert!(!same_font(&renderer.font, &shared_default_font()), "built-in font should not be used");
+        assert_eq!(renderer.size, config.icon_size);
+    }
+
+    #[test]
+    fn test_from_config_falls_back_past_bogus_fonts() {
+        let Some(valid) = system_font_path() else {
+            return; // No system font available
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let garbage = dir.path().join("garbage.ttf");
+        std::fs::write(&garbage, b"not a font").unwrap();
+
+        let config = Config {
+            font_path: Some(PathBuf::from("/nonexistent/font.ttf")),
+            font_fallbacks: vec![garbage, valid.clone()],
+            ..Config::default()
+        };
+        let renderer = IconRenderer::from_config(&config);
+        assert!(same_font(&renderer.font, &load_configured_font(&valid)), "should fall back to the valid font");
+
+        let config = Config {
+            font_path: Some(PathBuf::from("/nonexistent/font.ttf")),
+            ..Config::default()
+        };
+        let renderer = IconRenderer::from_config(&config);
+        assert!(same_font(&renderer.font, &shared_default_font()), "should end at the built-in search");
+    }
+
+    #[test]
+    fn test_failed_font_load_is_retried() {
+        let Some(valid) = system_font_path() else {
+            return; // No system font available
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("late.ttf");
+        assert!(load_configured_font(&path).is_none());
+
+        // The font turns up before the next reload
+        std::fs::copy(&valid, &path).unwrap();
+        let font = load_configured_font(&path);
+        assert!(font.is_some(), "a failed load should not be cached");
+        assert!(same_font(&font, &load_configured_font(&path)), "a loaded font should be shared");
+    }
+
     #[test]
     fn test_icon_renderer_with_font_constructor() {
         // Test the with_font constructor
@@ -1397,6 +2287,137 @@ mod tests {
         assert!(result.is_ok(), "Text with alpha should not cause error");
     }
 
+    // ========================================================================
+    // Wrapping / Truncation Tests
+    // ========================================================================
+
+    /// Every char one unit wide, so widths equal char counts
+    fn mono(_: char) -> f32 {
+        1.0
+    }
+
+    #[test]
+    fn test_wrap_lines_breaks_at_words() {
+        assert_eq!(wrap_lines("the quick brown fox", 10.0, mono), vec!["the quick", "brown fox"]);
+        assert_eq!(wrap_lines("short", 10.0, mono), vec!["short"]);
+        assert_eq!(wrap_lines("a\n\nb", 10.0, mono), vec!["a", "", "b"]);
+    }
+
+    #[test]
+    fn test_wrap_lines_splits_long_words() {
+        assert_eq!(wrap_lines("abcdefghij xy", 4.0, mono), vec!["abcd", "efgh", "ij", "xy"]);
+        assert_eq!(wrap_lines("café_files_ñ", 5.0, mono), vec!["café_", "files", "_ñ"]);
+    }
+
+    #[test]
+    fn test_ellipsize_respects_width_and_char_boundaries() {
+        assert_eq!(ellipsize("short.txt", 12.0, mono), "short.txt");
+        assert_eq!(ellipsize("café_files_ñ.txt", 10.0, mono), "café_fi...");
+        assert_eq!(ellipsize("日本語のファイル.txt", 8.0, mono), "日本語のフ...");
+
+        // Wide glyphs take more of the budget than narrow ones
+        let wide_m = |c: char| if c == 'm' { 3.0 } else { 1.0 };
+        assert_eq!(ellipsize("mmmmiiii", 9.0, wide_m), "mm...");
+    }
+
+    #[test]
+    fn test_text_box_wraps_onto_several_lines() {
+        let renderer = IconRenderer::new(128, 12.0);
+        if renderer.font.is_none() {
+            return; // No system font to render with
+        }
+        let mut pixmap = Pixmap::new(128, 128).unwrap();
+
+        let commands = vec![DrawCommand::TextBox {
+            text: "wrap this text over several lines".to_string(),
+            x: 0.0,
+            y: 16.0,
+            w: 60.0,
+            size: 12.0,
+            color: "#ffffff".to_string(),
+            align: "left".to_string(),
+        }];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        let rows_with_ink = (0..128)
+            .filter(|&y| (0..128).any(|x| pixmap.pixel(x, y).unwrap().alpha() > 0))
+            .max()
+            .unwrap();
+        assert!(rows_with_ink > 40, "text should continue on lines below the first");
+        assert!(
+            (0..128).all(|y| (62..128).all(|x| pixmap.pixel(x, y).unwrap().alpha() == 0)),
+            "wrapped text should stay inside the box"
+        );
+    }
+
+    #[test]
+    fn test_ellipsize_keeps_grapheme_clusters_whole() {
+        // "e" + combining acute accent is one grapheme of two chars
+        let decomposed = "cafe\u{301}_files";
+        assert_eq!(ellipsize(decomposed, 8.0, mono), "cafe\u{301}...");
+
+        // Family emoji: three people joined by zero-width joiners
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let name = format!("ab{}cdefgh", family);
+        // The cluster is five chars wide here, so it either fits whole or not at all
+        assert_eq!(ellipsize(&name, 7.0, mono), "ab...");
+        assert_eq!(ellipsize(&name, 10.0, mono), format!("ab{}...", family));
+    }
+
+    #[test]
+    fn test_render_cjk_and_emoji_labels_does_not_panic() {
+        // Regression: byte-slicing the label used to panic inside multibyte chars
+        let renderer = IconRenderer::new(64, 12.0);
+        let config = crate::config::Config::default();
+        let names = [
+            "日本語のファイル.txt",
+            "📁📂📄📝🗂️🗃️🗄️.md",
+            "👨\u{200d}👩\u{200d}👧 family photos",
+            "🇯🇵🇫🇷🇩🇪🇮🇹🇪🇸🇬🇧",
+            "한국어_문서_최종본.docx",
+        ];
+        for name in names {
+            let path = std::path::PathBuf::from("/tmp").join(name);
+            let icon = DesktopIcon::new(&path, &config).unwrap();
+            assert!(renderer.render(&icon).is_ok(), "label '{}' should render", name);
+        }
+    }
+
+    #[test]
+    fn test_render_multibyte_labels() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let config = crate::config::Config::default();
+        for name in ["café_files_ñ", "日本語のファイル.txt", "ñññññññññññññññ"] {
+            let path = std::path::PathBuf::from("/tmp").join(name);
+            let icon = DesktopIcon::new(&path, &config).unwrap();
+            assert!(renderer.render(&icon).is_ok(), "label '{}' should render", name);
+        }
+    }
+
+    #[test]
+    fn test_render_uses_icon_type_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let renderer = IconRenderer::new(64, 12.0);
+        let config: crate::config::Config = toml::from_str(
+            "[icon_types.folder]\nsize = 96\n\n[icon_types.document]\nshow_label = false\n",
+        )
+        .unwrap();
+
+        let folder = DesktopIcon::new(dir.path(), &config).unwrap();
+        let file = DesktopIcon::new(&dir.path().join("notes.unknown"), &config).unwrap();
+        let folder_pixmap = renderer.render(&folder).unwrap();
+        let file_pixmap = renderer.render(&file).unwrap();
+        assert_eq!((folder_pixmap.width(), folder_pixmap.height()), (96, 120));
+        assert_eq!((file_pixmap.width(), file_pixmap.height()), (64, 88));
+
+        // A hidden label leaves the label space empty
+        let label_alpha = |pixmap: &Pixmap| pixmap.pixel(32, 70).unwrap().alpha();
+        let document = DesktopIcon::new(&dir.path().join("notes.md"), &config).unwrap();
+        assert!(!document.show_label());
+        assert_eq!(label_alpha(&renderer.render(&document).unwrap()), 0);
+        assert_ne!(label_alpha(&file_pixmap), 0);
+    }
+
     // ========================================================================
     // Image Rendering Tests
     // ========================================================================
@@ -1627,6 +2648,83 @@ mod tests {
         let _ = std::fs::remove_file(&temp_path);
     }
 
+    // ========================================================================
+    // RoundRect / LinearGradient Tests
+    // ========================================================================
+
+    #[test]
+    fn test_round_rect_leaves_corners_transparent() {
+        let renderer = IconRenderer::with_font(64, 12.0, None);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+
+        let commands = vec![DrawCommand::RoundRect {
+            x: 0.0,
+            y: 0.0,
+            w: 64.0,
+            h: 64.0,
+            r: 16.0,
+            color: "#ff0000".to_string(),
+        }];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        for (x, y) in [(0, 0), (63, 0), (0, 63), (63, 63)] {
+            assert_eq!(pixmap.pixel(x, y).unwrap().alpha(), 0, "corner ({}, {}) should be transparent", x, y);
+        }
+        let center = pixmap.pixel(32, 32).unwrap();
+        assert_eq!((center.red(), center.alpha()), (255, 255), "center should be filled");
+        let edge = pixmap.pixel(32, 0).unwrap();
+        assert!(edge.alpha() > 0, "straight edges should be filled");
+    }
+
+    #[test]
+    fn test_round_rect_radius_is_clamped() {
+        // A radius larger than half the side turns the square into a circle
+        let path = round_rect_path(0.0, 0.0, 20.0, 10.0, 100.0).unwrap();
+        let bounds = path.bounds();
+        assert_eq!((bounds.width(), bounds.height()), (20.0, 10.0));
+        assert!(round_rect_path(0.0, 0.0, 0.0, 10.0, 2.0).is_none());
+    }
+
+    #[test]
+    fn test_linear_gradient_varies_left_to_right() {
+        let renderer = IconRenderer::with_font(64, 12.0, None);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+
+        let commands = vec![DrawCommand::LinearGradient {
+            x: 0.0,
+            y: 0.0,
+            w: 64.0,
+            h: 64.0,
+            stops: vec![
+                crate::lua::api::GradientStop { offset: 0.0, color: "#ff0000".to_string() },
+                crate::lua::api::GradientStop { offset: 1.0, color: "#0000ff".to_string() },
+            ],
+        }];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        let left = pixmap.pixel(1, 32).unwrap();
+        let right = pixmap.pixel(62, 32).unwrap();
+        assert!(left.red() > 200 && left.blue() < 50, "left should be red, got {:?}", left);
+        assert!(right.blue() > 200 && right.red() < 50, "right should be blue, got {:?}", right);
+        assert_eq!(left, pixmap.pixel(1, 2).unwrap(), "gradient should be constant vertically");
+    }
+
+    #[test]
+    fn test_linear_gradient_without_valid_stops_draws_nothing() {
+        let renderer = IconRenderer::with_font(64, 12.0, None);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+
+        let commands = vec![DrawCommand::LinearGradient {
+            x: 0.0,
+            y: 0.0,
+            w: 64.0,
+            h: 64.0,
+            stops: vec![crate::lua::api::GradientStop { offset: 0.0, color: "nope".to_string() }],
+        }];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+        assert!(pixmap.pixels().iter().all(|p| p.alpha() == 0));
+    }
+
     // ========================================================================
     // StrokeCircle Tests
     // ========================================================================
@@ -1702,4 +2800,470 @@ mod tests {
         let pixel = pixmap.pixel(32, 32).unwrap();
         assert_eq!(pixel.red(), 128, "Pixmap should be unchanged with invalid color");
     }
+
+    // ========================================================================
+    // Arc Tests
+    // ========================================================================
+
+    fn arc(start_deg: f32, end_deg: f32) -> DrawCommand {
+        DrawCommand::Arc {
+            cx: 32.0,
+            cy: 32.0,
+            r: 20.0,
+            start_deg,
+            end_deg,
+            color: "#ffffff".to_string(),
+            width: 4.0,
+        }
+    }
+
+    /// Whether anything was drawn in each quadrant around (32, 32):
+    /// [top-left, top-right, bottom-left, bottom-right]
+    fn quadrants(pixmap: &Pixmap) -> [bool; 4] {
+        let mut drawn = [false; 4];
+        for y in 0..64 {
+            for x in 0..64 {
+                if pixmap.pixel(x, y).unwrap().alpha() > 0 {
+                    drawn[(y >= 32) as usize * 2 + (x >= 32) as usize] = true;
+                }
+            }
+        }
+        drawn
+    }
+
+    fn render_arc(start_deg: f32, end_deg: f32) -> Pixmap {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        renderer.execute_commands(&mut pixmap, &[arc(start_deg, end_deg)]).unwrap();
+        pixmap
+    }
+
+    #[test]
+    fn test_arc_quarter_stays_in_its_quadrant() {
+        // 0° points right and angles run clockwise on screen (y down)
+        let pixmap = render_arc(0.0, 90.0);
+        assert_eq!(quadrants(&pixmap), [false, false, false, true]);
+        assert!(pixmap.pixel(46, 46).unwrap().alpha() > 0, "midpoint at 45° should be drawn");
+
+        let pixmap = render_arc(180.0, 270.0);
+        assert_eq!(quadrants(&pixmap), [true, false, false, false]);
+    }
+
+    #[test]
+    fn test_arc_wraps_through_zero() {
+        let pixmap = render_arc(270.0, 90.0);
+        assert_eq!(quadrants(&pixmap), [false, true, false, true]);
+        assert!(pixmap.pixel(52, 32).unwrap().alpha() > 0);
+        assert_eq!(pixmap.pixel(12, 32).unwrap().alpha(), 0);
+
+        assert_eq!(render_arc(-90.0, 90.0).data(), pixmap.data());
+    }
+
+    #[test]
+    fn test_full_arc_equals_stroke_circle() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut circle = Pixmap::new(64, 64).unwrap();
+        let commands = vec![DrawCommand::StrokeCircle {
+            cx: 32.0,
+            cy: 32.0,
+            r: 20.0,
+            color: "#ffffff".to_string(),
+            width: 4.0,
+        }];
+        renderer.execute_commands(&mut circle, &commands).unwrap();
+
+        assert_eq!(render_arc(0.0, 360.0).data(), circle.data());
+        assert_eq!(render_arc(90.0, 450.0).data(), circle.data());
+    }
+
+    #[test]
+    fn test_empty_arc_draws_nothing() {
+        assert!(render_arc(45.0, 45.0).pixels().iter().all(|p| p.alpha() == 0));
+        assert!(render_arc(0.0, f32::NAN).pixels().iter().all(|p| p.alpha() == 0));
+    }
+
+    // ========================================================================
+    // Clip Tests
+    // ========================================================================
+
+    fn fill_rect(x: f32, y: f32, w: f32, h: f32, color: &str) -> DrawCommand {
+        DrawCommand::FillRect { x, y, w, h, color: color.to_string() }
+    }
+
+    fn alpha(pixmap: &Pixmap, x: u32, y: u32) -> u8 {
+        pixmap.pixel(x, y).unwrap().alpha()
+    }
+
+    #[test]
+    fn test_clip_command_limits_fill_rect() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        let commands = vec![
+            DrawCommand::Clip { x: 16.0, y: 16.0, w: 32.0, h: 32.0 },
+            fill_rect(-10.0, -10.0, 100.0, 100.0, "#ff0000"),
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        for y in 0..64 {
+            for x in 0..64 {
+                let inside = (16..48).contains(&x) && (16..48).contains(&y);
+                assert_eq!(alpha(&pixmap, x, y) == 255, inside, "pixel ({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn test_reset_clip_restores_full_drawing() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        let commands = vec![
+            DrawCommand::Clip { x: 0.0, y: 0.0, w: 8.0, h: 8.0 },
+            DrawCommand::ResetClip,
+            fill_rect(0.0, 0.0, 64.0, 64.0, "#00ff00"),
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+        assert!(pixmap.pixels().iter().all(|p| p.alpha() == 255));
+    }
+
+    #[test]
+    fn test_base_clip_bounds_script_clips() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        let base = Rect::from_xywh(0.0, 0.0, 32.0, 64.0);
+        let commands = vec![
+            fill_rect(0.0, 0.0, 64.0, 8.0, "#ff0000"),
+            // A script clip can't widen the base clip, and reset returns to it
+            DrawCommand::Clip { x: 16.0, y: 16.0, w: 48.0, h: 16.0 },
+            fill_rect(0.0, 0.0, 64.0, 64.0, "#00ff00"),
+            DrawCommand::ResetClip,
+            fill_rect(0.0, 56.0, 64.0, 8.0, "#0000ff"),
+        ];
+        renderer.execute_commands_clipped(&mut pixmap, &commands, base).unwrap();
+
+        assert_eq!(alpha(&pixmap, 31, 4), 255);
+        assert_eq!(alpha(&pixmap, 32, 4), 0);
+        assert_eq!(pixmap.pixel(20, 20).unwrap().green(), 255);
+        assert_eq!(alpha(&pixmap, 40, 20), 0);
+        assert_eq!(alpha(&pixmap, 8, 40), 0);
+        assert_eq!(pixmap.pixel(31, 60).unwrap().blue(), 255);
+        assert_eq!(alpha(&pixmap, 32, 60), 0);
+    }
+
+    #[test]
+    fn test_clip_applies_to_clear_and_strokes() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        pixmap.fill(Color::from_rgba8(0, 0, 255, 255));
+        let commands = vec![
+            DrawCommand::Clip { x: 0.0, y: 0.0, w: 32.0, h: 64.0 },
+            DrawCommand::Clear { color: "#00000000".to_string() },
+            DrawCommand::Line { x1: 0.0, y1: 40.0, x2: 64.0, y2: 40.0, color: "#ffffff".to_string(), width: 4.0, dash: Vec::new() },
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        assert_eq!(alpha(&pixmap, 10, 10), 0, "clear inside the clip");
+        assert_eq!(pixmap.pixel(50, 10).unwrap().blue(), 255, "untouched outside the clip");
+        assert_eq!(pixmap.pixel(10, 40).unwrap().red(), 255);
+        assert_eq!(pixmap.pixel(50, 40).unwrap().red(), 0);
+    }
+
+    #[test]
+    fn test_empty_clip_draws_nothing() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        let commands = vec![
+            DrawCommand::Clip { x: 10.0, y: 10.0, w: -5.0, h: 5.0 },
+            fill_rect(0.0, 0.0, 64.0, 64.0, "#ff0000"),
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+        assert!(pixmap.pixels().iter().all(|p| p.alpha() == 0));
+    }
+
+    #[test]
+    fn test_clip_scales_with_commands() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let commands = vec![
+            DrawCommand::Clip { x: 0.0, y: 0.0, w: 8.0, h: 8.0 },
+            fill_rect(0.0, 0.0, 16.0, 16.0, "#ff0000"),
+        ];
+        let pixmap = renderer.render_commands(&commands, 16, 16, 2).unwrap();
+        assert_eq!(alpha(&pixmap, 15, 15), 255);
+        assert_eq!(alpha(&pixmap, 16, 16), 0);
+    }
+
+    // ========================================================================
+    // Global Alpha Tests
+    // ========================================================================
+
+    #[test]
+    fn test_set_alpha_halves_opacity() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(8, 8).unwrap();
+        let commands = vec![
+            DrawCommand::SetAlpha { alpha: 0.5 },
+            fill_rect(0.0, 0.0, 8.0, 8.0, "#ff0000"),
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        let pixel = pixmap.pixel(4, 4).unwrap().demultiply();
+        assert!((127..=128).contains(&pixel.alpha()), "alpha {}", pixel.alpha());
+        assert_eq!(pixel.red(), 255);
+        assert_eq!(pixel.green(), 0);
+    }
+
+    #[test]
+    fn test_set_alpha_composes_with_color_alpha() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(8, 8).unwrap();
+        let commands = vec![
+            DrawCommand::SetAlpha { alpha: 0.5 },
+            fill_rect(0.0, 0.0, 8.0, 8.0, "#ff000080"),
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        let alpha = pixmap.pixel(4, 4).unwrap().alpha();
+        assert!((63..=65).contains(&alpha), "alpha {}", alpha);
+    }
+
+    #[test]
+    fn test_reset_alpha_and_clamping() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(8, 8).unwrap();
+        let commands = vec![
+            DrawCommand::SetAlpha { alpha: 0.25 },
+            DrawCommand::ResetAlpha,
+            fill_rect(0.0, 0.0, 4.0, 8.0, "#ff0000"),
+            DrawCommand::SetAlpha { alpha: 3.0 },
+            fill_rect(4.0, 0.0, 4.0, 8.0, "#00ff00"),
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+        assert!(pixmap.pixels().iter().all(|p| p.alpha() == 255));
+    }
+
+    #[test]
+    fn test_set_alpha_applies_to_images() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let path = temp_dir.path().join("red.png");
+        image::RgbaImage::from_pixel(8, 8, image::Rgba([255, 0, 0, 255])).save(&path).unwrap();
+
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(8, 8).unwrap();
+        let commands = vec![
+            DrawCommand::SetAlpha { alpha: 0.5 },
+            DrawCommand::Image { path: path.to_string_lossy().to_string(), x: 0.0, y: 0.0, w: 8.0, h: 8.0 },
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        let alpha = pixmap.pixel(4, 4).unwrap().alpha();
+        assert!((127..=128).contains(&alpha), "alpha {}", alpha);
+    }
+
+    // ========================================================================
+    // Transform Tests
+    // ========================================================================
+
+    fn push_transform(rotate_deg: f32, tx: f32, ty: f32, scale: f32) -> DrawCommand {
+        DrawCommand::PushTransform { rotate_deg, tx, ty, scale }
+    }
+
+    #[test]
+    fn test_rotation_about_center() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        // A bar from the center to the right, turned a quarter clockwise
+        let commands = vec![
+            push_transform(90.0, 32.0, 32.0, 1.0),
+            fill_rect(0.0, -2.0, 20.0, 4.0, "#ff0000"),
+            DrawCommand::PopTransform,
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        // It now points down from the center
+        assert_eq!(alpha(&pixmap, 32, 40), 255);
+        assert_eq!(alpha(&pixmap, 32, 50), 255);
+        assert_eq!(alpha(&pixmap, 40, 32), 0);
+        assert_eq!(alpha(&pixmap, 32, 24), 0);
+    }
+
+    #[test]
+    fn test_transforms_nest_and_pop() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        let commands = vec![
+            push_transform(0.0, 32.0, 0.0, 1.0),
+            push_transform(0.0, 0.0, 32.0, 2.0),
+            fill_rect(0.0, 0.0, 4.0, 4.0, "#ff0000"),
+            DrawCommand::PopTransform,
+            fill_rect(0.0, 0.0, 4.0, 4.0, "#00ff00"),
+            DrawCommand::PopTransform,
+            fill_rect(0.0, 0.0, 4.0, 4.0, "#0000ff"),
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+
+        assert_eq!(pixmap.pixel(39, 39).unwrap().red(), 255, "scaled to 8x8 at (32, 32)");
+        assert_eq!(pixmap.pixel(33, 1).unwrap().green(), 255, "outer push only");
+        assert_eq!(pixmap.pixel(1, 1).unwrap().blue(), 255, "no transform");
+    }
+
+    #[test]
+    fn test_unbalanced_transforms() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+
+        // Extra pops are ignored and a push left open ends with the render
+        let commands = vec![DrawCommand::PopTransform, push_transform(0.0, 32.0, 32.0, 1.0)];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+        renderer.execute_commands(&mut pixmap, &[fill_rect(0.0, 0.0, 4.0, 4.0, "#ff0000")]).unwrap();
+        assert_eq!(alpha(&pixmap, 1, 1), 255);
+        assert_eq!(alpha(&pixmap, 33, 33), 0);
+
+        // An invalid push draws untransformed but still pairs with its pop
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        let commands = vec![
+            push_transform(0.0, 32.0, 32.0, 1.0),
+            push_transform(f32::NAN, 0.0, 0.0, 1.0),
+            DrawCommand::PopTransform,
+            fill_rect(0.0, 0.0, 4.0, 4.0, "#ff0000"),
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+        assert_eq!(alpha(&pixmap, 33, 33), 255);
+    }
+
+    #[test]
+    fn test_transform_scales_with_commands() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let commands = vec![
+            push_transform(90.0, 16.0, 16.0, 1.0),
+            fill_rect(0.0, -1.0, 10.0, 2.0, "#ff0000"),
+        ];
+        let pixmap = renderer.render_commands(&commands, 32, 32, 2).unwrap();
+        assert_eq!(alpha(&pixmap, 32, 50), 255);
+        assert_eq!(alpha(&pixmap, 50, 32), 0);
+    }
+
+    #[test]
+    fn test_transform_applies_to_clip() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let mut pixmap = Pixmap::new(64, 64).unwrap();
+        let commands = vec![
+            push_transform(0.0, 32.0, 0.0, 1.0),
+            DrawCommand::Clip { x: 0.0, y: 0.0, w: 8.0, h: 8.0 },
+            DrawCommand::PopTransform,
+            fill_rect(0.0, 0.0, 64.0, 64.0, "#ff0000"),
+        ];
+        renderer.execute_commands(&mut pixmap, &commands).unwrap();
+        assert_eq!(alpha(&pixmap, 35, 4), 255);
+        assert_eq!(alpha(&pixmap, 4, 4), 0);
+    }
+
+    // ========================================================================
+    // Parallel Rendering Tests
+    // ========================================================================
+
+    #[test]
+    fn test_render_many_matches_sequential() {
+        let renderer = IconRenderer::new(64, 12.0);
+        let lists: Vec<Vec<DrawCommand>> = (0..24)
+            .map(|i| {
+                let shade = format!("#{:02x}{:02x}80", i * 10, 255 - i * 10);
+                vec![
+                    DrawCommand::Clear { color: "#00000000".to_string() },
+                    fill_rect(i as f32, 2.0, 20.0, 30.0, &shade),
+                    DrawCommand::FillCircle { cx: 32.0, cy: 40.0, r: 4.0 + i as f32, color: "#ffffff80".to_string() },
+                    DrawCommand::SetAlpha { alpha: 0.5 },
+                    DrawCommand::Line {
+                        x1: 0.0,
+                        y1: 0.0,
+                        x2: 64.0,
+                        y2: i as f32 * 3.0,
+                        color: "#00ff00".to_string(),
+                        width: 2.0,
+                        dash: Vec::new(),
+                    },
+                ]
+            })
+            .collect();
+        // Canvases of different sizes, as icon types can have
+        let slices: Vec<(&[DrawCommand], u32, u32)> = lists
+            .iter()
+            .enumerate()
+            .map(|(i, commands)| if i % 2 == 0 { (commands.as_slice(), 64, 84) } else { (commands.as_slice(), 96, 96) })
+            .collect();
+
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let parallel = renderer.render_many(&pool, &slices, 2);
+
+        assert_eq!(parallel.len(), lists.len());
+        for (&(commands, width, height), pixmap) in slices.iter().zip(parallel) {
+            let sequential = renderer.render_commands(commands, width, height, 2).unwrap();
+            let pixmap = pixmap.unwrap();
+            assert_eq!((pixmap.width(), pixmap.height()), (width * 2, height * 2));
+            assert_eq!(pixmap.data(), sequential.data());
+        }
+    }
+
+    // ========================================================================
+    // Theme Icon Tests
+    // ========================================================================
+
+    /// Renderer using a fixture theme with a PNG folder and an SVG file icon
+    fn themed_renderer(base: &Path) -> IconRenderer {
+        let theme = base.join("Fixture");
+        std::fs::create_dir_all(theme.join("64x64/places")).unwrap();
+        std::fs::create_dir_all(theme.join("scalable/mimetypes")).unwrap();
+        std::fs::write(
+            theme.join("index.theme"),
+            "[Icon Theme]\nDirectories=64x64/places,scalable/mimetypes\n\n\
+             [64x64/places]\nSize=64\nType=Fixed\n\n\
+             [scalable/mimetypes]\nSize=64\nMinSize=16\nMaxSize=256\nType=Scalable\n",
+        )
+        .unwrap();
+
+        let mut img = image::RgbaImage::new(8, 8);
+        for pixel in img.pixels_mut() {
+            *pixel = image::Rgba([0, 255, 0, 255]);
+        }
+        img.save(theme.join("64x64/places/folder.png")).unwrap();
+        std::fs::write(
+            theme.join("scalable/mimetypes/text-x-generic.svg"),
+            r##"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16"><rect width="16" height="16" fill="#0000ff"/></svg>"##,
+        )
+        .unwrap();
+
+        IconRenderer {
+            theme: Some(IconTheme::with_base_dirs("Fixture", vec![base.to_path_buf()])),
+            ..IconRenderer::new(64, 12.0)
+        }
+    }
+
+    fn center(pixmap: &Pixmap) -> [u8; 3] {
+        let pixel = pixmap.pixel(32, 32).unwrap();
+        [pixel.red(), pixel.green(), pixel.blue()]
+    }
+
+    #[test]
+    fn test_render_uses_theme_png_and_svg() {
+        let dir = tempfile::tempdir().unwrap();
+        let renderer = themed_renderer(dir.path());
+        let config = crate::config::Config::default();
+
+        let folder = DesktopIcon::new(dir.path(), &config).unwrap();
+        assert_eq!(center(&renderer.render(&folder).unwrap()), [0, 255, 0]);
+
+        let file = DesktopIcon::new(&dir.path().join("notes.unknown"), &config).unwrap();
+        assert_eq!(center(&renderer.render(&file).unwrap()), [0, 0, 255]);
+    }
+
+    #[test]
+    fn test_render_falls_back_to_placeholder_without_theme_icon() {
+        let dir = tempfile::tempdir().unwrap();
+        let renderer = themed_renderer(dir.path());
+        let config = crate::config::Config::default();
+
+        // The fixture theme has no image-x-generic
+        let image = DesktopIcon::new(&dir.path().join("photo.png"), &config).unwrap();
+        let pixmap = renderer.render(&image).unwrap();
+        assert_eq!(center(&pixmap), [198, 120, 221]);
+        assert!(renderer.theme_icons.lock().unwrap()[&("image-x-generic", 64)].is_none());
+    }
 }
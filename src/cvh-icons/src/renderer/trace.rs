@@ -0,0 +1,144 @@
+//! Render trace: the draw commands each icon produced, as JSON lines
+//!
+//! Opt-in via `--trace-render`; one line per rendered icon, written before
+//! the commands reach the renderer so the output can be diffed directly.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::lua::DrawCommand;
+
+/// One line of the trace
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    pub path: String,
+    pub commands: Vec<DrawCommand>,
+}
+
+/// Writer for the render trace
+pub struct RenderTrace {
+    out: Box<dyn Write>,
+}
+
+impl RenderTrace {
+    /// Trace to `target`, or stdout if it is `-`
+    pub fn open(target: &Path) -> Result<Self> {
+        if target == Path::new("-") {
+            return Ok(Self::new(Box::new(io::stdout())));
+        }
+        let file = File::create(target)
+            .with_context(|| format!("Failed to create render trace: {}", target.display()))?;
+        Ok(Self::new(Box::new(BufWriter::new(file))))
+    }
+
+    pub fn new(out: Box<dyn Write>) -> Self {
+        Self { out }
+    }
+
+    /// Append the commands rendered for `path`
+    ///
+    /// Flushed per line so the trace stays readable while the daemon runs.
+    pub fn record(&mut self, path: &Path, commands: &[DrawCommand]) -> Result<()> {
+        let record = TraceRecord {
+            path: path.to_string_lossy().to_string(),
+            commands: commands.to_vec(),
+        };
+        serde_json::to_writer(&mut self.out, &record)?;
+        self.out.write_all(b"\n")?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Parse a trace back into its records
+#[cfg(test)]
+pub fn parse(trace: &str) -> Result<Vec<TraceRecord>> {
+    trace
+        .lines()
+        .map(|line| serde_json::from_str(line).context("Invalid trace line"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lua::api::GradientStop;
+    use std::sync::{Arc, Mutex};
+
+    /// Writer whose output stays readable after the trace takes it
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn sample_commands() -> Vec<DrawCommand> {
+        vec![
+            DrawCommand::Clear { color: "#00000000".to_string() },
+            DrawCommand::RoundRect { x: 4.0, y: 4.0, w: 56.0, h: 56.0, r: 8.0, color: "#88c0d0".to_string() },
+            DrawCommand::Text {
+                text: "notes \"v2\".txt".to_string(),
+                x: 32.0,
+                y: 76.5,
+                size: 12.0,
+                color: "#ffffff".to_string(),
+                align: "center".to_string(),
+            },
+            DrawCommand::LinearGradient {
+                x: 0.0,
+                y: 0.0,
+                w: 64.0,
+                h: 8.0,
+                stops: vec![
+                    GradientStop { offset: 0.0, color: "#000000".to_string() },
+                    GradientStop { offset: 1.0, color: "#ffffff".to_string() },
+                ],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_trace_roundtrips_commands() {
+        let buf = SharedBuf::default();
+        let mut trace = RenderTrace::new(Box::new(buf.clone()));
+        let commands = sample_commands();
+
+        trace.record(Path::new("/home/user/Desktop/notes.txt"), &commands).unwrap();
+        trace.record(Path::new("/home/user/Desktop/empty"), &[]).unwrap();
+
+        let output = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().count(), 2);
+
+        let records = parse(&output).unwrap();
+        assert_eq!(
+            records,
+            [
+                TraceRecord { path: "/home/user/Desktop/notes.txt".to_string(), commands },
+                TraceRecord { path: "/home/user/Desktop/empty".to_string(), commands: Vec::new() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_to_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("trace.jsonl");
+
+        let mut trace = RenderTrace::open(&target).unwrap();
+        trace.record(Path::new("/tmp/a"), &sample_commands()).unwrap();
+
+        let records = parse(&std::fs::read_to_string(&target).unwrap()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].commands, sample_commands());
+    }
+}
@@ -0,0 +1,212 @@
+//! Policy for actions icon scripts ask the daemon to perform
+//!
+//! Scripts never launch anything themselves: `cvh.open`/`cvh.spawn` and the
+//! actions returned from event handlers are requests, checked here before
+//! the daemon runs them outside the sandbox.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Component, Path};
+use std::process::{Command, Stdio};
+
+use crate::ipc::EventAction;
+
+/// Program used to open paths and URIs
+pub const OPENER: &str = "xdg-open";
+
+/// Programs `spawn` may run unless configured otherwise
+pub const DEFAULT_ALLOWED_COMMANDS: &[&str] = &[OPENER];
+
+/// URI schemes `open` accepts unless configured otherwise
+pub const DEFAULT_ALLOWED_URI_SCHEMES: &[&str] = &["file", "http", "https", "mailto"];
+
+/// What scripts may open and spawn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionPolicy {
+    /// Programs `spawn` may run, matched against the command's first word
+    pub allowed_commands: Vec<String>,
+    /// URI schemes `open` accepts; absolute paths are always accepted
+    pub allowed_uri_schemes: Vec<String>,
+    /// Whether scripts may move desktop files to the trash
+    pub allow_trash: bool,
+}
+
+impl Default for ActionPolicy {
+    fn default() -> Self {
+        Self {
+            allowed_commands: DEFAULT_ALLOWED_COMMANDS.iter().map(|c| c.to_string()).collect(),
+            allowed_uri_schemes: DEFAULT_ALLOWED_URI_SCHEMES.iter().map(|s| s.to_string()).collect(),
+            allow_trash: true,
+        }
+    }
+}
+
+impl ActionPolicy {
+    /// Build the command that carries out `action`, or fail if it isn't allowed
+    ///
+    /// `spawn` payloads are split on whitespace and run directly, never
+    /// through a shell. Spawning the opener is an `open` in disguise, so it
+    /// takes exactly one target and the same checks.
+    pub fn command(&self, action: &EventAction) -> Result<Command> {
+        let payload = action.payload.as_deref().unwrap_or_default();
+        let mut cmd = match action.action.as_str() {
+            "open" => {
+                self.check_open_target(payload)?;
+                let mut cmd = Command::new(OPENER);
+                cmd.arg(payload);
+                cmd
+            }
+            "spawn" => {
+                let mut words = payload.split_whitespace();
+                let program = words.next().context("Empty spawn command")?;
+                if !self.allowed_commands.iter().any(|allowed| allowed == program) {
+                    bail!("Command '{}' is not in the allowed commands", program);
+                }
+                let args: Vec<&str> = words.collect();
+                if program == OPENER {
+                    match args[..] {
+                        [target] => self.check_open_target(target)?,
+                        _ => bail!("{} takes exactly one target", OPENER),
+                    }
+                }
+                let mut cmd = Command::new(program);
+                cmd.args(args);
+                cmd
+            }
+            other => bail!("Action '{}' does not launch anything", other),
+        };
+
+        cmd.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+        Ok(cmd)
+    }
+
+    /// Check an action returned from an event handler
+    ///
+    /// Opens and spawns must yield an allowed command and trash requests
+    /// need `allow_trash`; where a trashed path may lie is up to the daemon,
+    /// which knows the desktop. Other actions pass.
+    pub fn check(&self, action: &EventAction) -> Result<()> {
+        match (action.action.as_str(), &action.payload) {
+            ("open" | "spawn", Some(_)) => self.command(action).map(drop),
+            ("trash", _) if !self.allow_trash => bail!("Trashing files is not allowed"),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether `action` would be carried out
+    #[cfg(test)]
+    pub fn allows(&self, action: &EventAction) -> bool {
+        self.command(action).is_ok()
+    }
+
+    /// Accept absolute paths without `..` and URIs with an allowed scheme
+    fn check_open_target(&self, target: &str) -> Result<()> {
+        let path = Path::new(target);
+        if path.is_absolute() {
+            if path.components().any(|c| c == Component::ParentDir) {
+                bail!("Refusing to open path with '..': {}", target);
+            }
+            return Ok(());
+        }
+
+        match target.split_once(':') {
+            Some((scheme, _)) if is_uri_scheme(scheme) => {
+                let scheme = scheme.to_ascii_lowercase();
+                if !self.allowed_uri_schemes.contains(&scheme) {
+                    bail!("URI scheme '{}' is not allowed", scheme);
+                }
+                Ok(())
+            }
+            _ => bail!("Can only open absolute paths and URIs, got '{}'", target),
+        }
+    }
+}
+
+/// RFC 3986 scheme: a letter followed by letters, digits, '+', '-' or '.'
+fn is_uri_scheme(scheme: &str) -> bool {
+    let mut chars = scheme.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(kind: &str, payload: &str) -> EventAction {
+        EventAction {
+            action: kind.to_string(),
+            payload: Some(payload.to_string()),
+        }
+    }
+
+    fn argv(cmd: &Command) -> Vec<String> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_spawn_allowed_command() {
+        let policy = ActionPolicy::default();
+        let cmd = policy.command(&action("spawn", "xdg-open /home/user/notes.txt")).unwrap();
+        assert_eq!(argv(&cmd), ["xdg-open", "/home/user/notes.txt"]);
+    }
+
+    #[test]
+    fn test_spawn_disallowed_command_rejected() {
+        let policy = ActionPolicy::default();
+        assert!(!policy.allows(&action("spawn", "rm -rf /")));
+        assert!(!policy.allows(&action("spawn", "sh -c 'xdg-open /tmp'")));
+        assert!(!policy.allows(&action("spawn", "/usr/bin/xdg-open /tmp")), "matched by exact name");
+        assert!(!policy.allows(&action("spawn", "   ")));
+    }
+
+    #[test]
+    fn test_spawn_opener_checks_target() {
+        let policy = ActionPolicy::default();
+        assert!(policy.allows(&action("spawn", "xdg-open https://example.org")));
+        assert!(!policy.allows(&action("spawn", "xdg-open evil-scheme://payload")));
+        assert!(!policy.allows(&action("spawn", "xdg-open relative/path")));
+        assert!(!policy.allows(&action("spawn", "xdg-open /home/user/../../etc/shadow")));
+        assert!(!policy.allows(&action("spawn", "xdg-open /tmp/a /tmp/b")));
+        assert!(!policy.allows(&action("spawn", "xdg-open")));
+    }
+
+    #[test]
+    fn test_spawn_configured_command() {
+        let policy = ActionPolicy {
+            allowed_commands: vec!["foot".to_string()],
+            ..Default::default()
+        };
+        assert!(policy.allows(&action("spawn", "foot --working-directory /tmp")));
+        assert!(!policy.allows(&action("spawn", "xdg-open /tmp")));
+    }
+
+    #[test]
+    fn test_open_paths_and_uris() {
+        let policy = ActionPolicy::default();
+        let cmd = policy.command(&action("open", "/home/user/Desktop/report.pdf")).unwrap();
+        assert_eq!(argv(&cmd), ["xdg-open", "/home/user/Desktop/report.pdf"]);
+
+        assert!(policy.allows(&action("open", "https://example.com/docs")));
+        assert!(policy.allows(&action("open", "MAILTO:someone@example.com")));
+    }
+
+    #[test]
+    fn test_open_rejects_unsafe_targets() {
+        let policy = ActionPolicy::default();
+        assert!(!policy.allows(&action("open", "relative/path.txt")));
+        assert!(!policy.allows(&action("open", "--help")));
+        assert!(!policy.allows(&action("open", "/home/user/../../etc/shadow")));
+        assert!(!policy.allows(&action("open", "javascript:alert(1)")));
+        assert!(!policy.allows(&EventAction { action: "open".to_string(), payload: None }));
+    }
+
+    #[test]
+    fn test_other_actions_launch_nothing() {
+        let policy = ActionPolicy::default();
+        assert!(!policy.allows(&action("notify", "hello")));
+        assert!(!policy.allows(&action("none", "")));
+    }
+}
@@ -0,0 +1,388 @@
+//! Landlock filesystem restriction for Lua processes
+//!
+//! Limits the interpreter to the sandbox's read-only and read-write paths
+//! plus the script directories, independently of any bind mounts. Landlock
+//! also forbids mount(2), so it can't be applied to bwrap itself: inside
+//! the sandbox, cvh-icons re-execs itself with `--landlock-exec`, which
+//! applies the ruleset after the mounts are set up and then execs the
+//! interpreter (see `exec_confined`).
+
+use std::ffi::{CString, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context, Result};
+use tracing::warn;
+
+use super::SandboxOptions;
+
+// Filesystem access rights from landlock ABI v1 (linux/landlock.h)
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+
+/// Rights for read-only paths
+pub const ACCESS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+/// Rights for read-write paths: everything ABI v1 can restrict
+pub const ACCESS_READ_WRITE: u64 = ACCESS_READ
+    | ACCESS_FS_WRITE_FILE
+    | ACCESS_FS_REMOVE_DIR
+    | ACCESS_FS_REMOVE_FILE
+    | ACCESS_FS_MAKE_CHAR
+    | ACCESS_FS_MAKE_DIR
+    | ACCESS_FS_MAKE_REG
+    | ACCESS_FS_MAKE_SOCK
+    | ACCESS_FS_MAKE_FIFO
+    | ACCESS_FS_MAKE_BLOCK
+    | ACCESS_FS_MAKE_SYM;
+
+const CREATE_RULESET_VERSION: u32 = 1 << 0;
+const RULE_PATH_BENEATH: libc::c_int = 1;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Access granted beneath one path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub path: PathBuf,
+    pub access: u64,
+}
+
+/// A set of rules, prepared so it can be applied between fork and exec
+#[derive(Debug)]
+pub struct Ruleset {
+    rules: Vec<Rule>,
+    /// NUL-terminated copies of the rule paths, so `apply` doesn't allocate
+    c_paths: Vec<CString>,
+}
+
+impl Ruleset {
+    /// Build the ruleset for a Lua process from the sandbox options
+    ///
+    /// Read-only paths and `script_dirs` get read access, read-write paths
+    /// full access. A path listed more than once keeps the widest access.
+    pub fn from_options(options: &SandboxOptions, script_dirs: &[&Path]) -> Self {
+        let read_only = options
+            .read_only_paths
+            .iter()
+            .map(PathBuf::as_path)
+            .chain(script_dirs.iter().copied());
+        Self::from_paths(read_only, options.read_write_paths.iter().map(PathBuf::as_path))
+    }
+
+    /// Build a ruleset granting read access beneath `read_only` and full
+    /// access beneath `read_write`
+    ///
+    /// Paths containing a NUL byte can't be opened and are skipped with a
+    /// warning.
+    pub fn from_paths<'a>(
+        read_only: impl IntoIterator<Item = &'a Path>,
+        read_write: impl IntoIterator<Item = &'a Path>,
+    ) -> Self {
+        let read_only = read_only.into_iter().map(|path| (path, ACCESS_READ));
+        let read_write = read_write.into_iter().map(|path| (path, ACCESS_READ_WRITE));
+
+        let mut rules: Vec<Rule> = Vec::new();
+        for (path, access) in read_only.chain(read_write) {
+            match rules.iter_mut().find(|rule| rule.path == path) {
+                Some(rule) => rule.access |= access,
+                None => rules.push(Rule { path: path.to_path_buf(), access }),
+            }
+        }
+
+        // Pair each rule with its C path so a bad path drops its whole rule
+        let (rules, c_paths) = rules
+            .into_iter()
+            .filter_map(|rule| match CString::new(rule.path.as_os_str().as_bytes()) {
+                Ok(c_path) => Some((rule, c_path)),
+                Err(_) => {
+                    warn!("Skipping Landlock rule for {:?}: path contains a NUL byte", rule.path);
+                    None
+                }
+            })
+            .unzip();
+        Self { rules, c_paths }
+    }
+
+    /// Arguments that rebuild this ruleset through `--landlock-read` and
+    /// `--landlock-write`
+    pub fn exec_args(&self) -> Vec<OsString> {
+        let mut args = Vec::with_capacity(self.rules.len() * 2);
+        for rule in &self.rules {
+            let flag = if rule.access == ACCESS_READ { "--landlock-read" } else { "--landlock-write" };
+            args.push(OsString::from(flag));
+            args.push(rule.path.clone().into_os_string());
+        }
+        args
+    }
+
+    /// The rules in the order they are added
+    #[cfg(test)]
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// Restrict the calling process to this ruleset
+    ///
+    /// Meant for a `pre_exec` hook: only makes raw syscalls and doesn't
+    /// allocate. Paths that don't exist are skipped.
+    pub fn apply(&self) -> io::Result<()> {
+        let attr = RulesetAttr { handled_access_fs: ACCESS_READ_WRITE };
+        // SAFETY: plain syscall with a valid pointer to a correctly sized struct
+        let ruleset_fd = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0u32,
+            )
+        };
+        if ruleset_fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ruleset_fd = ruleset_fd as libc::c_int;
+
+        let result = self.add_rules(ruleset_fd).and_then(|()| restrict_self(ruleset_fd));
+        // SAFETY: closing the fd we created above
+        unsafe { libc::close(ruleset_fd) };
+        result
+    }
+
+    fn add_rules(&self, ruleset_fd: libc::c_int) -> io::Result<()> {
+        for (rule, c_path) in self.rules.iter().zip(&self.c_paths) {
+            // SAFETY: c_path is a valid NUL-terminated string
+            let fd = unsafe { libc::open(c_path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+            if fd < 0 {
+                continue;
+            }
+
+            let beneath = PathBeneathAttr { allowed_access: rule.access, parent_fd: fd };
+            // SAFETY: plain syscall with a valid pointer to a packed struct
+            let result = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_add_rule,
+                    ruleset_fd,
+                    RULE_PATH_BENEATH,
+                    &beneath as *const PathBeneathAttr,
+                    0u32,
+                )
+            };
+            let error = io::Error::last_os_error();
+            // SAFETY: closing the fd we opened above
+            unsafe { libc::close(fd) };
+            if result < 0 {
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn restrict_self(ruleset_fd: libc::c_int) -> io::Result<()> {
+    // SAFETY: prctl and landlock_restrict_self take no pointers here
+    unsafe {
+        if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::syscall(libc::SYS_landlock_restrict_self, ruleset_fd, 0u32) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Restrict this process to `ruleset` and replace it with `command`
+///
+/// Runs inside the bubblewrap sandbox, once the mounts are in place. Only
+/// returns on failure; the interpreter never starts unconfined.
+pub fn exec_confined(ruleset: &Ruleset, command: &[OsString]) -> Result<()> {
+    let Some((program, args)) = command.split_first() else {
+        bail!("--landlock-exec needs a command");
+    };
+    ruleset.apply().context("failed to apply the Landlock ruleset")?;
+    let error = Command::new(program).args(args).exec();
+    Err(error).with_context(|| format!("failed to exec {}", program.to_string_lossy()))
+}
+
+/// Landlock ABI version supported by the running kernel, or None if
+/// Landlock is unavailable or disabled
+pub fn abi_version() -> Option<u32> {
+    // SAFETY: the version query takes no pointers
+    let version = unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            std::ptr::null::<RulesetAttr>(),
+            0usize,
+            CREATE_RULESET_VERSION,
+        )
+    };
+    (version > 0).then_some(version as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ruleset_from_options() {
+        let options = SandboxOptions {
+            read_only_paths: vec![PathBuf::from("/usr"), PathBuf::from("/data")],
+            read_write_paths: vec![PathBuf::from("/scratch")],
+            ..Default::default()
+        };
+        let ruleset = Ruleset::from_options(&options, &[Path::new("/opt/cvh/scripts")]);
+
+        assert_eq!(
+            ruleset.rules(),
+            [
+                Rule { path: PathBuf::from("/usr"), access: ACCESS_READ },
+                Rule { path: PathBuf::from("/data"), access: ACCESS_READ },
+                Rule { path: PathBuf::from("/opt/cvh/scripts"), access: ACCESS_READ },
+                Rule { path: PathBuf::from("/scratch"), access: ACCESS_READ_WRITE },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ruleset_merges_duplicate_paths() {
+        let options = SandboxOptions {
+            read_only_paths: vec![PathBuf::from("/data")],
+            read_write_paths: vec![PathBuf::from("/data")],
+            ..Default::default()
+        };
+        let ruleset = Ruleset::from_options(&options, &[Path::new("/data")]);
+        assert_eq!(
+            ruleset.rules(),
+            [Rule { path: PathBuf::from("/data"), access: ACCESS_READ_WRITE }]
+        );
+    }
+
+    #[test]
+    fn test_ruleset_read_access_is_subset() {
+        assert_eq!(ACCESS_READ & ACCESS_READ_WRITE, ACCESS_READ);
+        assert_eq!(ACCESS_READ & ACCESS_FS_WRITE_FILE, 0);
+    }
+
+    #[test]
+    fn test_ruleset_skips_paths_with_nul_as_a_whole() {
+        use std::ffi::OsStr;
+
+        let bad = PathBuf::from(OsStr::from_bytes(b"/bad\0path"));
+        let ruleset = Ruleset::from_paths(
+            [Path::new("/usr"), bad.as_path()],
+            [Path::new("/scratch")],
+        );
+
+        assert_eq!(
+            ruleset.rules(),
+            [
+                Rule { path: PathBuf::from("/usr"), access: ACCESS_READ },
+                Rule { path: PathBuf::from("/scratch"), access: ACCESS_READ_WRITE },
+            ]
+        );
+        let c_paths: Vec<_> = ruleset.c_paths.iter().map(|path| path.to_str().unwrap()).collect();
+        assert_eq!(c_paths, ["/usr", "/scratch"]);
+    }
+
+    #[test]
+    fn test_exec_args_round_trip() {
+        let options = SandboxOptions {
+            read_only_paths: vec![PathBuf::from("/usr"), PathBuf::from("/data")],
+            read_write_paths: vec![PathBuf::from("/data"), PathBuf::from("/scratch")],
+            ..Default::default()
+        };
+        let ruleset = Ruleset::from_options(&options, &[Path::new("/opt/cvh/scripts")]);
+
+        let args = ruleset.exec_args();
+        assert_eq!(
+            args,
+            [
+                "--landlock-read", "/usr",
+                "--landlock-write", "/data",
+                "--landlock-read", "/opt/cvh/scripts",
+                "--landlock-write", "/scratch",
+            ]
+        );
+
+        let paths = |flag: &str| -> Vec<PathBuf> {
+            args.chunks(2).filter(|pair| pair[0] == flag).map(|pair| PathBuf::from(&pair[1])).collect()
+        };
+        let (read_only, read_write) = (paths("--landlock-read"), paths("--landlock-write"));
+        let rebuilt = Ruleset::from_paths(
+            read_only.iter().map(PathBuf::as_path),
+            read_write.iter().map(PathBuf::as_path),
+        );
+        let mut expected = ruleset.rules().to_vec();
+        expected.sort_by(|a, b| a.path.cmp(&b.path));
+        let mut actual = rebuilt.rules().to_vec();
+        actual.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_exec_confined_rejects_empty_command() {
+        let ruleset = Ruleset::from_paths([Path::new("/usr")], []);
+        let error = exec_confined(&ruleset, &[]).unwrap_err();
+        assert!(error.to_string().contains("needs a command"));
+    }
+
+    #[test]
+    fn test_ruleset_enforced_in_child() {
+        if abi_version().is_none() {
+            eprintln!("Landlock unsupported by this kernel, skipping");
+            return;
+        }
+
+        let allowed = tempfile::tempdir().unwrap();
+        let denied = tempfile::tempdir().unwrap();
+        std::fs::write(allowed.path().join("ok.txt"), "ok").unwrap();
+        std::fs::write(denied.path().join("secret.txt"), "secret").unwrap();
+
+        let options = SandboxOptions {
+            read_only_paths: vec![
+                PathBuf::from("/usr"),
+                PathBuf::from("/lib"),
+                PathBuf::from("/lib64"),
+                PathBuf::from("/bin"),
+                PathBuf::from("/etc"),
+                allowed.path().to_path_buf(),
+            ],
+            ..Default::default()
+        };
+        let ruleset = Ruleset::from_options(&options, &[]);
+
+        let mut cmd = Command::new("cat");
+        cmd.arg(allowed.path().join("ok.txt")).arg(denied.path().join("secret.txt"));
+        // SAFETY: apply() only makes raw syscalls
+        unsafe {
+            cmd.pre_exec(move || ruleset.apply());
+        }
+
+        let output = cmd.output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "ok");
+        assert!(!output.status.success(), "reading outside the ruleset should fail");
+    }
+}
@@ -2,17 +2,46 @@
 //!
 //! Provides multi-layer sandboxing:
 //! 1. Bubblewrap container isolation
-//! 2. Restricted Lua environment
+//! 2. Seccomp syscall filtering
+//! 3. Landlock filesystem restriction when running without bubblewrap
+//! 4. Restricted Lua environment
 
-use anyhow::Result;
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::OnceLock;
 
+pub mod actions;
 mod bubblewrap;
+pub mod landlock;
+pub mod notify;
+pub mod seccomp;
+pub mod trash;
+
+/// Environment variables scripts may read unless configured otherwise:
+/// desktop and theme hints, nothing that could hold a secret
+pub const DEFAULT_ENV_ALLOWLIST: &[&str] = &[
+    "XDG_CURRENT_DESKTOP",
+    "XDG_SESSION_DESKTOP",
+    "XDG_SESSION_TYPE",
+    "DESKTOP_SESSION",
+    "GTK_THEME",
+    "QT_STYLE_OVERRIDE",
+    "XCURSOR_THEME",
+    "XCURSOR_SIZE",
+];
+
+/// Lua interpreters looked for unless configured otherwise, in order
+pub const DEFAULT_LUA_INTERPRETERS: &[&str] = &["lua", "lua5.4", "lua5.3", "luajit", "lua5.1"];
+
+/// Where interpreters are looked for: the sandbox binds `/usr` and links
+/// `/bin` to `/usr/bin`
+const INTERPRETER_DIR: &str = "/usr/bin";
 
 /// Sandbox configuration for icon scripts
 #[allow(dead_code)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SandboxOptions {
     /// Allow network access
     pub allow_network: bool,
@@ -26,6 +55,35 @@ pub struct SandboxOptions {
     /// Environment variables to pass
     pub env_vars: Vec<(String, String)>,
 
+    /// Daemon environment variables scripts may read; passed into the
+    /// sandbox and readable through `cvh.env.get`
+    pub env_allowlist: Vec<String>,
+
+    /// What scripts may ask the daemon to open or spawn
+    pub action_policy: actions::ActionPolicy,
+
+    /// Restrict syscalls with a seccomp filter
+    pub seccomp: bool,
+
+    /// Restrict filesystem access with Landlock where the kernel supports it
+    pub landlock: bool,
+
+    /// CPU time limit in seconds (RLIMIT_CPU); the process is killed past it
+    pub max_cpu_secs: Option<u64>,
+
+    /// Address space limit in bytes (RLIMIT_AS)
+    pub max_memory_bytes: Option<u64>,
+
+    /// Refuse to run scripts when bubblewrap is missing instead of running
+    /// them unsandboxed
+    pub require_sandbox: bool,
+
+    /// Name of the Lua interpreter in `/usr/bin`
+    pub lua_interpreter: String,
+
+    /// Directory holding each icon's `cvh.state` directory
+    pub state_dir: Option<PathBuf>,
+
     /// Working directory
     pub work_dir: Option<PathBuf>,
 }
@@ -41,34 +99,259 @@ impl Default for SandboxOptions {
             ],
             read_write_paths: Vec::new(),
             env_vars: Vec::new(),
+            env_allowlist: DEFAULT_ENV_ALLOWLIST.iter().map(|name| name.to_string()).collect(),
+            action_policy: actions::ActionPolicy::default(),
+            seccomp: true,
+            landlock: true,
+            max_cpu_secs: None,
+            max_memory_bytes: None,
+            require_sandbox: true,
+            lua_interpreter: DEFAULT_LUA_INTERPRETERS[0].to_string(),
+            state_dir: None,
             work_dir: None,
         }
     }
 }
 
+impl SandboxOptions {
+    /// Canonicalized read-only and read-write paths, i.e. everything a
+    /// sandboxed script can see besides the system directories
+    pub fn allowed_roots(&self) -> Vec<PathBuf> {
+        self.read_only_paths
+            .iter()
+            .chain(&self.read_write_paths)
+            .map(|root| root.canonicalize().unwrap_or_else(|_| root.clone()))
+            .collect()
+    }
+}
+
 /// Check if bubblewrap is available
-pub fn _is_bubblewrap_available() -> bool {
-    Command::new("bwrap")
-        .arg("--version")
-        .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+///
+/// Probed once; installing bwrap while the daemon runs needs a restart.
+pub fn is_bubblewrap_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        Command::new("bwrap")
+            .arg("--version")
+            .output()
+            .map(|o| o.status.success())
+            .unwrap_or(false)
+    })
 }
 
+/// The first of `candidates` that can run inside the sandbox
+///
+/// Fails with the names tried if none is installed in `/usr/bin`.
+pub fn lua_interpreter(candidates: &[String]) -> Result<String> {
+    find_interpreter(Path::new(INTERPRETER_DIR), Path::new("/usr"), candidates).with_context(|| {
+        format!(
+            "No Lua interpreter found in {} (tried {}); install Lua or set sandbox.lua_interpreters",
+            INTERPRETER_DIR,
+            candidates.join(", ")
+        )
+    })
+}
+
+/// The first of `candidates` that is an executable file in `dir` and,
+/// after following symlinks, stays inside `root`
+///
+/// A link out of `root` (say `/usr/bin/lua` to `/etc/alternatives/lua`)
+/// would dangle in the sandbox. Names with a path separator are skipped.
+fn find_interpreter(dir: &Path, root: &Path, candidates: &[String]) -> Option<String> {
+    let root = root.canonicalize().ok()?;
+    candidates
+        .iter()
+        .filter(|name| !name.is_empty() && !name.contains('/') && *name != "." && *name != "..")
+        .find(|name| {
+            dir.join(name).canonicalize().is_ok_and(|target| {
+                target.starts_with(&root)
+                    && target
+                        .metadata()
+                        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+            })
+        })
+        .cloned()
+}
+
+/// System trees a script must never be able to write to
+const PROTECTED_TREES: &[&str] = &[
+    "/etc", "/usr", "/bin", "/sbin", "/lib", "/lib64", "/boot", "/dev", "/proc", "/sys",
+];
+
+/// Credential directories, protected wherever they appear
+const SECRET_DIRS: &[&str] = &[".ssh", ".gnupg"];
+
 /// Validate sandbox configuration
-pub fn _validate_config(options: &SandboxOptions) -> Result<()> {
-    // Check that all specified paths exist
-    for path in &options.read_only_paths {
-        if !path.exists() {
-            tracing::warn!("Sandbox read-only path does not exist: {}", path.display());
-        }
+///
+/// Returns the options with every path canonicalized, so `..` components
+/// and symlinks can't smuggle a different directory into the sandbox.
+/// Paths that don't exist are dropped with a warning. A read-write path
+/// that is or contains `/`, the home directory, a system tree or a
+/// credential directory is an error.
+pub fn validate_config(options: &SandboxOptions) -> Result<SandboxOptions> {
+    let home = dirs::home_dir().and_then(|home| home.canonicalize().ok());
+    validate_config_with_home(options, home.as_deref())
+}
+
+fn validate_config_with_home(options: &SandboxOptions, home: Option<&Path>) -> Result<SandboxOptions> {
+    let canonical = |paths: &[PathBuf], kind: &str| -> Vec<PathBuf> {
+        paths
+            .iter()
+            .filter_map(|path| match path.canonicalize() {
+                Ok(canonical) => Some(canonical),
+                Err(_) => {
+                    tracing::warn!("Sandbox {} path does not exist: {}", kind, path.display());
+                    None
+                }
+            })
+            .collect()
+    };
+
+    let read_only_paths = canonical(&options.read_only_paths, "read-only");
+    let read_write_paths = canonical(&options.read_write_paths, "read-write");
+    for path in &read_write_paths {
+        check_writable(path, home)?;
     }
 
-    for path in &options.read_write_paths {
-        if !path.exists() {
-            tracing::warn!("Sandbox read-write path does not exist: {}", path.display());
+    Ok(SandboxOptions {
+        read_only_paths,
+        read_write_paths,
+        ..options.clone()
+    })
+}
+
+/// Reject a canonical read-write path that would expose sensitive files
+fn check_writable(path: &Path, home: Option<&Path>) -> Result<()> {
+    if path == Path::new("/") {
+        bail!("Sandbox read-write path / would expose the whole filesystem");
+    }
+    if let Some(tree) = PROTECTED_TREES.iter().find(|tree| path.starts_with(tree)) {
+        bail!("Sandbox read-write path {} is inside protected {}", path.display(), tree);
+    }
+    if home.is_some_and(|home| home.starts_with(path)) {
+        bail!("Sandbox read-write path {} would expose the home directory", path.display());
+    }
+    if path.iter().any(|part| SECRET_DIRS.iter().any(|dir| part == *dir)) {
+        bail!("Sandbox read-write path {} contains credentials", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_read_write(paths: &[&Path]) -> SandboxOptions {
+        SandboxOptions {
+            read_write_paths: paths.iter().map(|p| p.to_path_buf()).collect(),
+            ..Default::default()
         }
     }
 
-    Ok(())
+    fn write_program(path: &Path, mode: u32) {
+        std::fs::write(path, "").unwrap();
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode)).unwrap();
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_find_interpreter_search_order() {
+        let root = tempfile::tempdir().unwrap();
+        let usr = root.path().join("usr");
+        let bin = usr.join("bin");
+        let elsewhere = root.path().join("etc");
+        std::fs::create_dir_all(&bin).unwrap();
+        std::fs::create_dir_all(&elsewhere).unwrap();
+
+        write_program(&bin.join("lua5.4"), 0o755);
+        write_program(&bin.join("lua5.1"), 0o755);
+        write_program(&bin.join("luac"), 0o644);
+        write_program(&elsewhere.join("lua"), 0o755);
+        // Like /usr/bin/lua -> /etc/alternatives/lua, unreachable in the sandbox
+        std::os::unix::fs::symlink(elsewhere.join("lua"), bin.join("lua")).unwrap();
+        std::os::unix::fs::symlink("lua5.1", bin.join("luajit")).unwrap();
+
+        let find = |candidates: &[&str]| find_interpreter(&bin, &usr, &names(candidates));
+        assert_eq!(find(&["lua", "lua5.4", "lua5.1"]).as_deref(), Some("lua5.4"));
+        assert_eq!(find(&["luajit", "lua5.4"]).as_deref(), Some("luajit"));
+        assert_eq!(find(&["luac", "lua5.1"]).as_deref(), Some("lua5.1"), "not executable");
+        assert_eq!(find(&["../bin/lua5.4", "missing"]), None);
+        assert_eq!(find(&[]), None);
+    }
+
+    #[test]
+    fn test_lua_interpreter_error_names_candidates() {
+        let err = lua_interpreter(&names(&["no-such-lua", "nor-this-one"])).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("no-such-lua, nor-this-one"), "got: {}", message);
+        assert!(message.contains("sandbox.lua_interpreters"), "got: {}", message);
+    }
+
+    #[test]
+    fn test_validate_rejects_read_write_etc() {
+        let err = validate_config_with_home(&with_read_write(&[Path::new("/etc")]), None).unwrap_err();
+        assert!(err.to_string().contains("/etc"));
+    }
+
+    #[test]
+    fn test_validate_rejects_read_write_root() {
+        assert!(validate_config_with_home(&with_read_write(&[Path::new("/")]), None).is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_dot_dot_escape() {
+        let dir = tempfile::tempdir().unwrap();
+        let escape = dir.path().join("../../../../../../../../etc");
+        assert!(validate_config_with_home(&with_read_write(&[&escape]), None).is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_home_and_credentials() {
+        let home = tempfile::tempdir().unwrap();
+        let home = home.path().canonicalize().unwrap();
+        let ssh = home.join(".ssh");
+        std::fs::create_dir(&ssh).unwrap();
+
+        assert!(validate_config_with_home(&with_read_write(&[&home]), Some(&home)).is_err());
+        assert!(validate_config_with_home(&with_read_write(&[home.parent().unwrap()]), Some(&home)).is_err());
+        assert!(validate_config_with_home(&with_read_write(&[&ssh]), Some(&home)).is_err());
+    }
+
+    #[test]
+    fn test_validate_accepts_icon_data_dir() {
+        let home = tempfile::tempdir().unwrap();
+        let home = home.path().canonicalize().unwrap();
+        let data = home.join(".local/share/cvh-icons/clock");
+        std::fs::create_dir_all(&data).unwrap();
+
+        // A non-canonical spelling resolves to the same directory
+        let spelled = home.join(".local/share/cvh-icons/../cvh-icons/clock");
+        let options = validate_config_with_home(&with_read_write(&[&spelled]), Some(&home)).unwrap();
+        assert_eq!(options.read_write_paths, vec![data]);
+    }
+
+    #[test]
+    fn test_validate_drops_missing_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SandboxOptions {
+            read_only_paths: vec![dir.path().join("missing"), dir.path().to_path_buf()],
+            read_write_paths: vec![dir.path().join("also-missing")],
+            ..Default::default()
+        };
+        let options = validate_config_with_home(&options, None).unwrap();
+        assert_eq!(options.read_only_paths, vec![dir.path().canonicalize().unwrap()]);
+        assert!(options.read_write_paths.is_empty());
+    }
+
+    #[test]
+    fn test_validate_allows_read_only_system_paths() {
+        let options = SandboxOptions {
+            read_only_paths: vec![PathBuf::from("/etc")],
+            ..Default::default()
+        };
+        assert!(validate_config_with_home(&options, None).is_ok());
+    }
 }
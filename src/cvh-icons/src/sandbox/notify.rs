@@ -0,0 +1,208 @@
+//! Desktop notifications requested by icon scripts
+//!
+//! Scripts only describe a notification: `cvh.notify` and event handlers
+//! return a `notify` action whose payload is a `Notification`, and the
+//! daemon shows it with `notify-send` outside the sandbox, rate-limited per
+//! icon.
+
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Program used to show notifications
+pub const NOTIFIER: &str = "notify-send";
+
+/// Title used when a script gives none
+const DEFAULT_TITLE: &str = "cvh-icons";
+
+/// Freedesktop notification urgency levels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    /// Parse a script-given level; unknown names are None
+    pub fn parse(level: &str) -> Option<Self> {
+        match level.to_ascii_lowercase().as_str() {
+            "low" => Some(Self::Low),
+            "normal" => Some(Self::Normal),
+            "critical" => Some(Self::Critical),
+            _ => None,
+        }
+    }
+
+    /// Name `notify-send --urgency` expects
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+            Self::Critical => "critical",
+        }
+    }
+}
+
+/// A notification a script asked for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Notification {
+    pub title: String,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub urgency: Urgency,
+    /// Icon name or path for the notification server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+impl Notification {
+    /// Read a `notify` action payload
+    ///
+    /// Payloads that aren't a JSON notification (from older scripts) are
+    /// shown as the body under the default title.
+    pub fn from_payload(payload: &str) -> Self {
+        serde_json::from_str(payload).unwrap_or_else(|_| Self {
+            title: DEFAULT_TITLE.to_string(),
+            body: payload.to_string(),
+            urgency: Urgency::Normal,
+            icon: None,
+        })
+    }
+
+    /// Encode as a `notify` action payload
+    pub fn to_payload(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// `notify-send` invocation showing this notification
+    ///
+    /// Text goes after `--` as separate arguments, never through a shell.
+    pub fn command(&self) -> Command {
+        let mut cmd = Command::new(NOTIFIER);
+        cmd.args(["--app-name", DEFAULT_TITLE, "--urgency", self.urgency.as_str()]);
+        if let Some(icon) = &self.icon {
+            cmd.args(["--icon", icon]);
+        }
+        cmd.arg("--").arg(&self.title).arg(&self.body);
+        cmd.stdin(Stdio::null()).stdout(Stdio::null()).stderr(Stdio::null());
+        cmd
+    }
+}
+
+/// Sliding-window limit on how many notifications one icon may show
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    max: usize,
+    window: Duration,
+    sent: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    /// Notifications an icon may show per window by default
+    pub const DEFAULT_MAX: usize = 3;
+    /// Default window length
+    pub const DEFAULT_WINDOW: Duration = Duration::from_secs(10);
+
+    pub fn new(max: usize, window: Duration) -> Self {
+        Self { max, window, sent: VecDeque::new() }
+    }
+
+    /// Record a notification at `now` if the limit allows it
+    pub fn allow(&mut self, now: Instant) -> bool {
+        while self.sent.front().is_some_and(|&at| now.duration_since(at) >= self.window) {
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.max {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MAX, Self::DEFAULT_WINDOW)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn argv(cmd: &Command) -> Vec<String> {
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_urgency_levels() {
+        assert_eq!(Urgency::parse("low"), Some(Urgency::Low));
+        assert_eq!(Urgency::parse("Normal"), Some(Urgency::Normal));
+        assert_eq!(Urgency::parse("CRITICAL"), Some(Urgency::Critical));
+        assert_eq!(Urgency::parse("urgent"), None);
+    }
+
+    #[test]
+    fn test_payload_roundtrip_and_legacy_text() {
+        let notification = Notification {
+            title: "Backup".to_string(),
+            body: "Finished".to_string(),
+            urgency: Urgency::Critical,
+            icon: Some("drive-harddisk".to_string()),
+        };
+        assert_eq!(Notification::from_payload(&notification.to_payload()), notification);
+
+        let legacy = Notification::from_payload("plain message");
+        assert_eq!(legacy.title, DEFAULT_TITLE);
+        assert_eq!(legacy.body, "plain message");
+        assert_eq!(legacy.urgency, Urgency::Normal);
+    }
+
+    #[test]
+    fn test_command_passes_fields_as_arguments() {
+        let notification = Notification {
+            title: "-rf; echo".to_string(),
+            body: "$(whoami)".to_string(),
+            urgency: Urgency::Low,
+            icon: Some("dialog-information".to_string()),
+        };
+        assert_eq!(
+            argv(&notification.command()),
+            [
+                "notify-send",
+                "--app-name",
+                "cvh-icons",
+                "--urgency",
+                "low",
+                "--icon",
+                "dialog-information",
+                "--",
+                "-rf; echo",
+                "$(whoami)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rate_limiter_drops_burst() {
+        let mut limiter = RateLimiter::new(3, Duration::from_secs(10));
+        let start = Instant::now();
+
+        let burst: Vec<bool> = (0..10).map(|i| limiter.allow(start + Duration::from_millis(i))).collect();
+        assert_eq!(burst.iter().filter(|&&sent| sent).count(), 3);
+        assert!(burst[..3].iter().all(|&sent| sent));
+
+        // Room frees up as the window slides past the earliest ones
+        assert!(!limiter.allow(start + Duration::from_secs(9)));
+        assert!(limiter.allow(start + Duration::from_secs(10)));
+        assert!(!limiter.allow(start + Duration::from_secs(10)));
+    }
+}
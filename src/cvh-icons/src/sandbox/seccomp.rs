@@ -0,0 +1,231 @@
+//! Seccomp filter for sandboxed Lua processes
+//!
+//! Builds a classic BPF program that allows only the syscalls the Lua
+//! interpreter needs. Bubblewrap installs it right before exec'ing the
+//! interpreter, or the Landlock trampoline in front of it, when given
+//! `--seccomp <fd>`.
+
+use anyhow::{bail, Context, Result};
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::os::fd::OwnedFd;
+
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+
+// Classic BPF opcodes (linux/filter.h)
+const BPF_LD_W_ABS: u16 = 0x20; // BPF_LD | BPF_W | BPF_ABS
+const BPF_JMP_JEQ_K: u16 = 0x15; // BPF_JMP | BPF_JEQ | BPF_K
+const BPF_RET_K: u16 = 0x06; // BPF_RET | BPF_K
+
+// Offsets into struct seccomp_data
+const OFFSET_NR: u32 = 0;
+const OFFSET_ARCH: u32 = 4;
+
+// linux/audit.h
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Syscalls the Lua interpreter uses to start up, load scripts and talk
+/// over stdin/stdout; everything else fails with EPERM
+const ALLOWED_SYSCALLS: &[libc::c_long] = &[
+    // Process startup (bwrap execs the interpreter after loading the filter)
+    libc::SYS_execve,
+    libc::SYS_brk,
+    libc::SYS_mmap,
+    libc::SYS_munmap,
+    libc::SYS_mremap,
+    libc::SYS_mprotect,
+    libc::SYS_madvise,
+    libc::SYS_set_tid_address,
+    libc::SYS_set_robust_list,
+    libc::SYS_rseq,
+    libc::SYS_prlimit64,
+    libc::SYS_getrandom,
+    libc::SYS_uname,
+    libc::SYS_getpid,
+    libc::SYS_getuid,
+    libc::SYS_geteuid,
+    libc::SYS_getgid,
+    libc::SYS_getegid,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_arch_prctl,
+    // Loading the handler and widget scripts
+    libc::SYS_openat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_open,
+    libc::SYS_close,
+    libc::SYS_read,
+    libc::SYS_pread64,
+    libc::SYS_lseek,
+    libc::SYS_fstat,
+    libc::SYS_newfstatat,
+    libc::SYS_statx,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_stat,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_access,
+    libc::SYS_faccessat,
+    libc::SYS_readlinkat,
+    libc::SYS_getcwd,
+    libc::SYS_getdents64,
+    libc::SYS_fcntl,
+    libc::SYS_ioctl,
+    // Saving cvh.state through a temporary file
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_rename,
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+    // IPC over stdin/stdout
+    libc::SYS_write,
+    libc::SYS_readv,
+    libc::SYS_writev,
+    #[cfg(target_arch = "x86_64")]
+    libc::SYS_poll,
+    libc::SYS_ppoll,
+    // Signals, time and threads
+    libc::SYS_rt_sigaction,
+    libc::SYS_rt_sigprocmask,
+    libc::SYS_rt_sigreturn,
+    libc::SYS_futex,
+    libc::SYS_clock_gettime,
+    libc::SYS_clock_nanosleep,
+    libc::SYS_nanosleep,
+    libc::SYS_gettimeofday,
+    libc::SYS_sched_yield,
+    libc::SYS_sigaltstack,
+    // The Landlock trampoline confining itself before exec'ing the interpreter
+    libc::SYS_prctl,
+    libc::SYS_landlock_create_ruleset,
+    libc::SYS_landlock_add_rule,
+    libc::SYS_landlock_restrict_self,
+    // Exit
+    libc::SYS_exit,
+    libc::SYS_exit_group,
+];
+
+/// One `struct sock_filter` instruction
+fn instruction(code: u16, jt: u8, jf: u8, k: u32) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    bytes[0..2].copy_from_slice(&code.to_ne_bytes());
+    bytes[2] = jt;
+    bytes[3] = jf;
+    bytes[4..8].copy_from_slice(&k.to_ne_bytes());
+    bytes
+}
+
+/// Build the filter as the raw `struct sock_filter` array bwrap expects
+///
+/// Returns None on architectures without a known audit arch value.
+pub fn filter_program() -> Option<Vec<u8>> {
+    let arch = AUDIT_ARCH?;
+    let count = ALLOWED_SYSCALLS.len();
+    debug_assert!(count <= u8::MAX as usize);
+
+    let mut program = Vec::with_capacity((count + 6) * 8);
+
+    // Kill anything running under a different syscall ABI
+    program.extend(instruction(BPF_LD_W_ABS, 0, 0, OFFSET_ARCH));
+    program.extend(instruction(BPF_JMP_JEQ_K, 1, 0, arch));
+    program.extend(instruction(BPF_RET_K, 0, 0, libc::SECCOMP_RET_KILL_PROCESS));
+
+    // Jump to the final ALLOW on any listed syscall number
+    program.extend(instruction(BPF_LD_W_ABS, 0, 0, OFFSET_NR));
+    for (i, nr) in ALLOWED_SYSCALLS.iter().enumerate() {
+        let to_allow = (count - i) as u8;
+        program.extend(instruction(BPF_JMP_JEQ_K, to_allow, 0, *nr as u32));
+    }
+
+    program.extend(instruction(BPF_RET_K, 0, 0, libc::SECCOMP_RET_ERRNO | libc::EPERM as u32));
+    program.extend(instruction(BPF_RET_K, 0, 0, libc::SECCOMP_RET_ALLOW));
+    Some(program)
+}
+
+/// Write the filter to an anonymous file for `bwrap --seccomp <fd>`
+///
+/// The descriptor is close-on-exec, so children spawned meanwhile for
+/// other icons don't inherit it; the bwrap child clears the flag right
+/// before exec. Drop it once the child has been spawned.
+pub fn filter_fd() -> Result<OwnedFd> {
+    let Some(program) = filter_program() else {
+        bail!("seccomp filtering is not supported on this architecture");
+    };
+
+    let fd = memfd_create(c"cvh-icons-seccomp", MemFdCreateFlag::MFD_CLOEXEC)
+        .context("Failed to create seccomp memfd")?;
+
+    let mut file = File::from(fd);
+    file.write_all(&program).context("Failed to write seccomp filter")?;
+    file.rewind().context("Failed to rewind seccomp filter")?;
+    Ok(OwnedFd::from(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::os::fd::AsRawFd;
+
+    fn decode(program: &[u8]) -> Vec<(u16, u8, u8, u32)> {
+        program
+            .chunks_exact(8)
+            .map(|c| {
+                (
+                    u16::from_ne_bytes([c[0], c[1]]),
+                    c[2],
+                    c[3],
+                    u32::from_ne_bytes([c[4], c[5], c[6], c[7]]),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_filter_program_layout() {
+        let program = filter_program().expect("supported test architecture");
+        assert_eq!(program.len() % 8, 0);
+
+        let insns = decode(&program);
+        assert_eq!(insns.len(), ALLOWED_SYSCALLS.len() + 6);
+        assert_eq!(insns[0], (BPF_LD_W_ABS, 0, 0, OFFSET_ARCH));
+        assert_eq!(insns[1].3, AUDIT_ARCH.unwrap());
+        assert_eq!(insns[2].3, libc::SECCOMP_RET_KILL_PROCESS);
+        assert_eq!(insns[insns.len() - 2].3, libc::SECCOMP_RET_ERRNO | libc::EPERM as u32);
+        assert_eq!(insns[insns.len() - 1].3, libc::SECCOMP_RET_ALLOW);
+    }
+
+    #[test]
+    fn test_allowed_syscalls_jump_to_allow() {
+        let insns = decode(&filter_program().unwrap());
+        let allow = insns.len() - 1;
+
+        for (pc, &(code, jt, _, k)) in insns.iter().enumerate().skip(4).take(ALLOWED_SYSCALLS.len()) {
+            assert_eq!(code, BPF_JMP_JEQ_K);
+            assert_eq!(pc + 1 + jt as usize, allow, "syscall {} jumps elsewhere", k);
+        }
+
+        let listed: Vec<u32> = insns[4..allow - 1].iter().map(|i| i.3).collect();
+        assert!(listed.contains(&(libc::SYS_read as u32)));
+        assert!(listed.contains(&(libc::SYS_execve as u32)));
+        assert!(!listed.contains(&(libc::SYS_socket as u32)));
+        assert!(!listed.contains(&(libc::SYS_ptrace as u32)));
+    }
+
+    #[test]
+    fn test_filter_fd_contains_program() {
+        let fd = filter_fd().unwrap();
+        let mut contents = Vec::new();
+        File::from(fd).read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, filter_program().unwrap());
+    }
+
+    #[test]
+    fn test_filter_fd_is_close_on_exec() {
+        let fd = filter_fd().unwrap();
+        let flags = nix::fcntl::fcntl(fd.as_raw_fd(), nix::fcntl::FcntlArg::F_GETFD).unwrap();
+        assert!(nix::fcntl::FdFlag::from_bits_truncate(flags).contains(nix::fcntl::FdFlag::FD_CLOEXEC));
+    }
+}
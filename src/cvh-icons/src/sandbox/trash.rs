@@ -0,0 +1,250 @@
+//! Moving files to the freedesktop trash on behalf of icon scripts
+//!
+//! A script can only ask for a `trash` action; the daemon checks the target
+//! lies on the desktop and then moves it into the home trash following the
+//! freedesktop.org Trash specification: the file goes to `files/` and a
+//! `.trashinfo` in `info/` records where it came from and when.
+
+use anyhow::{bail, Context, Result};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Component, Path, PathBuf};
+
+use crate::clock::LocalTime;
+
+/// Extension of the metadata file kept for each trashed file
+const INFO_EXTENSION: &str = ".trashinfo";
+
+/// The home trash: `$XDG_DATA_HOME/Trash`, usually `~/.local/share/Trash`
+pub fn home_trash() -> Option<PathBuf> {
+    dirs::data_dir().map(|dir| dir.join("Trash"))
+}
+
+/// Check that a script may trash `target` on the desktop at `desktop_dir`
+///
+/// Only existing entries strictly inside the desktop are accepted, named by
+/// an absolute path without `..`. The directory holding `target` must
+/// resolve to within the desktop, so a symlinked directory on the desktop
+/// can't reach outside it; `target` itself may be a symlink, which is
+/// trashed as such.
+pub fn check_target(target: &Path, desktop_dir: &Path) -> Result<()> {
+    if !target.is_absolute() || target.components().any(|c| c == Component::ParentDir) {
+        bail!("Refusing to trash '{}': not a plain absolute path", target.display());
+    }
+    let not_on_desktop = || anyhow::anyhow!("Refusing to trash '{}': not on the desktop", target.display());
+    let (Some(parent), Some(_)) = (target.parent(), target.file_name()) else {
+        return Err(not_on_desktop());
+    };
+    let desktop = desktop_dir
+        .canonicalize()
+        .with_context(|| format!("Cannot resolve the desktop {}", desktop_dir.display()))?;
+    let parent = parent
+        .canonicalize()
+        .with_context(|| format!("Cannot trash '{}'", target.display()))?;
+    if !parent.starts_with(&desktop) {
+        return Err(not_on_desktop());
+    }
+    fs::symlink_metadata(target).with_context(|| format!("Cannot trash '{}'", target.display()))?;
+    Ok(())
+}
+
+/// Move `target` into `trash_dir`, returning where it now lives
+///
+/// The `.trashinfo` is created first, exclusively, which reserves the name;
+/// if the move then fails it is removed again and the file is left alone.
+/// Files on another file system than the trash are refused rather than
+/// copied.
+pub fn trash(target: &Path, trash_dir: &Path) -> Result<PathBuf> {
+    let name = target.file_name().context("Cannot trash a path without a file name")?;
+    let files_dir = trash_dir.join("files");
+    let info_dir = trash_dir.join("info");
+    for dir in [&files_dir, &info_dir] {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+    }
+
+    let info = trash_info(target, &deletion_date(&LocalTime::now()));
+    let (trashed_name, info_path) = reserve_name(&files_dir, &info_dir, name, &info)?;
+    let trashed = files_dir.join(&trashed_name);
+
+    if let Err(e) = fs::rename(target, &trashed) {
+        let _ = fs::remove_file(&info_path);
+        if e.raw_os_error() == Some(libc::EXDEV) {
+            bail!("'{}' is on another file system than the trash", target.display());
+        }
+        return Err(e).with_context(|| format!("Failed to move '{}' to the trash", target.display()));
+    }
+    Ok(trashed)
+}
+
+/// Pick a free name for `name` in the trash and write its `.trashinfo`
+///
+/// Taken names get a counter appended, `notes.txt` becoming `notes.txt.2`,
+/// `notes.txt.3` and so on.
+fn reserve_name(files_dir: &Path, info_dir: &Path, name: &OsStr, info: &str) -> Result<(OsString, PathBuf)> {
+    for n in 1..=u16::MAX {
+        let candidate = trashed_name(name, n);
+        if fs::symlink_metadata(files_dir.join(&candidate)).is_ok() {
+            continue;
+        }
+
+        let mut info_name = candidate.clone();
+        info_name.push(INFO_EXTENSION);
+        let info_path = info_dir.join(info_name);
+        match OpenOptions::new().write(true).create_new(true).open(&info_path) {
+            Ok(mut file) => {
+                file.write_all(info.as_bytes())
+                    .with_context(|| format!("Failed to write {}", info_path.display()))?;
+                return Ok((candidate, info_path));
+            }
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {}", info_path.display())),
+        }
+    }
+    bail!("No free name in the trash for {:?}", name)
+}
+
+/// Name of the `n`th trashed file called `name`, counting from 1
+fn trashed_name(name: &OsStr, n: u16) -> OsString {
+    let mut candidate = name.to_os_string();
+    if n > 1 {
+        candidate.push(format!(".{}", n));
+    }
+    candidate
+}
+
+/// Contents of the `.trashinfo` for `original` deleted at `deletion_date`
+pub fn trash_info(original: &Path, deletion_date: &str) -> String {
+    format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        percent_encode(original.as_os_str()),
+        deletion_date
+    )
+}
+
+/// Escape a path for the `Path=` key: every byte but unreserved URI
+/// characters and `/` becomes `%XX`
+fn percent_encode(path: &OsStr) -> String {
+    let mut out = String::with_capacity(path.len());
+    for &byte in path.as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~/".contains(&byte) {
+            out.push(byte as char);
+        } else {
+            out.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    out
+}
+
+/// `YYYY-MM-DDThh:mm:ss`, as `DeletionDate=` expects in local time
+fn deletion_date(when: &LocalTime) -> String {
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        when.year, when.month, when.day, when.hour, when.min, when.sec
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trash_info_content() {
+        let info = trash_info(Path::new("/home/u/Desktop/My Notes #1.txt"), "2026-10-15T09:30:00");
+        assert_eq!(
+            info,
+            "[Trash Info]\nPath=/home/u/Desktop/My%20Notes%20%231.txt\nDeletionDate=2026-10-15T09:30:00\n"
+        );
+        assert_eq!(percent_encode(OsStr::new("/tmp/caf\u{e9}")), "/tmp/caf%C3%A9");
+    }
+
+    #[test]
+    fn test_deletion_date_format() {
+        let offset = chrono::FixedOffset::east_opt(3600).unwrap();
+        assert_eq!(deletion_date(&LocalTime::with_offset(1_700_000_000, offset)), "2023-11-14T23:13:20");
+
+        let date = deletion_date(&LocalTime::now());
+        assert_eq!(date.len(), "YYYY-MM-DDThh:mm:ss".len());
+        assert_eq!(&date[4..5], "-");
+        assert_eq!(&date[10..11], "T");
+    }
+
+    #[test]
+    fn test_trashed_names_are_deduplicated() {
+        assert_eq!(trashed_name(OsStr::new("notes.txt"), 1), "notes.txt");
+        assert_eq!(trashed_name(OsStr::new("notes.txt"), 3), "notes.txt.3");
+
+        let trash = tempfile::tempdir().unwrap();
+        let (files, info) = (trash.path().join("files"), trash.path().join("info"));
+        fs::create_dir_all(&files).unwrap();
+        fs::create_dir_all(&info).unwrap();
+        // A leftover file without its info still blocks the name
+        fs::write(files.join("notes.txt.2"), "").unwrap();
+
+        let names: Vec<OsString> = (0..3)
+            .map(|_| reserve_name(&files, &info, OsStr::new("notes.txt"), "[Trash Info]\n").unwrap().0)
+            .collect();
+        assert_eq!(names, ["notes.txt", "notes.txt.3", "notes.txt.4"]);
+        assert!(info.join("notes.txt.4.trashinfo").is_file());
+        assert!(!info.join("notes.txt.2.trashinfo").exists());
+    }
+
+    #[test]
+    fn test_trash_moves_file_and_writes_info() {
+        let desktop = tempfile::tempdir().unwrap();
+        let trash_dir = tempfile::tempdir().unwrap();
+        let target = desktop.path().join("report.pdf");
+        fs::write(&target, "pdf").unwrap();
+
+        check_target(&target, desktop.path()).unwrap();
+        let trashed = trash(&target, trash_dir.path()).unwrap();
+        assert!(!target.exists());
+        assert_eq!(trashed, trash_dir.path().join("files/report.pdf"));
+        assert_eq!(fs::read_to_string(&trashed).unwrap(), "pdf");
+
+        let info = fs::read_to_string(trash_dir.path().join("info/report.pdf.trashinfo")).unwrap();
+        assert!(info.starts_with("[Trash Info]\n"));
+        assert!(info.contains(&format!("Path={}\n", percent_encode(target.as_os_str()))));
+    }
+
+    #[test]
+    fn test_check_target_stays_on_desktop() {
+        let desktop = tempfile::tempdir().unwrap();
+        let inside = desktop.path().join("a.txt");
+        fs::write(&inside, "").unwrap();
+
+        assert!(check_target(&inside, desktop.path()).is_ok());
+        assert!(check_target(desktop.path(), desktop.path()).is_err(), "the desktop itself");
+        assert!(check_target(&desktop.path().join("missing"), desktop.path()).is_err());
+        assert!(check_target(&desktop.path().join("../a.txt"), desktop.path()).is_err());
+        assert!(check_target(Path::new("a.txt"), desktop.path()).is_err());
+        assert!(check_target(Path::new("/etc/passwd"), desktop.path()).is_err());
+    }
+
+    #[test]
+    fn test_check_target_rejects_paths_through_symlinked_dirs() {
+        let desktop = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let secret = outside.path().join("secret.txt");
+        fs::write(&secret, "").unwrap();
+        std::os::unix::fs::symlink(outside.path(), desktop.path().join("link")).unwrap();
+        std::os::unix::fs::symlink(&secret, desktop.path().join("shortcut.txt")).unwrap();
+
+        assert!(check_target(&desktop.path().join("link/secret.txt"), desktop.path()).is_err());
+        // A symlink on the desktop is trashed itself, not what it points to
+        assert!(check_target(&desktop.path().join("shortcut.txt"), desktop.path()).is_ok());
+        assert!(check_target(&desktop.path().join("link"), desktop.path()).is_ok());
+
+        // A desktop reached through a symlink still contains its files
+        let alias = outside.path().join("desktop-alias");
+        std::os::unix::fs::symlink(desktop.path(), &alias).unwrap();
+        fs::write(desktop.path().join("a.txt"), "").unwrap();
+        assert!(check_target(&desktop.path().join("a.txt"), &alias).is_ok());
+    }
+}
@@ -0,0 +1,151 @@
+//! Dry-run validation of icon scripts
+//!
+//! Loads a script in-process and renders it once against a synthetic
+//! context, so widget authors can check a script without the daemon.
+
+use anyhow::{Context, Result};
+use mlua::Function;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::ipc::RenderContext;
+use crate::lua::api::DrawCommand;
+use crate::lua::LuaRuntime;
+use crate::renderer::trace::RenderTrace;
+
+/// Canvas used for the validation render, matching the default icon cell
+pub fn synthetic_context() -> RenderContext {
+    RenderContext {
+        canvas_width: 64,
+        canvas_height: 80,
+        device_pixel_ratio: 1.0,
+    }
+}
+
+/// Load `path` in a runtime set up from `config`, check it defines
+/// `Icon.render` and render it once
+///
+/// The icon table gets the same state fields the IPC handler sets,
+/// describing the script file itself.
+pub fn validate_script(path: &Path, context: &RenderContext, config: &Config) -> Result<Vec<DrawCommand>> {
+    let runtime = LuaRuntime::for_config(config)?;
+    let script = runtime.load_script(path)?;
+
+    script
+        .get::<Function>("render")
+        .context("Icon table must define a 'render' function")?;
+
+    let name = path.file_name().unwrap_or(path.as_os_str()).to_string_lossy();
+    script.set("path", path.to_string_lossy().to_string())?;
+    script.set("name", name.to_string())?;
+    script.set("width", context.canvas_width)?;
+    script.set("height", context.canvas_height)?;
+    script.set("selected", false)?;
+    script.set("hovered", false)?;
+
+    script.call_init().context("Icon:init failed")?;
+    script
+        .call_render(context.canvas_width, context.canvas_height)
+        .context("Icon:render failed")
+}
+
+/// Validate `path` and report the draw commands on stdout, also writing
+/// them to `trace` if given
+pub fn run(path: &Path, trace: Option<&Path>, config: &Config) -> Result<()> {
+    let context = synthetic_context();
+    let commands = validate_script(path, &context, config)
+        .with_context(|| format!("{} is not a valid icon script", path.display()))?;
+
+    println!(
+        "{}: OK, {} draw command(s) at {}x{}",
+        path.display(),
+        commands.len(),
+        context.canvas_width,
+        context.canvas_height
+    );
+    for command in &commands {
+        println!("  {:?}", command);
+    }
+
+    if let Some(target) = trace {
+        RenderTrace::open(target)?.record(path, &commands)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_script(dir: &Path, content: &str) -> std::path::PathBuf {
+        let path = dir.join("widget.lua");
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_validate_good_script() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_script(
+            dir.path(),
+            r##"
+            Icon = {}
+            function Icon:render(canvas)
+                canvas:fill_rect(0, 0, self.width, self.height, "#336699")
+            end
+            "##,
+        );
+
+        let commands = validate_script(&path, &synthetic_context(), &Config::default()).unwrap();
+        assert_eq!(commands.len(), 1);
+        assert!(matches!(
+            commands[0],
+            DrawCommand::FillRect { w, h, .. } if w == 64.0 && h == 80.0
+        ));
+    }
+
+    #[test]
+    fn test_validate_bundled_widgets() {
+        let widgets = Path::new(env!("CARGO_MANIFEST_DIR")).join("lua/widgets");
+        for name in ["file.lua", "folder.lua"] {
+            let commands = validate_script(&widgets.join(name), &synthetic_context(), &Config::default())
+                .unwrap_or_else(|e| panic!("{}: {:#}", name, e));
+            assert!(!commands.is_empty(), "{} drew nothing", name);
+        }
+    }
+
+    #[test]
+    fn test_validate_syntax_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_script(dir.path(), "Icon = {\nfunction Icon:render(canvas)\n");
+        assert!(validate_script(&path, &synthetic_context(), &Config::default()).is_err());
+    }
+
+    #[test]
+    fn test_validate_missing_icon_table() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_script(dir.path(), "Widget = {}\n");
+        let err = validate_script(&path, &synthetic_context(), &Config::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("'Icon' table"));
+    }
+
+    #[test]
+    fn test_validate_missing_render() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_script(dir.path(), "Icon = { name = 'broken' }\n");
+        let err = validate_script(&path, &synthetic_context(), &Config::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("'render' function"));
+    }
+
+    #[test]
+    fn test_validate_render_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_script(
+            dir.path(),
+            "Icon = {}\nfunction Icon:render(canvas) canvas:no_such_method() end\n",
+        );
+        let err = validate_script(&path, &synthetic_context(), &Config::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("Icon:render failed"));
+    }
+}
@@ -9,8 +9,8 @@ use tracing::{debug, info};
 
 use smithay_client_toolkit::{
     compositor::{CompositorHandler, CompositorState},
-    delegate_compositor, delegate_layer, delegate_output, delegate_pointer, delegate_registry,
-    delegate_seat, delegate_shm,
+    delegate_compositor, delegate_keyboard, delegate_layer, delegate_output, delegate_pointer,
+    delegate_registry, delegate_seat, delegate_shm,
     output::{OutputHandler, OutputState},
     reexports::{
         calloop::{EventLoop, LoopHandle},
@@ -18,6 +18,7 @@ use smithay_client_toolkit::{
         client::{
             globals::registry_queue_init,
             protocol::{
+                wl_keyboard::WlKeyboard,
                 wl_output::WlOutput,
                 wl_pointer::WlPointer,
                 wl_seat::WlSeat,
@@ -30,6 +31,7 @@ use smithay_client_toolkit::{
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
     seat::{
+        keyboard::{KeyEvent, KeyboardHandler, Keysym, Modifiers, RawModifiers},
         pointer::{PointerEvent, PointerEventKind, PointerHandler},
         Capability, SeatHandler, SeatState,
     },
@@ -49,6 +51,9 @@ use smithay_client_toolkit::{
 /// Unique identifier for icon surfaces
 pub type SurfaceId = u64;
 
+/// Longest gap between two clicks that still counts as a double-click
+pub const DOUBLE_CLICK_INTERVAL_MS: u32 = 400;
+
 /// Input event from Wayland
 #[derive(Debug, Clone)]
 pub enum InputEvent {
@@ -74,6 +79,90 @@ pub enum InputEvent {
         x: f64,
         y: f64,
     },
+    /// Button pressed and released on the same surface
+    Click {
+        surface_id: SurfaceId,
+        button: u32,
+        x: f64,
+        y: f64,
+        /// Second click of a double-click
+        double: bool,
+    },
+    /// A key icons react to was pressed, or is repeating
+    Key(Key),
+}
+
+/// Keys for moving the keyboard focus between icons and acting on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Up,
+    Down,
+    Left,
+    Right,
+    /// Return or keypad Enter
+    Enter,
+    Space,
+}
+
+impl Key {
+    /// The key for `keysym`, if icons react to it
+    pub fn from_keysym(keysym: Keysym) -> Option<Self> {
+        match keysym {
+            Keysym::Up | Keysym::KP_Up => Some(Self::Up),
+            Keysym::Down | Keysym::KP_Down => Some(Self::Down),
+            Keysym::Left | Keysym::KP_Left => Some(Self::Left),
+            Keysym::Right | Keysym::KP_Right => Some(Self::Right),
+            Keysym::Return | Keysym::KP_Enter => Some(Self::Enter),
+            Keysym::space => Some(Self::Space),
+            _ => None,
+        }
+    }
+}
+
+/// Pairs button presses with releases and tells single clicks from
+/// double-clicks
+///
+/// Times are the compositor's millisecond event timestamps.
+#[derive(Debug, Default)]
+pub struct ClickTracker {
+    /// Surface and button currently held down
+    pressed: Option<(SurfaceId, u32)>,
+    /// Surface, button and time of the last single click
+    last_click: Option<(SurfaceId, u32, u32)>,
+}
+
+impl ClickTracker {
+    /// Record a button press on a surface
+    pub fn press(&mut self, surface_id: SurfaceId, button: u32) {
+        self.pressed = Some((surface_id, button));
+    }
+
+    /// Record a button release
+    ///
+    /// Returns `Some(double)` if this completes a click, i.e. the same
+    /// button was pressed on the same surface. `double` is true when it
+    /// follows a single click of that button on that surface within
+    /// `DOUBLE_CLICK_INTERVAL_MS`; a third click starts over as a single.
+    pub fn release(&mut self, surface_id: SurfaceId, button: u32, time: u32) -> Option<bool> {
+        if self.pressed.take() != Some((surface_id, button)) {
+            return None;
+        }
+
+        let double = matches!(
+            self.last_click,
+            Some((last_surface, last_button, last_time))
+                if last_surface == surface_id
+                    && last_button == button
+                    && time.wrapping_sub(last_time) <= DOUBLE_CLICK_INTERVAL_MS
+        );
+        self.last_click = if double { None } else { Some((surface_id, button, time)) };
+        Some(double)
+    }
+
+    /// Forget a press when the pointer leaves its surface
+    pub fn cancel(&mut self) {
+        self.pressed = None;
+    }
 }
 
 /// Icon surface data
@@ -117,11 +206,15 @@ pub struct WaylandState {
     outputs: Vec<WlOutput>,
     /// Current pointer
     pointer: Option<WlPointer>,
+    /// Current keyboard
+    keyboard: Option<WlKeyboard>,
     /// Pointer position
     pointer_x: f64,
     pointer_y: f64,
     /// Surface under pointer
     pointer_surface: Option<SurfaceId>,
+    /// Click and double-click detection
+    clicks: ClickTracker,
     /// Pending input events
     input_events: Vec<InputEvent>,
     /// Whether to exit
@@ -154,7 +247,8 @@ impl WaylandState {
         layer_surface.set_exclusive_zone(-1); // Don't reserve space
         layer_surface.set_size(width, height);
         layer_surface.set_margin(y, 0, 0, x); // top, right, bottom, left margins for positioning
-        layer_surface.set_keyboard_interactivity(KeyboardInteractivity::None);
+        // Take keyboard focus when clicked, for arrow key navigation
+        layer_surface.set_keyboard_interactivity(KeyboardInteractivity::OnDemand);
 
         // Commit initial state
         layer_surface.commit();
@@ -201,7 +295,17 @@ impl WaylandState {
     }
 
     /// Attach a pixmap buffer to a surface
-    pub fn attach_buffer(&mut self, surface_id: SurfaceId, pixels: &[u8], width: u32, height: u32) -> Result<()> {
+    ///
+    /// `width` and `height` are in buffer pixels; the surface keeps its
+    /// logical size, `buffer_scale` times smaller.
+    pub fn attach_buffer(
+        &mut self,
+        surface_id: SurfaceId,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        buffer_scale: u32,
+    ) -> Result<()> {
         let surface_data = self.surfaces.get_mut(&surface_id)
             .ok_or_else(|| anyhow::anyhow!("Surface {} not found", surface_id))?;
 
@@ -230,23 +334,12 @@ impl WaylandState {
             )
             .context("Failed to create buffer")?;
 
-        // Copy pixels (converting from RGBA to ARGB if needed)
-        // tiny-skia uses RGBA premultiplied, Wayland expects ARGB
-        for (i, chunk) in pixels.chunks(4).enumerate() {
-            let r = chunk[0];
-            let g = chunk[1];
-            let b = chunk[2];
-            let a = chunk[3];
-            let offset = i * 4;
-            canvas[offset] = b;     // B
-            canvas[offset + 1] = g; // G
-            canvas[offset + 2] = r; // R
-            canvas[offset + 3] = a; // A
-        }
+        rgba_to_argb8888(pixels, canvas);
 
         // Attach and commit
         let wl_surface = surface_data.layer_surface.wl_surface();
         buffer.attach_to(wl_surface).context("Failed to attach buffer")?;
+        wl_surface.set_buffer_scale(buffer_scale as i32);
         wl_surface.damage_buffer(0, 0, width as i32, height as i32);
         wl_surface.commit();
 
@@ -286,9 +379,10 @@ impl CompositorHandler for WaylandState {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _surface: &WlSurface,
-        _new_factor: i32,
+        new_factor: i32,
     ) {
-        // Handle scale factor changes if needed
+        // The daemon polls get_output_scale() and re-renders on change
+        debug!("Surface scale factor changed to {}", new_factor);
     }
 
     fn transform_changed(
@@ -386,6 +480,15 @@ impl WaylandState {
     }
 }
 
+impl WaylandState {
+    /// Get the integer scale factor of the primary output
+    pub fn get_output_scale(&self) -> Option<u32> {
+        let output = self.outputs.first()?;
+        let info = self.output_state.info(output)?;
+        Some(info.scale_factor.max(1) as u32)
+    }
+}
+
 impl LayerShellHandler for WaylandState {
     fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, layer: &LayerSurface) {
         // Find the surface that was closed
@@ -448,6 +551,10 @@ impl SeatHandler for WaylandState {
             debug!("Creating pointer for seat");
             self.pointer = self.seat_state.get_pointer(qh, &seat).ok();
         }
+        if capability == Capability::Keyboard && self.keyboard.is_none() {
+            debug!("Creating keyboard for seat");
+            self.keyboard = self.seat_state.get_keyboard(qh, &seat, None).ok();
+        }
     }
 
     fn remove_capability(
@@ -460,6 +567,11 @@ impl SeatHandler for WaylandState {
         if capability == Capability::Pointer {
             self.pointer = None;
         }
+        if capability == Capability::Keyboard {
+            if let Some(keyboard) = self.keyboard.take() {
+                keyboard.release();
+            }
+        }
     }
 
     fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: WlSeat) {
@@ -495,6 +607,7 @@ impl PointerHandler for WaylandState {
                 PointerEventKind::Leave { .. } => {
                     if let Some(&surface_id) = self.surface_ids.get(surface) {
                         self.pointer_surface = None;
+                        self.clicks.cancel();
                         self.input_events.push(InputEvent::PointerLeave { surface_id });
                     }
                 }
@@ -511,6 +624,7 @@ impl PointerHandler for WaylandState {
                 }
                 PointerEventKind::Press { button, .. } => {
                     if let Some(surface_id) = self.pointer_surface {
+                        self.clicks.press(surface_id, *button);
                         self.input_events.push(InputEvent::PointerButton {
                             surface_id,
                             button: *button,
@@ -520,7 +634,7 @@ impl PointerHandler for WaylandState {
                         });
                     }
                 }
-                PointerEventKind::Release { button, .. } => {
+                PointerEventKind::Release { button, time, .. } => {
                     if let Some(surface_id) = self.pointer_surface {
                         self.input_events.push(InputEvent::PointerButton {
                             surface_id,
@@ -529,6 +643,15 @@ impl PointerHandler for WaylandState {
                             x: self.pointer_x,
                             y: self.pointer_y,
                         });
+                        if let Some(double) = self.clicks.release(surface_id, *button, *time) {
+                            self.input_events.push(InputEvent::Click {
+                                surface_id,
+                                button: *button,
+                                x: self.pointer_x,
+                                y: self.pointer_y,
+                                double,
+                            });
+                        }
                     }
                 }
                 PointerEventKind::Axis { .. } => {
@@ -539,6 +662,79 @@ impl PointerHandler for WaylandState {
     }
 }
 
+impl KeyboardHandler for WaylandState {
+    fn enter(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _surface: &WlSurface,
+        _serial: u32,
+        _raw: &[u32],
+        _keysyms: &[Keysym],
+    ) {
+    }
+
+    fn leave(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _surface: &WlSurface,
+        _serial: u32,
+    ) {
+    }
+
+    fn press_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        event: KeyEvent,
+    ) {
+        if let Some(key) = Key::from_keysym(event.keysym) {
+            self.input_events.push(InputEvent::Key(key));
+        }
+    }
+
+    fn repeat_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        event: KeyEvent,
+    ) {
+        // Holding an arrow keeps moving; holding Enter or Space does nothing more
+        if let Some(key @ (Key::Up | Key::Down | Key::Left | Key::Right)) = Key::from_keysym(event.keysym) {
+            self.input_events.push(InputEvent::Key(key));
+        }
+    }
+
+    fn release_key(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        _event: KeyEvent,
+    ) {
+    }
+
+    fn update_modifiers(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _keyboard: &WlKeyboard,
+        _serial: u32,
+        _modifiers: Modifiers,
+        _raw_modifiers: RawModifiers,
+        _layout: u32,
+    ) {
+    }
+}
+
 impl ShmHandler for WaylandState {
     fn shm_state(&mut self) -> &mut Shm {
         &mut self.shm
@@ -557,9 +753,35 @@ delegate_output!(WaylandState);
 delegate_layer!(WaylandState);
 delegate_seat!(WaylandState);
 delegate_pointer!(WaylandState);
+delegate_keyboard!(WaylandState);
 delegate_shm!(WaylandState);
 delegate_registry!(WaylandState);
 
+/// Convert tiny-skia pixels into a wl_shm `Argb8888` buffer
+///
+/// Both are premultiplied, so only the channel order changes: tiny-skia
+/// stores bytes as R, G, B, A, while `Argb8888` is a little-endian 32-bit
+/// word, i.e. bytes B, G, R, A in memory.
+fn rgba_to_argb8888(rgba: &[u8], argb: &mut [u8]) {
+    for (src, dst) in rgba.chunks_exact(4).zip(argb.chunks_exact_mut(4)) {
+        let word = u32::from_be_bytes([src[3], src[0], src[1], src[2]]);
+        dst.copy_from_slice(&word.to_le_bytes());
+    }
+}
+
+/// Buffer scale for a (possibly fractional) scale factor
+///
+/// `wl_surface.set_buffer_scale` only takes integers, so fractional factors
+/// round up and the compositor downsamples; this stays sharper than
+/// rendering at 1x and upscaling.
+pub fn buffer_scale(scale: f32) -> u32 {
+    if scale.is_finite() && scale > 1.0 {
+        scale.ceil() as u32
+    } else {
+        1
+    }
+}
+
 /// Wayland manager - high level interface for the daemon
 pub struct WaylandManager {
     /// The event loop
@@ -629,9 +851,11 @@ impl WaylandManager {
             next_surface_id: 1,
             outputs: Vec::new(),
             pointer: None,
+            keyboard: None,
             pointer_x: 0.0,
             pointer_y: 0.0,
             pointer_surface: None,
+            clicks: ClickTracker::default(),
             input_events: Vec::new(),
             exit: false,
         };
@@ -666,8 +890,15 @@ impl WaylandManager {
     }
 
     /// Attach a buffer to a surface (pixels in RGBA format)
-    pub fn attach_buffer(&mut self, surface_id: SurfaceId, pixels: &[u8], width: u32, height: u32) -> Result<()> {
-        self.state.attach_buffer(surface_id, pixels, width, height)
+    pub fn attach_buffer(
+        &mut self,
+        surface_id: SurfaceId,
+        pixels: &[u8],
+        width: u32,
+        height: u32,
+        buffer_scale: u32,
+    ) -> Result<()> {
+        self.state.attach_buffer(surface_id, pixels, width, height, buffer_scale)
     }
 
     /// Dispatch Wayland events (non-blocking)
@@ -678,6 +909,16 @@ impl WaylandManager {
         Ok(())
     }
 
+    /// A duplicate of the event loop's fd, readable while Wayland events are
+    /// waiting, so an outer event loop can wake up for them
+    pub fn wake_fd(&self) -> Result<std::os::fd::OwnedFd> {
+        use std::os::fd::AsFd;
+        self.event_loop
+            .as_fd()
+            .try_clone_to_owned()
+            .context("Failed to duplicate the Wayland event loop fd")
+    }
+
     /// Get pending input events
     pub fn take_input_events(&mut self) -> Vec<InputEvent> {
         self.state.take_input_events()
@@ -704,6 +945,11 @@ impl WaylandManager {
     pub fn get_output_dimensions(&self) -> Option<(u32, u32)> {
         self.state.get_output_dimensions()
     }
+
+    /// Get the integer scale factor of the primary output
+    pub fn get_output_scale(&self) -> Option<u32> {
+        self.state.get_output_scale()
+    }
 }
 
 #[cfg(test)]
@@ -742,6 +988,130 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_key_from_keysym() {
+        assert_eq!(Key::from_keysym(Keysym::Left), Some(Key::Left));
+        assert_eq!(Key::from_keysym(Keysym::KP_Down), Some(Key::Down));
+        assert_eq!(Key::from_keysym(Keysym::Return), Some(Key::Enter));
+        assert_eq!(Key::from_keysym(Keysym::KP_Enter), Some(Key::Enter));
+        assert_eq!(Key::from_keysym(Keysym::space), Some(Key::Space));
+        assert_eq!(Key::from_keysym(Keysym::a), None);
+    }
+
+    #[test]
+    fn test_rgba_to_argb8888_byte_order() {
+        let rgba = [0x11, 0x22, 0x33, 0xff, 0xaa, 0xbb, 0xcc, 0xdd];
+        let mut argb = [0u8; 8];
+        rgba_to_argb8888(&rgba, &mut argb);
+        assert_eq!(argb, [0x33, 0x22, 0x11, 0xff, 0xcc, 0xbb, 0xaa, 0xdd]);
+
+        let word = u32::from_le_bytes([argb[0], argb[1], argb[2], argb[3]]);
+        assert_eq!(word, 0xff11_2233);
+    }
+
+    #[test]
+    fn test_rgba_to_argb8888_keeps_premultiplied_alpha() {
+        // Half-transparent red as tiny-skia stores it: color already scaled by alpha
+        let mut pixmap = tiny_skia::Pixmap::new(1, 1).unwrap();
+        pixmap.fill(tiny_skia::Color::from_rgba8(255, 0, 0, 128));
+
+        let mut argb = [0u8; 4];
+        rgba_to_argb8888(pixmap.data(), &mut argb);
+        assert_eq!(argb, [0, 0, 128, 128]);
+    }
+
+    #[test]
+    fn test_rgba_to_argb8888_fully_transparent() {
+        let pixmap = tiny_skia::Pixmap::new(2, 2).unwrap();
+        let mut argb = [0xffu8; 16];
+        rgba_to_argb8888(pixmap.data(), &mut argb);
+        assert!(argb.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn test_buffer_scale_rounds_up() {
+        assert_eq!(buffer_scale(1.0), 1);
+        assert_eq!(buffer_scale(2.0), 2);
+        assert_eq!(buffer_scale(1.25), 2);
+        assert_eq!(buffer_scale(1.5), 2);
+        assert_eq!(buffer_scale(2.5), 3);
+    }
+
+    #[test]
+    fn test_buffer_scale_invalid_is_one() {
+        assert_eq!(buffer_scale(0.0), 1);
+        assert_eq!(buffer_scale(0.5), 1);
+        assert_eq!(buffer_scale(-2.0), 1);
+        assert_eq!(buffer_scale(f32::NAN), 1);
+    }
+
+    #[test]
+    fn test_click_tracker_single_click() {
+        let mut clicks = ClickTracker::default();
+        clicks.press(1, 272);
+        assert_eq!(clicks.release(1, 272, 1000), Some(false));
+    }
+
+    #[test]
+    fn test_click_tracker_double_click_within_interval() {
+        let mut clicks = ClickTracker::default();
+        clicks.press(1, 272);
+        assert_eq!(clicks.release(1, 272, 1000), Some(false));
+        clicks.press(1, 272);
+        assert_eq!(clicks.release(1, 272, 1000 + DOUBLE_CLICK_INTERVAL_MS), Some(true));
+    }
+
+    #[test]
+    fn test_click_tracker_slow_second_click_is_single() {
+        let mut clicks = ClickTracker::default();
+        clicks.press(1, 272);
+        clicks.release(1, 272, 1000);
+        clicks.press(1, 272);
+        assert_eq!(clicks.release(1, 272, 1001 + DOUBLE_CLICK_INTERVAL_MS), Some(false));
+    }
+
+    #[test]
+    fn test_click_tracker_third_click_starts_over() {
+        let mut clicks = ClickTracker::default();
+        for (time, expected) in [(1000, false), (1100, true), (1200, false), (1300, true)] {
+            clicks.press(1, 272);
+            assert_eq!(clicks.release(1, 272, time), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_click_tracker_requires_same_surface_and_button() {
+        let mut clicks = ClickTracker::default();
+        clicks.press(1, 272);
+        clicks.release(1, 272, 1000);
+        clicks.press(2, 272);
+        assert_eq!(clicks.release(2, 272, 1100), Some(false), "different surface");
+        clicks.press(2, 273);
+        assert_eq!(clicks.release(2, 273, 1150), Some(false), "different button");
+    }
+
+    #[test]
+    fn test_click_tracker_release_without_matching_press() {
+        let mut clicks = ClickTracker::default();
+        assert_eq!(clicks.release(1, 272, 1000), None);
+
+        clicks.press(1, 272);
+        assert_eq!(clicks.release(2, 272, 1000), None, "released over another surface");
+
+        clicks.press(1, 272);
+        clicks.cancel();
+        assert_eq!(clicks.release(1, 272, 1000), None, "pointer left in between");
+    }
+
+    #[test]
+    fn test_click_tracker_timestamp_wraparound() {
+        let mut clicks = ClickTracker::default();
+        clicks.press(1, 272);
+        clicks.release(1, 272, u32::MAX - 50);
+        clicks.press(1, 272);
+        assert_eq!(clicks.release(1, 272, 100), Some(true));
+    }
+
     // Note: WaylandManager tests require a running Wayland display
     // and are better suited for integration testing
 }